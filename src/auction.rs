@@ -1,27 +1,73 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Id of an item, qualified with its auction house unless it's in the default one
+///
+/// Eg. `54321` in the default auction house, `ebay:54321` in the `ebay` one.
 pub type ItemId = String;
 pub type ItemIdRef<'s> = &'s str;
 pub type Amount = u64;
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+/// Identifies one of the auction houses the sniper bids in
+pub type AuctionHouseId = String;
+pub type AuctionHouseIdRef<'s> = &'s str;
+
+/// Separates the [`AuctionHouseId`] from the id of the item within it, in an [`ItemId`]
+pub const AUCTION_HOUSE_SEPARATOR: char = ':';
+
+/// The id of `item_id` of `auction_house`, as the rest of the sniper knows it
+pub fn qualified_item_id(auction_house: AuctionHouseIdRef, item_id: ItemIdRef) -> ItemId {
+    format!("{auction_house}{AUCTION_HOUSE_SEPARATOR}{item_id}")
+}
+
+/// The auction house an item is qualified with, and its id within it
+pub fn split_item_id(item_id: ItemIdRef) -> Option<(AuctionHouseIdRef, ItemIdRef)> {
+    item_id.split_once(AUCTION_HOUSE_SEPARATOR)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Bidder {
     Sniper,
     #[allow(unused)]
     Other,
 }
 
+impl Bidder {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Bidder::Sniper => "sniper",
+            Bidder::Other => "other",
+        }
+    }
+}
+
+impl FromStr for Bidder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "sniper" => Bidder::Sniper,
+            "other" => Bidder::Other,
+            _ => bail!("unknown bidder: {s}"),
+        })
+    }
+}
+
+#[allow(unused)]
 #[derive(Clone, PartialEq, Eq)]
 pub struct Bid {
     pub item: ItemId,
     pub details: BidDetails,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct ItemBid {
     pub item: ItemId,
     pub price: Amount,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BidDetails {
     pub bidder: Bidder,
     pub price: Amount,
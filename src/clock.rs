@@ -0,0 +1,109 @@
+//! Clocks
+//!
+//! Anything that depends on what time it is (deadlines, last moment bids,
+//! timer ticks) asks a [`Clock`] instead of the OS, so tests can run
+//! the whole system on a [`SimulatedClock`] and fast-forward time
+//! deterministically.
+//!
+//! Timeouts that only bound how long a thread blocks (eg. in
+//! [`crate::event_log::Reader::read`], so services notice being stopped)
+//! are not about what time it is, and stay in real time: a simulated
+//! clock nobody advances would block them forever.
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+#[cfg(test)]
+use std::{
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
+
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    fn now(&self) -> SystemTime;
+
+    /// Block until `duration` passes on this clock
+    ///
+    /// Might return early, so callers that care should check [`Clock::now`].
+    fn sleep(&self, duration: Duration);
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The real time, as reported by the OS
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn new_shared() -> SharedClock {
+        Arc::new(Self)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Clock that stands still until [`SimulatedClock::advance`]d
+#[cfg(test)]
+#[derive(Debug)]
+pub struct SimulatedClock {
+    now: Mutex<SystemTime>,
+    /// Wakes up sleepers when the time advances
+    condvar: Condvar,
+}
+
+#[cfg(test)]
+impl SimulatedClock {
+    /// How long to block in [`Clock::sleep`] at most, in real time,
+    /// so sleeping services can still be stopped
+    const MAX_REAL_SLEEP: Duration = Duration::from_millis(100);
+
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(start),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn new_shared(start: SystemTime) -> Arc<Self> {
+        Arc::new(Self::new(start))
+    }
+
+    /// Move the time forward by `duration`, waking up whoever it is due for
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().expect("mutex poisoned");
+        *now += duration;
+        self.condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+impl Clock for SimulatedClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().expect("mutex poisoned")
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let real_deadline = Instant::now() + Self::MAX_REAL_SLEEP;
+        let mut now = self.now.lock().expect("mutex poisoned");
+        let until = *now + duration;
+        while *now < until {
+            let real_now = Instant::now();
+            if real_deadline <= real_now {
+                break;
+            }
+            now = self
+                .condvar
+                .wait_timeout(now, real_deadline - real_now)
+                .expect("mutex poisoned")
+                .0;
+        }
+    }
+}
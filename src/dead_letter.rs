@@ -0,0 +1,116 @@
+//! Dead letters: log events a service gave up on handling
+//!
+//! When a [`crate::service::LogFollowerService`] keeps failing on a poison
+//! event, the event is moved here, so the service can make progress.
+//! An admin can then inspect them, and either discard them, or request
+//! a re-drive, which makes the service try to handle the event again.
+//!
+//! A re-driven event is handled after all the events that followed it in
+//! the log, possibly long after, eg. a max bid set before others for the
+//! same item would override them. Services don't guard against that, so
+//! a re-drive should only be requested when the service can take the event
+//! out of order, and discarding it is the safe default otherwise.
+mod in_memory;
+mod postgres;
+mod sqlite;
+
+pub use self::{in_memory::*, postgres::*, sqlite::*};
+
+use crate::{
+    event_log::{LogEvent, Offset},
+    persistence::{Connection, Transaction},
+    service::{ServiceId, ServiceIdRef},
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{sync::Arc, time::SystemTime};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeadLetter {
+    pub service_id: ServiceId,
+    pub event: LogEvent,
+    /// Error of the last attempt to handle the event
+    pub error: String,
+    /// Number of failed attempts to handle the event
+    pub attempts: u32,
+    /// Time of the last failed attempt
+    pub failed_at: SystemTime,
+    /// An admin asked for the event to be handled again
+    pub redrive_requested: bool,
+}
+
+/// A persistent store of [`DeadLetter`]s, identified by the service id and event offset
+pub trait DeadLetterStore {
+    /// Insert a new dead letter, or replace an existing one
+    fn store_tr(&self, conn: &mut dyn Transaction<'_>, dead_letter: DeadLetter) -> Result<()>;
+
+    fn load_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        service_id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<Option<DeadLetter>>;
+
+    /// Returns `false` if there was no such dead letter
+    fn remove_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        service_id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<bool>;
+
+    /// List dead letters of `service_id` (or all of them), ordered by the service id and offset
+    fn list(
+        &self,
+        conn: &mut dyn Connection,
+        service_id: Option<ServiceIdRef>,
+    ) -> Result<Vec<DeadLetter>>;
+
+    /// The dead letter of `service_id` with the lowest offset, that has a re-drive requested
+    fn next_redrive(
+        &self,
+        conn: &mut dyn Connection,
+        service_id: ServiceIdRef,
+    ) -> Result<Option<DeadLetter>>;
+
+    /// Ask for the dead letter to be handled again by its service
+    ///
+    /// Returns `false` if there was no such dead letter.
+    fn request_redrive(
+        &self,
+        conn: &mut dyn Connection,
+        service_id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<bool> {
+        let mut transaction = conn.start_transaction()?;
+        let Some(dead_letter) = self.load_tr(&mut *transaction, service_id, offset)? else {
+            return Ok(false);
+        };
+        self.store_tr(
+            &mut *transaction,
+            DeadLetter {
+                redrive_requested: true,
+                ..dead_letter
+            },
+        )?;
+        transaction.commit()?;
+        Ok(true)
+    }
+
+    /// Drop the dead letter, without handling it
+    ///
+    /// Returns `false` if there was no such dead letter.
+    fn discard(
+        &self,
+        conn: &mut dyn Connection,
+        service_id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<bool> {
+        let mut transaction = conn.start_transaction()?;
+        let removed = self.remove_tr(&mut *transaction, service_id, offset)?;
+        transaction.commit()?;
+        Ok(removed)
+    }
+}
+
+pub type SharedDeadLetterStore = Arc<dyn DeadLetterStore + Send + Sync + 'static>;
@@ -0,0 +1,88 @@
+use crate::persistence::{InMemoryConnection, InMemoryTable, InMemoryTransaction};
+
+use super::*;
+
+type Key = (ServiceId, Offset);
+
+#[derive(Default)]
+pub struct InMemoryDeadLetterStore {
+    store: InMemoryTable<Key, DeadLetter>,
+}
+
+impl InMemoryDeadLetterStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_shared() -> SharedDeadLetterStore {
+        Arc::new(Self::new())
+    }
+}
+
+impl DeadLetterStore for InMemoryDeadLetterStore {
+    fn store_tr(&self, conn: &mut dyn Transaction<'_>, dead_letter: DeadLetter) -> Result<()> {
+        let mut caster = conn.cast();
+        let transaction = caster.as_mut::<InMemoryTransaction>()?;
+
+        let key = (dead_letter.service_id.clone(), dead_letter.event.offset);
+        self.store.insert_tr(transaction, key, dead_letter);
+        Ok(())
+    }
+
+    fn load_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        service_id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<Option<DeadLetter>> {
+        let mut caster = conn.cast();
+        let transaction = caster.as_mut::<InMemoryTransaction>()?;
+        Ok(self
+            .store
+            .get_tr(transaction, &(service_id.to_owned(), offset)))
+    }
+
+    fn remove_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        service_id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<bool> {
+        let mut caster = conn.cast();
+        let transaction = caster.as_mut::<InMemoryTransaction>()?;
+
+        let key = (service_id.to_owned(), offset);
+        Ok(self.store.remove_tr(transaction, key).is_some())
+    }
+
+    fn list(
+        &self,
+        conn: &mut dyn Connection,
+        service_id: Option<ServiceIdRef>,
+    ) -> Result<Vec<DeadLetter>> {
+        conn.cast().as_mut::<InMemoryConnection>()?;
+        Ok(self
+            .store
+            .lock()
+            .values()
+            .filter(|dead_letter| service_id.map_or(true, |id| dead_letter.service_id == id))
+            .cloned()
+            .collect())
+    }
+
+    fn next_redrive(
+        &self,
+        conn: &mut dyn Connection,
+        service_id: ServiceIdRef,
+    ) -> Result<Option<DeadLetter>> {
+        conn.cast().as_mut::<InMemoryConnection>()?;
+        Ok(self
+            .store
+            .lock()
+            .values()
+            .find(|dead_letter| {
+                dead_letter.service_id == service_id && dead_letter.redrive_requested
+            })
+            .cloned())
+    }
+}
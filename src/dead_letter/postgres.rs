@@ -0,0 +1,168 @@
+use crate::{
+    event::wire,
+    persistence::{
+        migration::{ComponentMigrations, Migration},
+        PostgresConnection, PostgresTransaction,
+    },
+};
+use ::postgres::{types::Json, GenericClient, Row};
+use std::convert::TryFrom;
+
+use super::*;
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    sql: "CREATE TABLE IF NOT EXISTS dead_letters (
+        service_id TEXT NOT NULL,
+        log_offset BIGINT NOT NULL,
+        metadata JSONB NOT NULL,
+        details JSONB NOT NULL,
+        error TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        failed_at TIMESTAMPTZ NOT NULL,
+        redrive_requested BOOLEAN NOT NULL,
+        PRIMARY KEY (service_id, log_offset)
+    )",
+}];
+
+const COLUMNS: &str =
+    "service_id, log_offset, metadata, details, error, attempts, failed_at, redrive_requested";
+
+/// [`DeadLetterStore`] keeping dead letters in a Postgres table
+#[derive(Debug, Clone)]
+pub struct PostgresDeadLetterStore;
+
+impl PostgresDeadLetterStore {
+    pub const MIGRATIONS: ComponentMigrations = ComponentMigrations {
+        component: "dead_letters",
+        migrations: MIGRATIONS,
+    };
+
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn new_shared() -> SharedDeadLetterStore {
+        Arc::new(Self::new())
+    }
+}
+
+fn dead_letter_from_row(row: Row) -> Result<DeadLetter> {
+    Ok(DeadLetter {
+        service_id: row.get("service_id"),
+        event: LogEvent {
+            offset: u64::try_from(row.get::<'_, _, i64>("log_offset"))?,
+            metadata: serde_json::from_value(
+                row.get::<'_, _, Json<serde_json::Value>>("metadata").0,
+            )?,
+            details: wire::decode(row.get::<'_, _, Json<serde_json::Value>>("details").0)?,
+        },
+        error: row.get("error"),
+        attempts: u32::try_from(row.get::<'_, _, i32>("attempts"))?,
+        failed_at: row.get("failed_at"),
+        redrive_requested: row.get("redrive_requested"),
+    })
+}
+
+/// Select dead letters matching `condition`, ordered and possibly limited with `limit`
+fn query_dead_letters(
+    client: &mut impl GenericClient,
+    condition: &str,
+    limit: &str,
+    params: &[&(dyn ::postgres::types::ToSql + Sync)],
+) -> Result<Vec<DeadLetter>> {
+    client
+        .query(
+            &format!(
+                "SELECT {COLUMNS} FROM dead_letters WHERE {condition}
+                ORDER BY service_id, log_offset {limit}"
+            ),
+            params,
+        )?
+        .into_iter()
+        .map(dead_letter_from_row)
+        .collect()
+}
+
+impl DeadLetterStore for PostgresDeadLetterStore {
+    fn store_tr(&self, conn: &mut dyn Transaction<'_>, dead_letter: DeadLetter) -> Result<()> {
+        conn.cast().as_mut::<PostgresTransaction>()?.0.execute(
+            &format!(
+                "INSERT INTO dead_letters ({COLUMNS}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                ON CONFLICT (service_id, log_offset) DO UPDATE SET
+                    metadata = EXCLUDED.metadata,
+                    details = EXCLUDED.details,
+                    error = EXCLUDED.error,
+                    attempts = EXCLUDED.attempts,
+                    failed_at = EXCLUDED.failed_at,
+                    redrive_requested = EXCLUDED.redrive_requested"
+            ),
+            &[
+                &dead_letter.service_id,
+                &i64::try_from(dead_letter.event.offset)?,
+                &Json(serde_json::to_value(&dead_letter.event.metadata)?),
+                &Json(wire::encode(&dead_letter.event.details)?),
+                &dead_letter.error,
+                &i32::try_from(dead_letter.attempts)?,
+                &dead_letter.failed_at,
+                &dead_letter.redrive_requested,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn load_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        service_id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<Option<DeadLetter>> {
+        Ok(query_dead_letters(
+            &mut conn.cast().as_mut::<PostgresTransaction>()?.0,
+            "service_id = $1 AND log_offset = $2",
+            "",
+            &[&service_id, &i64::try_from(offset)?],
+        )?
+        .pop())
+    }
+
+    fn remove_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        service_id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<bool> {
+        let removed = conn.cast().as_mut::<PostgresTransaction>()?.0.execute(
+            "DELETE FROM dead_letters WHERE service_id = $1 AND log_offset = $2",
+            &[&service_id, &i64::try_from(offset)?],
+        )?;
+        Ok(0 < removed)
+    }
+
+    fn list(
+        &self,
+        conn: &mut dyn Connection,
+        service_id: Option<ServiceIdRef>,
+    ) -> Result<Vec<DeadLetter>> {
+        query_dead_letters(
+            &mut *conn.cast().as_mut::<PostgresConnection>()?.0,
+            "($1::TEXT IS NULL OR service_id = $1)",
+            "",
+            &[&service_id],
+        )
+    }
+
+    fn next_redrive(
+        &self,
+        conn: &mut dyn Connection,
+        service_id: ServiceIdRef,
+    ) -> Result<Option<DeadLetter>> {
+        Ok(query_dead_letters(
+            &mut *conn.cast().as_mut::<PostgresConnection>()?.0,
+            "service_id = $1 AND redrive_requested",
+            "LIMIT 1",
+            &[&service_id],
+        )?
+        .pop())
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    event::wire,
+    persistence::{
+        migration::{ComponentMigrations, Migration},
+        timestamp_from_sql, timestamp_to_sql, SqliteConnection, SqliteTransaction,
+    },
+};
+use std::convert::TryFrom;
+
+use super::*;
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    // `failed_at` is in microseconds since the unix epoch
+    sql: "CREATE TABLE IF NOT EXISTS dead_letters (
+        service_id TEXT NOT NULL,
+        log_offset INTEGER NOT NULL,
+        metadata TEXT NOT NULL,
+        details TEXT NOT NULL,
+        error TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        failed_at INTEGER NOT NULL,
+        redrive_requested BOOLEAN NOT NULL,
+        PRIMARY KEY (service_id, log_offset)
+    )",
+}];
+
+const COLUMNS: &str =
+    "service_id, log_offset, metadata, details, error, attempts, failed_at, redrive_requested";
+
+/// [`DeadLetterStore`] keeping dead letters in a SQLite table
+#[derive(Debug, Clone)]
+pub struct SqliteDeadLetterStore;
+
+impl SqliteDeadLetterStore {
+    pub const MIGRATIONS: ComponentMigrations = ComponentMigrations {
+        component: "dead_letters",
+        migrations: MIGRATIONS,
+    };
+
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn new_shared() -> SharedDeadLetterStore {
+        Arc::new(Self::new())
+    }
+}
+
+/// Select dead letters matching `condition`, ordered and possibly limited with `limit`
+fn query_dead_letters(
+    conn: &rusqlite::Connection,
+    condition: &str,
+    limit: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<DeadLetter>> {
+    conn.prepare_cached(&format!(
+        "SELECT {COLUMNS} FROM dead_letters WHERE {condition}
+        ORDER BY service_id, log_offset {limit}"
+    ))?
+    .query_map(params, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, String>(4)?,
+            row.get::<_, i64>(5)?,
+            row.get::<_, i64>(6)?,
+            row.get::<_, bool>(7)?,
+        ))
+    })?
+    .map(|row| {
+        let (service_id, offset, metadata, details, error, attempts, failed_at, redrive_requested) =
+            row?;
+        Ok(DeadLetter {
+            service_id,
+            event: LogEvent {
+                offset: u64::try_from(offset)?,
+                metadata: serde_json::from_str(&metadata)?,
+                details: wire::decode(serde_json::from_str(&details)?)?,
+            },
+            error,
+            attempts: u32::try_from(attempts)?,
+            failed_at: timestamp_from_sql(failed_at)?,
+            redrive_requested,
+        })
+    })
+    .collect()
+}
+
+impl DeadLetterStore for SqliteDeadLetterStore {
+    fn store_tr(&self, conn: &mut dyn Transaction<'_>, dead_letter: DeadLetter) -> Result<()> {
+        conn.cast().as_mut::<SqliteTransaction>()?.0.execute(
+            &format!(
+                "INSERT INTO dead_letters ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                ON CONFLICT (service_id, log_offset) DO UPDATE SET
+                    metadata = excluded.metadata,
+                    details = excluded.details,
+                    error = excluded.error,
+                    attempts = excluded.attempts,
+                    failed_at = excluded.failed_at,
+                    redrive_requested = excluded.redrive_requested"
+            ),
+            rusqlite::params![
+                dead_letter.service_id,
+                i64::try_from(dead_letter.event.offset)?,
+                serde_json::to_string(&dead_letter.event.metadata)?,
+                serde_json::to_string(&wire::encode(&dead_letter.event.details)?)?,
+                dead_letter.error,
+                dead_letter.attempts,
+                timestamp_to_sql(dead_letter.failed_at)?,
+                dead_letter.redrive_requested,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn load_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        service_id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<Option<DeadLetter>> {
+        Ok(query_dead_letters(
+            &conn.cast().as_mut::<SqliteTransaction>()?.0,
+            "service_id = ?1 AND log_offset = ?2",
+            "",
+            rusqlite::params![service_id, i64::try_from(offset)?],
+        )?
+        .pop())
+    }
+
+    fn remove_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        service_id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<bool> {
+        let removed = conn.cast().as_mut::<SqliteTransaction>()?.0.execute(
+            "DELETE FROM dead_letters WHERE service_id = ?1 AND log_offset = ?2",
+            rusqlite::params![service_id, i64::try_from(offset)?],
+        )?;
+        Ok(0 < removed)
+    }
+
+    fn list(
+        &self,
+        conn: &mut dyn Connection,
+        service_id: Option<ServiceIdRef>,
+    ) -> Result<Vec<DeadLetter>> {
+        query_dead_letters(
+            &conn.cast().as_mut::<SqliteConnection>()?.0,
+            "(?1 IS NULL OR service_id = ?1)",
+            "",
+            [service_id],
+        )
+    }
+
+    fn next_redrive(
+        &self,
+        conn: &mut dyn Connection,
+        service_id: ServiceIdRef,
+    ) -> Result<Option<DeadLetter>> {
+        Ok(query_dead_letters(
+            &conn.cast().as_mut::<SqliteConnection>()?.0,
+            "service_id = ?1 AND redrive_requested",
+            "LIMIT 1",
+            [service_id],
+        )?
+        .pop())
+    }
+}
@@ -1,56 +1,65 @@
-use crate::auction::*;
-use thiserror::Error;
-
-// TODO: This type makes everything cyclical:
-// All services depend on it, and it depends
-// on events of each of the services. Not a
-// big deal for this small program, but something
-// to take care of in a more realistic implementation.
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum Event {
-    AuctionHouse(AuctionHouseEvent),
-    BiddingEngine(BiddingEngineEvent),
-    Ui(UiEvent),
-    #[cfg(test)]
-    Test,
-}
+//! Events stored in the log
+//!
+//! The log itself doesn't know about any particular event types. Every
+//! service declares the types of events it produces by implementing
+//! [`EventType`] for them, each on its own topic, and only the services
+//! that subscribe to a topic need to know its type.
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt::Debug;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct AuctionHouseEvent {
-    pub item: ItemId,
-    pub event: AuctionHouseItemEvent,
-}
+pub mod topics;
+pub mod wire;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum AuctionHouseItemEvent {
-    Bid(BidDetails),
-    Closed,
+/// A type of events that can be written to the log
+pub trait EventType: Serialize + DeserializeOwned + Debug {
+    /// Name of the topic events of this type are written to
+    ///
+    /// Must be unique across all the event types.
+    const TOPIC: &'static str;
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum BiddingEngineEvent {
-    /// We are placing a bid
-    Bid(ItemBid),
-    /// Auction house event caused an error
-    AuctionError(BiddingEngineAuctionError),
-    /// User event caused an error
-    UserError(BiddingEngineUserError),
+/// An event of any type, as stored in the log
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub topic: String,
+    pub payload: serde_json::Value,
 }
 
-#[derive(Error, Debug, Copy, Clone, PartialEq, Eq)]
-pub enum BiddingEngineUserError {
-    #[error("auction already closed")]
-    AlreadyClosed,
-    #[error("bid is too low")]
-    TooLow,
+impl Event {
+    pub fn new<T: EventType>(event: &T) -> Result<Self> {
+        Ok(Self {
+            topic: T::TOPIC.to_owned(),
+            payload: serde_json::to_value(event)?,
+        })
+    }
+
+    /// Convert multiple events of the same type
+    pub fn new_all<T: EventType>(events: &[T]) -> Result<Vec<Self>> {
+        events.iter().map(Self::new).collect()
+    }
+
+    /// Decode the event as `T`, or `None` if it's on a different topic
+    pub fn decode<T: EventType>(&self) -> Result<Option<T>> {
+        if self.topic != T::TOPIC {
+            return Ok(None);
+        }
+        Ok(Some(T::deserialize(&self.payload)?))
+    }
 }
 
-#[derive(Error, Clone, Debug, PartialEq, Eq)]
-pub enum BiddingEngineAuctionError {
-    #[error("unknown auction: {0}")]
-    UnknownAuction(ItemId),
+/// A set of event types a service subscribes to
+///
+/// Implemented for every [`EventType`] for services that subscribe
+/// to a single topic. Services subscribing to multiple topics implement
+/// it on an enum with a variant for each of the types.
+pub trait Subscription: Sized + Debug {
+    /// Decode `event`, or `None` if it's not on any of the subscribed topics
+    fn decode(event: &Event) -> Result<Option<Self>>;
 }
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum UiEvent {
-    MaxBidSet(ItemBid),
+
+impl<T: EventType> Subscription for T {
+    fn decode(event: &Event) -> Result<Option<Self>> {
+        event.decode()
+    }
 }
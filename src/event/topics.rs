@@ -0,0 +1,161 @@
+//! Types of the events on each topic
+//!
+//! They are shared by the services producing and consuming them, so
+//! that services don't depend on each other just to read their events.
+use super::EventType;
+use crate::auction::{Amount, BidDetails, ItemBid, ItemId};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// All the topics of the types here
+pub const KNOWN_TOPICS: &[&str] = &[
+    UiEvent::TOPIC,
+    AuctionHouseEvent::TOPIC,
+    TimerEvent::TOPIC,
+    BiddingEngineEvent::TOPIC,
+];
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UiEvent {
+    MaxBidSet(ItemBid),
+    StrategySet(ItemStrategy),
+}
+
+impl EventType for UiEvent {
+    const TOPIC: &'static str = "ui";
+}
+
+/// Outbid anyone else right away, with the minimal valid bid
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct ImmediateOutbid;
+
+/// Outbid right away, but by `step` more than the minimal valid bid,
+/// to discourage others from outbidding by just a bit
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct FixedStep {
+    pub step: Amount,
+}
+
+/// Don't reveal our interest: bid only within `window` before the auction ends
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct LastMoment {
+    pub window: Duration,
+}
+
+/// The bidding strategy selected for an item, [`ImmediateOutbid`] by default
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Strategy {
+    ImmediateOutbid(ImmediateOutbid),
+    FixedStep(FixedStep),
+    LastMoment(LastMoment),
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::ImmediateOutbid(ImmediateOutbid)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemStrategy {
+    pub item: ItemId,
+    pub strategy: Strategy,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuctionHouseEvent {
+    pub item: ItemId,
+    pub event: AuctionHouseItemEvent,
+}
+
+impl EventType for AuctionHouseEvent {
+    const TOPIC: &'static str = "auction-house";
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuctionHouseItemEvent {
+    Bid(BidDetails),
+    /// The auction is going to close at this time (or is extended until)
+    ClosesAt(SystemTime),
+    Closed,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimerEvent {
+    /// It's now this time
+    Tick(SystemTime),
+}
+
+impl EventType for TimerEvent {
+    const TOPIC: &'static str = "timer";
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BiddingEngineEvent {
+    /// We are placing a bid
+    Bid(ItemBid),
+    /// Auction house event caused an error
+    AuctionError(BiddingEngineAuctionError),
+    /// User event caused an error
+    UserError(BiddingEngineUserError),
+    /// Our status in an auction changed
+    StatusChanged(ItemStatus),
+    /// We would bid, but don't (yet)
+    BidWithheld(BidWithheld),
+}
+
+impl EventType for BiddingEngineEvent {
+    const TOPIC: &'static str = "bidding-engine";
+}
+
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BiddingEngineUserError {
+    #[error("auction already closed")]
+    AlreadyClosed,
+    #[error("bid is too low")]
+    TooLow,
+}
+
+#[derive(Error, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BiddingEngineAuctionError {
+    #[error("unknown auction: {0}")]
+    UnknownAuction(ItemId),
+}
+
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WithholdReason {
+    #[error("bid would take the total committed over the budget of {budget} ({committed} committed elsewhere)")]
+    OverBudget { budget: Amount, committed: Amount },
+}
+
+/// A bid the strategy wanted to place, deferred until the reason is gone
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BidWithheld {
+    pub item: ItemId,
+    pub price: Amount,
+    pub reason: WithholdReason,
+}
+
+/// Where the sniper is in an auction
+///
+/// Derived from the state of the auction, so it's never stored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SniperStatus {
+    /// No bids in the auction yet
+    Joining,
+    /// Someone else has the highest bid
+    Bidding,
+    /// We have the highest bid
+    Winning,
+    /// Auction closed with someone else's bid as the highest
+    Lost,
+    /// Auction closed with our bid as the highest
+    Won,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemStatus {
+    pub item: ItemId,
+    pub status: SniperStatus,
+}
@@ -0,0 +1,112 @@
+//! Versioned wire format of [`Event`]s stored in durable logs
+//!
+//! Every event is stored wrapped in an envelope recording the version
+//! of the schema it was written with:
+//!
+//! ```text
+//! {"version": 2, "event": {"topic": "ui", "payload": {"MaxBidSet": {"item": "foo", "price": 10}}}}
+//! ```
+//!
+//! Reading an event written with an older version first runs it through
+//! [`UPCASTERS`], one version at a time, until it's at [`CURRENT_VERSION`],
+//! so event types only ever need to deserialize the latest schema.
+//!
+//! Any change to the envelope, or to the serialized form of any
+//! [`super::EventType`], must bump [`CURRENT_VERSION`] and add an upcaster
+//! from the previous version.
+use super::Event;
+use serde::Serialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+/// Schema version of events written by this binary
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Converts an event at some version into the next one
+type Upcaster = fn(Value) -> Result<Value, WireError>;
+
+/// Upcaster at index `i` converts an event at version `i` into version `i + 1`
+const UPCASTERS: &[Upcaster] = &[upcast_v0_to_v1, upcast_v1_to_v2];
+
+const _: () = assert!(UPCASTERS.len() == CURRENT_VERSION as usize);
+
+#[derive(Error, Debug)]
+pub enum WireError {
+    #[error("event is at version {version}, newer than the supported {supported}")]
+    VersionTooNew { version: u64, supported: u32 },
+    #[error("invalid event envelope: {0}")]
+    InvalidEnvelope(&'static str),
+    #[error("unknown version 1 event: {0}")]
+    UnknownV1Event(Value),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u32,
+    event: &'a Event,
+}
+
+/// Events written before the envelope was introduced are the bare
+/// serialized `Event` enum, which is exactly the version 1 schema.
+fn upcast_v0_to_v1(event: Value) -> Result<Value, WireError> {
+    Ok(event)
+}
+
+/// Version 1 was a single enum of all the events, with a variant
+/// for each of the services; now each of them is on its own topic.
+fn upcast_v1_to_v2(event: Value) -> Result<Value, WireError> {
+    let (variant, payload) = match event {
+        Value::String(variant) => (variant, Value::Null),
+        Value::Object(map) if map.len() == 1 => map.into_iter().next().expect("no fail"),
+        other => return Err(WireError::UnknownV1Event(other)),
+    };
+    let topic = match variant.as_str() {
+        "AuctionHouse" => "auction-house",
+        "BiddingEngine" => "bidding-engine",
+        "Ui" => "ui",
+        "Test" => "test",
+        _ => return Err(WireError::UnknownV1Event(json!({ variant: payload }))),
+    };
+    Ok(json!({ "topic": topic, "payload": payload }))
+}
+
+/// Serialize `event` at [`CURRENT_VERSION`]
+pub fn encode(event: &Event) -> Result<Value, WireError> {
+    Ok(serde_json::to_value(Envelope {
+        version: CURRENT_VERSION,
+        event,
+    })?)
+}
+
+/// Deserialize an event written with [`CURRENT_VERSION`] or any older one
+pub fn decode(value: Value) -> Result<Event, WireError> {
+    let (version, mut event) = match value {
+        Value::Object(mut map) if map.contains_key("version") => {
+            let version = map
+                .remove("version")
+                .and_then(|v| v.as_u64())
+                .ok_or(WireError::InvalidEnvelope("version is not a number"))?;
+            let event = map
+                .remove("event")
+                .ok_or(WireError::InvalidEnvelope("missing event"))?;
+            (version, event)
+        }
+        // no envelope: written before the versions were introduced
+        unversioned => (0, unversioned),
+    };
+
+    if u64::from(CURRENT_VERSION) < version {
+        return Err(WireError::VersionTooNew {
+            version,
+            supported: CURRENT_VERSION,
+        });
+    }
+
+    for upcaster in &UPCASTERS[usize::try_from(version).expect("no fail")..] {
+        event = upcaster(event)?;
+    }
+
+    Ok(serde_json::from_value(event)?)
+}
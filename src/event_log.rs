@@ -1,18 +1,65 @@
 use crate::{
+    clock::{SharedClock, SystemClock},
     event::Event,
-    persistence::{Connection, Transaction},
+    persistence::{
+        AsyncConnection, AsyncTransaction, BlockingTransactionFn, Connection, Transaction,
+    },
+};
+use anyhow::{bail, format_err, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::TryFrom,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
-use anyhow::{format_err, Result};
-use std::{convert::TryFrom, sync::Arc, time::Duration};
 
+mod blocking;
+pub mod file;
 mod in_memory;
-pub use self::in_memory::*;
+mod postgres;
+mod sqlite;
+pub use self::{blocking::*, in_memory::*, postgres::*, sqlite::*};
 
 pub type Offset = u64;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+pub type EventId = uuid::Uuid;
+
+/// Information about an event other than its details
+///
+/// Every event written while handling another one (see
+/// [`Transaction::handled_event`]) records it as its cause, which allows
+/// tracing eg. which [`crate::event::UiEvent::MaxBidSet`] led to which bids.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventMetadata {
+    /// Unique id of the event
+    pub id: EventId,
+    /// Wall-clock time when the event was written
+    pub timestamp: SystemTime,
+    /// Id of the event that caused this one
+    pub causation_id: Option<EventId>,
+    /// Id of the event that started the whole chain of events
+    /// this one is a part of (its own id, if it has no cause)
+    pub correlation_id: EventId,
+}
+
+impl EventMetadata {
+    /// Metadata for a new event caused by `cause`, if any, written at `timestamp`
+    pub fn new(cause: Option<&EventMetadata>, timestamp: SystemTime) -> Self {
+        let id = EventId::new_v4();
+        Self {
+            id,
+            timestamp,
+            causation_id: cause.map(|cause| cause.id),
+            correlation_id: cause.map(|cause| cause.correlation_id).unwrap_or(id),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub struct LogEvent {
     pub offset: Offset,
+    pub metadata: EventMetadata,
     pub details: Event,
 }
 
@@ -22,6 +69,12 @@ pub struct WithOffset<T> {
     pub data: T,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WithMetadata<T> {
+    pub metadata: EventMetadata,
+    pub data: T,
+}
+
 pub trait Reader {
     fn get_start_offset(&self) -> Result<Offset>;
 
@@ -33,6 +86,7 @@ pub trait Reader {
         timeout: Option<Duration>,
     ) -> Result<WithOffset<Vec<LogEvent>>>;
 
+    #[cfg(test)]
     fn read_one(
         &self,
         conn: &mut dyn Connection,
@@ -50,7 +104,10 @@ pub trait Reader {
 
 pub trait Writer {
     fn write(&self, conn: &mut dyn Connection, events: &[Event]) -> Result<Offset> {
-        self.write_tr(&mut *conn.start_transaction()?, events)
+        let mut transaction = conn.start_transaction()?;
+        let offset = self.write_tr(&mut *transaction, events)?;
+        transaction.commit()?;
+        Ok(offset)
     }
 
     fn write_tr(&self, conn: &mut dyn Transaction<'_>, events: &[Event]) -> Result<Offset>;
@@ -58,3 +115,126 @@ pub trait Writer {
 
 pub type SharedReader = Arc<dyn Reader + Sync + Send + 'static>;
 pub type SharedWriter = Arc<dyn Writer + Sync + Send + 'static>;
+
+/// Async counterpart of [`Reader`]
+// only async log followers read asynchronously, and the binary runs none yet
+#[allow(dead_code)]
+#[async_trait]
+pub trait AsyncReader: Send + Sync {
+    fn get_start_offset(&self) -> Result<Offset>;
+
+    async fn read(
+        &self,
+        conn: &mut dyn AsyncConnection,
+        offset: Offset,
+        limit: usize,
+        timeout: Option<Duration>,
+    ) -> Result<WithOffset<Vec<LogEvent>>>;
+}
+
+/// Async counterpart of [`Writer`]
+#[async_trait]
+pub trait AsyncWriter: Send + Sync {
+    async fn write(&self, conn: &mut dyn AsyncConnection, events: &[Event]) -> Result<Offset> {
+        let mut transaction = conn.start_transaction().await?;
+        let offset = self.write_tr(&mut *transaction, events).await?;
+        transaction.commit().await?;
+        Ok(offset)
+    }
+
+    async fn write_tr(
+        &self,
+        conn: &mut dyn AsyncTransaction<'_>,
+        events: &[Event],
+    ) -> Result<Offset>;
+}
+
+#[allow(dead_code)] // see `AsyncReader`
+pub type SharedAsyncReader = Arc<dyn AsyncReader + 'static>;
+pub type SharedAsyncWriter = Arc<dyn AsyncWriter + 'static>;
+
+/// A [`Transaction`] handling an event
+///
+/// Wraps the actual transaction, so that any events written in it
+/// are recorded as caused by the handled one.
+pub struct CausedTransaction<'t, 'a> {
+    inner: &'t mut (dyn Transaction<'a> + 't),
+    cause: EventMetadata,
+}
+
+impl<'t, 'a> CausedTransaction<'t, 'a> {
+    pub fn new(inner: &'t mut (dyn Transaction<'a> + 't), cause: EventMetadata) -> Self {
+        Self { inner, cause }
+    }
+}
+
+impl<'t, 'a> Transaction<'a> for CausedTransaction<'t, 'a> {
+    fn commit(self: Box<Self>) -> Result<()> {
+        bail!("event handling transaction can only be committed by its owner")
+    }
+
+    fn rollback(self: Box<Self>) -> Result<()> {
+        bail!("event handling transaction can only be rolled back by its owner")
+    }
+
+    fn cast<'b>(&'b mut self) -> crate::persistence::Caster<'b, 'a>
+    where
+        'a: 'b,
+    {
+        self.inner.cast()
+    }
+
+    fn handled_event(&self) -> Option<&EventMetadata> {
+        Some(&self.cause)
+    }
+}
+
+/// Async counterpart of [`CausedTransaction`]
+// used by async log followers, which the binary runs none of yet
+#[allow(dead_code)]
+pub struct AsyncCausedTransaction<'t, 'a> {
+    inner: &'t mut (dyn AsyncTransaction<'a> + 't),
+    cause: EventMetadata,
+}
+
+impl<'t, 'a> AsyncCausedTransaction<'t, 'a> {
+    pub fn new(inner: &'t mut (dyn AsyncTransaction<'a> + 't), cause: EventMetadata) -> Self {
+        Self { inner, cause }
+    }
+}
+
+#[async_trait]
+impl<'t, 'a> AsyncTransaction<'a> for AsyncCausedTransaction<'t, 'a> {
+    async fn commit(self: Box<Self>) -> Result<()> {
+        Err(format_err!(
+            "event handling transaction can only be committed by its owner"
+        ))
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<()> {
+        Err(format_err!(
+            "event handling transaction can only be rolled back by its owner"
+        ))
+    }
+
+    fn cast<'b>(&'b mut self) -> crate::persistence::Caster<'b, 'a>
+    where
+        'a: 'b,
+    {
+        self.inner.cast()
+    }
+
+    fn handled_event(&self) -> Option<&EventMetadata> {
+        Some(&self.cause)
+    }
+
+    async fn run_blocking(&mut self, f: BlockingTransactionFn) -> Result<()> {
+        // so sync code sees the cause too
+        let cause = self.cause.clone();
+        self.inner
+            .run_blocking(Box::new(move |transaction| {
+                f(&mut CausedTransaction::new(transaction, cause))
+            }))
+            .await
+    }
+}
@@ -0,0 +1,60 @@
+use super::*;
+
+/// An [`AsyncReader`] on top of a sync [`Reader`]
+///
+/// Reads using [`AsyncConnection::run_blocking`], so the connection
+/// must be one that allows blocking, eg. from a
+/// [`crate::persistence::BlockingPersistence`].
+#[allow(dead_code)] // for async log followers, see `AsyncReader`
+pub struct BlockingReader(pub SharedReader);
+
+impl BlockingReader {
+    #[allow(dead_code)] // see the struct
+    pub fn new_shared(inner: SharedReader) -> SharedAsyncReader {
+        Arc::new(Self(inner))
+    }
+}
+
+#[async_trait]
+impl AsyncReader for BlockingReader {
+    fn get_start_offset(&self) -> Result<Offset> {
+        self.0.get_start_offset()
+    }
+
+    async fn read(
+        &self,
+        conn: &mut dyn AsyncConnection,
+        offset: Offset,
+        limit: usize,
+        timeout: Option<Duration>,
+    ) -> Result<WithOffset<Vec<LogEvent>>> {
+        let inner = self.0.clone();
+        conn.blocking(move |conn| inner.read(conn, offset, limit, timeout))
+            .await
+    }
+}
+
+/// An [`AsyncWriter`] on top of a sync [`Writer`]
+///
+/// See [`BlockingReader`].
+pub struct BlockingWriter(pub SharedWriter);
+
+impl BlockingWriter {
+    pub fn new_shared(inner: SharedWriter) -> SharedAsyncWriter {
+        Arc::new(Self(inner))
+    }
+}
+
+#[async_trait]
+impl AsyncWriter for BlockingWriter {
+    async fn write_tr(
+        &self,
+        conn: &mut dyn AsyncTransaction<'_>,
+        events: &[Event],
+    ) -> Result<Offset> {
+        let inner = self.0.clone();
+        let events = events.to_vec();
+        conn.blocking(move |transaction| inner.write_tr(transaction, &events))
+            .await
+    }
+}
@@ -0,0 +1,483 @@
+//! Event log in append-only segment files on local disk
+//!
+//! Each segment is a file named after the offset of its first event,
+//! containing a sequence of records:
+//!
+//! ```text
+//! [payload length: u32 LE][crc32 of payload: u32 LE][payload]
+//! ```
+//!
+//! where the payload is a JSON [`Record`].
+//!
+//! On opening, all segments are scanned to rebuild the offset index,
+//! and a torn write at the end of the last segment (eg. after a crash)
+//! is truncated away. Corruption anywhere else is an error.
+use super::*;
+use crate::{
+    event::{wire, Event},
+    persistence::{store_id, InMemoryTransaction, PendingChanges},
+};
+use anyhow::Context;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex, MutexGuard},
+    time::Instant,
+};
+use tracing::warn;
+
+const RECORD_HEADER_LEN: u64 = 8;
+const SEGMENT_EXTENSION: &str = "log";
+/// Default size after which a new segment is started
+pub const DEFAULT_MAX_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+/// Anything bigger than that must be garbage
+const MAX_RECORD_LEN: u32 = 64 * 1024 * 1024;
+
+/// Payload of a single record
+#[derive(Serialize, Deserialize)]
+struct Record {
+    metadata: EventMetadata,
+    /// In the [`wire`] format
+    event: serde_json::Value,
+}
+
+#[derive(Debug)]
+struct Segment {
+    base_offset: Offset,
+    path: PathBuf,
+    /// File position of each record
+    positions: Vec<u64>,
+    /// Length of all the valid records
+    len: u64,
+}
+
+impl Segment {
+    fn next_offset(&self) -> Offset {
+        self.base_offset + u64::try_from(self.positions.len()).expect("no fail")
+    }
+}
+
+#[derive(Debug)]
+struct FileLogInner {
+    /// Always at least one, sorted by `base_offset`
+    segments: Vec<Segment>,
+    /// Append handle to the last segment
+    file: File,
+}
+
+impl FileLogInner {
+    fn last_segment(&self) -> &Segment {
+        self.segments.last().expect("at least one segment")
+    }
+
+    fn next_offset(&self) -> Offset {
+        self.last_segment().next_offset()
+    }
+}
+
+#[derive(Debug)]
+struct FileLogShared {
+    dir: PathBuf,
+    max_segment_size: u64,
+    inner: Mutex<FileLogInner>,
+    condvar: Condvar,
+}
+
+/// Records written to a [`FileLog`] in a transaction
+struct FileLogChanges {
+    shared: Arc<FileLogShared>,
+    records: Vec<Vec<u8>>,
+}
+
+impl PendingChanges for FileLogChanges {
+    fn commit(self) -> Result<()> {
+        self.shared.append(&self.records)
+    }
+}
+
+/// Event log persisted in append-only segment files in a directory
+///
+/// Works with [`InMemoryTransaction`]s: events are appended to disk
+/// (and synced) when the transaction commits. This makes the log
+/// the only durable part of such setup, so it's meant for services that
+/// can rebuild their state by reading it from the start.
+#[derive(Debug, Clone)]
+pub struct FileLog {
+    shared: Arc<FileLogShared>,
+    /// Stamps the written events
+    clock: SharedClock,
+}
+
+fn segment_path(dir: &Path, base_offset: Offset) -> PathBuf {
+    dir.join(format!("{base_offset:020}.{SEGMENT_EXTENSION}"))
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+/// Make sure a newly created file in `dir` survives a crash
+fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Read the header of a record: the payload length and its crc32
+fn read_header(reader: &mut impl Read) -> io::Result<(u32, u32)> {
+    let mut header = [0u8; RECORD_HEADER_LEN as usize];
+    reader.read_exact(&mut header)?;
+    Ok((
+        u32::from_le_bytes(header[..4].try_into().expect("no fail")),
+        u32::from_le_bytes(header[4..].try_into().expect("no fail")),
+    ))
+}
+
+/// Read a single record, returning `None` if it's corrupted
+fn read_record(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let (len, crc) = read_header(reader)?;
+    if MAX_RECORD_LEN < len {
+        return Ok(None);
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+
+    Ok((crc32fast::hash(&payload) == crc).then_some(payload))
+}
+
+/// Result of scanning a record of a segment
+enum Scanned {
+    Record(Vec<u8>),
+    /// Nothing left in the segment
+    End,
+    /// The rest of the segment is an incomplete or corrupted record, eg. a crashed write
+    Torn,
+}
+
+/// Scan the record at the start of `reader`, with `remaining` bytes left in the segment
+///
+/// A corrupted record that has anything after it can't be a crashed write,
+/// so it's an error.
+fn scan_record(reader: &mut impl Read, remaining: u64) -> io::Result<Scanned> {
+    if remaining == 0 {
+        return Ok(Scanned::End);
+    }
+    if remaining < RECORD_HEADER_LEN {
+        return Ok(Scanned::Torn);
+    }
+    let (len, crc) = read_header(reader)?;
+    let record_len = RECORD_HEADER_LEN + u64::from(len);
+    if remaining < record_len {
+        return Ok(Scanned::Torn);
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+    match (
+        MAX_RECORD_LEN < len || crc32fast::hash(&payload) != crc,
+        remaining == record_len,
+    ) {
+        (false, _) => Ok(Scanned::Record(payload)),
+        (true, true) => Ok(Scanned::Torn),
+        (true, false) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "corrupted record followed by more data",
+        )),
+    }
+}
+
+/// Encode a record of `payload`, rejecting ones too big to ever read back
+fn encode_record(payload: &[u8]) -> Result<Vec<u8>> {
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| *len <= MAX_RECORD_LEN)
+        .ok_or_else(|| format_err!("event of {} bytes is too big", payload.len()))?;
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN as usize + payload.len());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    record.extend_from_slice(payload);
+    Ok(record)
+}
+
+/// Scan a segment file, rebuilding its index
+///
+/// A torn tail is truncated if the segment is the `last` one, and is
+/// an error otherwise, as is any corruption before the tail.
+fn scan_segment(path: PathBuf, base_offset: Offset, last: bool) -> Result<Segment> {
+    let file = File::open(&path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut positions = vec![];
+    let mut len = 0;
+    loop {
+        match scan_record(&mut reader, file_len - len)
+            .with_context(|| format!("corrupted segment {}", path.display()))?
+        {
+            Scanned::Record(payload) => {
+                positions.push(len);
+                len += RECORD_HEADER_LEN + u64::try_from(payload.len())?;
+            }
+            Scanned::End => break,
+            Scanned::Torn if !last => bail!("corrupted segment {}", path.display()),
+            Scanned::Torn => {
+                warn!(
+                    path = %path.display(),
+                    valid = len,
+                    total = file_len,
+                    "truncating torn tail of the event log"
+                );
+                let file = OpenOptions::new().write(true).open(&path)?;
+                file.set_len(len)?;
+                file.sync_all()?;
+                break;
+            }
+        }
+    }
+
+    Ok(Segment {
+        base_offset,
+        path,
+        positions,
+        len,
+    })
+}
+
+// the binary keeps its events in a database, along with its state
+#[allow(dead_code)]
+impl FileLog {
+    /// Open a log stored in `dir`, creating it if needed
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_max_segment_size(dir, DEFAULT_MAX_SEGMENT_SIZE)
+    }
+
+    pub fn open_with_max_segment_size(
+        dir: impl AsRef<Path>,
+        max_segment_size: u64,
+    ) -> Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir)?;
+
+        let mut base_offsets = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let base_offset = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<Offset>().ok())
+                .ok_or_else(|| format_err!("invalid segment file name: {}", path.display()))?;
+            base_offsets.push(base_offset);
+        }
+        base_offsets.sort_unstable();
+
+        if base_offsets.is_empty() {
+            File::create(segment_path(&dir, 0))?;
+            sync_dir(&dir)?;
+            base_offsets.push(0);
+        }
+
+        let mut segments: Vec<Segment> = vec![];
+        for (i, &base_offset) in base_offsets.iter().enumerate() {
+            let expected = segments.last().map(Segment::next_offset).unwrap_or(0);
+            if base_offset != expected {
+                bail!("missing events between offsets {expected} and {base_offset}");
+            }
+            segments.push(scan_segment(
+                segment_path(&dir, base_offset),
+                base_offset,
+                i + 1 == base_offsets.len(),
+            )?);
+        }
+
+        let file = open_append(&segments.last().expect("at least one segment").path)?;
+
+        Ok(Self {
+            shared: Arc::new(FileLogShared {
+                dir,
+                max_segment_size,
+                inner: Mutex::new(FileLogInner { segments, file }),
+                condvar: Condvar::new(),
+            }),
+            clock: SystemClock::new_shared(),
+        })
+    }
+
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock, ..self }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, FileLogInner>> {
+        self.shared
+            .inner
+            .lock()
+            .map_err(|_e| format_err!("mutex poisoned"))
+    }
+}
+
+impl FileLogShared {
+    /// Append `records` of a transaction, all or none of them
+    ///
+    /// They all go into the same segment, so a segment can get bigger than
+    /// `max_segment_size` by up to one transaction.
+    fn append(&self, records: &[Vec<u8>]) -> Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|_e| format_err!("mutex poisoned"))?;
+
+        let segment = inner.last_segment();
+        if self.max_segment_size <= segment.len {
+            inner.file.sync_data()?;
+
+            let base_offset = segment.next_offset();
+            let path = segment_path(&self.dir, base_offset);
+            inner.file = open_append(&path)?;
+            sync_dir(&self.dir)?;
+            inner.segments.push(Segment {
+                base_offset,
+                path,
+                positions: vec![],
+                len: 0,
+            });
+        }
+
+        let start = inner.last_segment().len;
+        if let Err(e) = inner
+            .file
+            .write_all(&records.concat())
+            .and_then(|()| inner.file.sync_data())
+        {
+            // don't leave a partial transaction that later appends would follow
+            inner.file.set_len(start)?;
+            return Err(e.into());
+        }
+
+        let segment = inner.segments.last_mut().expect("at least one segment");
+        for record in records {
+            segment.positions.push(segment.len);
+            segment.len += u64::try_from(record.len())?;
+        }
+
+        self.condvar.notify_all();
+        Ok(())
+    }
+}
+
+impl Reader for FileLog {
+    fn read(
+        &self,
+        _conn: &mut dyn Connection,
+        offset: Offset,
+        limit: usize,
+        timeout: Option<Duration>,
+    ) -> Result<WithOffset<Vec<LogEvent>>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        let mut inner = self.lock()?;
+        while inner.next_offset() == offset {
+            inner = if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if deadline <= now {
+                    break;
+                }
+                self.shared
+                    .condvar
+                    .wait_timeout(inner, deadline - now)
+                    .map_err(|_e| format_err!("mutex poisoned"))?
+                    .0
+            } else {
+                self.shared
+                    .condvar
+                    .wait(inner)
+                    .map_err(|_e| format_err!("mutex poisoned"))?
+            };
+        }
+
+        if inner.next_offset() < offset {
+            bail!("out of bounds");
+        }
+
+        let mut res = vec![];
+        let mut segment_i = inner
+            .segments
+            .partition_point(|segment| segment.base_offset <= offset)
+            - 1;
+        let mut current = offset;
+
+        while res.len() < limit && segment_i < inner.segments.len() {
+            let segment = &inner.segments[segment_i];
+            let first = usize::try_from(current - segment.base_offset)?;
+
+            if first < segment.positions.len() {
+                let mut file = File::open(&segment.path)?;
+                file.seek(SeekFrom::Start(segment.positions[first]))?;
+                let mut reader = BufReader::new(file);
+
+                for _ in first..segment.positions.len() {
+                    if limit <= res.len() {
+                        break;
+                    }
+                    let payload = read_record(&mut reader)?.ok_or_else(|| {
+                        format_err!("corrupted segment {}", segment.path.display())
+                    })?;
+                    let record: Record = serde_json::from_slice(&payload)?;
+                    res.push(LogEvent {
+                        offset: current,
+                        metadata: record.metadata,
+                        details: wire::decode(record.event)?,
+                    });
+                    current += 1;
+                }
+            }
+            segment_i += 1;
+        }
+
+        Ok(WithOffset {
+            offset: current,
+            data: res,
+        })
+    }
+
+    fn get_start_offset(&self) -> Result<Offset> {
+        Ok(0)
+    }
+}
+
+impl Writer for FileLog {
+    fn write_tr(&self, conn: &mut dyn Transaction<'_>, events: &[Event]) -> Result<Offset> {
+        // encode upfront, so that only IO can fail on commit
+        let records = events
+            .iter()
+            .map(|event| {
+                encode_record(&serde_json::to_vec(&Record {
+                    metadata: EventMetadata::new(conn.handled_event(), self.clock.now()),
+                    event: wire::encode(event)?,
+                })?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut caster = conn.cast();
+        let transaction = caster.as_mut::<InMemoryTransaction>()?;
+
+        let committed_len = self.lock()?.next_offset();
+        let changes = transaction.pending_changes(store_id(&self.shared), || FileLogChanges {
+            shared: self.shared.clone(),
+            records: vec![],
+        });
+        changes.records.extend(records);
+
+        Ok(committed_len + u64::try_from(changes.records.len())?)
+    }
+}
+
+#[allow(dead_code)] // see `FileLog::open`
+pub fn new_file_shared(dir: impl AsRef<Path>) -> Result<(SharedWriter, SharedReader)> {
+    let log = Arc::new(FileLog::open(dir)?);
+    Ok((log.clone(), log))
+}
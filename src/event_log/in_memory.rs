@@ -1,33 +1,125 @@
 use super::*;
-use crate::{event::Event, persistence::InMemoryTransaction};
-use async_condvar_fair::Condvar;
-use futures::FutureExt;
-use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
-
-type InMemoryLogInner = Vec<Event>;
-
+use crate::{
+    event::Event,
+    persistence::{
+        store_id, AsyncConnection, AsyncTransaction, InMemoryTransaction, PendingChanges,
+    },
+};
+use async_trait::async_trait;
+use std::{
+    sync::{Condvar, Mutex, MutexGuard},
+    time::Instant,
+};
+use tokio::sync::watch;
+
+type InMemoryLogInner = Vec<(EventMetadata, Event)>;
+
+/// In-memory event log
+///
+/// Events written in an [`InMemoryTransaction`] are appended only once it
+/// commits, so readers never see events that might be rolled back.
+///
+/// Implements both the sync and the async log interfaces, so it can be
+/// shared between sync and async services.
 pub struct InMemoryLog {
-    inner: Arc<RwLock<InMemoryLogInner>>,
+    inner: Arc<Mutex<InMemoryLogInner>>,
+    /// Wakes up sync readers waiting for new events
+    condvar: Arc<Condvar>,
+    /// Number of committed events, for async readers waiting for new events
+    committed: Arc<watch::Sender<u64>>,
+    /// Stamps the written events
+    clock: SharedClock,
+}
+
+/// Events written to an [`InMemoryLog`] in a transaction
+struct LogChanges {
+    inner: Arc<Mutex<InMemoryLogInner>>,
     condvar: Arc<Condvar>,
-    runtime: tokio::runtime::Runtime,
+    committed: Arc<watch::Sender<u64>>,
+    events: InMemoryLogInner,
+}
+
+impl PendingChanges for LogChanges {
+    fn commit(self) -> Result<()> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|_e| format_err!("mutex poisoned"))?;
+        inner.extend(self.events);
+        self.committed.send_replace(u64::try_from(inner.len())?);
+        self.condvar.notify_all();
+        Ok(())
+    }
 }
 
 impl InMemoryLog {
-    pub async fn read(&self) -> OwnedRwLockReadGuard<InMemoryLogInner> {
-        self.inner.clone().read_owned().await
+    pub fn new() -> Self {
+        Self {
+            inner: Default::default(),
+            condvar: Default::default(),
+            committed: Arc::new(watch::channel(0).0),
+            clock: SystemClock::new_shared(),
+        }
     }
 
-    pub async fn write(&self) -> OwnedRwLockWriteGuard<InMemoryLogInner> {
-        self.inner.clone().write_owned().await
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock, ..self }
     }
 
-    async fn write_events(&self, events: &[Event]) -> Result<Offset> {
-        let mut write = self.write().await;
+    fn lock(&self) -> Result<MutexGuard<'_, InMemoryLogInner>> {
+        self.inner
+            .lock()
+            .map_err(|_e| format_err!("mutex poisoned"))
+    }
 
-        write.extend_from_slice(events);
-        self.condvar.notify_all();
+    fn read_committed(&self, offset: Offset, limit: usize) -> Result<WithOffset<Vec<LogEvent>>> {
+        let offset_usize = usize::try_from(offset)?;
+
+        let res: Vec<_> = self
+            .lock()?
+            .get(offset_usize..)
+            .ok_or_else(|| format_err!("out of bounds"))?
+            .iter()
+            .take(limit)
+            .enumerate()
+            .map(|(i, (metadata, details))| LogEvent {
+                offset: offset + u64::try_from(i).expect("no fail"),
+                metadata: metadata.clone(),
+                details: details.clone(),
+            })
+            .collect();
+
+        Ok(WithOffset {
+            offset: offset + u64::try_from(res.len()).expect("no fail"),
+            data: res,
+        })
+    }
+
+    /// Append `events` when `transaction` commits
+    ///
+    /// Returns the offset after the last of them, assuming no other
+    /// transaction writing to this log commits first.
+    fn write_in_memory_tr(
+        &self,
+        transaction: &mut InMemoryTransaction,
+        events: Vec<(EventMetadata, Event)>,
+    ) -> Result<Offset> {
+        let committed_len = u64::try_from(self.lock()?.len())?;
+        let changes = transaction.pending_changes(store_id(&self.inner), || LogChanges {
+            inner: self.inner.clone(),
+            condvar: self.condvar.clone(),
+            committed: self.committed.clone(),
+            events: vec![],
+        });
+        changes.events.extend(events);
+
+        Ok(committed_len + u64::try_from(changes.events.len())?)
+    }
+}
 
-        Ok(u64::try_from(write.len())?)
+impl Default for InMemoryLog {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -39,53 +131,71 @@ impl Reader for InMemoryLog {
         limit: usize,
         timeout: Option<Duration>,
     ) -> Result<WithOffset<Vec<LogEvent>>> {
-        let offset_usize = usize::try_from(offset)?;
-        let condvar = self.condvar.clone();
-
-        let read = self.runtime.block_on(async {
-            let read = self.read().await;
-
-            if read.len() == offset_usize {
-                if let Some(timeout) = timeout {
-                    let timeout_fut = async move {
-                        tokio::time::sleep(timeout).await;
-                        condvar.notify_all();
-                    }
-                    .fuse();
-                    let wait_fut = self.condvar.wait((read, self.inner.clone())).fuse();
-                    let mut timeout_fut = Box::pin(timeout_fut);
-                    let mut wait_fut = Box::pin(wait_fut);
-
-                    loop {
-                        futures::select! {
-                            read = wait_fut => break read,
-                            _ = timeout_fut => {},
-                        };
-                    }
-                } else {
-                    self.condvar.wait((read, self.inner.clone())).await
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        let mut inner = self.lock()?;
+        while u64::try_from(inner.len())? == offset {
+            inner = if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if deadline <= now {
+                    break;
                 }
+                self.condvar
+                    .wait_timeout(inner, deadline - now)
+                    .map_err(|_e| format_err!("mutex poisoned"))?
+                    .0
             } else {
-                read
-            }
-        });
+                self.condvar
+                    .wait(inner)
+                    .map_err(|_e| format_err!("mutex poisoned"))?
+            };
+        }
+        drop(inner);
+
+        self.read_committed(offset, limit)
+    }
 
-        let res: Vec<_> = read
-            .get(offset_usize..)
-            .ok_or_else(|| format_err!("out of bounds"))?
+    fn get_start_offset(&self) -> Result<Offset> {
+        Ok(0)
+    }
+}
+
+impl Writer for InMemoryLog {
+    fn write_tr(&self, conn: &mut dyn Transaction, events: &[Event]) -> Result<Offset> {
+        let events = events
             .iter()
-            .take(limit)
-            .enumerate()
-            .map(|(i, e)| LogEvent {
-                offset: offset + u64::try_from(i).expect("no fail"),
-                details: e.clone(),
+            .map(|event| {
+                (
+                    EventMetadata::new(conn.handled_event(), self.clock.now()),
+                    event.clone(),
+                )
             })
             .collect();
 
-        Ok(WithOffset {
-            offset: offset + u64::try_from(res.len()).expect("no fail"),
-            data: res,
-        })
+        let mut caster = conn.cast();
+        self.write_in_memory_tr(caster.as_mut::<InMemoryTransaction>()?, events)
+    }
+}
+
+#[async_trait]
+impl AsyncReader for InMemoryLog {
+    async fn read(
+        &self,
+        _conn: &mut dyn AsyncConnection,
+        offset: Offset,
+        limit: usize,
+        timeout: Option<Duration>,
+    ) -> Result<WithOffset<Vec<LogEvent>>> {
+        let mut committed = self.committed.subscribe();
+        let wait = committed.wait_for(|committed| offset != *committed);
+        if let Some(timeout) = timeout {
+            // on timeout just return whatever is there
+            let _ = tokio::time::timeout(timeout, wait).await;
+        } else {
+            wait.await?;
+        }
+
+        self.read_committed(offset, limit)
     }
 
     fn get_start_offset(&self) -> Result<Offset> {
@@ -93,18 +203,30 @@ impl Reader for InMemoryLog {
     }
 }
 
-impl Writer for InMemoryLog {
-    fn write_tr<'a>(&self, conn: &mut dyn Transaction, events: &[Event]) -> Result<Offset> {
-        conn.cast().as_mut::<InMemoryTransaction>()?;
-        futures::executor::block_on(self.write_events(events))
+#[async_trait]
+impl AsyncWriter for InMemoryLog {
+    async fn write_tr(
+        &self,
+        conn: &mut dyn AsyncTransaction<'_>,
+        events: &[Event],
+    ) -> Result<Offset> {
+        let events = events
+            .iter()
+            .map(|event| {
+                (
+                    EventMetadata::new(conn.handled_event(), self.clock.now()),
+                    event.clone(),
+                )
+            })
+            .collect();
+
+        let mut caster = conn.cast();
+        self.write_in_memory_tr(caster.as_mut::<InMemoryTransaction>()?, events)
     }
 }
 
+#[cfg(test)]
 pub fn new_in_memory_shared() -> Result<(SharedWriter, SharedReader)> {
-    let log = Arc::new(InMemoryLog {
-        inner: Arc::new(RwLock::new(Vec::new())),
-        condvar: Arc::new(Condvar::default()),
-        runtime: tokio::runtime::Runtime::new()?,
-    });
+    let log = Arc::new(InMemoryLog::new());
     Ok((log.clone(), log))
 }
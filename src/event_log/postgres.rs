@@ -0,0 +1,210 @@
+use super::*;
+use crate::{
+    event::wire,
+    persistence::{
+        migration::{ComponentMigrations, Migration},
+        PostgresConnection, PostgresTransaction,
+    },
+};
+use ::postgres::{fallible_iterator::FallibleIterator, types::Json};
+use std::time::Instant;
+
+/// Name of the channel used to `NOTIFY` readers about new events
+const NOTIFY_CHANNEL: &str = "event_log";
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        sql: "CREATE TABLE IF NOT EXISTS event_log (
+            log_offset BIGINT PRIMARY KEY,
+            details JSONB NOT NULL
+        )",
+    },
+    Migration {
+        version: 2,
+        // events written before metadata was recorded get fresh ids
+        // and are considered to have no cause
+        sql: "ALTER TABLE event_log
+                ADD COLUMN event_id UUID,
+                ADD COLUMN recorded_at TIMESTAMPTZ,
+                ADD COLUMN causation_id UUID,
+                ADD COLUMN correlation_id UUID;
+            UPDATE event_log SET event_id = gen_random_uuid(), recorded_at = now();
+            UPDATE event_log SET correlation_id = event_id;
+            ALTER TABLE event_log
+                ALTER COLUMN event_id SET NOT NULL,
+                ALTER COLUMN recorded_at SET NOT NULL,
+                ALTER COLUMN correlation_id SET NOT NULL;
+            CREATE UNIQUE INDEX event_log_event_id ON event_log (event_id);
+            CREATE INDEX event_log_correlation_id ON event_log (correlation_id)",
+    },
+];
+
+/// Event log stored in a Postgres table
+///
+/// Events are appended within the caller's [`PostgresTransaction`], so
+/// they become visible atomically with any other writes done in it.
+/// Readers waiting for new events are woken up using `LISTEN/NOTIFY`.
+#[derive(Debug, Clone)]
+pub struct PostgresLog {
+    /// Stamps the written events
+    clock: SharedClock,
+}
+
+impl PostgresLog {
+    pub const MIGRATIONS: ComponentMigrations = ComponentMigrations {
+        component: "event_log",
+        migrations: MIGRATIONS,
+    };
+
+    pub fn new() -> Self {
+        Self {
+            clock: SystemClock::new_shared(),
+        }
+    }
+
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock }
+    }
+
+    #[cfg(test)]
+    pub fn new_shared() -> (SharedWriter, SharedReader) {
+        let log = Arc::new(Self::new());
+        (log.clone(), log)
+    }
+
+    /// Offset the next event written is going to get, once the writers are serialized
+    fn next_offset(client: &mut impl ::postgres::GenericClient) -> Result<i64> {
+        Ok(client
+            .query_one(
+                "SELECT COALESCE(MAX(log_offset) + 1, 0) FROM event_log",
+                &[],
+            )?
+            .get(0))
+    }
+
+    fn query(
+        client: &mut ::postgres::Client,
+        offset: Offset,
+        limit: usize,
+    ) -> Result<Vec<LogEvent>> {
+        client
+            .query(
+                "SELECT log_offset, event_id, recorded_at, causation_id, correlation_id, details
+                FROM event_log WHERE log_offset >= $1 ORDER BY log_offset LIMIT $2",
+                &[&i64::try_from(offset)?, &i64::try_from(limit)?],
+            )?
+            .into_iter()
+            .map(|row| {
+                Ok(LogEvent {
+                    offset: u64::try_from(row.get::<'_, _, i64>("log_offset"))?,
+                    metadata: EventMetadata {
+                        id: row.get("event_id"),
+                        timestamp: row.get("recorded_at"),
+                        causation_id: row.get("causation_id"),
+                        correlation_id: row.get("correlation_id"),
+                    },
+                    details: wire::decode(row.get::<'_, _, Json<serde_json::Value>>("details").0)?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Reader for PostgresLog {
+    fn get_start_offset(&self) -> Result<Offset> {
+        Ok(0)
+    }
+
+    fn read(
+        &self,
+        conn: &mut dyn Connection,
+        offset: Offset,
+        limit: usize,
+        timeout: Option<Duration>,
+    ) -> Result<WithOffset<Vec<LogEvent>>> {
+        let mut caster = conn.cast();
+        let client = &mut caster.as_mut::<PostgresConnection>()?.0;
+
+        let mut events = Self::query(client, offset, limit)?;
+
+        if events.is_empty() && timeout != Some(Duration::ZERO) {
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+            // Start listening before checking again, so no notification
+            // can slip between the query and the wait.
+            client.batch_execute(&format!("LISTEN {NOTIFY_CHANNEL}"))?;
+
+            loop {
+                events = Self::query(client, offset, limit)?;
+                if !events.is_empty() {
+                    break;
+                }
+
+                let mut notifications = client.notifications();
+                if let Some(deadline) = deadline {
+                    let now = Instant::now();
+                    if deadline <= now {
+                        break;
+                    }
+                    notifications.timeout_iter(deadline - now).next()?;
+                } else {
+                    notifications.blocking_iter().next()?;
+                }
+            }
+
+            client.batch_execute(&format!("UNLISTEN {NOTIFY_CHANNEL}"))?;
+            // drop anything that arrived in the meantime, so it doesn't
+            // pile up in a pooled connection
+            client.notifications().iter().count()?;
+        }
+
+        Ok(WithOffset {
+            offset: events.last().map(|e| e.offset + 1).unwrap_or(offset),
+            data: events,
+        })
+    }
+}
+
+impl Writer for PostgresLog {
+    fn write_tr(&self, conn: &mut dyn Transaction<'_>, events: &[Event]) -> Result<Offset> {
+        let cause = conn.handled_event().cloned();
+        let mut caster = conn.cast();
+        let transaction = &mut caster.as_mut::<PostgresTransaction>()?.0;
+
+        if events.is_empty() {
+            // nothing to order, so no need to wait for the other writers
+            return Ok(u64::try_from(Self::next_offset(transaction)?)?);
+        }
+
+        // Serialize all writers, so offsets are assigned in commit order and
+        // without gaps; readers are not blocked by this lock.
+        transaction.batch_execute("LOCK TABLE event_log IN EXCLUSIVE MODE")?;
+        let next_offset = Self::next_offset(transaction)?;
+
+        let statement = transaction.prepare(
+            "INSERT INTO event_log (log_offset, event_id, recorded_at, causation_id, correlation_id, details)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )?;
+
+        for (i, event) in events.iter().enumerate() {
+            let metadata = EventMetadata::new(cause.as_ref(), self.clock.now());
+            transaction.execute(
+                &statement,
+                &[
+                    &(next_offset + i64::try_from(i)?),
+                    &metadata.id,
+                    &metadata.timestamp,
+                    &metadata.causation_id,
+                    &metadata.correlation_id,
+                    &Json(wire::encode(event)?),
+                ],
+            )?;
+        }
+
+        // delivered only when (and if) the transaction commits
+        transaction.batch_execute(&format!("NOTIFY {NOTIFY_CHANNEL}"))?;
+
+        Ok(u64::try_from(next_offset + i64::try_from(events.len())?)?)
+    }
+}
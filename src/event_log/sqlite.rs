@@ -0,0 +1,178 @@
+use super::*;
+use crate::{
+    event::wire,
+    persistence::{
+        migration::{ComponentMigrations, Migration},
+        timestamp_from_sql, timestamp_to_sql, SqliteConnection, SqliteTransaction,
+    },
+};
+use std::time::Instant;
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        sql: "CREATE TABLE IF NOT EXISTS event_log (
+            log_offset INTEGER PRIMARY KEY,
+            details TEXT NOT NULL
+        )",
+    },
+    Migration {
+        version: 2,
+        // `recorded_at` is in microseconds since the unix epoch; events
+        // written before metadata was recorded get fresh (v4) ids
+        // and are considered to have no cause
+        sql: "ALTER TABLE event_log ADD COLUMN event_id TEXT;
+            ALTER TABLE event_log ADD COLUMN recorded_at INTEGER;
+            ALTER TABLE event_log ADD COLUMN causation_id TEXT;
+            ALTER TABLE event_log ADD COLUMN correlation_id TEXT;
+            UPDATE event_log SET
+                event_id = lower(
+                    hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' ||
+                    substr(hex(randomblob(2)), 2) || '-' ||
+                    substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' ||
+                    hex(randomblob(6))
+                ),
+                recorded_at = CAST(unixepoch('subsec') * 1000000 AS INTEGER);
+            UPDATE event_log SET correlation_id = event_id;
+            CREATE UNIQUE INDEX event_log_event_id ON event_log (event_id);
+            CREATE INDEX event_log_correlation_id ON event_log (correlation_id)",
+    },
+];
+
+/// Event log stored in a SQLite table
+///
+/// Readers waiting for new events are woken up on every commit
+/// of a [`SqliteTransaction`] of the same [`crate::persistence::SqlitePersistence`].
+#[derive(Debug, Clone)]
+pub struct SqliteLog {
+    /// Stamps the written events
+    clock: SharedClock,
+}
+
+impl SqliteLog {
+    pub const MIGRATIONS: ComponentMigrations = ComponentMigrations {
+        component: "event_log",
+        migrations: MIGRATIONS,
+    };
+
+    pub fn new() -> Self {
+        Self {
+            clock: SystemClock::new_shared(),
+        }
+    }
+
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock }
+    }
+
+    #[cfg(test)]
+    pub fn new_shared() -> (SharedWriter, SharedReader) {
+        let log = Arc::new(Self::new());
+        (log.clone(), log)
+    }
+
+    fn query(conn: &rusqlite::Connection, offset: Offset, limit: usize) -> Result<Vec<LogEvent>> {
+        conn.prepare_cached(
+            "SELECT log_offset, event_id, recorded_at, causation_id, correlation_id, details
+            FROM event_log WHERE log_offset >= ?1 ORDER BY log_offset LIMIT ?2",
+        )?
+        .query_map(
+            rusqlite::params![i64::try_from(offset)?, i64::try_from(limit)?],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            },
+        )?
+        .map(|row| {
+            let (offset, id, recorded_at, causation_id, correlation_id, details) = row?;
+            Ok(LogEvent {
+                offset: u64::try_from(offset)?,
+                metadata: EventMetadata {
+                    id: id.parse()?,
+                    timestamp: timestamp_from_sql(recorded_at)?,
+                    causation_id: causation_id.map(|id| id.parse()).transpose()?,
+                    correlation_id: correlation_id.parse()?,
+                },
+                details: wire::decode(serde_json::from_str(&details)?)?,
+            })
+        })
+        .collect()
+    }
+}
+
+impl Reader for SqliteLog {
+    fn get_start_offset(&self) -> Result<Offset> {
+        Ok(0)
+    }
+
+    fn read(
+        &self,
+        conn: &mut dyn Connection,
+        offset: Offset,
+        limit: usize,
+        timeout: Option<Duration>,
+    ) -> Result<WithOffset<Vec<LogEvent>>> {
+        let mut caster = conn.cast();
+        let SqliteConnection(conn, notifier) = caster.as_mut::<SqliteConnection>()?;
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        let events = loop {
+            // Get the generation before querying, so no commit
+            // can slip between the query and the wait.
+            let generation = notifier.generation();
+            let events = Self::query(conn, offset, limit)?;
+
+            if !events.is_empty() || deadline.map_or(false, |deadline| deadline <= Instant::now()) {
+                break events;
+            }
+
+            notifier.wait(generation, deadline);
+        };
+
+        Ok(WithOffset {
+            offset: events.last().map(|e| e.offset + 1).unwrap_or(offset),
+            data: events,
+        })
+    }
+}
+
+impl Writer for SqliteLog {
+    fn write_tr(&self, conn: &mut dyn Transaction<'_>, events: &[Event]) -> Result<Offset> {
+        let cause = conn.handled_event().cloned();
+        let mut caster = conn.cast();
+        let transaction = &caster.as_mut::<SqliteTransaction>()?.0;
+
+        // `SqliteTransaction` already holds the database write lock,
+        // so offsets are assigned in commit order and without gaps.
+        let next_offset: i64 = transaction.query_row(
+            "SELECT COALESCE(MAX(log_offset) + 1, 0) FROM event_log",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let mut statement = transaction.prepare_cached(
+            "INSERT INTO event_log (log_offset, event_id, recorded_at, causation_id, correlation_id, details)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+
+        for (i, event) in events.iter().enumerate() {
+            let metadata = EventMetadata::new(cause.as_ref(), self.clock.now());
+            statement.execute(rusqlite::params![
+                next_offset + i64::try_from(i)?,
+                metadata.id.to_string(),
+                timestamp_to_sql(metadata.timestamp)?,
+                metadata.causation_id.map(|id| id.to_string()),
+                metadata.correlation_id.to_string(),
+                serde_json::to_string(&wire::encode(event)?)?
+            ])?;
+        }
+
+        Ok(u64::try_from(next_offset + i64::try_from(events.len())?)?)
+    }
+}
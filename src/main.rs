@@ -1,4 +1,6 @@
 mod auction;
+mod clock;
+mod dead_letter;
 mod event;
 mod event_log;
 mod persistence;
@@ -6,17 +8,187 @@ mod progress;
 mod service;
 
 use anyhow::Result;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+
+/// Number of partitions (threads) the bidding engine handles auctions in
+const DEFAULT_BIDDING_ENGINE_PARTITIONS: usize = 4;
+
+/// How often the bidding engine checks for auctions about to close
+const TIMER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// [`service::auction_house::XmppConfig`], with the defaults overridden by `SNIPER_XMPP_*` env vars
+///
+/// `None` with `SNIPER_XMPP_ENABLED=false`, to bid only in the HTTP auction houses.
+fn xmpp_config_from_env() -> Result<Option<service::auction_house::XmppConfig>> {
+    if let Ok(enabled) = std::env::var("SNIPER_XMPP_ENABLED") {
+        if !enabled.parse::<bool>()? {
+            return Ok(None);
+        }
+    }
+    let default = service::auction_house::XmppConfig::default();
+    let var = |name, default| std::env::var(name).unwrap_or(default);
+    let domain = var("SNIPER_XMPP_DOMAIN", default.domain);
+    Ok(Some(service::auction_house::XmppConfig {
+        address: var("SNIPER_XMPP_ADDRESS", default.address),
+        username: var("SNIPER_XMPP_USERNAME", default.username),
+        password: var("SNIPER_XMPP_PASSWORD", default.password),
+        resource: default.resource,
+        auction_domain: var("SNIPER_XMPP_AUCTION_DOMAIN", domain.clone()),
+        domain,
+        timeout: default.timeout,
+    }))
+}
+
+/// Auction houses with REST APIs, from `SNIPER_HTTP_AUCTION_HOUSES`
+///
+/// Eg. `ebay=http://localhost:8080/api,other=http://localhost:8081`, for items
+/// `ebay:<item>` and `other:<item>`; the rest of the items are in the XMPP one, if enabled.
+fn http_auction_houses_from_env() -> Result<Vec<(String, service::auction_house::HttpConfig)>> {
+    let Ok(auction_houses) = std::env::var("SNIPER_HTTP_AUCTION_HOUSES") else {
+        return Ok(vec![]);
+    };
+    auction_houses
+        .split(',')
+        .filter(|auction_house| !auction_house.is_empty())
+        .map(|auction_house| {
+            let (id, url) = auction_house
+                .split_once('=')
+                .ok_or_else(|| anyhow::format_err!("invalid auction house: {auction_house}"))?;
+            Ok((id.to_owned(), service::auction_house::HttpConfig::new(url)))
+        })
+        .collect()
+}
 
 fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
-    let persistence = Arc::new(persistence::InMemoryPersistence::new());
-    let progress_store = progress::InMemoryProgressTracker::new_shared();
-    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
-    let auction_house_client = service::auction_house::XmppAuctionHouseClient::new_shared();
+    let clock = clock::SystemClock::new_shared();
+
+    let (
+        persistence,
+        async_persistence,
+        progress_store,
+        dead_letter_store,
+        event_writer,
+        async_event_writer,
+        event_reader,
+        bidding_state_store,
+    ) = if let Ok(config) = std::env::var("SNIPER_POSTGRES_URL") {
+        let persistence = persistence::PostgresPersistence::new(&config)?;
+        let event_log = Arc::new(event_log::PostgresLog::new().with_clock(clock.clone()));
+        let (event_writer, event_reader): (event_log::SharedWriter, event_log::SharedReader) =
+            (event_log.clone(), event_log);
+        let progress_store = progress::PostgresProgressTracker::new_shared();
+        let dead_letter_store = dead_letter::PostgresDeadLetterStore::new_shared();
+        let bidding_state_store = service::PostgresBiddingStateStore::new_shared();
+        persistence.migrate(&[
+            event_log::PostgresLog::MIGRATIONS,
+            progress::PostgresProgressTracker::MIGRATIONS,
+            dead_letter::PostgresDeadLetterStore::MIGRATIONS,
+            service::PostgresBiddingStateStore::MIGRATIONS,
+        ])?;
+
+        let persistence: persistence::SharedPersistence = Arc::new(persistence);
+        (
+            persistence.clone(),
+            persistence::BlockingPersistence::new_shared(persistence),
+            progress_store,
+            dead_letter_store,
+            event_writer.clone(),
+            event_log::BlockingWriter::new_shared(event_writer),
+            event_reader,
+            bidding_state_store,
+        )
+    } else if let Ok(path) = std::env::var("SNIPER_SQLITE_PATH") {
+        let persistence = persistence::SqlitePersistence::new(path)?;
+        let event_log = Arc::new(event_log::SqliteLog::new().with_clock(clock.clone()));
+        let (event_writer, event_reader): (event_log::SharedWriter, event_log::SharedReader) =
+            (event_log.clone(), event_log);
+        let progress_store = progress::SqliteProgressTracker::new_shared();
+        let dead_letter_store = dead_letter::SqliteDeadLetterStore::new_shared();
+        let bidding_state_store = service::SqliteBiddingStateStore::new_shared();
+        persistence.migrate(&[
+            event_log::SqliteLog::MIGRATIONS,
+            progress::SqliteProgressTracker::MIGRATIONS,
+            dead_letter::SqliteDeadLetterStore::MIGRATIONS,
+            service::SqliteBiddingStateStore::MIGRATIONS,
+        ])?;
+
+        let persistence: persistence::SharedPersistence = Arc::new(persistence);
+        (
+            persistence.clone(),
+            persistence::BlockingPersistence::new_shared(persistence),
+            progress_store,
+            dead_letter_store,
+            event_writer.clone(),
+            event_log::BlockingWriter::new_shared(event_writer),
+            event_reader,
+            bidding_state_store,
+        )
+    } else {
+        let persistence = Arc::new(persistence::InMemoryPersistence::new());
+        let event_log = Arc::new(event_log::InMemoryLog::new().with_clock(clock.clone()));
+        (
+            persistence.clone() as persistence::SharedPersistence,
+            persistence as persistence::SharedAsyncPersistence,
+            progress::InMemoryProgressTracker::new_shared(),
+            dead_letter::InMemoryDeadLetterStore::new_shared(),
+            event_log.clone() as event_log::SharedWriter,
+            event_log.clone() as event_log::SharedAsyncWriter,
+            event_log as event_log::SharedReader,
+            service::InMemoryBiddingStateStore::new_shared(),
+        )
+    };
+    let bidding_engine_partitions = match std::env::var("SNIPER_BIDDING_ENGINE_PARTITIONS") {
+        Ok(partitions) => partitions.parse()?,
+        Err(_) => DEFAULT_BIDDING_ENGINE_PARTITIONS,
+    };
+    // total we are willing to spend across all the auctions
+    let budget = match std::env::var("SNIPER_BUDGET") {
+        Ok(budget) => Some(budget.parse()?),
+        Err(_) => None,
+    };
 
-    let svc_ctr = service::ServiceControl::new(persistence.clone(), progress_store);
+    let svc_ctr = service::ServiceControl::new(
+        persistence.clone(),
+        progress_store.clone(),
+        dead_letter_store.clone(),
+    )
+    .with_clock(clock);
+    let xmpp_config = xmpp_config_from_env()?;
+    let http_auction_houses = http_auction_houses_from_env()?;
+    if xmpp_config.is_none() && http_auction_houses.is_empty() {
+        anyhow::bail!("no auction houses: XMPP is disabled, and no HTTP ones are set");
+    }
+    let router = service::auction_house::AuctionHouseRouter::new();
+    let router = match xmpp_config {
+        Some(config) => router.with_default(
+            service::auction_house::XmppAuctionHouseClient::new_shared(config),
+        ),
+        None => router,
+    };
+    let auction_house_client =
+        http_auction_houses
+            .into_iter()
+            .fold(router, |router, (id, config)| {
+                router.with_auction_house(
+                    &id,
+                    service::auction_house::HttpAuctionHouseClient::new_shared(config),
+                )
+            });
+    let auction_house_client: service::auction_house::SharedAuctionHouseClient =
+        Arc::new(auction_house_client);
+    service::auction_house::join_open_auctions(
+        &*persistence,
+        &*bidding_state_store,
+        &*auction_house_client,
+    )?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let async_svc_ctr = service::AsyncServiceControl::new(
+        &svc_ctr,
+        runtime.handle().clone(),
+        async_persistence.clone(),
+    );
 
     ctrlc::set_handler({
         let svc_ctr = svc_ctr.clone();
@@ -26,25 +198,52 @@ fn main() -> Result<()> {
         }
     })?;
 
-    let bidding_state_store = service::InMemoryBiddingStateStore::new_shared();
-    for handle in vec![
-        svc_ctr.spawn_log_follower(
-            service::bidding_engine::BiddingEngine::new(bidding_state_store, event_writer.clone()),
-            event_reader.clone(),
+    let ui = service::Ui::new(async_persistence, async_event_writer, dead_letter_store);
+    let ui = match std::env::var("SNIPER_UI_ADDRESS") {
+        Ok(address) => ui.with_address(address.parse()?),
+        Err(_) => ui,
+    };
+    let ui = async_svc_ctr.spawn_loop(ui);
+
+    let bidding_engine = svc_ctr.spawn_partitioned_log_follower(
+        |partition| {
+            let bidding_engine = service::bidding_engine::BiddingEngine::new(
+                bidding_state_store.clone(),
+                event_writer.clone(),
+            )
+            .with_partition(partition);
+            match budget {
+                Some(budget) => bidding_engine.with_budget(budget),
+                None => bidding_engine,
+            }
+        },
+        bidding_engine_partitions,
+        event_reader.clone(),
+    )?;
+    for handle in bidding_engine.into_iter().chain([
+        svc_ctr.spawn_loop(
+            service::Timer::new(
+                persistence.clone(),
+                event_writer.clone(),
+                svc_ctr.clock(),
+                TIMER_INTERVAL,
+            )
+            .with_pending_auctions(bidding_state_store.clone()),
         ),
         svc_ctr.spawn_loop(service::AuctionHouseReceiver::new(
             persistence.clone(),
             event_writer.clone(),
             auction_house_client.clone(),
+            progress_store.clone(),
         )),
         svc_ctr.spawn_log_follower(
             service::AuctionHouseSender::new(auction_house_client.clone()),
             event_reader.clone(),
         ),
-        svc_ctr.spawn_loop(service::Ui::new(persistence, event_writer.clone())?),
-    ] {
+    ]) {
         handle.join()?
     }
+    runtime.block_on(ui.join())?;
 
     Ok(())
 }
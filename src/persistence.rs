@@ -8,15 +8,19 @@
 //!
 //! * https://www.reddit.com/r/rust/comments/p9amqt/hexagonal_architecture_in_rust_1/h9ypjoo?utm_source=share&utm_medium=web2x&context=3
 //! * https://www.reddit.com/r/golang/comments/i1vy4s/ddd_vs_db_transactions_how_to_reconcile/
+pub mod blocking;
 pub mod in_memory;
+pub mod migration;
 pub mod postgres;
+pub mod sqlite;
 
-pub use self::{in_memory::*, postgres::*};
+pub use self::{blocking::*, in_memory::*, postgres::*, sqlite::*};
 use thiserror::Error;
 
 use dyno::{Tag, Tagged};
 
-use anyhow::{bail, Result};
+use anyhow::{format_err, Result};
+use async_trait::async_trait;
 use std::{any::Any, sync::Arc};
 
 /// An interface of any persistence
@@ -45,10 +49,107 @@ pub trait Transaction<'a> {
     fn cast<'b>(&'b mut self) -> Caster<'b, 'a>
     where
         'a: 'b;
+
+    /// Metadata of the log event this transaction is handling, if any
+    ///
+    /// See [`crate::event_log::CausedTransaction`].
+    fn handled_event(&self) -> Option<&crate::event_log::EventMetadata> {
+        None
+    }
 }
 
 pub type OwnedTransaction<'a> = Box<dyn Transaction<'a> + 'a>;
 
+/// Async counterpart of [`Persistence`]
+///
+/// Async code can't just use the sync [`Persistence`], since most of its
+/// operations block. Sync repositories can still be used from async code,
+/// with [`AsyncConnection::run_blocking`] and
+/// [`AsyncTransaction::run_blocking`], which run them where blocking is fine.
+#[async_trait]
+pub trait AsyncPersistence: Send + Sync {
+    /// Get a connection to persistence
+    async fn get_connection(&self) -> Result<OwnedAsyncConnection>;
+}
+
+pub type SharedAsyncPersistence = Arc<dyn AsyncPersistence>;
+
+/// A sync operation on a [`Connection`], see [`AsyncConnection::run_blocking`]
+pub type BlockingConnectionFn = Box<dyn FnOnce(&mut dyn Connection) + Send>;
+
+/// A sync operation on a [`Transaction`], see [`AsyncTransaction::run_blocking`]
+pub type BlockingTransactionFn = Box<dyn FnOnce(&mut dyn Transaction<'_>) + Send>;
+
+#[async_trait]
+pub trait AsyncConnection: Send {
+    async fn start_transaction<'c>(&'c mut self) -> Result<OwnedAsyncTransaction<'c>>;
+
+    /// Run `f` with a sync [`Connection`] to the same persistence
+    ///
+    /// See [`Self::blocking`] for a more convenient version.
+    async fn run_blocking(&mut self, f: BlockingConnectionFn) -> Result<()>;
+}
+
+impl<'c> dyn AsyncConnection + 'c {
+    /// Run `f` with a sync [`Connection`] to the same persistence, and return its result
+    pub async fn blocking<R: Send + 'static>(
+        &mut self,
+        f: impl FnOnce(&mut dyn Connection) -> Result<R> + Send + 'static,
+    ) -> Result<R> {
+        let res = Arc::new(parking_lot::Mutex::new(None));
+        self.run_blocking(Box::new({
+            let res = res.clone();
+            move |conn| *res.lock() = Some(f(conn))
+        }))
+        .await?;
+        let res = res.lock().take();
+        res.ok_or_else(|| format_err!("blocking operation did not complete"))?
+    }
+}
+
+pub type OwnedAsyncConnection = Box<dyn AsyncConnection>;
+
+#[async_trait]
+pub trait AsyncTransaction<'a>: Send {
+    async fn commit(self: Box<Self>) -> Result<()>;
+    // async services roll back by dropping the transaction, like the sync ones
+    #[allow(dead_code)]
+    async fn rollback(self: Box<Self>) -> Result<()>;
+
+    fn cast<'b>(&'b mut self) -> Caster<'b, 'a>
+    where
+        'a: 'b;
+
+    /// See [`Transaction::handled_event`]
+    fn handled_event(&self) -> Option<&crate::event_log::EventMetadata> {
+        None
+    }
+
+    /// Run `f` with a sync [`Transaction`] that is a part of this one
+    ///
+    /// See [`Self::blocking`] for a more convenient version.
+    async fn run_blocking(&mut self, f: BlockingTransactionFn) -> Result<()>;
+}
+
+impl<'t, 'a> dyn AsyncTransaction<'a> + 't {
+    /// Run `f` with a sync [`Transaction`] that is a part of this one, and return its result
+    pub async fn blocking<R: Send + 'static>(
+        &mut self,
+        f: impl FnOnce(&mut dyn Transaction<'_>) -> Result<R> + Send + 'static,
+    ) -> Result<R> {
+        let res = Arc::new(parking_lot::Mutex::new(None));
+        self.run_blocking(Box::new({
+            let res = res.clone();
+            move |transaction| *res.lock() = Some(f(transaction))
+        }))
+        .await?;
+        let res = res.lock().take();
+        res.ok_or_else(|| format_err!("blocking operation did not complete"))?
+    }
+}
+
+pub type OwnedAsyncTransaction<'a> = Box<dyn AsyncTransaction<'a> + 'a>;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("wrong type")]
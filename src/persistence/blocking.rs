@@ -0,0 +1,206 @@
+use super::*;
+use std::sync::mpsc;
+use tokio::sync::oneshot;
+
+/// An [`AsyncPersistence`] on top of a sync [`Persistence`]
+///
+/// Every connection gets a task on the blocking thread pool of the tokio
+/// runtime (see [`tokio::task::spawn_blocking`]), that owns the actual sync
+/// connection and runs all the operations on it, including any
+/// [`AsyncConnection::run_blocking`] and [`AsyncTransaction::run_blocking`].
+/// It works on any runtime, but keeps a thread of the pool for as long
+/// as the connection lives, so once the pool is used up, getting
+/// a connection waits for another one to be dropped.
+/// Repositories can't cast its connections and transactions to the
+/// concrete ones, so it can only be used with the sync repositories.
+///
+/// Note that [`PostgresPersistence`] panics when dropped within an async
+/// context, so the last reference to it should be dropped outside of it.
+pub struct BlockingPersistence {
+    inner: SharedPersistence,
+}
+
+impl BlockingPersistence {
+    pub fn new(inner: SharedPersistence) -> Self {
+        Self { inner }
+    }
+
+    pub fn new_shared(inner: SharedPersistence) -> SharedAsyncPersistence {
+        Arc::new(Self::new(inner))
+    }
+}
+
+#[async_trait]
+impl AsyncPersistence for BlockingPersistence {
+    async fn get_connection(&self) -> Result<OwnedAsyncConnection> {
+        let (jobs_tx, jobs_rx) = mpsc::channel();
+        let (connected_tx, connected_rx) = oneshot::channel();
+
+        tokio::task::spawn_blocking({
+            let persistence = self.inner.clone();
+            move || match persistence.get_connection() {
+                Ok(connection) => {
+                    let _ = connected_tx.send(Ok(()));
+                    run_connection(connection, jobs_rx);
+                }
+                Err(e) => {
+                    let _ = connected_tx.send(Err(e));
+                }
+            }
+        });
+
+        connected_rx.await??;
+        Ok(Box::new(BlockingConnection { jobs: jobs_tx }))
+    }
+}
+
+/// Operations sent to a connection thread
+enum Job {
+    Connection(BlockingConnectionFn, oneshot::Sender<()>),
+    StartTransaction(oneshot::Sender<Result<()>>),
+    Transaction(BlockingTransactionFn, oneshot::Sender<()>),
+    Commit(oneshot::Sender<Result<()>>),
+    /// Sent without a response channel when a transaction is dropped
+    Rollback(Option<oneshot::Sender<Result<()>>>),
+}
+
+fn run_connection(mut connection: OwnedConnection, jobs: mpsc::Receiver<Job>) {
+    while let Ok(job) = jobs.recv() {
+        match job {
+            Job::Connection(f, done) => {
+                f(&mut *connection);
+                let _ = done.send(());
+            }
+            Job::StartTransaction(started) => match connection.start_transaction() {
+                Ok(transaction) => {
+                    let _ = started.send(Ok(()));
+                    run_transaction(transaction, &jobs);
+                }
+                Err(e) => {
+                    let _ = started.send(Err(e));
+                }
+            },
+            Job::Transaction(..) | Job::Commit(_) | Job::Rollback(_) => {
+                unreachable!("no transaction in progress")
+            }
+        }
+    }
+}
+
+fn run_transaction(mut transaction: OwnedTransaction<'_>, jobs: &mpsc::Receiver<Job>) {
+    // the connection going away in the middle of a transaction just rolls it back on drop
+    while let Ok(job) = jobs.recv() {
+        match job {
+            Job::Transaction(f, done) => {
+                f(&mut *transaction);
+                let _ = done.send(());
+            }
+            Job::Commit(done) => {
+                let _ = done.send(transaction.commit());
+                return;
+            }
+            Job::Rollback(done) => {
+                let res = transaction.rollback();
+                if let Some(done) = done {
+                    let _ = done.send(res);
+                }
+                return;
+            }
+            Job::Connection(..) | Job::StartTransaction(_) => {
+                unreachable!("connection is borrowed by the transaction")
+            }
+        }
+    }
+}
+
+/// A connection of [`BlockingPersistence`]
+pub struct BlockingConnection {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl BlockingConnection {
+    fn send(&self, job: Job) -> Result<()> {
+        self.jobs
+            .send(job)
+            .map_err(|_| format_err!("connection thread terminated"))
+    }
+}
+
+impl<'a> dyno::Tag<'a> for BlockingConnection {
+    type Type = BlockingConnection;
+}
+
+#[async_trait]
+impl AsyncConnection for BlockingConnection {
+    async fn start_transaction<'c>(&'c mut self) -> Result<OwnedAsyncTransaction<'c>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Job::StartTransaction(tx))?;
+        rx.await??;
+        Ok(Box::new(BlockingTransaction {
+            connection: self,
+            finished: false,
+        }))
+    }
+
+    async fn run_blocking(&mut self, f: BlockingConnectionFn) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Job::Connection(f, tx))?;
+        Ok(rx.await?)
+    }
+}
+
+/// A transaction of [`BlockingPersistence`]
+///
+/// Dropping it without committing rolls it back.
+pub struct BlockingTransaction<'a> {
+    connection: &'a mut BlockingConnection,
+    finished: bool,
+}
+
+impl<'a> BlockingTransaction<'a> {
+    async fn finish(
+        mut self: Box<Self>,
+        job: impl FnOnce(oneshot::Sender<Result<()>>) -> Job,
+    ) -> Result<()> {
+        self.finished = true;
+        let (tx, rx) = oneshot::channel();
+        self.connection.send(job(tx))?;
+        rx.await?
+    }
+}
+
+impl<'a> Drop for BlockingTransaction<'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.connection.send(Job::Rollback(None));
+        }
+    }
+}
+
+impl<'a> dyno::Tag<'a> for BlockingTransaction<'static> {
+    type Type = BlockingTransaction<'a>;
+}
+
+#[async_trait]
+impl<'a> AsyncTransaction<'a> for BlockingTransaction<'a> {
+    async fn commit(self: Box<Self>) -> Result<()> {
+        self.finish(Job::Commit).await
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<()> {
+        self.finish(|tx| Job::Rollback(Some(tx))).await
+    }
+
+    fn cast<'caster>(&'caster mut self) -> Caster<'caster, 'a>
+    where
+        'a: 'caster,
+    {
+        Caster::new::<BlockingTransaction<'static>>(self)
+    }
+
+    async fn run_blocking(&mut self, f: BlockingTransactionFn) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.connection.send(Job::Transaction(f, tx))?;
+        Ok(rx.await?)
+    }
+}
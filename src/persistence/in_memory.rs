@@ -1,10 +1,14 @@
 use super::*;
 use futures;
-use tokio::sync::{Mutex, MutexGuard};
+use std::{any::Any, borrow::Borrow, collections::BTreeMap, marker::PhantomData};
+use tokio::sync::{Mutex, OwnedMutexGuard};
 
 /// Fake in-memory persistence.
 ///
-/// Useful for unit-tests.
+/// Useful for unit-tests. Implements both [`Persistence`] and [`AsyncPersistence`],
+/// so sync and async code can share the same data. As async, it works on
+/// any tokio runtime: [`AsyncConnection::run_blocking`] and
+/// [`AsyncTransaction::run_blocking`] use [`tokio::task::spawn_blocking`].
 #[derive(Debug, Clone)]
 pub struct InMemoryPersistence {
     lock: Arc<Mutex<()>>,
@@ -26,6 +30,15 @@ impl Persistence for InMemoryPersistence {
     }
 }
 
+#[async_trait]
+impl AsyncPersistence for InMemoryPersistence {
+    async fn get_connection(&self) -> Result<OwnedAsyncConnection> {
+        Ok(Box::new(InMemoryConnection {
+            lock: self.lock.clone(),
+        }))
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct InMemoryConnection {
     lock: Arc<Mutex<()>>,
@@ -37,9 +50,9 @@ impl<'a> dyno::Tag<'a> for InMemoryConnection {
 
 impl Connection for InMemoryConnection {
     fn start_transaction(&mut self) -> Result<OwnedTransaction<'_>> {
-        Ok(Box::new(InMemoryTransaction {
-            _lock_guard: futures::executor::block_on(self.lock.lock()),
-        }))
+        Ok(Box::new(InMemoryTransaction::new(
+            futures::executor::block_on(self.lock.clone().lock_owned()),
+        )))
     }
 
     fn cast(&mut self) -> Caster<'_, 'static> {
@@ -47,9 +60,195 @@ impl Connection for InMemoryConnection {
     }
 }
 
-#[derive(Debug)]
+#[async_trait]
+impl AsyncConnection for InMemoryConnection {
+    async fn start_transaction<'c>(&'c mut self) -> Result<OwnedAsyncTransaction<'c>> {
+        Ok(Box::new(InMemoryTransaction::new(
+            self.lock.clone().lock_owned().await,
+        )))
+    }
+
+    async fn run_blocking(&mut self, f: BlockingConnectionFn) -> Result<()> {
+        let mut connection = InMemoryConnection {
+            lock: self.lock.clone(),
+        };
+        tokio::task::spawn_blocking(move || f(&mut connection)).await?;
+        Ok(())
+    }
+}
+
+/// Changes of an in-memory store, kept in an [`InMemoryTransaction`] until it commits
+pub trait PendingChanges: Send + 'static {
+    /// Make the changes visible to everyone
+    fn commit(self) -> Result<()>;
+}
+
+type BoxedChanges = Box<dyn Any + Send>;
+type CommitFn = fn(BoxedChanges) -> Result<()>;
+
+/// Identifies an in-memory store in an [`InMemoryTransaction`], by the address of its shared data
+pub fn store_id<T: ?Sized>(data: &Arc<T>) -> usize {
+    Arc::as_ptr(data) as *const () as usize
+}
+
+/// A transaction of [`InMemoryPersistence`]
+///
+/// Holds a global lock for its whole duration, so transactions are
+/// fully serialized. Since there's no real database underneath, every
+/// in-memory store keeps its changes in the transaction (see
+/// [`Self::pending_changes`]), and applies them only when it commits,
+/// so no one sees them before, or at all if it's rolled back (or dropped).
 pub struct InMemoryTransaction<'a> {
-    _lock_guard: MutexGuard<'a, ()>,
+    /// `None` only while lent to a blocking task, see [`AsyncTransaction::run_blocking`]
+    lock_guard: Option<OwnedMutexGuard<()>>,
+    /// By store id, in order of the first change
+    pending: Vec<(usize, BoxedChanges, CommitFn)>,
+    _connection: PhantomData<&'a mut InMemoryConnection>,
+}
+
+impl<'a> InMemoryTransaction<'a> {
+    fn new(lock_guard: OwnedMutexGuard<()>) -> Self {
+        Self {
+            lock_guard: Some(lock_guard),
+            pending: vec![],
+            _connection: PhantomData,
+        }
+    }
+
+    /// Changes of the store `store_id` done in this transaction so far, if any
+    pub fn pending_changes_ref<T: PendingChanges>(&self, store_id: usize) -> Option<&T> {
+        self.pending
+            .iter()
+            .find(|(id, _, _)| *id == store_id)
+            .map(|(_, changes, _)| changes.downcast_ref().expect("same store, same type"))
+    }
+
+    /// Changes of the store `store_id` done in this transaction, starting with `new()`
+    ///
+    /// Changes of all the stores are committed in the order of their first
+    /// change. If any of them fails, the rest is still committed, and
+    /// the first error is returned from [`Transaction::commit`].
+    pub fn pending_changes<T: PendingChanges>(
+        &mut self,
+        store_id: usize,
+        new: impl FnOnce() -> T,
+    ) -> &mut T {
+        let i = match self.pending.iter().position(|(id, _, _)| *id == store_id) {
+            Some(i) => i,
+            None => {
+                self.pending.push((store_id, Box::new(new()), |changes| {
+                    changes.downcast::<T>().expect("same type").commit()
+                }));
+                self.pending.len() - 1
+            }
+        };
+        self.pending[i]
+            .1
+            .downcast_mut()
+            .expect("same store, same type")
+    }
+}
+
+/// Rows of an in-memory store
+///
+/// Changes done in an [`InMemoryTransaction`] stay in it until it commits.
+pub struct InMemoryTable<K, V>(Arc<std::sync::Mutex<BTreeMap<K, V>>>);
+
+impl<K, V> Default for InMemoryTable<K, V> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+/// Changes of an [`InMemoryTable`] in a transaction, `None` for removed rows
+struct TableChanges<K, V> {
+    table: Arc<std::sync::Mutex<BTreeMap<K, V>>>,
+    rows: BTreeMap<K, Option<V>>,
+}
+
+impl<K, V> PendingChanges for TableChanges<K, V>
+where
+    K: Ord + Send + 'static,
+    V: Send + 'static,
+{
+    fn commit(self) -> Result<()> {
+        let mut table = self.table.lock().expect("lock");
+        for (key, value) in self.rows {
+            match value {
+                Some(value) => table.insert(key, value),
+                None => table.remove(&key),
+            };
+        }
+        Ok(())
+    }
+}
+
+impl<K, V> InMemoryTable<K, V>
+where
+    K: Ord + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    /// Committed rows
+    pub fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<K, V>> {
+        self.0.lock().expect("lock")
+    }
+
+    fn changes_ref<'t>(
+        &self,
+        transaction: &'t InMemoryTransaction,
+    ) -> Option<&'t TableChanges<K, V>> {
+        transaction.pending_changes_ref(store_id(&self.0))
+    }
+
+    fn changes<'t>(&self, transaction: &'t mut InMemoryTransaction) -> &'t mut TableChanges<K, V> {
+        transaction.pending_changes(store_id(&self.0), || TableChanges {
+            table: self.0.clone(),
+            rows: BTreeMap::new(),
+        })
+    }
+
+    /// Row `key` as seen in `transaction`
+    pub fn get_tr<Q>(&self, transaction: &InMemoryTransaction, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match self
+            .changes_ref(transaction)
+            .and_then(|changes| changes.rows.get(key))
+        {
+            Some(value) => value.clone(),
+            None => self.lock().get(key).cloned(),
+        }
+    }
+
+    /// All rows as seen in `transaction`
+    pub fn rows_tr(&self, transaction: &InMemoryTransaction) -> BTreeMap<K, V> {
+        let mut rows = self.lock().clone();
+        if let Some(changes) = self.changes_ref(transaction) {
+            for (key, value) in &changes.rows {
+                match value {
+                    Some(value) => rows.insert(key.clone(), value.clone()),
+                    None => rows.remove(key),
+                };
+            }
+        }
+        rows
+    }
+
+    /// Returns the previous value, as seen in `transaction`
+    pub fn insert_tr(&self, transaction: &mut InMemoryTransaction, key: K, value: V) -> Option<V> {
+        let prev = self.get_tr(transaction, &key);
+        self.changes(transaction).rows.insert(key, Some(value));
+        prev
+    }
+
+    /// Returns the removed value, as seen in `transaction`
+    pub fn remove_tr(&self, transaction: &mut InMemoryTransaction, key: K) -> Option<V> {
+        let prev = self.get_tr(transaction, &key);
+        self.changes(transaction).rows.insert(key, None);
+        prev
+    }
 }
 
 impl<'a> dyno::Tag<'a> for InMemoryTransaction<'static> {
@@ -57,16 +256,15 @@ impl<'a> dyno::Tag<'a> for InMemoryTransaction<'static> {
 }
 
 impl<'a> Transaction<'a> for InMemoryTransaction<'a> {
-    fn commit(self: Box<Self>) -> Result<()> {
-        Ok(())
+    fn commit(mut self: Box<Self>) -> Result<()> {
+        std::mem::take(&mut self.pending)
+            .into_iter()
+            .map(|(_, changes, commit)| commit(changes))
+            .fold(Ok(()), Result::and)
     }
 
-    // TODO: simulating rollbacks in a general way is not trivial
-    // and it would require all the `InMemory*` stores implementations
-    // to register previous value when creating the transaction or
-    // something like this.
     fn rollback(self: Box<Self>) -> Result<()> {
-        bail!("Not supported")
+        Ok(())
     }
 
     fn cast<'caster>(&'caster mut self) -> Caster<'caster, 'a>
@@ -76,3 +274,38 @@ impl<'a> Transaction<'a> for InMemoryTransaction<'a> {
         Caster::new::<InMemoryTransaction<'static>>(self)
     }
 }
+
+#[async_trait]
+impl<'a> AsyncTransaction<'a> for InMemoryTransaction<'a> {
+    async fn commit(self: Box<Self>) -> Result<()> {
+        Transaction::commit(self)
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<()> {
+        Transaction::rollback(self)
+    }
+
+    fn cast<'caster>(&'caster mut self) -> Caster<'caster, 'a>
+    where
+        'a: 'caster,
+    {
+        Caster::new::<InMemoryTransaction<'static>>(self)
+    }
+
+    async fn run_blocking(&mut self, f: BlockingTransactionFn) -> Result<()> {
+        // lend the lock and the changes to a blocking task, and take them back after
+        let mut lent = InMemoryTransaction::<'static> {
+            lock_guard: self.lock_guard.take(),
+            pending: std::mem::take(&mut self.pending),
+            _connection: PhantomData,
+        };
+        let lent = tokio::task::spawn_blocking(move || {
+            f(&mut lent);
+            lent
+        })
+        .await?;
+        self.lock_guard = lent.lock_guard;
+        self.pending = lent.pending;
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! Versioned schema migrations
+//!
+//! Each repository that needs a database schema has an ordered list of
+//! [`Migration`]s under its own component name ([`ComponentMigrations`]).
+//! At startup, the binary passes the migrations of all the repositories it
+//! uses to the `migrate` of their persistence, which applies the ones
+//! that are not applied yet and records applied versions, so the schema
+//! of every component can evolve independently.
+use super::*;
+use std::collections::BTreeMap;
+
+/// A single schema change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    /// Schema version after applying this migration, starting at 1
+    pub version: u32,
+    pub sql: &'static str,
+}
+
+/// Migrations of a single component, eg. a repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentMigrations {
+    pub component: &'static str,
+    pub migrations: &'static [Migration],
+}
+
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error(
+        "schema of `{component}` is at version {current}, newer than the supported {supported}"
+    )]
+    SchemaTooNew {
+        component: String,
+        current: u32,
+        supported: u32,
+    },
+    #[error("migrations of `{component}` are not numbered consecutively from 1")]
+    InvalidVersions { component: String },
+    #[error("conflicting migrations of `{component}`")]
+    Conflicting { component: String },
+    #[error("schema has migrations of `{component}`, which this binary doesn't know")]
+    UnknownComponent { component: String },
+}
+
+/// Migrations of all the components using a persistence
+#[derive(Debug, Default, Clone)]
+pub struct MigrationRegistry(BTreeMap<&'static str, &'static [Migration]>);
+
+impl MigrationRegistry {
+    pub fn new(components: &[ComponentMigrations]) -> Result<Self, MigrationError> {
+        let mut registry = Self::default();
+        for component in components {
+            registry.register(*component)?;
+        }
+        Ok(registry)
+    }
+
+    /// Register migrations of a component
+    ///
+    /// Registering the same migrations again is a no-op, but
+    /// different ones under the same component are an error.
+    pub fn register(&mut self, component: ComponentMigrations) -> Result<(), MigrationError> {
+        match self.0.insert(component.component, component.migrations) {
+            Some(existing) if existing != component.migrations => {
+                Err(MigrationError::Conflicting {
+                    component: component.component.to_owned(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = (&'static str, &'static [Migration])> + '_ {
+        self.0.iter().map(|(k, v)| (*k, *v))
+    }
+
+    /// Fail if any of the components migrations were `applied` to is not registered
+    ///
+    /// Their schema might be newer than anything this binary supports,
+    /// eg. after a downgrade, and nothing would notice.
+    pub fn check_known(&self, applied: &[String]) -> Result<(), MigrationError> {
+        match applied
+            .iter()
+            .find(|component| !self.0.contains_key(component.as_str()))
+        {
+            Some(component) => Err(MigrationError::UnknownComponent {
+                component: component.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What [`migrate`] needs from a transaction of a database
+pub trait MigrationTransaction {
+    /// Create the `schema_migrations` table, if needed, and lock out other migrations
+    fn init(&mut self) -> Result<()>;
+    /// Components that have any migrations applied
+    fn applied_components(&mut self) -> Result<Vec<String>>;
+    /// Version of `component`, `0` if it has no migrations applied
+    fn current_version(&mut self, component: &str) -> Result<u32>;
+    /// Run `migration` and record it as applied
+    fn apply(&mut self, component: &str, migration: &Migration) -> Result<()>;
+}
+
+/// Apply all the migrations of `components` that were not applied yet in `transaction`
+///
+/// Fails if the schema of any component is newer than what its
+/// migrations support, or if it has any other components, eg.
+/// after a downgrade.
+pub fn migrate(
+    transaction: &mut impl MigrationTransaction,
+    components: &[ComponentMigrations],
+) -> Result<()> {
+    let registry = MigrationRegistry::new(components)?;
+    transaction.init()?;
+    registry.check_known(&transaction.applied_components()?)?;
+
+    for (component, migrations) in registry.components() {
+        let current = transaction.current_version(component)?;
+        for migration in pending_migrations(component, migrations, current)? {
+            tracing::info!(component, version = migration.version, "applying migration");
+            transaction.apply(component, migration)?;
+        }
+    }
+    Ok(())
+}
+
+/// Select `migrations` that need to be applied to a schema at version `current`
+///
+/// `current` is `0` for a component that has no migrations applied yet.
+pub fn pending_migrations(
+    component: &str,
+    migrations: &'static [Migration],
+    current: u32,
+) -> Result<&'static [Migration], MigrationError> {
+    if migrations
+        .iter()
+        .enumerate()
+        .any(|(i, m)| usize::try_from(m.version).ok() != Some(i + 1))
+    {
+        return Err(MigrationError::InvalidVersions {
+            component: component.to_owned(),
+        });
+    }
+
+    let supported = migrations.last().map(|m| m.version).unwrap_or(0);
+    if supported < current {
+        return Err(MigrationError::SchemaTooNew {
+            component: component.to_owned(),
+            current,
+            supported,
+        });
+    }
+
+    Ok(&migrations[usize::try_from(current).expect("no fail")..])
+}
@@ -1,10 +1,79 @@
-use super::*;
+use super::{migration::*, *};
 
 #[derive(Debug, Clone)]
 pub struct PostgresPersistence {
     pool: r2d2::Pool<r2d2_postgres::PostgresConnectionManager<r2d2_postgres::postgres::NoTls>>,
 }
 
+impl PostgresPersistence {
+    /// Create a new connection pool using a libpq-style `config` string
+    ///
+    /// Eg. `host=localhost user=postgres dbname=sniper`
+    pub fn new(config: &str) -> Result<Self> {
+        let manager = r2d2_postgres::PostgresConnectionManager::new(
+            config.parse()?,
+            r2d2_postgres::postgres::NoTls,
+        );
+
+        Ok(Self {
+            pool: r2d2::Pool::new(manager)?,
+        })
+    }
+
+    /// Apply all the migrations of `components` that were not applied yet
+    ///
+    /// See [`migration::migrate`].
+    pub fn migrate(&self, components: &[ComponentMigrations]) -> Result<()> {
+        let mut client = self.pool.get()?;
+        let mut transaction = client.transaction()?;
+        migration::migrate(&mut transaction, components)?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+impl<'a> MigrationTransaction for ::postgres::Transaction<'a> {
+    fn init(&mut self) -> Result<()> {
+        self.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                component TEXT NOT NULL,
+                version INTEGER NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                PRIMARY KEY (component, version)
+            );
+            LOCK TABLE schema_migrations IN EXCLUSIVE MODE",
+        )?;
+        Ok(())
+    }
+
+    fn applied_components(&mut self) -> Result<Vec<String>> {
+        Ok(self
+            .query("SELECT DISTINCT component FROM schema_migrations", &[])?
+            .iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    fn current_version(&mut self, component: &str) -> Result<u32> {
+        let current: i32 = self
+            .query_one(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_migrations WHERE component = $1",
+                &[&component],
+            )?
+            .get(0);
+        Ok(u32::try_from(current)?)
+    }
+
+    fn apply(&mut self, component: &str, migration: &Migration) -> Result<()> {
+        self.batch_execute(migration.sql)?;
+        self.execute(
+            "INSERT INTO schema_migrations (component, version) VALUES ($1, $2)",
+            &[&component, &i32::try_from(migration.version)?],
+        )?;
+        Ok(())
+    }
+}
+
 impl Persistence for PostgresPersistence {
     fn get_connection(&self) -> Result<Box<dyn Connection>> {
         Ok(Box::new(PostgresConnection(self.pool.get()?)))
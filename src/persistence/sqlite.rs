@@ -0,0 +1,195 @@
+use super::{migration::*, *};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::TransactionBehavior;
+use std::{
+    path::Path,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Convert `timestamp` to microseconds since the unix epoch,
+/// which is how all the timestamps are stored
+pub fn timestamp_to_sql(timestamp: SystemTime) -> Result<i64> {
+    Ok(i64::try_from(
+        timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_micros(),
+    )?)
+}
+
+pub fn timestamp_from_sql(micros: i64) -> Result<SystemTime> {
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_micros(u64::try_from(micros)?))
+}
+
+/// Wakes up anyone waiting for a [`SqliteTransaction`] to commit
+///
+/// SQLite has no notification mechanism of its own, but since it is
+/// an in-process database, all the writers are in the same process.
+#[derive(Debug, Default)]
+pub struct CommitNotifier {
+    generation: Mutex<u64>,
+    condvar: Condvar,
+}
+
+impl CommitNotifier {
+    /// Get the current generation, to wait for the next commit after it
+    pub fn generation(&self) -> u64 {
+        *self.generation.lock().expect("lock")
+    }
+
+    fn notify(&self) {
+        *self.generation.lock().expect("lock") += 1;
+        self.condvar.notify_all();
+    }
+
+    /// Wait until any transaction commits after `generation` or `deadline` passes
+    pub fn wait(&self, generation: u64, deadline: Option<Instant>) {
+        let mut lock = self.generation.lock().expect("lock");
+        while *lock == generation {
+            lock = if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if deadline <= now {
+                    return;
+                }
+                self.condvar
+                    .wait_timeout(lock, deadline - now)
+                    .expect("lock")
+                    .0
+            } else {
+                self.condvar.wait(lock).expect("lock")
+            };
+        }
+    }
+}
+
+/// Persistence in a single SQLite database file
+#[derive(Debug, Clone)]
+pub struct SqlitePersistence {
+    pool: r2d2::Pool<SqliteConnectionManager>,
+    notifier: Arc<CommitNotifier>,
+}
+
+impl SqlitePersistence {
+    /// Open (or create) a database at `path`
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+            conn.busy_timeout(Duration::from_secs(10))?;
+            conn.execute_batch("PRAGMA journal_mode = WAL;")
+        });
+
+        Ok(Self {
+            pool: r2d2::Pool::new(manager)?,
+            notifier: Arc::new(CommitNotifier::default()),
+        })
+    }
+
+    /// Apply all the migrations of `components` that were not applied yet
+    ///
+    /// See [`migration::migrate`].
+    pub fn migrate(&self, components: &[ComponentMigrations]) -> Result<()> {
+        let mut conn = self.pool.get()?;
+        let mut transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        migration::migrate(&mut transaction, components)?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+impl<'a> MigrationTransaction for rusqlite::Transaction<'a> {
+    fn init(&mut self) -> Result<()> {
+        // already locked, by being an immediate transaction
+        self.execute_batch(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                component TEXT NOT NULL,
+                version INTEGER NOT NULL,
+                applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (component, version)
+            )",
+        )?;
+        Ok(())
+    }
+
+    fn applied_components(&mut self) -> Result<Vec<String>> {
+        Ok(self
+            .prepare("SELECT DISTINCT component FROM schema_migrations")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn current_version(&mut self, component: &str) -> Result<u32> {
+        Ok(self.query_row(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_migrations WHERE component = ?1",
+            [component],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn apply(&mut self, component: &str, migration: &Migration) -> Result<()> {
+        self.execute_batch(migration.sql)?;
+        self.execute(
+            "INSERT INTO schema_migrations (component, version) VALUES (?1, ?2)",
+            rusqlite::params![component, migration.version],
+        )?;
+        Ok(())
+    }
+}
+
+impl Persistence for SqlitePersistence {
+    fn get_connection(&self) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(SqliteConnection(
+            self.pool.get()?,
+            self.notifier.clone(),
+        )))
+    }
+}
+
+pub struct SqliteConnection(
+    pub r2d2::PooledConnection<SqliteConnectionManager>,
+    pub Arc<CommitNotifier>,
+);
+
+impl<'a> dyno::Tag<'a> for SqliteConnection {
+    type Type = SqliteConnection;
+}
+
+impl Connection for SqliteConnection {
+    fn start_transaction<'a>(&'a mut self) -> Result<Box<dyn Transaction<'a> + 'a>> {
+        // Take the write lock upfront, so concurrent transactions wait for each other
+        // instead of failing when trying to upgrade a read lock.
+        Ok(Box::new(SqliteTransaction(
+            self.0
+                .transaction_with_behavior(TransactionBehavior::Immediate)?,
+            self.1.clone(),
+        )))
+    }
+
+    fn cast(&mut self) -> Caster<'_, 'static> {
+        Caster::new::<SqliteConnection>(self)
+    }
+}
+
+pub struct SqliteTransaction<'a>(pub rusqlite::Transaction<'a>, Arc<CommitNotifier>);
+
+impl<'a> dyno::Tag<'a> for SqliteTransaction<'static> {
+    type Type = SqliteTransaction<'a>;
+}
+
+impl<'a> Transaction<'a> for SqliteTransaction<'a> {
+    fn commit(self: Box<Self>) -> Result<()> {
+        let Self(transaction, notifier) = *self;
+        transaction.commit()?;
+        notifier.notify();
+        Ok(())
+    }
+
+    fn rollback(self: Box<Self>) -> Result<()> {
+        Ok(self.0.rollback()?)
+    }
+
+    fn cast<'caster>(&'caster mut self) -> Caster<'caster, 'a>
+    where
+        'a: 'caster,
+    {
+        Caster::new::<SqliteTransaction<'static>>(self)
+    }
+}
@@ -1,13 +1,14 @@
 mod in_memory;
+mod postgres;
+mod sqlite;
 
-pub use self::in_memory::*;
+pub use self::{in_memory::*, postgres::*, sqlite::*};
 
 use crate::{
     event_log::Offset,
     persistence::{Connection, Transaction},
     service::{ServiceId, ServiceIdRef},
 };
-use anyhow::format_err;
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -23,6 +24,19 @@ pub trait ProgressTracker {
         offset: Offset,
     ) -> Result<()>;
     fn load_tr(&self, conn: &mut dyn Transaction<'_>, id: ServiceIdRef) -> Result<Option<Offset>>;
+
+    /// Last cursor stored for `id`, in a feed of events outside of the event log
+    ///
+    /// Cursors are opaque, like the ones of an HTTP auction house, so they
+    /// are kept apart from the offsets.
+    fn load_cursor(&self, conn: &mut dyn Connection, id: ServiceIdRef) -> Result<Option<String>>;
+
+    fn store_cursor_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        id: ServiceIdRef,
+        cursor: &str,
+    ) -> Result<()>;
 }
 
 pub type SharedProgressTracker = Arc<dyn ProgressTracker + Send + Sync + 'static>;
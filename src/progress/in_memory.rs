@@ -1,20 +1,22 @@
-use crate::persistence::{InMemoryConnection, InMemoryTransaction};
+use crate::persistence::{InMemoryConnection, InMemoryTable, InMemoryTransaction};
 use anyhow::Result;
 use std::{
     collections::BTreeMap,
-    sync::{Arc, Mutex, MutexGuard},
+    sync::{Arc, MutexGuard},
 };
 
 use super::*;
 
 pub struct InMemoryProgressTracker {
-    store: Mutex<BTreeMap<ServiceId, Offset>>,
+    store: InMemoryTable<ServiceId, Offset>,
+    cursors: InMemoryTable<ServiceId, String>,
 }
 
 impl InMemoryProgressTracker {
     pub fn new() -> Self {
         Self {
-            store: Mutex::new(BTreeMap::default()),
+            store: InMemoryTable::default(),
+            cursors: InMemoryTable::default(),
         }
     }
 
@@ -22,10 +24,9 @@ impl InMemoryProgressTracker {
         Arc::new(Self::new())
     }
 
+    /// Committed progress of all services
     pub fn lock(&self) -> Result<MutexGuard<'_, BTreeMap<ServiceId, Offset>>> {
-        self.store
-            .lock()
-            .map_err(|_e| format_err!("mutex poisoned"))
+        Ok(self.store.lock())
     }
 }
 
@@ -41,14 +42,35 @@ impl ProgressTracker for InMemoryProgressTracker {
         id: ServiceIdRef,
         event_id: Offset,
     ) -> Result<()> {
-        conn.cast().as_mut::<InMemoryTransaction>()?;
+        let mut caster = conn.cast();
+        let transaction = caster.as_mut::<InMemoryTransaction>()?;
 
-        self.lock()?.insert(id.to_owned(), event_id.to_owned());
+        self.store.insert_tr(transaction, id.to_owned(), event_id);
         Ok(())
     }
 
     fn load_tr<'a>(&self, conn: &mut dyn Transaction, id: ServiceIdRef) -> Result<Option<Offset>> {
-        conn.cast().as_mut::<InMemoryTransaction>()?;
-        Ok(self.lock()?.get(id).cloned())
+        let mut caster = conn.cast();
+        let transaction = caster.as_mut::<InMemoryTransaction>()?;
+        Ok(self.store.get_tr(transaction, id))
+    }
+
+    fn load_cursor(&self, conn: &mut dyn Connection, id: ServiceIdRef) -> Result<Option<String>> {
+        conn.cast().as_mut::<InMemoryConnection>()?;
+        Ok(self.cursors.lock().get(id).cloned())
+    }
+
+    fn store_cursor_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        id: ServiceIdRef,
+        cursor: &str,
+    ) -> Result<()> {
+        let mut caster = conn.cast();
+        let transaction = caster.as_mut::<InMemoryTransaction>()?;
+
+        self.cursors
+            .insert_tr(transaction, id.to_owned(), cursor.to_owned());
+        Ok(())
     }
 }
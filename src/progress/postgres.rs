@@ -0,0 +1,114 @@
+use crate::persistence::{
+    migration::{ComponentMigrations, Migration},
+    PostgresConnection, PostgresTransaction,
+};
+use anyhow::Result;
+use std::{convert::TryFrom, sync::Arc};
+
+use super::*;
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        sql: "CREATE TABLE IF NOT EXISTS log_progress (
+            service_id TEXT PRIMARY KEY,
+            log_offset BIGINT NOT NULL
+        )",
+    },
+    Migration {
+        version: 2,
+        sql: "CREATE TABLE IF NOT EXISTS feed_cursor (
+            service_id TEXT PRIMARY KEY,
+            cursor TEXT NOT NULL
+        )",
+    },
+];
+
+/// [`ProgressTracker`] keeping offsets in a Postgres table
+///
+/// Storing the progress in the same [`PostgresTransaction`] as the effects
+/// of handling an event makes each event handled exactly once.
+#[derive(Debug, Clone)]
+pub struct PostgresProgressTracker;
+
+impl PostgresProgressTracker {
+    pub const MIGRATIONS: ComponentMigrations = ComponentMigrations {
+        component: "log_progress",
+        migrations: MIGRATIONS,
+    };
+
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn new_shared() -> SharedProgressTracker {
+        Arc::new(Self::new())
+    }
+}
+
+fn query_offset(
+    client: &mut impl ::postgres::GenericClient,
+    id: ServiceIdRef,
+) -> Result<Option<Offset>> {
+    client
+        .query_opt(
+            "SELECT log_offset FROM log_progress WHERE service_id = $1",
+            &[&id],
+        )?
+        .map(|row| Ok(u64::try_from(row.get::<'_, _, i64>("log_offset"))?))
+        .transpose()
+}
+
+fn query_cursor(
+    client: &mut impl ::postgres::GenericClient,
+    id: ServiceIdRef,
+) -> Result<Option<String>> {
+    Ok(client
+        .query_opt(
+            "SELECT cursor FROM feed_cursor WHERE service_id = $1",
+            &[&id],
+        )?
+        .map(|row| row.get("cursor")))
+}
+
+impl ProgressTracker for PostgresProgressTracker {
+    fn load(&self, conn: &mut dyn Connection, id: ServiceIdRef) -> Result<Option<Offset>> {
+        query_offset(&mut *conn.cast().as_mut::<PostgresConnection>()?.0, id)
+    }
+
+    fn store_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<()> {
+        conn.cast().as_mut::<PostgresTransaction>()?.0.execute(
+            "INSERT INTO log_progress (service_id, log_offset) VALUES ($1, $2)
+            ON CONFLICT (service_id) DO UPDATE SET log_offset = EXCLUDED.log_offset",
+            &[&id, &i64::try_from(offset)?],
+        )?;
+        Ok(())
+    }
+
+    fn load_tr(&self, conn: &mut dyn Transaction<'_>, id: ServiceIdRef) -> Result<Option<Offset>> {
+        query_offset(&mut conn.cast().as_mut::<PostgresTransaction>()?.0, id)
+    }
+
+    fn load_cursor(&self, conn: &mut dyn Connection, id: ServiceIdRef) -> Result<Option<String>> {
+        query_cursor(&mut *conn.cast().as_mut::<PostgresConnection>()?.0, id)
+    }
+
+    fn store_cursor_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        id: ServiceIdRef,
+        cursor: &str,
+    ) -> Result<()> {
+        conn.cast().as_mut::<PostgresTransaction>()?.0.execute(
+            "INSERT INTO feed_cursor (service_id, cursor) VALUES ($1, $2)
+            ON CONFLICT (service_id) DO UPDATE SET cursor = EXCLUDED.cursor",
+            &[&id, &cursor],
+        )?;
+        Ok(())
+    }
+}
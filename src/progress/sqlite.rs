@@ -0,0 +1,108 @@
+use crate::persistence::{
+    migration::{ComponentMigrations, Migration},
+    SqliteConnection, SqliteTransaction,
+};
+use anyhow::Result;
+use rusqlite::OptionalExtension;
+use std::{convert::TryFrom, sync::Arc};
+
+use super::*;
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        sql: "CREATE TABLE IF NOT EXISTS log_progress (
+            service_id TEXT PRIMARY KEY,
+            log_offset INTEGER NOT NULL
+        )",
+    },
+    Migration {
+        version: 2,
+        sql: "CREATE TABLE IF NOT EXISTS feed_cursor (
+            service_id TEXT PRIMARY KEY,
+            cursor TEXT NOT NULL
+        )",
+    },
+];
+
+/// [`ProgressTracker`] keeping offsets in a SQLite table
+#[derive(Debug, Clone)]
+pub struct SqliteProgressTracker;
+
+impl SqliteProgressTracker {
+    pub const MIGRATIONS: ComponentMigrations = ComponentMigrations {
+        component: "log_progress",
+        migrations: MIGRATIONS,
+    };
+
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn new_shared() -> SharedProgressTracker {
+        Arc::new(Self::new())
+    }
+}
+
+fn query_offset(conn: &rusqlite::Connection, id: ServiceIdRef) -> Result<Option<Offset>> {
+    conn.query_row(
+        "SELECT log_offset FROM log_progress WHERE service_id = ?1",
+        [id],
+        |row| row.get::<_, i64>(0),
+    )
+    .optional()?
+    .map(|offset| Ok(u64::try_from(offset)?))
+    .transpose()
+}
+
+fn query_cursor(conn: &rusqlite::Connection, id: ServiceIdRef) -> Result<Option<String>> {
+    Ok(conn
+        .query_row(
+            "SELECT cursor FROM feed_cursor WHERE service_id = ?1",
+            [id],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+impl ProgressTracker for SqliteProgressTracker {
+    fn load(&self, conn: &mut dyn Connection, id: ServiceIdRef) -> Result<Option<Offset>> {
+        query_offset(&conn.cast().as_mut::<SqliteConnection>()?.0, id)
+    }
+
+    fn store_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        id: ServiceIdRef,
+        offset: Offset,
+    ) -> Result<()> {
+        conn.cast().as_mut::<SqliteTransaction>()?.0.execute(
+            "INSERT INTO log_progress (service_id, log_offset) VALUES (?1, ?2)
+            ON CONFLICT (service_id) DO UPDATE SET log_offset = excluded.log_offset",
+            rusqlite::params![id, i64::try_from(offset)?],
+        )?;
+        Ok(())
+    }
+
+    fn load_tr(&self, conn: &mut dyn Transaction<'_>, id: ServiceIdRef) -> Result<Option<Offset>> {
+        query_offset(&conn.cast().as_mut::<SqliteTransaction>()?.0, id)
+    }
+
+    fn load_cursor(&self, conn: &mut dyn Connection, id: ServiceIdRef) -> Result<Option<String>> {
+        query_cursor(&conn.cast().as_mut::<SqliteConnection>()?.0, id)
+    }
+
+    fn store_cursor_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        id: ServiceIdRef,
+        cursor: &str,
+    ) -> Result<()> {
+        conn.cast().as_mut::<SqliteTransaction>()?.0.execute(
+            "INSERT INTO feed_cursor (service_id, cursor) VALUES (?1, ?2)
+            ON CONFLICT (service_id) DO UPDATE SET cursor = excluded.cursor",
+            rusqlite::params![id, cursor],
+        )?;
+        Ok(())
+    }
+}
@@ -1,11 +1,15 @@
+pub mod asynchronous;
 pub mod auction_house;
 pub mod bidding_engine;
+pub mod timer;
 pub mod ui;
 
-pub use self::{auction_house::*, bidding_engine::*, ui::*};
+pub use self::{asynchronous::*, auction_house::*, bidding_engine::*, timer::*, ui::*};
 use crate::{
-    event::Event,
-    event_log::{self, WithOffset},
+    clock::{SharedClock, SystemClock},
+    dead_letter::{DeadLetter, SharedDeadLetterStore},
+    event::{topics, Subscription},
+    event_log::{self, CausedTransaction, LogEvent, Offset, WithMetadata, WithOffset},
     persistence::{Persistence, SharedPersistence, Transaction},
     progress,
 };
@@ -16,21 +20,473 @@ use std::{
         Arc,
     },
     thread,
+    time::{Duration, Instant, SystemTime},
 };
+use tracing::{debug, error, info, warn};
+
+/// Decode `event` as `E`, warning if it's on a topic no event type is known for
+///
+/// Events on other known topics are skipped quietly, as the service just
+/// doesn't subscribe to them, but an unknown topic is likely a bug, or an
+/// event from a newer version, so it shouldn't go unnoticed.
+pub fn decode_subscribed<E: Subscription>(event: &LogEvent) -> Result<Option<E>> {
+    let decoded = E::decode(&event.details)?;
+    if decoded.is_none() && !topics::KNOWN_TOPICS.contains(&event.details.topic.as_str()) {
+        warn!(
+            offset = event.offset,
+            topic = event.details.topic,
+            "skipping event on an unknown topic"
+        );
+    }
+    Ok(decoded)
+}
 
 pub type ServiceId = String;
 pub type ServiceIdRef<'a> = &'a str;
 
+/// What to do when a service returns an error
+///
+/// A service is restarted by just calling it again: the failed
+/// iteration of a [`LoopService`], or the transaction handling a batch of
+/// a [`LogFollowerService`]. Once the policy gives up, the error is
+/// escalated and all the services are stopped. Panics are always escalated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestartPolicy {
+    /// Escalate the first error
+    #[default]
+    Never,
+    /// Restart after every error, after a fixed `delay`
+    #[allow(dead_code)] // all the services back off so far
+    Always { delay: Duration },
+    /// Restart after a delay that doubles with every failure in a row,
+    /// escalating after `max_retries` consecutive failed restarts
+    ExponentialBackoff {
+        initial_delay: Duration,
+        max_delay: Duration,
+        max_retries: u32,
+    },
+}
+
+impl RestartPolicy {
+    /// Delay before restarting a service that failed `failures` times in a row
+    /// already, or `None` if it should not be restarted anymore
+    pub fn restart_delay(self, failures: u32) -> Option<Duration> {
+        match self {
+            RestartPolicy::Never => None,
+            RestartPolicy::Always { delay } => Some(delay),
+            RestartPolicy::ExponentialBackoff {
+                initial_delay,
+                max_delay,
+                max_retries,
+            } => (failures < max_retries).then(|| {
+                2u32.checked_pow(failures)
+                    .and_then(|factor| initial_delay.checked_mul(factor))
+                    .map_or(max_delay, |delay| delay.min(max_delay))
+            }),
+        }
+    }
+}
+
+/// Whether handling an event failed for reasons that might go away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Eg. a database or network error: try again, according
+    /// to the [`RestartPolicy`] of the service
+    Retryable,
+    /// Something wrong with the event itself: after a few attempts, move
+    /// it to the dead letters (see [`crate::dead_letter`]) and go on
+    Poison,
+}
+
+/// An error handling a batch of events, classified by the service
+struct HandleError {
+    class: ErrorClass,
+    error: anyhow::Error,
+}
+
+/// How many log events a [`LogFollowerService`] handles at once
+///
+/// All events of a batch are handled in a single transaction, which
+/// also records the progress of the service just once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// Maximum number of events in a batch
+    pub max_size: usize,
+    /// How long to wait for more events to fill up a batch,
+    /// after receiving the first one of it
+    pub max_latency: Duration,
+}
+
+impl Default for BatchConfig {
+    /// One event at the time
+    fn default() -> Self {
+        Self {
+            max_size: 1,
+            max_latency: Duration::ZERO,
+        }
+    }
+}
+
+/// How often an event loop looks for dead letters to re-drive
+const REDRIVE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Delays between the attempts to handle a poison event
+const POISON_RETRY_BACKOFF: RestartPolicy = RestartPolicy::ExponentialBackoff {
+    initial_delay: Duration::from_millis(100),
+    max_delay: Duration::from_secs(10),
+    max_retries: u32::MAX,
+};
+
+/// Decisions of an event loop of a log follower, without any IO
+///
+/// Shared by the sync event loop (see [`ServiceControl::spawn_log_follower`])
+/// and the async one, which only read, handle and store what it asks them to.
+struct EventLoopState {
+    service_id: ServiceId,
+    batch_config: BatchConfig,
+    poison_event_max_attempts: u32,
+    clock: SharedClock,
+    /// Offset of the next event to handle
+    progress: Offset,
+    /// After a poison error in a batch, handle events one by one up to this offset,
+    /// to find out which one is the poison
+    isolate_until: Option<Offset>,
+    /// Offset of the last poison event, and attempts to handle it so far
+    poison_attempts: (Offset, u32),
+    /// Don't try the poison event again before this
+    retry_at: Option<SystemTime>,
+    /// Don't look for dead letters to re-drive before this
+    next_redrive_check: Instant,
+}
+
+impl EventLoopState {
+    fn new(
+        service_id: ServiceId,
+        batch_config: BatchConfig,
+        poison_event_max_attempts: u32,
+        clock: SharedClock,
+        progress: Offset,
+    ) -> Self {
+        Self {
+            service_id,
+            batch_config,
+            poison_event_max_attempts,
+            clock,
+            progress,
+            isolate_until: None,
+            poison_attempts: (progress, 0),
+            retry_at: None,
+            next_redrive_check: Instant::now(),
+        }
+    }
+
+    /// Whether to look for dead letters to re-drive now
+    ///
+    /// A re-driven event is handled after the ones that followed it,
+    /// see [`crate::dead_letter`].
+    fn redrive_due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_redrive_check {
+            return false;
+        }
+        self.next_redrive_check = now + REDRIVE_POLL_INTERVAL;
+        true
+    }
+
+    /// The re-driven dead letter at `offset` got handled, to be removed
+    fn redrive_handled(&mut self, offset: Offset) {
+        info!(service = %self.service_id, offset, "re-driven dead letter handled");
+        // there might be more
+        self.next_redrive_check = Instant::now();
+    }
+
+    /// Handling the re-driven `dead_letter` failed, returns it updated to be stored
+    fn redrive_failed(&mut self, dead_letter: DeadLetter, error: anyhow::Error) -> DeadLetter {
+        warn!(service = %self.service_id, offset = dead_letter.event.offset, %error, "re-driven dead letter failed again");
+        self.next_redrive_check = Instant::now();
+        DeadLetter {
+            error: format!("{error:#}"),
+            attempts: dead_letter.attempts + 1,
+            failed_at: self.clock.now(),
+            redrive_requested: false,
+            ..dead_letter
+        }
+    }
+
+    /// How long to wait before trying the poison event again, if at all
+    fn retry_delay(&self) -> Option<Duration> {
+        self.retry_at
+            .and_then(|retry_at| retry_at.duration_since(self.clock.now()).ok())
+            .filter(|delay| !delay.is_zero())
+    }
+
+    /// Maximum size of the next batch
+    fn batch_size(&self) -> usize {
+        if self
+            .isolate_until
+            .map_or(false, |until| self.progress < until)
+        {
+            1
+        } else {
+            self.batch_config.max_size.max(1)
+        }
+    }
+
+    /// Until when to wait for more events to fill up a batch, once it got the first one
+    fn batch_deadline(&self) -> Instant {
+        Instant::now() + self.batch_config.max_latency
+    }
+
+    /// A batch of events up to `new_offset` was handled (or dead-lettered)
+    fn handled(&mut self, new_offset: Offset) {
+        self.progress = new_offset;
+    }
+
+    /// Handling `events` up to `new_offset` failed with a poison `error`
+    ///
+    /// Returns the dead letter to store, along with the progress past it,
+    /// if it's time to give up on the event; otherwise the next batch retries.
+    fn poisoned(
+        &mut self,
+        mut events: Vec<LogEvent>,
+        new_offset: Offset,
+        error: anyhow::Error,
+    ) -> Option<(DeadLetter, Offset)> {
+        if 1 < events.len() {
+            debug!(service = %self.service_id, %error, "poison event in a batch, isolating it");
+            self.isolate_until = Some(new_offset);
+            return None;
+        }
+        let event = events.pop().expect("not empty");
+
+        let attempts = if self.poison_attempts.0 == event.offset {
+            self.poison_attempts.1 + 1
+        } else {
+            1
+        };
+        self.poison_attempts = (event.offset, attempts);
+        if attempts < self.poison_event_max_attempts {
+            warn!(service = %self.service_id, offset = event.offset, attempts, %error, "poison event, retrying");
+            self.retry_at = POISON_RETRY_BACKOFF
+                .restart_delay(attempts - 1)
+                .map(|delay| self.clock.now() + delay);
+            return None;
+        }
+
+        error!(service = %self.service_id, offset = event.offset, attempts, %error, "poison event, moving to dead letters");
+        Some((
+            DeadLetter {
+                service_id: self.service_id.clone(),
+                event,
+                error: format!("{error:#}"),
+                attempts,
+                failed_at: self.clock.now(),
+                redrive_requested: false,
+            },
+            new_offset,
+        ))
+    }
+}
+
+/// One of the partitions of a [`LogFollowerService`] spawned with
+/// [`ServiceControl::spawn_partitioned_log_follower`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Partition {
+    /// Id the progress (and dead letters) of this partition are kept under
+    ///
+    /// Doesn't depend on the number of partitions, so when it changes, they
+    /// have to be moved (see [`ServiceControl::spawn_partitioned_log_follower`]).
+    pub fn progress_id(self, service_id: ServiceIdRef) -> ServiceId {
+        partition_id(service_id, self.index)
+    }
+
+    /// Whether events with a partition `key` belong to this partition
+    ///
+    /// Events without a key go to every partition.
+    pub fn contains(self, key: Option<&str>) -> bool {
+        key.map_or(true, |key| partition_index(key, self.count) == self.index)
+    }
+}
+
+/// Id the progress (and dead letters) of the partition `index` of `service_id` are kept under
+fn partition_id(service_id: ServiceIdRef, index: usize) -> ServiceId {
+    format!("{service_id}/{index}")
+}
+
+/// Id the number of partitions of `service_id` is kept under, in the progress store
+fn partition_count_id(service_id: ServiceIdRef) -> ServiceId {
+    format!("{service_id}/partitions")
+}
+
+/// Progress of the partitions of a service before their number last changed
+///
+/// The new partitions all start from the lowest of them, and skip the events
+/// the old partition of their key had already handled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct PartitionHandover {
+    /// Progress of each of the old partitions (just one, if the service wasn't partitioned)
+    progress: Vec<Offset>,
+}
+
+impl PartitionHandover {
+    /// Id the number of the old partitions is kept under, in the progress store
+    fn count_id(service_id: ServiceIdRef) -> ServiceId {
+        format!("{service_id}/handover")
+    }
+
+    /// Id the progress of the old partition `index` is kept under, in the progress store
+    fn progress_id(service_id: ServiceIdRef, index: usize) -> ServiceId {
+        format!("{service_id}/handover/{index}")
+    }
+
+    fn load_tr(
+        progress_store: &dyn progress::ProgressTracker,
+        conn: &mut dyn Transaction<'_>,
+        service_id: ServiceIdRef,
+    ) -> Result<Self> {
+        let count = progress_store
+            .load_tr(conn, &Self::count_id(service_id))?
+            .unwrap_or(0);
+        let progress = (0..usize::try_from(count)?)
+            .map(|index| {
+                progress_store
+                    .load_tr(conn, &Self::progress_id(service_id, index))?
+                    .ok_or_else(|| format_err!("missing handover progress of partition {index}"))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { progress })
+    }
+
+    fn store_tr(
+        &self,
+        progress_store: &dyn progress::ProgressTracker,
+        conn: &mut dyn Transaction<'_>,
+        service_id: ServiceIdRef,
+    ) -> Result<()> {
+        for (index, progress) in self.progress.iter().enumerate() {
+            progress_store.store_tr(conn, &Self::progress_id(service_id, index), *progress)?;
+        }
+        progress_store.store_tr(
+            conn,
+            &Self::count_id(service_id),
+            u64::try_from(self.progress.len())?,
+        )
+    }
+
+    /// Whether the old partition of `key` already handled the event at `offset`
+    ///
+    /// Events without a key went to all of the old partitions, so they
+    /// are handled again if any of them didn't get to them yet.
+    fn handled(&self, key: Option<&str>, offset: Offset) -> bool {
+        match key {
+            Some(key) if !self.progress.is_empty() => {
+                offset < self.progress[partition_index(key, self.progress.len())]
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Partition index of `key`
+///
+/// Uses FNV-1a, which unlike `std`'s hashers is guaranteed to never change,
+/// as the progress of each partition depends on which events it got.
+fn partition_index(key: &str, count: usize) -> usize {
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    usize::try_from(hash % u64::try_from(count).expect("no fail")).expect("no fail")
+}
+
 /// A service that handles events on the log
 pub trait LogFollowerService: Send + Sync {
+    /// Events the service subscribes to; any others are skipped
+    type Event: Subscription;
+
     fn get_log_progress_id(&self) -> String;
 
-    fn handle_event(&mut self, transaction: &mut dyn Transaction<'_>, event: Event) -> Result<()>;
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+
+    fn batch_config(&self) -> BatchConfig {
+        BatchConfig::default()
+    }
+
+    /// Classify an `error` returned when handling events
+    ///
+    /// Events that can't even be decoded are always poison.
+    fn classify_error(&self, _error: &anyhow::Error) -> ErrorClass {
+        ErrorClass::Retryable
+    }
+
+    /// How many times to try handling a poison event before giving up on it
+    fn poison_event_max_attempts(&self) -> u32 {
+        3
+    }
+
+    /// Key to partition events by, when spawned with
+    /// [`ServiceControl::spawn_partitioned_log_follower`]
+    ///
+    /// Events with the same key are handled by the same partition, in order.
+    /// Events without a key (eg. ticks of a timer) are handled by all of them.
+    fn partition_key<'e>(&self, _event: &'e Self::Event) -> Option<&'e str> {
+        None
+    }
+
+    fn handle_event(
+        &mut self,
+        transaction: &mut dyn Transaction<'_>,
+        event: Self::Event,
+    ) -> Result<()>;
+
+    /// Handle a (non-empty) batch of events
+    ///
+    /// Services that can process events in bulk can override it, in
+    /// which case they need to take care of wrapping `transaction` in
+    /// a [`CausedTransaction`] themselves, if they write any events.
+    fn handle_batch(
+        &mut self,
+        transaction: &mut dyn Transaction<'_>,
+        events: Vec<WithMetadata<Self::Event>>,
+    ) -> Result<()> {
+        for WithMetadata { metadata, data } in events {
+            // any events written while handling `data` are caused by it
+            self.handle_event(&mut CausedTransaction::new(transaction, metadata), data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings of a [`LogFollowerService`] used by its event loop
+struct EventLoopConfig {
+    restart_policy: RestartPolicy,
+    batch_config: BatchConfig,
+    poison_event_max_attempts: u32,
+}
+
+impl EventLoopConfig {
+    fn of(service: &impl LogFollowerService) -> Self {
+        Self {
+            restart_policy: service.restart_policy(),
+            batch_config: service.batch_config(),
+            poison_event_max_attempts: service.poison_event_max_attempts(),
+        }
+    }
 }
 
 /// A service that is a loop that does something
 pub trait LoopService: Send + Sync {
     fn run_iteration(&mut self) -> Result<()>;
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
 }
 
 /// Service execution control instance
@@ -42,21 +498,37 @@ pub trait LoopService: Send + Sync {
 pub struct ServiceControl {
     stop_all: Arc<AtomicBool>,
     progress_store: progress::SharedProgressTracker,
+    dead_letter_store: SharedDeadLetterStore,
     persistence: Arc<dyn Persistence>,
+    clock: SharedClock,
 }
 
 impl ServiceControl {
     pub fn new(
         persistence: SharedPersistence,
         progress_store: progress::SharedProgressTracker,
+        dead_letter_store: SharedDeadLetterStore,
     ) -> Self {
         Self {
             stop_all: Default::default(),
             progress_store,
+            dead_letter_store,
             persistence,
+            clock: SystemClock::new_shared(),
         }
     }
 
+    /// Use `clock` instead of the [`SystemClock`]
+    ///
+    /// Services should be created with the same one (see [`Self::clock`]).
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock, ..self }
+    }
+
+    pub fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
     // Notify all spawned service instances to shutdown
     pub fn send_stop_to_all(&self) {
         self.stop_all.store(true, Ordering::SeqCst);
@@ -64,27 +536,228 @@ impl ServiceControl {
 
     /// Spawn a service instance that implements a [`LogFollowerService`]
     /// to track log events from `event_reader`.
-    pub fn spawn_log_follower(
+    pub fn spawn_log_follower<S: LogFollowerService + 'static>(
+        &self,
+        service: S,
+        event_reader: event_log::SharedReader,
+    ) -> JoinHandle {
+        self.spawn_log_follower_partition(service, None, Default::default(), event_reader)
+    }
+
+    /// Spawn `partitions` instances of a [`LogFollowerService`], created
+    /// with `new_service`, each handling only the events with
+    /// [`LogFollowerService::partition_key`] in its [`Partition`]
+    ///
+    /// Each partition keeps its own progress, so a slow event
+    /// holds back only the partition it belongs to. Since the number of
+    /// partitions decides which events each of them gets, changing it
+    /// starts all the new ones from the lowest progress of the old ones
+    /// (or of the unpartitioned service), skipping the events the old
+    /// partition of their key already handled, and moves the dead letters
+    /// to the new partition of their key.
+    ///
+    /// Events without a key went to every old partition, so the ones
+    /// past the lowest progress are handled again. The number can't change
+    /// again until all the partitions got past the highest one.
+    pub fn spawn_partitioned_log_follower<S: LogFollowerService + 'static>(
+        &self,
+        mut new_service: impl FnMut(Partition) -> S,
+        partitions: usize,
+        event_reader: event_log::SharedReader,
+    ) -> Result<Vec<JoinHandle>> {
+        if partitions == 0 {
+            bail!("at least one partition needed");
+        }
+        let services = (0..partitions)
+            .map(|index| {
+                let partition = Partition {
+                    index,
+                    count: partitions,
+                };
+                (partition, new_service(partition))
+            })
+            .collect::<Vec<_>>();
+        let handover = self.migrate_partitions(
+            &services[0].1.get_log_progress_id(),
+            partitions,
+            &event_reader,
+            |event| {
+                let service = &services[0].1;
+                let data = decode_subscribed::<S::Event>(event).ok().flatten()?;
+                service.partition_key(&data).map(str::to_owned)
+            },
+        )?;
+
+        Ok(services
+            .into_iter()
+            .map(|(partition, service)| {
+                self.spawn_log_follower_partition(
+                    service,
+                    Some(partition),
+                    handover.clone(),
+                    event_reader.clone(),
+                )
+            })
+            .collect())
+    }
+
+    /// Move the progress and dead letters of `service_id` to `partitions`,
+    /// if their number changed, returning the progress of the old ones
+    ///
+    /// `key_of` returns the partition key of a dead lettered event, if it has one.
+    /// Ones without it stay in the partition of the same index, or go
+    /// to the first one if it's gone.
+    fn migrate_partitions(
         &self,
-        mut service: impl LogFollowerService + 'static,
+        service_id: ServiceIdRef,
+        partitions: usize,
+        event_reader: &event_log::SharedReader,
+        key_of: impl Fn(&LogEvent) -> Option<String>,
+    ) -> Result<PartitionHandover> {
+        let count = u64::try_from(partitions)?;
+        let count_id = partition_count_id(service_id);
+        let mut connection = self.persistence.get_connection()?;
+
+        // the partitions are not running yet, so nothing changes in the meantime
+        let prev_count = self.progress_store.load(&mut *connection, &count_id)?;
+        let prev_ids = match prev_count {
+            Some(prev_count) if prev_count != count => (0..usize::try_from(prev_count)?)
+                .map(|index| partition_id(service_id, index))
+                .collect(),
+            Some(_) => vec![],
+            None => vec![service_id.to_owned()],
+        };
+        let mut dead_letters = vec![];
+        for (index, id) in prev_ids.iter().enumerate() {
+            for dead_letter in self.dead_letter_store.list(&mut *connection, Some(id))? {
+                dead_letters.push((index, dead_letter));
+            }
+        }
+
+        let mut transaction = connection.start_transaction()?;
+        let prev_handover =
+            PartitionHandover::load_tr(&*self.progress_store, &mut *transaction, service_id)?;
+        if prev_count == Some(count) {
+            return Ok(prev_handover);
+        }
+
+        let log_start = event_reader.get_start_offset()?;
+        let mut prev_progress = vec![];
+        for id in &prev_ids {
+            let progress = self.progress_store.load_tr(&mut *transaction, id)?;
+            prev_progress.push(progress.unwrap_or(log_start));
+        }
+        let start = prev_progress.iter().copied().min().expect("not empty");
+        if let Some(&prev_end) = prev_handover.progress.iter().max() {
+            if start < prev_end {
+                bail!(
+                    "{service_id}: partitions didn't get past offset {prev_end} yet, \
+                    since their number last changed, so it can't change again"
+                );
+            }
+        }
+
+        for index in 0..partitions {
+            self.progress_store.store_tr(
+                &mut *transaction,
+                &partition_id(service_id, index),
+                start,
+            )?;
+        }
+        self.progress_store
+            .store_tr(&mut *transaction, &count_id, count)?;
+        let handover = PartitionHandover {
+            progress: prev_progress,
+        };
+        handover.store_tr(&*self.progress_store, &mut *transaction, service_id)?;
+
+        let moved = dead_letters.len();
+        for (_, dead_letter) in &dead_letters {
+            self.dead_letter_store.remove_tr(
+                &mut *transaction,
+                &dead_letter.service_id,
+                dead_letter.event.offset,
+            )?;
+        }
+        for (prev_index, dead_letter) in dead_letters {
+            let index = match key_of(&dead_letter.event) {
+                Some(key) => partition_index(&key, partitions),
+                None if prev_index < partitions => prev_index,
+                None => 0,
+            };
+            self.dead_letter_store.store_tr(
+                &mut *transaction,
+                DeadLetter {
+                    service_id: partition_id(service_id, index),
+                    ..dead_letter
+                },
+            )?;
+        }
+        transaction.commit()?;
+        info!(service = %service_id, ?prev_count, count, start, moved, "partition count changed");
+        Ok(handover)
+    }
+
+    fn spawn_log_follower_partition<S: LogFollowerService + 'static>(
+        &self,
+        mut service: S,
+        partition: Option<Partition>,
+        handover: PartitionHandover,
         event_reader: event_log::SharedReader,
     ) -> JoinHandle {
         self.spawn_event_loop(
             &service.get_log_progress_id(),
+            partition,
+            EventLoopConfig::of(&service),
             event_reader,
-            move |transaction, event_details| service.handle_event(transaction, event_details),
+            move |transaction, events, redriven| {
+                let mut batch = vec![];
+                for event in events {
+                    let decoded =
+                        decode_subscribed::<S::Event>(event).map_err(|error| HandleError {
+                            class: ErrorClass::Poison,
+                            error,
+                        })?;
+                    if let Some(data) = decoded {
+                        let key = service.partition_key(&data);
+                        if partition.map_or(true, |partition| partition.contains(key))
+                            // a re-driven event is always behind the progress of its old partition
+                            && (redriven || !handover.handled(key, event.offset))
+                        {
+                            batch.push(WithMetadata {
+                                metadata: event.metadata.clone(),
+                                data,
+                            });
+                        }
+                    }
+                }
+                if batch.is_empty() {
+                    return Ok(false);
+                }
+                service
+                    .handle_batch(transaction, batch)
+                    .map_err(|error| HandleError {
+                        class: service.classify_error(&error),
+                        error,
+                    })?;
+                Ok(true)
+            },
         )
     }
 
-    pub fn spawn_loop(&self, mut service: impl LoopService + 'static) -> JoinHandle {
-        self.spawn_loop_raw(move || service.run_iteration())
+    pub fn spawn_loop<S: LoopService + 'static>(&self, mut service: S) -> JoinHandle {
+        self.spawn_loop_raw(
+            std::any::type_name::<S>().to_owned(),
+            service.restart_policy(),
+            move || service.run_iteration(),
+        )
     }
 
     /// Start a new service as a loop, with a certain body
     ///
     /// This will take care of checking termination condition and
-    /// handling any errors returned by `f`
-    fn spawn_loop_raw<F>(&self, mut f: F) -> JoinHandle
+    /// handling any errors returned by `f` according to the `restart_policy`
+    fn spawn_loop_raw<F>(&self, name: String, restart_policy: RestartPolicy, mut f: F) -> JoinHandle
     where
         F: FnMut() -> Result<()> + Send + Sync + 'static,
     {
@@ -94,13 +767,37 @@ impl ServiceControl {
             stop.clone(),
             thread::spawn({
                 let stop_all = self.stop_all.clone();
+                let clock = self.clock.clone();
                 move || match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                    while !stop.load(atomic::Ordering::SeqCst)
-                        && !stop_all.load(atomic::Ordering::SeqCst)
-                    {
-                        if let Err(e) = f() {
-                            stop_all.store(true, atomic::Ordering::SeqCst);
-                            return Err(e);
+                    let is_stopped = || {
+                        stop.load(atomic::Ordering::SeqCst)
+                            || stop_all.load(atomic::Ordering::SeqCst)
+                    };
+                    let mut failures = 0;
+
+                    while !is_stopped() {
+                        match f() {
+                            Ok(()) => failures = 0,
+                            Err(e) => {
+                                let Some(delay) = restart_policy.restart_delay(failures) else {
+                                    error!(service = %name, error = %e, "service failed, stopping all services");
+                                    stop_all.store(true, atomic::Ordering::SeqCst);
+                                    return Err(e);
+                                };
+                                failures += 1;
+                                warn!(service = %name, error = %e, ?delay, failures, "service failed, restarting");
+
+                                // sleep, but don't delay stopping
+                                let deadline = clock.now() + delay;
+                                while !is_stopped() && clock.now() < deadline {
+                                    clock.sleep(
+                                        deadline
+                                            .duration_since(clock.now())
+                                            .unwrap_or_default()
+                                            .min(Duration::from_millis(100)),
+                                    );
+                                }
+                            }
                         }
                     }
                     Ok(())
@@ -115,27 +812,42 @@ impl ServiceControl {
         )
     }
 
+    /// Run `f` on the events of the log, and the re-driven dead letters
+    ///
+    /// `f` is told whether the events are re-driven, and returns whether any
+    /// of them were for the service; a re-driven one that wasn't stays a dead letter.
     fn spawn_event_loop<F>(
         &self,
         service_id: ServiceIdRef,
+        partition: Option<Partition>,
+        EventLoopConfig {
+            restart_policy,
+            batch_config,
+            poison_event_max_attempts,
+        }: EventLoopConfig,
         event_reader: event_log::SharedReader,
         mut f: F,
     ) -> JoinHandle
     where
-        F: for<'a> FnMut(&mut dyn Transaction<'a>, Event) -> Result<()> + Send + Sync + 'static,
+        F: for<'a> FnMut(&mut dyn Transaction<'a>, &[LogEvent], bool) -> Result<bool, HandleError>
+            + Send
+            + Sync
+            + 'static,
     {
-        let service_id = service_id.to_owned();
+        let progress_id = partition.map(|partition| partition.progress_id(service_id));
 
-        let mut progress = {
+        let progress = {
             match (|| {
                 let mut connection = self.persistence.get_connection()?;
-                Ok(
-                    if let Some(offset) = self.progress_store.load(&mut *connection, &service_id)? {
-                        offset
-                    } else {
-                        event_reader.get_start_offset()?
-                    },
-                )
+                let offset = self.progress_store.load(
+                    &mut *connection,
+                    progress_id.as_deref().unwrap_or(service_id),
+                )?;
+                Ok(if let Some(offset) = offset {
+                    offset
+                } else {
+                    event_reader.get_start_offset()?
+                })
             })() {
                 // To avoid returning a `Result` directly from here, spawn a thread that will immediately terminate with an error,
                 // just like the initial progress load was done from the spawned thread itself.
@@ -148,32 +860,124 @@ impl ServiceControl {
                 Ok(o) => o,
             }
         };
+        // from now on, a partition is a separate service
+        let service_id = progress_id.unwrap_or_else(|| service_id.to_owned());
+
+        let mut state = EventLoopState::new(
+            service_id.clone(),
+            batch_config,
+            poison_event_max_attempts,
+            self.clock.clone(),
+            progress,
+        );
 
-        self.spawn_loop_raw({
+        self.spawn_loop_raw(service_id.clone(), restart_policy, {
             let progress_store = self.progress_store.clone();
+            let dead_letter_store = self.dead_letter_store.clone();
             let persistence = self.persistence.clone();
             move || {
                 let mut connection = persistence.get_connection()?;
 
+                if let Some(dead_letter) = state
+                    .redrive_due()
+                    .then(|| dead_letter_store.next_redrive(&mut *connection, &service_id))
+                    .transpose()?
+                    .flatten()
+                {
+                    let offset = dead_letter.event.offset;
+                    let mut transaction = connection.start_transaction()?;
+                    let res = f(
+                        &mut *transaction,
+                        std::slice::from_ref(&dead_letter.event),
+                        true,
+                    )
+                    .and_then(|handled| {
+                        // eg. moved to a partition the event doesn't belong to
+                        handled.then_some(()).ok_or_else(|| HandleError {
+                            class: ErrorClass::Poison,
+                            error: format_err!("event not handled by {service_id}"),
+                        })
+                    });
+                    match res {
+                        Ok(()) => {
+                            dead_letter_store.remove_tr(&mut *transaction, &service_id, offset)?;
+                            transaction.commit()?;
+                            state.redrive_handled(offset);
+                        }
+                        Err(HandleError { error, .. }) => {
+                            transaction.rollback()?;
+                            let dead_letter = state.redrive_failed(dead_letter, error);
+                            let mut transaction = connection.start_transaction()?;
+                            dead_letter_store.store_tr(&mut *transaction, dead_letter)?;
+                            transaction.commit()?;
+                        }
+                    }
+                    return Ok(());
+                }
+
+                if let Some(delay) = state.retry_delay() {
+                    // sleep in slices, so as not to delay stopping
+                    state.clock.sleep(delay.min(Duration::from_millis(100)));
+                    return Ok(());
+                }
+
+                let max_size = state.batch_size();
                 let WithOffset {
-                    offset: new_offset,
+                    offset: mut new_offset,
                     data: mut events,
                 } = event_reader.read(
                     &mut *connection,
-                    progress,
-                    1,
-                    Some(std::time::Duration::from_secs(1)),
+                    state.progress,
+                    max_size,
+                    Some(Duration::from_secs(1)),
                 )?;
 
-                let mut transaction = connection.start_transaction()?;
+                if events.is_empty() {
+                    return Ok(());
+                }
+
+                let deadline = state.batch_deadline();
+                while events.len() < max_size {
+                    let now = Instant::now();
+                    if deadline <= now {
+                        break;
+                    }
+                    let more = event_reader.read(
+                        &mut *connection,
+                        new_offset,
+                        max_size - events.len(),
+                        Some(deadline - now),
+                    )?;
+                    new_offset = more.offset;
+                    events.extend(more.data);
+                }
 
-                for event in events.drain(..) {
-                    f(&mut *transaction, event.details)?;
+                let mut transaction = connection.start_transaction()?;
+                let error = match f(&mut *transaction, &events, false) {
+                    Ok(_) => {
+                        progress_store.store_tr(&mut *transaction, &service_id, new_offset)?;
+                        transaction.commit()?;
+                        state.handled(new_offset);
+                        return Ok(());
+                    }
+                    Err(HandleError {
+                        class: ErrorClass::Retryable,
+                        error,
+                    }) => return Err(error),
+                    Err(HandleError {
+                        class: ErrorClass::Poison,
+                        error,
+                    }) => error,
+                };
+                transaction.rollback()?;
 
-                    progress = new_offset;
+                if let Some((dead_letter, new_offset)) = state.poisoned(events, new_offset, error) {
+                    let mut transaction = connection.start_transaction()?;
+                    dead_letter_store.store_tr(&mut *transaction, dead_letter)?;
                     progress_store.store_tr(&mut *transaction, &service_id, new_offset)?;
+                    transaction.commit()?;
+                    state.handled(new_offset);
                 }
-                transaction.commit()?;
                 Ok(())
             }
         })
@@ -0,0 +1,516 @@
+//! Async services
+//!
+//! Counterparts of [`LogFollowerService`] and [`LoopService`] for services
+//! that would rather `.await` than block a thread, eg. because they do
+//! network IO. All of them run as tasks on one shared tokio runtime, and
+//! are stopped cooperatively: at the next `.await` after a stop was requested.
+//!
+//! [`LogFollowerService`]: super::LogFollowerService
+//! [`LoopService`]: super::LoopService
+use super::{
+    decode_subscribed, BatchConfig, ErrorClass, EventLoopState, HandleError, RestartPolicy,
+    ServiceControl, ServiceId,
+};
+use crate::{
+    clock::{Clock, SharedClock},
+    dead_letter::{DeadLetter, SharedDeadLetterStore},
+    event::Subscription,
+    event_log::{self, AsyncCausedTransaction, LogEvent, Offset, WithMetadata, WithOffset},
+    persistence::{AsyncTransaction, SharedAsyncPersistence},
+    progress,
+};
+use anyhow::{bail, format_err, Result};
+use async_trait::async_trait;
+use futures::FutureExt;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::runtime::Handle;
+use tracing::{error, warn};
+
+/// Async counterpart of [`super::LogFollowerService`]
+// the services the binary runs all follow the log synchronously so far
+#[allow(dead_code)]
+#[async_trait]
+pub trait AsyncLogFollowerService: Send + Sync {
+    /// Events the service subscribes to; any others are skipped
+    type Event: Subscription + Send;
+
+    fn get_log_progress_id(&self) -> String;
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+
+    fn batch_config(&self) -> BatchConfig {
+        BatchConfig::default()
+    }
+
+    /// See [`super::LogFollowerService::classify_error`]
+    fn classify_error(&self, _error: &anyhow::Error) -> ErrorClass {
+        ErrorClass::Retryable
+    }
+
+    /// How many times to try handling a poison event before giving up on it
+    fn poison_event_max_attempts(&self) -> u32 {
+        3
+    }
+
+    async fn handle_event(
+        &mut self,
+        transaction: &mut dyn AsyncTransaction<'_>,
+        event: Self::Event,
+    ) -> Result<()>;
+
+    /// See [`super::LogFollowerService::handle_batch`]
+    async fn handle_batch(
+        &mut self,
+        transaction: &mut dyn AsyncTransaction<'_>,
+        events: Vec<WithMetadata<Self::Event>>,
+    ) -> Result<()> {
+        for WithMetadata { metadata, data } in events {
+            // any events written while handling `data` are caused by it
+            self.handle_event(
+                &mut AsyncCausedTransaction::new(transaction, metadata),
+                data,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Async counterpart of [`super::LoopService`]
+///
+/// An iteration can take as long as it needs, eg. serve requests forever,
+/// since it's just dropped when the service is stopped.
+#[async_trait]
+pub trait AsyncLoopService: Send {
+    async fn run_iteration(&mut self) -> Result<()>;
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+/// Async counterpart of [`ServiceControl`]
+///
+/// Shares the stop flag with the [`ServiceControl`] it was created from,
+/// so stopping (or a failure of) any service stops all of them, sync and async.
+#[derive(Clone)]
+pub struct AsyncServiceControl {
+    runtime: Handle,
+    stop_all: Arc<AtomicBool>,
+    progress_store: progress::SharedProgressTracker,
+    dead_letter_store: SharedDeadLetterStore,
+    persistence: SharedAsyncPersistence,
+    clock: SharedClock,
+}
+
+impl AsyncServiceControl {
+    /// Create a control running services on `runtime`
+    ///
+    /// A current-thread runtime runs them only while something is
+    /// blocked on it, so it's best to use a multi-threaded one.
+    pub fn new(
+        svc_ctr: &ServiceControl,
+        runtime: Handle,
+        persistence: SharedAsyncPersistence,
+    ) -> Self {
+        Self {
+            runtime,
+            stop_all: svc_ctr.stop_all.clone(),
+            progress_store: svc_ctr.progress_store.clone(),
+            dead_letter_store: svc_ctr.dead_letter_store.clone(),
+            persistence,
+            clock: svc_ctr.clock(),
+        }
+    }
+
+    // Notify all spawned service instances to shutdown
+    #[allow(dead_code)] // the binary stops them through the `ServiceControl`
+    pub fn send_stop_to_all(&self) {
+        self.stop_all.store(true, Ordering::SeqCst);
+    }
+
+    /// Spawn a service instance that implements a [`AsyncLogFollowerService`]
+    /// to track log events from `event_reader`.
+    #[allow(dead_code)] // see `AsyncLogFollowerService`
+    pub fn spawn_log_follower<S: AsyncLogFollowerService + 'static>(
+        &self,
+        service: S,
+        event_reader: event_log::SharedAsyncReader,
+    ) -> AsyncJoinHandle {
+        let service_id = service.get_log_progress_id();
+        self.spawn_loop_raw(
+            service_id.clone(),
+            service.restart_policy(),
+            EventLoop {
+                service,
+                service_id,
+                event_reader,
+                progress_store: self.progress_store.clone(),
+                dead_letter_store: self.dead_letter_store.clone(),
+                persistence: self.persistence.clone(),
+                clock: self.clock.clone(),
+                state: None,
+            },
+        )
+    }
+
+    pub fn spawn_loop<S: AsyncLoopService + 'static>(&self, service: S) -> AsyncJoinHandle {
+        self.spawn_loop_raw(
+            std::any::type_name::<S>().to_owned(),
+            service.restart_policy(),
+            service,
+        )
+    }
+
+    /// Start a new task running iterations of `service` until stopped
+    ///
+    /// Errors are handled according to the `restart_policy`.
+    fn spawn_loop_raw<S: AsyncLoopService + 'static>(
+        &self,
+        name: String,
+        restart_policy: RestartPolicy,
+        mut service: S,
+    ) -> AsyncJoinHandle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_all = self.stop_all.clone();
+        let clock = self.clock.clone();
+
+        let task = self.runtime.spawn({
+            let stop = stop.clone();
+            async move {
+                let run = std::panic::AssertUnwindSafe(async {
+                    let mut failures = 0;
+
+                    loop {
+                        let res = tokio::select! {
+                            res = service.run_iteration() => res,
+                            () = stopped(&stop, &stop_all) => return Ok(()),
+                        };
+                        match res {
+                            Ok(()) => failures = 0,
+                            Err(e) => {
+                                let Some(delay) = restart_policy.restart_delay(failures) else {
+                                    error!(service = %name, error = %e, "service failed, stopping all services");
+                                    stop_all.store(true, Ordering::SeqCst);
+                                    return Err(e);
+                                };
+                                failures += 1;
+                                warn!(service = %name, error = %e, ?delay, failures, "service failed, restarting");
+
+                                // sleep, but don't delay stopping
+                                tokio::select! {
+                                    () = sleep(&*clock, delay) => {},
+                                    () = stopped(&stop, &stop_all) => return Ok(()),
+                                }
+                            }
+                        }
+                    }
+                });
+
+                match run.catch_unwind().await {
+                    Err(_e) => {
+                        stop_all.store(true, Ordering::SeqCst);
+                        bail!("service panicked");
+                    }
+                    Ok(res) => res,
+                }
+            }
+        });
+
+        AsyncJoinHandle {
+            stop,
+            task: Some(task),
+        }
+    }
+}
+
+/// Resolves once `stop` or `stop_all` is set
+///
+/// The flags are shared with the sync services, that can't wake up
+/// anything, so they are just checked periodically, like the sync
+/// services do.
+async fn stopped(stop: &AtomicBool, stop_all: &AtomicBool) {
+    while !stop.load(Ordering::SeqCst) && !stop_all.load(Ordering::SeqCst) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Wait until `duration` passes on `clock`
+///
+/// A [`Clock`] can only block a thread, so this just checks it periodically.
+async fn sleep(clock: &dyn Clock, duration: Duration) {
+    let deadline = clock.now() + duration;
+    while let Ok(left) = deadline.duration_since(clock.now()) {
+        if left.is_zero() {
+            break;
+        }
+        tokio::time::sleep(left.min(Duration::from_millis(100))).await;
+    }
+}
+
+/// An [`AsyncLogFollowerService`] as an [`AsyncLoopService`]
+///
+/// Makes the same decisions as the sync event loop (see
+/// [`ServiceControl::spawn_log_follower`]), just with `.await`s.
+struct EventLoop<S> {
+    service: S,
+    service_id: ServiceId,
+    event_reader: event_log::SharedAsyncReader,
+    progress_store: progress::SharedProgressTracker,
+    dead_letter_store: SharedDeadLetterStore,
+    persistence: SharedAsyncPersistence,
+    clock: SharedClock,
+    /// Created on the first iteration, once the progress is loaded
+    state: Option<EventLoopState>,
+}
+
+impl<S: AsyncLogFollowerService> EventLoop<S> {
+    async fn handle(
+        &mut self,
+        transaction: &mut dyn AsyncTransaction<'_>,
+        events: &[LogEvent],
+    ) -> Result<(), HandleError> {
+        let mut batch = vec![];
+        for event in events {
+            let decoded = decode_subscribed::<S::Event>(event).map_err(|error| HandleError {
+                class: ErrorClass::Poison,
+                error,
+            })?;
+            if let Some(data) = decoded {
+                batch.push(WithMetadata {
+                    metadata: event.metadata.clone(),
+                    data,
+                });
+            }
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.service
+            .handle_batch(transaction, batch)
+            .await
+            .map_err(|error| HandleError {
+                class: self.service.classify_error(&error),
+                error,
+            })
+    }
+
+    async fn load_state(&self) -> Result<EventLoopState> {
+        let mut connection = self.persistence.get_connection().await?;
+        let progress_store = self.progress_store.clone();
+        let service_id = self.service_id.clone();
+        let progress = match connection
+            .blocking(move |conn| progress_store.load(conn, &service_id))
+            .await?
+        {
+            Some(offset) => offset,
+            None => self.event_reader.get_start_offset()?,
+        };
+        Ok(EventLoopState::new(
+            self.service_id.clone(),
+            self.service.batch_config(),
+            self.service.poison_event_max_attempts(),
+            self.clock.clone(),
+            progress,
+        ))
+    }
+
+    fn state(&mut self) -> &mut EventLoopState {
+        self.state.as_mut().expect("loaded")
+    }
+
+    async fn store_progress(
+        &self,
+        transaction: &mut dyn AsyncTransaction<'_>,
+        offset: Offset,
+    ) -> Result<()> {
+        let progress_store = self.progress_store.clone();
+        let service_id = self.service_id.clone();
+        transaction
+            .blocking(move |transaction| progress_store.store_tr(transaction, &service_id, offset))
+            .await
+    }
+
+    async fn store_dead_letter(
+        &self,
+        transaction: &mut dyn AsyncTransaction<'_>,
+        dead_letter: DeadLetter,
+    ) -> Result<()> {
+        let dead_letter_store = self.dead_letter_store.clone();
+        transaction
+            .blocking(move |transaction| dead_letter_store.store_tr(transaction, dead_letter))
+            .await
+    }
+
+    /// Handle a dead letter, if a re-drive of any was requested
+    ///
+    /// Returns `false` if there was nothing to re-drive.
+    async fn redrive(&mut self) -> Result<bool> {
+        if !self.state().redrive_due() {
+            return Ok(false);
+        }
+        let persistence = self.persistence.clone();
+        let mut connection = persistence.get_connection().await?;
+
+        let dead_letter = {
+            let dead_letter_store = self.dead_letter_store.clone();
+            let service_id = self.service_id.clone();
+            connection
+                .blocking(move |conn| dead_letter_store.next_redrive(conn, &service_id))
+                .await?
+        };
+        let Some(dead_letter) = dead_letter else {
+            return Ok(false);
+        };
+
+        let offset = dead_letter.event.offset;
+        let mut transaction = connection.start_transaction().await?;
+        match self
+            .handle(&mut *transaction, std::slice::from_ref(&dead_letter.event))
+            .await
+        {
+            Ok(()) => {
+                let dead_letter_store = self.dead_letter_store.clone();
+                let service_id = self.service_id.clone();
+                transaction
+                    .blocking(move |transaction| {
+                        dead_letter_store.remove_tr(transaction, &service_id, offset)
+                    })
+                    .await?;
+                transaction.commit().await?;
+                self.state().redrive_handled(offset);
+            }
+            Err(HandleError { error, .. }) => {
+                transaction.rollback().await?;
+                let dead_letter = self.state().redrive_failed(dead_letter, error);
+                let mut transaction = connection.start_transaction().await?;
+                self.store_dead_letter(&mut *transaction, dead_letter)
+                    .await?;
+                transaction.commit().await?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl<S: AsyncLogFollowerService> AsyncLoopService for EventLoop<S> {
+    async fn run_iteration(&mut self) -> Result<()> {
+        if self.state.is_none() {
+            self.state = Some(self.load_state().await?);
+        }
+
+        if self.redrive().await? {
+            return Ok(());
+        }
+
+        if let Some(delay) = self.state().retry_delay() {
+            sleep(&*self.clock, delay).await;
+            return Ok(());
+        }
+
+        let max_size = self.state().batch_size();
+        let progress = self.state().progress;
+
+        let persistence = self.persistence.clone();
+        let mut connection = persistence.get_connection().await?;
+        let WithOffset {
+            offset: mut new_offset,
+            data: mut events,
+        } = self
+            .event_reader
+            .read(
+                &mut *connection,
+                progress,
+                max_size,
+                Some(Duration::from_secs(1)),
+            )
+            .await?;
+
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let deadline = self.state().batch_deadline();
+        while events.len() < max_size {
+            let now = Instant::now();
+            if deadline <= now {
+                break;
+            }
+            let more = self
+                .event_reader
+                .read(
+                    &mut *connection,
+                    new_offset,
+                    max_size - events.len(),
+                    Some(deadline - now),
+                )
+                .await?;
+            new_offset = more.offset;
+            events.extend(more.data);
+        }
+
+        let mut transaction = connection.start_transaction().await?;
+        let error = match self.handle(&mut *transaction, &events).await {
+            Ok(()) => {
+                self.store_progress(&mut *transaction, new_offset).await?;
+                transaction.commit().await?;
+                self.state().handled(new_offset);
+                return Ok(());
+            }
+            Err(HandleError {
+                class: ErrorClass::Retryable,
+                error,
+            }) => return Err(error),
+            Err(HandleError {
+                class: ErrorClass::Poison,
+                error,
+            }) => error,
+        };
+        transaction.rollback().await?;
+
+        if let Some((dead_letter, new_offset)) = self.state().poisoned(events, new_offset, error) {
+            let mut transaction = connection.start_transaction().await?;
+            self.store_dead_letter(&mut *transaction, dead_letter)
+                .await?;
+            self.store_progress(&mut *transaction, new_offset).await?;
+            transaction.commit().await?;
+            self.state().handled(new_offset);
+        }
+        Ok(())
+    }
+}
+
+/// Handle of a task running an async service
+///
+/// Like [`super::JoinHandle`], stops the service when dropped, but
+/// can't wait for it to actually stop.
+pub struct AsyncJoinHandle {
+    stop: Arc<AtomicBool>,
+    task: Option<tokio::task::JoinHandle<Result<()>>>,
+}
+
+impl AsyncJoinHandle {
+    pub async fn join(mut self) -> Result<()> {
+        match self.task.take() {
+            Some(task) => task
+                .await
+                .map_err(|e| format_err!("join failed: {:?}", e))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AsyncJoinHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
@@ -1,25 +1,138 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use crate::{
-    auction::{Amount, ItemIdRef},
-    event::{AuctionHouseEvent, BiddingEngineEvent, Event},
+    auction::{Amount, AuctionHouseId, AuctionHouseIdRef, BidDetails, ItemBid, ItemId, ItemIdRef},
+    event::{
+        topics::{
+            AuctionHouseEvent, AuctionHouseItemEvent, BiddingEngineEvent, ItemStrategy, UiEvent,
+        },
+        Event, Subscription,
+    },
     event_log,
+    persistence::Persistence,
+    progress::SharedProgressTracker,
+    service::bidding_engine::BiddingStateStore,
 };
 use anyhow::Result;
+use serde::Deserialize;
 use tracing::debug;
 
 use super::*;
 
-mod xmpp;
-pub use self::xmpp::*;
+pub(crate) mod http;
+pub(crate) mod router;
+pub mod sol;
+pub(crate) mod xmpp;
+pub use self::{http::*, router::*, xmpp::*};
+
+/// Where we are in the events of an auction house, to get the ones after it after a restart
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuctionHouseCursor {
+    /// Of the auction house in an [`AuctionHouseRouter`], `None` for the default one
+    pub auction_house: Option<AuctionHouseId>,
+    /// Opaque, only ever given back to the auction house
+    pub position: String,
+}
+
+/// An event from [`AuctionHouseClient::poll`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PolledEvent {
+    pub event: AuctionHouseEvent,
+    /// Cursor after `event`, for auction houses that have cursors, and only
+    /// once it's known (eg. on the last event of a batch)
+    pub cursor: Option<AuctionHouseCursor>,
+}
+
+impl PolledEvent {
+    /// Event of an auction house without cursors
+    pub fn new(event: AuctionHouseEvent) -> Self {
+        Self {
+            event,
+            cursor: None,
+        }
+    }
+}
+
+/// Gives the last position stored for an auction house (see [`AuctionHouseCursor`])
+pub type LoadCursor<'a> = dyn FnMut(Option<AuctionHouseIdRef>) -> Result<Option<String>> + 'a;
 
 pub trait AuctionHouseClient {
+    /// Start getting the events of the auction of `item_id`, if not yet
+    ///
+    /// Auction houses that send the events of all the auctions don't need to do anything.
+    fn join(&self, _item_id: ItemIdRef) -> Result<()> {
+        Ok(())
+    }
+
+    /// Bid in the auction of `item_id`, joining it first if needed
     fn place_bid(&self, item_id: ItemIdRef, price: Amount) -> Result<()>;
-    fn poll(&self, timeout: Option<Duration>) -> Result<Option<AuctionHouseEvent>>;
+    fn poll(&self, timeout: Option<Duration>) -> Result<Option<PolledEvent>>;
+
+    /// The event last returned by [`Self::poll`] got written to the log
+    ///
+    /// Clients polling ahead (like [`AuctionHouseRouter`]) don't take
+    /// the next event from the auction house before that.
+    fn acknowledge(&self) {}
+
+    /// Get the events from after the cursors stored before a restart, as given by `load_cursor`
+    ///
+    /// Auction houses without cursors don't need to do anything.
+    fn resume(&self, _load_cursor: &mut LoadCursor<'_>) -> Result<()> {
+        Ok(())
+    }
 }
 
+/// Errors talking to the auction house are usually transient (eg. a dropped
+/// connection), so services using it retry for a while before giving up
+const AUCTION_HOUSE_RESTART_POLICY: RestartPolicy = RestartPolicy::ExponentialBackoff {
+    initial_delay: Duration::from_millis(100),
+    max_delay: Duration::from_secs(30),
+    max_retries: 10,
+};
+
 pub type SharedAuctionHouseClient = Arc<dyn AuctionHouseClient + Send + Sync + 'static>;
 
+/// Join the auctions still open in `bidding_state_store`
+///
+/// Clients only remember the auctions they joined since they were created,
+/// so this is needed on startup, to keep getting events of the auctions
+/// joined before.
+pub fn join_open_auctions(
+    persistence: &dyn Persistence,
+    bidding_state_store: &dyn BiddingStateStore,
+    auction_house_client: &dyn AuctionHouseClient,
+) -> Result<()> {
+    let mut connection = persistence.get_connection()?;
+    let mut transaction = connection.start_transaction()?;
+    for (item_id, state) in bidding_state_store.load_committed_tr(&mut *transaction)? {
+        if !state.auction_state.closed {
+            auction_house_client.join(&item_id)?;
+        }
+    }
+    transaction.commit()
+}
+
+/// Events the [`AuctionHouseSender`] subscribes to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuctionHouseSenderInput {
+    /// Setting a max bid or a strategy joins the auction
+    Ui(UiEvent),
+    BiddingEngine(BiddingEngineEvent),
+}
+
+impl Subscription for AuctionHouseSenderInput {
+    fn decode(event: &Event) -> Result<Option<Self>> {
+        Ok(if let Some(event) = event.decode()? {
+            Some(Self::Ui(event))
+        } else {
+            event.decode()?.map(Self::BiddingEngine)
+        })
+    }
+}
+
 pub struct AuctionHouseSender {
     auction_house_client: SharedAuctionHouseClient,
 }
@@ -33,27 +146,51 @@ impl AuctionHouseSender {
 }
 
 impl LogFollowerService for AuctionHouseSender {
+    type Event = AuctionHouseSenderInput;
+
     fn get_log_progress_id(&self) -> String {
         "auction-house-sender".to_owned()
     }
 
-    fn handle_event(&mut self, _transaction: &mut dyn Transaction<'_>, event: Event) -> Result<()> {
+    fn restart_policy(&self) -> RestartPolicy {
+        AUCTION_HOUSE_RESTART_POLICY
+    }
+
+    fn handle_event(
+        &mut self,
+        _transaction: &mut dyn Transaction<'_>,
+        event: AuctionHouseSenderInput,
+    ) -> Result<()> {
         debug!(?event, "event");
         match event {
-            Event::BiddingEngine(BiddingEngineEvent::Bid(item_bid)) => {
+            AuctionHouseSenderInput::Ui(UiEvent::MaxBidSet(ItemBid { item, .. }))
+            | AuctionHouseSenderInput::Ui(UiEvent::StrategySet(ItemStrategy { item, .. })) => {
+                self.auction_house_client.join(&item)
+            }
+            AuctionHouseSenderInput::BiddingEngine(BiddingEngineEvent::Bid(item_bid)) => {
                 // Note: we rely on idempotency of this call to the server here
                 self.auction_house_client
                     .place_bid(&item_bid.item, item_bid.price)
             }
-            _ => Ok(()),
+            AuctionHouseSenderInput::BiddingEngine(_) => Ok(()),
         }
     }
 }
 
+/// Writes the events of the auction house to the event log
+///
+/// The cursor after the events (see [`AuctionHouseCursor`]) is stored in the
+/// same transaction as them, and the auction house resumes from it on start,
+/// so no events are missed over a restart. The events of a batch that were
+/// written before the restart may be written again.
 pub struct AuctionHouseReceiver {
     persistence: SharedPersistence,
     even_writer: event_log::SharedWriter,
     auction_house_client: SharedAuctionHouseClient,
+    progress_store: SharedProgressTracker,
+    resumed: bool,
+    /// Event polled but not written yet, as writing it failed
+    pending: Option<PolledEvent>,
 }
 
 impl AuctionHouseReceiver {
@@ -61,27 +198,69 @@ impl AuctionHouseReceiver {
         persistence: SharedPersistence,
         even_writer: event_log::SharedWriter,
         auction_house_client: SharedAuctionHouseClient,
+        progress_store: SharedProgressTracker,
     ) -> Self {
         Self {
             persistence,
             auction_house_client,
             even_writer,
+            progress_store,
+            resumed: false,
+            pending: None,
         }
     }
+
+    /// Id the cursor of `auction_house` is stored under, in the `progress_store`
+    fn cursor_id(auction_house: Option<AuctionHouseIdRef>) -> String {
+        match auction_house {
+            Some(auction_house) => format!("auction-house-receiver/{auction_house}"),
+            None => "auction-house-receiver".to_owned(),
+        }
+    }
+
+    fn resume(&self) -> Result<()> {
+        let mut connection = self.persistence.get_connection()?;
+        self.auction_house_client.resume(&mut |auction_house| {
+            self.progress_store
+                .load_cursor(&mut *connection, &Self::cursor_id(auction_house))
+        })
+    }
 }
 
 impl LoopService for AuctionHouseReceiver {
     fn run_iteration<'a>(&mut self) -> Result<()> {
-        // TODO: no atomicity offered by the auction_house_client interface
-        if let Some(event) = self
-            .auction_house_client
-            .poll(Some(Duration::from_secs(1)))?
-        {
+        if !self.resumed {
+            self.resume()?;
+            self.resumed = true;
+        }
+
+        // the auction house doesn't give it again, so keep writing it until it's written
+        if self.pending.is_none() {
+            self.pending = self
+                .auction_house_client
+                .poll(Some(Duration::from_secs(1)))?;
+        }
+        if let Some(PolledEvent { event, cursor }) = &self.pending {
             let mut connection = self.persistence.get_connection()?;
+            let mut transaction = connection.start_transaction()?;
             self.even_writer
-                .write(&mut *connection, &[Event::AuctionHouse(event)])?;
+                .write_tr(&mut *transaction, &[Event::new(event)?])?;
+            if let Some(cursor) = cursor {
+                self.progress_store.store_cursor_tr(
+                    &mut *transaction,
+                    &Self::cursor_id(cursor.auction_house.as_deref()),
+                    &cursor.position,
+                )?;
+            }
+            transaction.commit()?;
+            self.pending = None;
+            self.auction_house_client.acknowledge();
         }
 
         Ok(())
     }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        AUCTION_HOUSE_RESTART_POLICY
+    }
 }
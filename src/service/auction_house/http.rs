@@ -0,0 +1,218 @@
+//! HTTP auction house
+//!
+//! Bids are POSTed as JSON to the bid endpoint:
+//!
+//! ```text
+//! {"item": "54321", "price": 199, "bidder": "sniper"}
+//! ```
+//!
+//! and events are long-polled from the events endpoint, with
+//! `GET <events url>?timeout_ms=<ms>&cursor=<cursor>`, which returns as
+//! soon as there are any events after `cursor` (or from the start, without
+//! one), or with no events once the timeout passes:
+//!
+//! ```text
+//! {
+//!   "events": [
+//!     {"item": "54321", "type": "price", "price": 192, "increment": 7, "bidder": "someone"},
+//!     {"item": "54321", "type": "closes_at", "at": 1700000000},
+//!     {"item": "54321", "type": "closed"}
+//!   ],
+//!   "cursor": "3"
+//! }
+//! ```
+//!
+//! `at` is in seconds since the Unix epoch, and `cursor` is opaque: it's
+//! only ever passed back to get the events that came after.
+use super::*;
+use crate::auction::Bidder;
+use serde_json::json;
+use std::{collections::VecDeque, sync::Mutex, time::Instant};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpConfig {
+    /// URL bids are POSTed to
+    pub bid_url: String,
+    /// URL events are long-polled from
+    pub events_url: String,
+    /// Who we are to the auction house, in our bids and its prices
+    pub bidder: String,
+}
+
+impl HttpConfig {
+    /// Config for the `/bids` and `/events` endpoints at `base_url`
+    pub fn new(base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+        Self {
+            bid_url: format!("{base_url}/bids"),
+            events_url: format!("{base_url}/events"),
+            bidder: "sniper".to_owned(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EventsResponse {
+    events: Vec<HttpEvent>,
+    cursor: String,
+}
+
+#[derive(Deserialize)]
+struct HttpEvent {
+    item: ItemId,
+    #[serde(flatten)]
+    event: HttpItemEvent,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum HttpItemEvent {
+    Price {
+        price: Amount,
+        increment: Amount,
+        bidder: String,
+    },
+    ClosesAt {
+        at: u64,
+    },
+    Closed,
+}
+
+impl HttpEvent {
+    fn into_auction_house_event(self, config: &HttpConfig) -> AuctionHouseEvent {
+        AuctionHouseEvent {
+            item: self.item,
+            event: match self.event {
+                HttpItemEvent::Price {
+                    price,
+                    increment,
+                    bidder,
+                } => AuctionHouseItemEvent::Bid(BidDetails {
+                    bidder: if bidder == config.bidder {
+                        Bidder::Sniper
+                    } else {
+                        Bidder::Other
+                    },
+                    price,
+                    increment,
+                }),
+                HttpItemEvent::ClosesAt { at } => AuctionHouseItemEvent::ClosesAt(
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(at),
+                ),
+                HttpItemEvent::Closed => AuctionHouseItemEvent::Closed,
+            },
+        }
+    }
+}
+
+/// Where we are in the events of the auction house
+#[derive(Default)]
+struct PollState {
+    /// Cursor after the last event returned from [`AuctionHouseClient::poll`]
+    cursor: Option<String>,
+    /// Events fetched, but not returned yet
+    pending: VecDeque<AuctionHouseEvent>,
+    /// Cursor after the last of `pending`
+    pending_cursor: Option<String>,
+}
+
+/// [`AuctionHouseClient`] talking to a REST API, long-polling it for events
+pub struct HttpAuctionHouseClient {
+    config: HttpConfig,
+    agent: ureq::Agent,
+    state: Mutex<PollState>,
+}
+
+impl HttpAuctionHouseClient {
+    /// Longest a single request for events waits for them
+    const MAX_LONG_POLL: Duration = Duration::from_secs(30);
+
+    /// How much longer than the long-poll timeout to wait for the response
+    const RESPONSE_GRACE: Duration = Duration::from_secs(10);
+
+    pub fn new(config: HttpConfig) -> Self {
+        Self {
+            config,
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(10))
+                .build(),
+            state: Mutex::new(PollState::default()),
+        }
+    }
+
+    pub fn new_shared(config: HttpConfig) -> SharedAuctionHouseClient {
+        Arc::new(Self::new(config))
+    }
+
+    /// Fetch the events after `cursor`, waiting up to `timeout` for any
+    fn fetch_events(&self, cursor: Option<&str>, timeout: Duration) -> Result<EventsResponse> {
+        let mut request = self
+            .agent
+            .get(&self.config.events_url)
+            .timeout(timeout + Self::RESPONSE_GRACE)
+            .query("timeout_ms", &timeout.as_millis().to_string());
+        if let Some(cursor) = cursor {
+            request = request.query("cursor", cursor);
+        }
+        Ok(request.call()?.into_json()?)
+    }
+}
+
+impl AuctionHouseClient for HttpAuctionHouseClient {
+    fn place_bid(&self, item_id: ItemIdRef, price: Amount) -> Result<()> {
+        debug!(?item_id, ?price, "sending bid");
+        self.agent.post(&self.config.bid_url).send_json(json!({
+            "item": item_id,
+            "price": price,
+            "bidder": self.config.bidder,
+        }))?;
+        Ok(())
+    }
+
+    fn poll(&self, timeout: Option<Duration>) -> Result<Option<PolledEvent>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().expect("lock");
+        while state.pending.is_empty() {
+            let wait = deadline.map_or(Self::MAX_LONG_POLL, |deadline| {
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(Self::MAX_LONG_POLL)
+            });
+            let response = self.fetch_events(state.cursor.as_deref(), wait)?;
+            if response.events.is_empty() {
+                state.cursor = Some(response.cursor);
+                if deadline.map_or(false, |deadline| deadline <= Instant::now()) {
+                    return Ok(None);
+                }
+                continue;
+            }
+            state.pending = response
+                .events
+                .into_iter()
+                .map(|event| event.into_auction_house_event(&self.config))
+                .collect();
+            state.pending_cursor = Some(response.cursor);
+        }
+
+        let event = state.pending.pop_front();
+        // the events of a batch only have a cursor after the last of them
+        let cursor = if state.pending.is_empty() {
+            state.cursor = state.pending_cursor.take();
+            state.cursor.clone().map(|position| AuctionHouseCursor {
+                auction_house: None,
+                position,
+            })
+        } else {
+            None
+        };
+        Ok(event.map(|event| PolledEvent { event, cursor }))
+    }
+
+    fn resume(&self, load_cursor: &mut LoadCursor<'_>) -> Result<()> {
+        *self.state.lock().expect("lock") = PollState {
+            cursor: load_cursor(None)?,
+            ..Default::default()
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+//! Bidding in several auction houses at once
+use super::*;
+use crate::auction::{qualified_item_id, split_item_id, AuctionHouseId, AuctionHouseIdRef};
+use anyhow::{bail, format_err};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+    time::Instant,
+};
+use tracing::warn;
+
+/// Result of polling the auction house at an index of [`AuctionHouseRouter::all`]
+type PollResult = (usize, Result<Option<PolledEvent>>);
+
+/// [`AuctionHouseClient`] dispatching to the clients of several auction houses
+///
+/// Items qualified with the id of an auction house added with
+/// [`Self::with_auction_house`] (see [`crate::auction::ItemId`]) are in that
+/// auction house, under their unqualified id. Any other item is in the
+/// default one (see [`Self::with_default`]), as is, if there is one.
+///
+/// Each auction house is polled in its own thread, started on the first
+/// [`AuctionHouseClient::poll`], so a quiet one doesn't hold up the events of
+/// the others, and a failing one doesn't either, as long as any other works.
+/// A thread polls its auction house again only once the event it got is
+/// [`AuctionHouseClient::acknowledge`]d, so no more events are taken from
+/// the auction houses than get written.
+#[derive(Default)]
+pub struct AuctionHouseRouter {
+    default: Option<SharedAuctionHouseClient>,
+    auction_houses: Vec<(AuctionHouseId, SharedAuctionHouseClient)>,
+    /// Results of the poll threads, once started
+    poll_results: Mutex<Option<PollResults>>,
+    /// Tells the poll threads to stop, once the router is dropped
+    stop: Arc<AtomicBool>,
+}
+
+struct PollResults {
+    receiver: mpsc::Receiver<PollResult>,
+    /// Ids of the auction houses, as in [`AuctionHouseRouter::all`]
+    ids: Vec<Option<AuctionHouseId>>,
+    /// Whether the last poll of each auction house failed
+    failing: Vec<bool>,
+    /// Lets the poll thread of each auction house poll again
+    acknowledgements: Vec<mpsc::Sender<()>>,
+    /// Auction house of the event returned last, until acknowledged
+    unacknowledged: Option<usize>,
+}
+
+impl AuctionHouseRouter {
+    /// Longest a poll thread waits for events, before checking if it should stop
+    const POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// How long a poll thread waits after a failed poll, before polling again
+    const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `client` for the items not qualified with any of the other auction houses
+    pub fn with_default(mut self, client: SharedAuctionHouseClient) -> Self {
+        self.default = Some(client);
+        self
+    }
+
+    pub fn with_auction_house(
+        mut self,
+        id: AuctionHouseIdRef,
+        client: SharedAuctionHouseClient,
+    ) -> Self {
+        self.auction_houses.push((id.to_owned(), client));
+        self
+    }
+
+    /// All the auction houses, the default one first, without an id
+    fn all(
+        &self,
+    ) -> impl Iterator<Item = (Option<AuctionHouseIdRef<'_>>, &SharedAuctionHouseClient)> {
+        self.default.iter().map(|client| (None, client)).chain(
+            self.auction_houses
+                .iter()
+                .map(|(id, client)| (Some(id.as_str()), client)),
+        )
+    }
+
+    /// The client of the auction house of `item_id`, and the id of the item in it
+    fn route<'a>(
+        &self,
+        item_id: ItemIdRef<'a>,
+    ) -> Result<(&SharedAuctionHouseClient, ItemIdRef<'a>)> {
+        if let Some((auction_house, item_id)) = split_item_id(item_id) {
+            if let Some((_, client)) = self
+                .auction_houses
+                .iter()
+                .find(|(id, _)| id == auction_house)
+            {
+                return Ok((client, item_id));
+            }
+        }
+        match &self.default {
+            Some(client) => Ok((client, item_id)),
+            None => bail!("no auction house for item {item_id}"),
+        }
+    }
+
+    fn spawn_poll_threads(&self) -> PollResults {
+        // without a buffer, each thread only ever holds the one event it's sending
+        let (sender, receiver) = mpsc::sync_channel(0);
+        let mut ids = vec![];
+        let mut acknowledgements = vec![];
+        for (index, (id, client)) in self.all().enumerate() {
+            let id = id.map(str::to_owned);
+            let (client, sender, stop) = (client.clone(), sender.clone(), self.stop.clone());
+            let (acknowledgement, acknowledged) = mpsc::channel();
+            thread::spawn({
+                let id = id.clone();
+                move || {
+                    while !stop.load(Ordering::SeqCst) {
+                        let result = client
+                            .poll(Some(Self::POLL_TIMEOUT))
+                            .map(|polled| polled.map(|polled| qualify(id.as_deref(), polled)));
+                        let (failed, got_event) = (result.is_err(), matches!(result, Ok(Some(_))));
+                        if sender.send((index, result)).is_err() {
+                            return;
+                        }
+                        if failed {
+                            thread::sleep(Self::RETRY_DELAY);
+                        }
+                        if got_event && acknowledged.recv().is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+            ids.push(id);
+            acknowledgements.push(acknowledgement);
+        }
+        PollResults {
+            receiver,
+            failing: vec![false; ids.len()],
+            ids,
+            acknowledgements,
+            unacknowledged: None,
+        }
+    }
+}
+
+/// `polled`, with its item and cursor in the auction house `id`
+fn qualify(id: Option<AuctionHouseIdRef>, polled: PolledEvent) -> PolledEvent {
+    let Some(id) = id else {
+        return polled;
+    };
+    let PolledEvent { mut event, cursor } = polled;
+    event.item = qualified_item_id(id, &event.item);
+    PolledEvent {
+        event,
+        cursor: cursor.map(|cursor| AuctionHouseCursor {
+            auction_house: Some(id.to_owned()),
+            ..cursor
+        }),
+    }
+}
+
+impl Drop for AuctionHouseRouter {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+impl AuctionHouseClient for AuctionHouseRouter {
+    fn join(&self, item_id: ItemIdRef) -> Result<()> {
+        let (client, item_id) = self.route(item_id)?;
+        client.join(item_id)
+    }
+
+    fn place_bid(&self, item_id: ItemIdRef, price: Amount) -> Result<()> {
+        let (client, item_id) = self.route(item_id)?;
+        client.place_bid(item_id, price)
+    }
+
+    fn poll(&self, timeout: Option<Duration>) -> Result<Option<PolledEvent>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut poll_results = self.poll_results.lock().expect("lock");
+        let poll_results = poll_results.get_or_insert_with(|| self.spawn_poll_threads());
+        loop {
+            let (index, result) = match deadline {
+                Some(deadline) => match poll_results
+                    .receiver
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                {
+                    Ok(result) => result,
+                    Err(mpsc::RecvTimeoutError::Timeout) => return Ok(None),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        bail!("no auction houses to poll")
+                    }
+                },
+                None => poll_results
+                    .receiver
+                    .recv()
+                    .map_err(|_| format_err!("no auction houses to poll"))?,
+            };
+            match result {
+                Ok(polled) => {
+                    poll_results.failing[index] = false;
+                    if polled.is_some() {
+                        poll_results.unacknowledged = Some(index);
+                        return Ok(polled);
+                    }
+                }
+                Err(e) => {
+                    warn!(auction_house = ?poll_results.ids[index], error = %e, "polling auction house failed");
+                    poll_results.failing[index] = true;
+                    // the failing ones are retried by their threads, unless none works
+                    if poll_results.failing.iter().all(|failing| *failing) {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn acknowledge(&self) {
+        let mut poll_results = self.poll_results.lock().expect("lock");
+        if let Some(poll_results) = poll_results.as_mut() {
+            if let Some(index) = poll_results.unacknowledged.take() {
+                // the thread is only gone if the router is being dropped
+                let _ = poll_results.acknowledgements[index].send(());
+            }
+        }
+    }
+
+    /// Resume each auction house from its cursor, before the first poll
+    fn resume(&self, load_cursor: &mut LoadCursor<'_>) -> Result<()> {
+        for (id, client) in self.all() {
+            client.resume(&mut |_| load_cursor(id))?;
+        }
+        Ok(())
+    }
+}
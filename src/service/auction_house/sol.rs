@@ -0,0 +1,213 @@
+//! SOL, the text protocol auctions talk in the GOOS book
+//!
+//! Every message is a list of `Key: value;` fields, starting with the
+//! version of the protocol. Auctions send events:
+//!
+//! ```text
+//! SOLVersion: 1.1; Event: PRICE; CurrentPrice: 192; Increment: 7; Bidder: someone;
+//! SOLVersion: 1.1; Event: CLOSE;
+//! ```
+//!
+//! and bidders send commands:
+//!
+//! ```text
+//! SOLVersion: 1.1; Command: JOIN;
+//! SOLVersion: 1.1; Command: BID; Price: 199;
+//! ```
+//!
+//! Only the message bodies are handled here, so any transport can carry them.
+use crate::{
+    auction::{Amount, BidDetails, Bidder},
+    event::topics::AuctionHouseItemEvent,
+};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+use thiserror::Error;
+
+/// The only version of the protocol we speak
+pub const VERSION: &str = "1.1";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SolMessage {
+    /// Event: the auction got a new highest bid
+    Price {
+        current_price: Amount,
+        increment: Amount,
+        /// Identifies the bidder, in whatever way the transport does
+        bidder: TextValue,
+    },
+    /// Event: the auction closed
+    Close,
+    /// Command: join the auction, to get its events
+    Join,
+    /// Command: bid `price` in the auction
+    Bid { price: Amount },
+}
+
+/// Value of a text field, that can't break the message up
+///
+/// Can't contain a `;`, which ends a field, nor start or end with
+/// whitespace, which is trimmed when parsing. A `:` is fine, as only
+/// the first one in a field ends its key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextValue(String);
+
+impl TextValue {
+    pub fn new(value: impl Into<String>) -> Result<Self, SolError> {
+        let value = value.into();
+        if value.contains(';') || value.trim() != value {
+            return Err(SolError::InvalidValue(value));
+        }
+        Ok(Self(value))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for TextValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+pub enum SolError {
+    #[error("invalid field: {0:?}")]
+    InvalidField(String),
+    #[error("invalid value: {0:?}")]
+    InvalidValue(String),
+    #[error("duplicate field: {0}")]
+    DuplicateField(String),
+    #[error("missing field: {0}")]
+    MissingField(&'static str),
+    #[error("unsupported version: {0}")]
+    UnsupportedVersion(String),
+    #[error("invalid amount of {field}: {value:?}")]
+    InvalidAmount { field: &'static str, value: String },
+    #[error("unknown event: {0}")]
+    UnknownEvent(String),
+    #[error("unknown command: {0}")]
+    UnknownCommand(String),
+    #[error("message is neither an event nor a command")]
+    NeitherEventNorCommand,
+    #[error("message is both an event and a command")]
+    BothEventAndCommand,
+}
+
+impl SolMessage {
+    /// The [`AuctionHouseItemEvent`] this is, if it's an event
+    ///
+    /// `is_sniper` tells if the bidder of a price is us.
+    pub fn to_item_event(
+        &self,
+        is_sniper: impl FnOnce(&str) -> bool,
+    ) -> Option<AuctionHouseItemEvent> {
+        match self {
+            SolMessage::Price {
+                current_price,
+                increment,
+                bidder,
+            } => Some(AuctionHouseItemEvent::Bid(BidDetails {
+                bidder: if is_sniper(bidder.as_str()) {
+                    Bidder::Sniper
+                } else {
+                    Bidder::Other
+                },
+                price: *current_price,
+                increment: *increment,
+            })),
+            SolMessage::Close => Some(AuctionHouseItemEvent::Closed),
+            SolMessage::Join | SolMessage::Bid { .. } => None,
+        }
+    }
+}
+
+/// The fields of a message, by key
+struct Fields<'s>(BTreeMap<&'s str, &'s str>);
+
+impl<'s> Fields<'s> {
+    fn parse(message: &'s str) -> Result<Self, SolError> {
+        let mut fields = BTreeMap::new();
+        for field in message.split(';').map(str::trim) {
+            if field.is_empty() {
+                continue;
+            }
+            let (key, value) = field
+                .split_once(':')
+                .ok_or_else(|| SolError::InvalidField(field.to_owned()))?;
+            let key = key.trim();
+            if key.is_empty() {
+                return Err(SolError::InvalidField(field.to_owned()));
+            }
+            if fields.insert(key, value.trim()).is_some() {
+                return Err(SolError::DuplicateField(key.to_owned()));
+            }
+        }
+        Ok(Self(fields))
+    }
+
+    fn get(&self, key: &'static str) -> Option<&'s str> {
+        self.0.get(key).copied()
+    }
+
+    fn require(&self, key: &'static str) -> Result<&'s str, SolError> {
+        self.get(key).ok_or(SolError::MissingField(key))
+    }
+
+    fn amount(&self, key: &'static str) -> Result<Amount, SolError> {
+        let value = self.require(key)?;
+        value.parse().map_err(|_| SolError::InvalidAmount {
+            field: key,
+            value: value.to_owned(),
+        })
+    }
+}
+
+impl FromStr for SolMessage {
+    type Err = SolError;
+
+    fn from_str(message: &str) -> Result<Self, SolError> {
+        let fields = Fields::parse(message)?;
+        let version = fields.require("SOLVersion")?;
+        if version != VERSION {
+            return Err(SolError::UnsupportedVersion(version.to_owned()));
+        }
+
+        Ok(match (fields.get("Event"), fields.get("Command")) {
+            (Some("PRICE"), None) => SolMessage::Price {
+                current_price: fields.amount("CurrentPrice")?,
+                increment: fields.amount("Increment")?,
+                bidder: TextValue::new(fields.require("Bidder")?)?,
+            },
+            (Some("CLOSE"), None) => SolMessage::Close,
+            (Some(event), None) => return Err(SolError::UnknownEvent(event.to_owned())),
+            (None, Some("JOIN")) => SolMessage::Join,
+            (None, Some("BID")) => SolMessage::Bid {
+                price: fields.amount("Price")?,
+            },
+            (None, Some(command)) => return Err(SolError::UnknownCommand(command.to_owned())),
+            (None, None) => return Err(SolError::NeitherEventNorCommand),
+            (Some(_), Some(_)) => return Err(SolError::BothEventAndCommand),
+        })
+    }
+}
+
+impl fmt::Display for SolMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SOLVersion: {VERSION};")?;
+        match self {
+            SolMessage::Price {
+                current_price,
+                increment,
+                bidder,
+            } => write!(
+                f,
+                " Event: PRICE; CurrentPrice: {current_price}; Increment: {increment}; Bidder: {bidder};"
+            ),
+            SolMessage::Close => write!(f, " Event: CLOSE;"),
+            SolMessage::Join => write!(f, " Command: JOIN;"),
+            SolMessage::Bid { price } => write!(f, " Command: BID; Price: {price};"),
+        }
+    }
+}
@@ -1,28 +1,353 @@
-use super::*;
-use tracing::debug;
+//! XMPP auction house
+//!
+//! Like in the GOOS book, each item is auctioned in a chat with
+//! `auction-<item>@<auction domain>`: we join it with a JOIN message, then
+//! get PRICE and CLOSE messages from it, and send it BID messages.
+//!
+//! The connection is plain (unencrypted) XMPP with `PLAIN` authentication,
+//! so it's meant for a server on a trusted network, eg. a locally run one.
+use super::{sol::SolMessage, *};
+use anyhow::{bail, format_err};
+use base64::Engine;
+use std::{
+    collections::BTreeSet,
+    io::{BufReader, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+};
+use tracing::warn;
 
-#[derive(Clone, Debug)]
-pub struct XmppAuctionHouseClient;
+pub(crate) mod stream;
+use self::stream::{escape, Element, StreamReader, BIND_NS, SASL_NS, STREAM_NS};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XmppConfig {
+    /// `host:port` of the server
+    pub address: String,
+    pub username: String,
+    pub password: String,
+    /// Domain of our account
+    pub domain: String,
+    pub resource: String,
+    /// Domain of the `auction-<item>` accounts
+    pub auction_domain: String,
+    /// Longest to wait for connecting, for the server during the handshake,
+    /// and for sending a message
+    pub timeout: Duration,
+}
+
+impl Default for XmppConfig {
+    fn default() -> Self {
+        Self {
+            address: "localhost:5222".to_owned(),
+            username: "sniper".to_owned(),
+            password: "sniper".to_owned(),
+            domain: "localhost".to_owned(),
+            resource: "Auction".to_owned(),
+            auction_domain: "localhost".to_owned(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl XmppConfig {
+    pub fn bare_jid(&self) -> String {
+        format!("{}@{}", self.username, self.domain)
+    }
+
+    /// Our full JID, which the auctions report our bids with
+    pub fn jid(&self) -> String {
+        format!("{}/{}", self.bare_jid(), self.resource)
+    }
+
+    pub fn auction_jid(&self, item_id: ItemIdRef) -> String {
+        format!("auction-{item_id}@{}", self.auction_domain)
+    }
+
+    /// The item auctioned by `jid`, if it's an auction at all
+    pub fn auction_item(&self, jid: &str) -> Option<ItemId> {
+        let bare_jid = jid.split_once('/').map_or(jid, |(bare_jid, _)| bare_jid);
+        let (local, domain) = bare_jid.split_once('@')?;
+        if domain != self.auction_domain {
+            return None;
+        }
+        local.strip_prefix("auction-").map(ToOwned::to_owned)
+    }
+}
+
+/// [`AuctionHouseClient`] talking to auctions over XMPP
+///
+/// Connects on first use, and again after the connection was lost,
+/// joining again all the auctions it joined before.
+/// Once connected, it waits for messages from auctions without a timeout,
+/// as they can be quiet for long, so a lost connection is noticed
+/// only once the server closes it, or sending a message fails.
+pub struct XmppAuctionHouseClient {
+    config: XmppConfig,
+    connection: Mutex<Option<Arc<Connection>>>,
+    /// Items we joined the auctions of
+    joined: Mutex<BTreeSet<ItemId>>,
+}
 
 impl XmppAuctionHouseClient {
-    pub fn new() -> Self {
-        Self
+    pub fn new(config: XmppConfig) -> Self {
+        Self {
+            config,
+            connection: Mutex::new(None),
+            joined: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    pub fn new_shared(config: XmppConfig) -> SharedAuctionHouseClient {
+        Arc::new(Self::new(config))
+    }
+
+    fn connection(&self) -> Result<Arc<Connection>> {
+        let mut connection = self.connection.lock().expect("lock");
+        if let Some(connection) = connection.as_ref().filter(|c| !c.is_broken()) {
+            return Ok(connection.clone());
+        }
+        *connection = None;
+
+        let new_connection = Arc::new(Connection::open(&self.config)?);
+        for item_id in self.joined.lock().expect("lock").iter() {
+            new_connection.send_message(&self.config.auction_jid(item_id), &SolMessage::Join)?;
+        }
+        *connection = Some(new_connection.clone());
+        Ok(new_connection)
+    }
+}
+
+/// A connection, authenticated and ready for chatting
+struct Connection {
+    writer: Mutex<TcpStream>,
+    /// From the thread reading the connection
+    events: Mutex<mpsc::Receiver<Result<AuctionHouseEvent>>>,
+    /// Failed reading or writing, so it needs to be replaced
+    broken: AtomicBool,
+}
+
+impl Connection {
+    fn open(config: &XmppConfig) -> Result<Self> {
+        let stream = connect(config)?;
+        stream.set_read_timeout(Some(config.timeout))?;
+        stream.set_write_timeout(Some(config.timeout))?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = StreamReader::new(BufReader::new(stream));
+
+        let features = open_stream(&mut writer, &mut reader, config)?;
+        let plain_supported = features.child("mechanisms").map_or(false, |mechanisms| {
+            mechanisms
+                .children
+                .iter()
+                .any(|mechanism| mechanism.text == "PLAIN")
+        });
+        if !plain_supported {
+            bail!("server doesn't support PLAIN authentication");
+        }
+        let credentials = base64::engine::general_purpose::STANDARD
+            .encode(format!("\0{}\0{}", config.username, config.password));
+        writer.write_all(
+            format!("<auth xmlns='{SASL_NS}' mechanism='PLAIN'>{credentials}</auth>").as_bytes(),
+        )?;
+        let response = reader.read_stanza()?;
+        if response.name != "success" {
+            bail!("authentication as {} failed", config.username);
+        }
+
+        let features = open_stream(&mut writer, &mut reader, config)?;
+        if features.child("bind").is_none() {
+            bail!("server doesn't support binding a resource");
+        }
+        writer.write_all(
+            format!(
+                "<iq type='set' id='bind'><bind xmlns='{BIND_NS}'><resource>{}</resource></bind></iq>",
+                escape(&config.resource)
+            )
+            .as_bytes(),
+        )?;
+        let response = reader.read_stanza()?;
+        if response.name != "iq" || response.attr("type") != Some("result") {
+            bail!("binding resource {} failed", config.resource);
+        }
+        writer.write_all(b"<presence/>")?;
+        // shared with the reader
+        writer.set_read_timeout(None)?;
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn({
+            let config = config.clone();
+            move || read_events(&config, reader, sender)
+        });
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+            events: Mutex::new(receiver),
+            broken: AtomicBool::new(false),
+        })
+    }
+
+    fn is_broken(&self) -> bool {
+        self.broken.load(Ordering::SeqCst)
     }
 
-    pub fn new_shared() -> SharedAuctionHouseClient {
-        Arc::new(Self::new())
+    fn check<T>(&self, res: Result<T>) -> Result<T> {
+        if res.is_err() {
+            self.broken.store(true, Ordering::SeqCst);
+        }
+        res
+    }
+
+    fn send_message(&self, to: &str, message: &SolMessage) -> Result<()> {
+        let stanza = format!(
+            "<message to='{}' type='chat'><body>{}</body></message>",
+            escape(to),
+            escape(&message.to_string())
+        );
+        let res = self
+            .writer
+            .lock()
+            .expect("lock")
+            .write_all(stanza.as_bytes());
+        self.check(res.map_err(Into::into))
+    }
+
+    fn recv(&self, timeout: Option<Duration>) -> Result<Option<AuctionHouseEvent>> {
+        let events = self.events.lock().expect("lock");
+        let res = match timeout {
+            Some(timeout) => events.recv_timeout(timeout),
+            None => events
+                .recv()
+                .map_err(|mpsc::RecvError| mpsc::RecvTimeoutError::Disconnected),
+        };
+        match res {
+            Ok(event) => self.check(event).map(Some),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                self.check(Err(format_err!("connection closed")))
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // makes the reading thread finish too
+        let _ = self.writer.lock().expect("lock").shutdown(Shutdown::Both);
+    }
+}
+
+/// Connect to any of the addresses of the server, giving each one `config.timeout`
+fn connect(config: &XmppConfig) -> Result<TcpStream> {
+    let mut last_err = None;
+    for address in config.address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, config.timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.map_or_else(
+        || format_err!("no address for {}", config.address),
+        Into::into,
+    ))
+}
+
+/// Open (or restart) the stream, returning the features the server offers
+fn open_stream(
+    writer: &mut TcpStream,
+    reader: &mut StreamReader<BufReader<TcpStream>>,
+    config: &XmppConfig,
+) -> Result<Element> {
+    writer.write_all(
+        format!(
+            "<?xml version='1.0'?><stream:stream to='{}' version='1.0' xmlns='jabber:client' xmlns:stream='{STREAM_NS}'>",
+            escape(&config.domain)
+        )
+        .as_bytes(),
+    )?;
+    reader.read_stream_start()?;
+    let features = reader.read_stanza()?;
+    if features.name != "stream:features" {
+        bail!("expected stream features, got: {}", features.name);
+    }
+    Ok(features)
+}
+
+/// Turn messages from auctions into [`AuctionHouseEvent`]s, until the connection fails
+fn read_events(
+    config: &XmppConfig,
+    mut reader: StreamReader<BufReader<TcpStream>>,
+    sender: mpsc::Sender<Result<AuctionHouseEvent>>,
+) {
+    loop {
+        let stanza = match reader.read_stanza() {
+            Ok(stanza) => stanza,
+            Err(e) => {
+                let _ = sender.send(Err(e));
+                return;
+            }
+        };
+        if stanza.name != "message" {
+            continue;
+        }
+        let (Some(item), Some(body)) = (
+            stanza
+                .attr("from")
+                .and_then(|from| config.auction_item(from)),
+            stanza.child("body"),
+        ) else {
+            continue;
+        };
+        let message = match body.text.parse::<SolMessage>() {
+            Ok(message) => message,
+            Err(e) => {
+                warn!(%item, body = %body.text, error = %e, "invalid auction message");
+                continue;
+            }
+        };
+        let Some(event) =
+            message.to_item_event(|bidder| bidder == config.jid() || bidder == config.bare_jid())
+        else {
+            warn!(%item, body = %body.text, "unexpected auction message");
+            continue;
+        };
+        if sender.send(Ok(AuctionHouseEvent { item, event })).is_err() {
+            return;
+        }
     }
 }
 
 impl AuctionHouseClient for XmppAuctionHouseClient {
+    fn join(&self, item_id: ItemIdRef) -> Result<()> {
+        // same lock order as `Self::connection`, so a new connection can't miss it
+        let connection = self.connection.lock().expect("lock");
+        if !self.joined.lock().expect("lock").insert(item_id.to_owned()) {
+            return Ok(());
+        }
+        match connection.as_ref().filter(|c| !c.is_broken()) {
+            Some(connection) => {
+                debug!(?item_id, "joining auction");
+                connection.send_message(&self.config.auction_jid(item_id), &SolMessage::Join)
+            }
+            // joined once connected
+            None => Ok(()),
+        }
+    }
+
     fn place_bid(&self, item_id: ItemIdRef, price: Amount) -> Result<()> {
         debug!(?item_id, ?price, "sending bid");
-        todo!()
+        self.join(item_id)?;
+        self.connection()?.send_message(
+            &self.config.auction_jid(item_id),
+            &SolMessage::Bid { price },
+        )
     }
 
-    fn poll(&self, timeout: Option<Duration>) -> Result<Option<AuctionHouseEvent>> {
-        timeout.map(|t| std::thread::sleep(t));
-        // TODO
-        Ok(None)
+    fn poll(&self, timeout: Option<Duration>) -> Result<Option<PolledEvent>> {
+        Ok(self.connection()?.recv(timeout)?.map(PolledEvent::new))
     }
 }
@@ -0,0 +1,123 @@
+//! Just enough of an XML stream (RFC 6120) to talk XMPP
+//!
+//! Stanzas are small, so each one is read whole into an [`Element`].
+use anyhow::{bail, Result};
+use quick_xml::events::{BytesStart, Event};
+use std::io::BufRead;
+
+pub use quick_xml::escape::escape;
+
+pub const STREAM_NS: &str = "http://etherx.jabber.org/streams";
+pub const SASL_NS: &str = "urn:ietf:params:xml:ns:xmpp-sasl";
+pub const BIND_NS: &str = "urn:ietf:params:xml:ns:xmpp-bind";
+
+/// An XML element, with all its text content concatenated
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Element {
+    /// Qualified name, eg. `stream:features`
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    fn from_start(start: &BytesStart<'_>) -> Result<Self> {
+        let mut attrs = vec![];
+        for attr in start.attributes() {
+            let attr = attr?;
+            attrs.push((
+                String::from_utf8(attr.key.as_ref().to_vec())?,
+                attr.unescape_value()?.into_owned(),
+            ));
+        }
+        Ok(Self {
+            name: String::from_utf8(start.name().as_ref().to_vec())?,
+            attrs,
+            ..Default::default()
+        })
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+}
+
+/// Reads the stream of an XMPP connection, one stanza at the time
+pub struct StreamReader<R> {
+    reader: quick_xml::Reader<R>,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> StreamReader<R> {
+    pub fn new(reader: R) -> Self {
+        let mut reader = quick_xml::Reader::from_reader(reader);
+        // a restarted stream opens again without the previous one being closed
+        reader.trim_text(true).check_end_names(false);
+        Self {
+            reader,
+            buf: vec![],
+        }
+    }
+
+    /// Read the opening `<stream:stream>` tag
+    pub fn read_stream_start(&mut self) -> Result<Element> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(start) if start.name().as_ref() == b"stream:stream" => {
+                    return Element::from_start(&start)
+                }
+                Event::Decl(_) | Event::Comment(_) | Event::PI(_) => {}
+                Event::Eof => bail!("connection closed"),
+                event => bail!("expected a stream start, got: {event:?}"),
+            }
+        }
+    }
+
+    /// Read the next whole stanza (or any other element at the top of the stream)
+    pub fn read_stanza(&mut self) -> Result<Element> {
+        let mut open: Vec<Element> = vec![];
+        loop {
+            self.buf.clear();
+            let complete = match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(start) => {
+                    open.push(Element::from_start(&start)?);
+                    None
+                }
+                Event::Empty(start) => Some(Element::from_start(&start)?),
+                Event::End(_) => match open.pop() {
+                    Some(element) => Some(element),
+                    None => bail!("stream closed"),
+                },
+                Event::Text(text) => {
+                    if let Some(element) = open.last_mut() {
+                        element.text.push_str(&text.unescape()?);
+                    }
+                    None
+                }
+                Event::CData(data) => {
+                    if let Some(element) = open.last_mut() {
+                        element.text.push_str(std::str::from_utf8(&data)?);
+                    }
+                    None
+                }
+                Event::Eof => bail!("connection closed"),
+                Event::Decl(_) | Event::Comment(_) | Event::PI(_) | Event::DocType(_) => None,
+            };
+            if let Some(element) = complete {
+                match open.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+        }
+    }
+}
@@ -4,19 +4,50 @@
 //! determines if new bids should be created and of what amount.
 use crate::{
     auction::{Amount, BidDetails, Bidder, ItemBid, ItemId, ItemIdRef},
-    event::{AuctionHouseItemEvent, BiddingEngineAuctionError, BiddingEngineEvent, Event, UiEvent},
-    event_log,
-    persistence::{Connection, InMemoryTransaction, Transaction},
-    service,
+    event::{
+        topics::{
+            AuctionHouseEvent, AuctionHouseItemEvent, BidWithheld, BiddingEngineAuctionError,
+            BiddingEngineEvent, ItemStatus, SniperStatus, Strategy, TimerEvent, UiEvent,
+            WithholdReason,
+        },
+        Event, Subscription,
+    },
+    event_log::{self, CausedTransaction, WithMetadata},
+    persistence::{Connection, InMemoryTable, InMemoryTransaction, Transaction},
+    service::{self, Partition},
 };
 use anyhow::Result;
 use std::{
-    collections::BTreeMap,
-    sync::{Arc, Mutex},
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 use tracing::{debug, span, Level};
 
 mod postgres;
+mod sql;
+mod sqlite;
+mod strategy;
+pub use self::{postgres::*, sqlite::*, strategy::*};
+
+/// Events the [`BiddingEngine`] subscribes to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BiddingEngineInput {
+    AuctionHouse(AuctionHouseEvent),
+    Ui(UiEvent),
+    Timer(TimerEvent),
+}
+
+impl Subscription for BiddingEngineInput {
+    fn decode(event: &Event) -> Result<Option<Self>> {
+        Ok(if let Some(event) = event.decode()? {
+            Some(Self::AuctionHouse(event))
+        } else if let Some(event) = event.decode()? {
+            Some(Self::Ui(event))
+        } else {
+            event.decode()?.map(Self::Timer)
+        })
+    }
+}
 
 /// A store for the current state of each auction we participate in
 pub trait BiddingStateStore {
@@ -33,6 +64,34 @@ pub trait BiddingStateStore {
         state: AuctionBiddingState,
     ) -> Result<()>;
 
+    /// Load all the auctions that are still open and time passing might
+    /// make us bid in: with a known deadline, or a withheld bid
+    fn load_pending_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+    ) -> Result<Vec<(ItemId, AuctionBiddingState)>>;
+
+    /// Load all the auctions we might have money committed to:
+    /// the open ones and the ones we won
+    ///
+    /// Transactions that load them run one at a time, so each one sees
+    /// the amounts committed by the others, eg. when checking a budget.
+    fn load_committed_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+    ) -> Result<Vec<(ItemId, AuctionBiddingState)>>;
+
+    /// Make the other transactions loading the committed auctions wait for
+    /// this one, like [`Self::load_committed_tr`] does, without loading them
+    ///
+    /// Stores that run all their transactions one at a time don't need to
+    /// do anything.
+    fn lock_committed_tr(&self, _conn: &mut dyn Transaction<'_>) -> Result<()> {
+        Ok(())
+    }
+
+    // services only ever load and store within the transactions of their events
+    #[allow(dead_code)]
     fn load(
         &self,
         conn: &mut dyn Connection,
@@ -41,23 +100,27 @@ pub trait BiddingStateStore {
         self.load_tr(&mut *conn.start_transaction()?, item_id)
     }
 
+    #[allow(dead_code)] // see `Self::load`
     fn store(
         &self,
         conn: &mut dyn Connection,
         item_id: ItemIdRef,
         state: AuctionBiddingState,
     ) -> Result<()> {
-        self.store_tr(&mut *conn.start_transaction()?, item_id, state)
+        let mut transaction = conn.start_transaction()?;
+        self.store_tr(&mut *transaction, item_id, state)?;
+        transaction.commit()
     }
 }
 
 pub type SharedBiddingStateStore = Arc<dyn BiddingStateStore + Send + Sync>;
 
-pub struct InMemoryBiddingStateStore(Mutex<BTreeMap<ItemId, AuctionBiddingState>>);
+#[derive(Default)]
+pub struct InMemoryBiddingStateStore(InMemoryTable<ItemId, AuctionBiddingState>);
 
 impl InMemoryBiddingStateStore {
     pub fn new() -> Self {
-        Self(Mutex::new(BTreeMap::default()))
+        Self::default()
     }
 
     pub fn new_shared() -> SharedBiddingStateStore {
@@ -71,8 +134,9 @@ impl BiddingStateStore for InMemoryBiddingStateStore {
         conn: &mut dyn Transaction,
         item_id: ItemIdRef,
     ) -> Result<Option<AuctionBiddingState>> {
-        conn.cast().as_mut::<InMemoryTransaction>()?;
-        Ok(self.0.lock().expect("lock").get(item_id).cloned())
+        let mut caster = conn.cast();
+        let transaction = caster.as_mut::<InMemoryTransaction>()?;
+        Ok(self.0.get_tr(transaction, item_id))
     }
 
     fn store_tr<'a>(
@@ -81,13 +145,48 @@ impl BiddingStateStore for InMemoryBiddingStateStore {
         item_id: ItemIdRef,
         state: AuctionBiddingState,
     ) -> Result<()> {
-        conn.cast().as_mut::<InMemoryTransaction>()?;
-        self.0
-            .lock()
-            .expect("lock")
-            .insert(item_id.to_owned(), state);
+        let mut caster = conn.cast();
+        let transaction = caster.as_mut::<InMemoryTransaction>()?;
+
+        self.0.insert_tr(transaction, item_id.to_owned(), state);
         Ok(())
     }
+
+    fn load_pending_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+    ) -> Result<Vec<(ItemId, AuctionBiddingState)>> {
+        self.load_filtered_tr(conn, |state| {
+            !state.auction_state.closed
+                && (state.auction_state.deadline.is_some() || state.withheld_bid.is_some())
+        })
+    }
+
+    fn load_committed_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+    ) -> Result<Vec<(ItemId, AuctionBiddingState)>> {
+        self.load_filtered_tr(conn, |state| {
+            !state.auction_state.closed || state.status() == SniperStatus::Won
+        })
+    }
+}
+
+impl InMemoryBiddingStateStore {
+    fn load_filtered_tr(
+        &self,
+        conn: &mut dyn Transaction<'_>,
+        filter: impl Fn(&AuctionBiddingState) -> bool,
+    ) -> Result<Vec<(ItemId, AuctionBiddingState)>> {
+        let mut caster = conn.cast();
+        let transaction = caster.as_mut::<InMemoryTransaction>()?;
+        Ok(self
+            .0
+            .rows_tr(transaction)
+            .into_iter()
+            .filter(|(_, state)| filter(state))
+            .collect())
+    }
 }
 
 /// Bidding state from a perspective of the auction house
@@ -97,6 +196,8 @@ impl BiddingStateStore for InMemoryBiddingStateStore {
 pub struct AuctionState {
     pub higest_bid: Option<BidDetails>,
     pub closed: bool,
+    /// When the auction is going to close, if the auction house told us
+    pub deadline: Option<SystemTime>,
 }
 
 impl AuctionState {
@@ -113,6 +214,12 @@ impl AuctionState {
                 }
                 self
             }
+            AuctionHouseItemEvent::ClosesAt(deadline) => {
+                if !self.closed {
+                    self.deadline = Some(deadline);
+                }
+                self
+            }
             AuctionHouseItemEvent::Closed => {
                 self.closed = true;
                 self
@@ -153,6 +260,16 @@ impl AuctionState {
     }
     */
 
+    pub fn sniper_status(self) -> SniperStatus {
+        match (self.closed, self.higest_bid.map(|bid| bid.bidder)) {
+            (false, None) => SniperStatus::Joining,
+            (false, Some(Bidder::Other)) => SniperStatus::Bidding,
+            (false, Some(Bidder::Sniper)) => SniperStatus::Winning,
+            (true, Some(Bidder::Sniper)) => SniperStatus::Won,
+            (true, _) => SniperStatus::Lost,
+        }
+    }
+
     fn get_next_valid_bid(self, max_price: Amount) -> Option<Amount> {
         if self.closed {
             return None;
@@ -184,6 +301,9 @@ pub struct AuctionBiddingState {
     pub max_bid_limit: Amount,
     pub last_bid_sent: Option<Amount>,
     pub auction_state: AuctionState,
+    pub strategy: Strategy,
+    /// Bid the strategy wanted to place, but we withheld
+    pub withheld_bid: Option<Amount>,
 }
 
 impl AuctionBiddingState {
@@ -197,13 +317,46 @@ impl AuctionBiddingState {
             ..self
         }
     }
+
+    pub fn status(self) -> SniperStatus {
+        self.auction_state.sniper_status()
+    }
+
+    /// How much we might have to pay in this auction
+    ///
+    /// The price of our highest bid, or of the last bid we sent, if it
+    /// wasn't outbid yet.
+    pub fn committed_amount(self) -> Amount {
+        let higest_bid = self.auction_state.higest_bid;
+        let winning = higest_bid
+            .filter(|bid| bid.bidder == Bidder::Sniper)
+            .map(|bid| bid.price);
+        if self.auction_state.closed {
+            return winning.unwrap_or(0);
+        }
+        let outstanding = self
+            .last_bid_sent
+            .filter(|&sent| higest_bid.map_or(true, |bid| bid.price < sent));
+        winning.max(outstanding).unwrap_or(0)
+    }
 }
 
 pub const BIDDING_ENGINE_SERVICE_ID: &str = "bidding-engine";
 
+/// Can be spawned partitioned by [`ItemId`], as each auction
+/// is handled independently from others
+#[derive(Clone)]
 pub struct BiddingEngine {
     bidding_state_store: SharedBiddingStateStore,
     event_writer: event_log::SharedWriter,
+    /// Auctions this instance handles, if partitioned
+    partition: Option<Partition>,
+    /// Time of the last [`TimerEvent::Tick`], which all the other events are
+    /// handled at, so the decisions depend only on the log, not on when
+    /// it's handled. Until the first one, it's the dawn of time.
+    last_tick: SystemTime,
+    /// Total we are willing to have committed across all the auctions
+    budget: Option<Amount>,
 }
 
 impl BiddingEngine {
@@ -214,6 +367,46 @@ impl BiddingEngine {
         Self {
             bidding_state_store,
             event_writer,
+            partition: None,
+            last_tick: SystemTime::UNIX_EPOCH,
+            budget: None,
+        }
+    }
+
+    /// Withhold bids that would take the sum of
+    /// [`AuctionBiddingState::committed_amount`] of all the auctions over `budget`
+    ///
+    /// Withheld bids are retried on the next event of their auction, or
+    /// timer tick. Partitions check the budget one at a time (see
+    /// [`BiddingStateStore::load_committed_tr`]), so they can't overspend it together.
+    pub fn with_budget(self, budget: Amount) -> Self {
+        Self {
+            budget: Some(budget),
+            ..self
+        }
+    }
+
+    /// Sum of [`AuctionBiddingState::committed_amount`] of auctions other than `item_id`
+    fn committed_elsewhere_tr(
+        &self,
+        transaction: &mut dyn Transaction<'_>,
+        item_id: ItemIdRef,
+    ) -> Result<Amount> {
+        Ok(self
+            .bidding_state_store
+            .load_committed_tr(transaction)?
+            .into_iter()
+            .filter(|(other_item_id, _)| other_item_id != item_id)
+            .map(|(_, state)| state.committed_amount())
+            .fold(0, Amount::saturating_add))
+    }
+
+    /// Handle only the auctions in `partition`, for
+    /// [`service::ServiceControl::spawn_partitioned_log_follower`]
+    pub fn with_partition(self, partition: Partition) -> Self {
+        Self {
+            partition: Some(partition),
+            ..self
         }
     }
 
@@ -222,31 +415,54 @@ impl BiddingEngine {
         transaction: &mut dyn Transaction<'_>,
         item_id: ItemIdRef,
         data: T,
+        now: SystemTime,
         f: impl FnOnce(
             ItemIdRef,
             Option<AuctionBiddingState>,
             T,
+            SystemTime,
         ) -> Result<(Option<AuctionBiddingState>, Vec<BiddingEngineEvent>)>,
     ) -> Result<()> {
         let old_auction_state = self.bidding_state_store.load_tr(transaction, item_id)?;
 
-        let (new_auction_state, events) = f(item_id, old_auction_state, data)?;
+        let (new_auction_state, mut events) = f(item_id, old_auction_state, data, now)?;
+
+        if let Some(mut new_state) = new_auction_state {
+            let bidding = events
+                .iter()
+                .any(|event| matches!(event, BiddingEngineEvent::Bid(_)));
+            let committed_elsewhere = match self.budget {
+                Some(_) if bidding => self.committed_elsewhere_tr(transaction, item_id)?,
+                _ => 0,
+            };
+            (new_state, events) = Self::handle_budget(
+                self.budget,
+                committed_elsewhere,
+                old_auction_state,
+                new_state,
+                events,
+            );
 
-        if let Some(new_state) = new_auction_state {
             if Some(new_state) != old_auction_state {
                 self.bidding_state_store
                     .store_tr(transaction, item_id, new_state)?;
             }
+            events.extend(Self::handle_status_change(
+                item_id,
+                old_auction_state,
+                new_state,
+            ));
         }
 
         debug!(?events, "write events");
-        self.event_writer.write_tr(
-            transaction,
-            &events
-                .into_iter()
-                .map(Event::BiddingEngine)
-                .collect::<Vec<_>>(),
-        )?;
+        if self.budget.is_some() && !events.is_empty() {
+            // the budget check locks the committed auctions before writing
+            // its bid, so lock them before every write, or partitions
+            // locking them and the event log in the other order deadlock
+            self.bidding_state_store.lock_committed_tr(transaction)?;
+        }
+        self.event_writer
+            .write_tr(transaction, &Event::new_all(&events)?)?;
 
         Ok(())
     }
@@ -255,11 +471,13 @@ impl BiddingEngine {
         item_id: ItemIdRef,
         old_state: Option<AuctionBiddingState>,
         event: AuctionHouseItemEvent,
+        now: SystemTime,
     ) -> Result<(Option<AuctionBiddingState>, Vec<BiddingEngineEvent>)> {
         if let Some(auction_state) = old_state {
             Self::handle_next_bid_decision_for_new_state(
                 item_id,
                 auction_state.handle_auction_house_event(event),
+                now,
             )
         } else {
             Ok((
@@ -275,6 +493,7 @@ impl BiddingEngine {
         item_id: ItemIdRef,
         old_state: Option<AuctionBiddingState>,
         price: Amount,
+        now: SystemTime,
     ) -> Result<(Option<AuctionBiddingState>, Vec<BiddingEngineEvent>)> {
         let old_state = old_state.unwrap_or_default();
 
@@ -284,16 +503,107 @@ impl BiddingEngine {
                 max_bid_limit: price,
                 ..old_state
             },
+            now,
+        )
+    }
+
+    /// Time passed, which might make a [`LastMoment`] strategy bid
+    pub fn handle_tick_event(
+        item_id: ItemIdRef,
+        old_state: Option<AuctionBiddingState>,
+        now: SystemTime,
+    ) -> Result<(Option<AuctionBiddingState>, Vec<BiddingEngineEvent>)> {
+        match old_state {
+            Some(old_state) => {
+                Self::handle_next_bid_decision_for_new_state(item_id, old_state, now)
+            }
+            None => Ok((None, vec![])),
+        }
+    }
+
+    /// Event announcing the status of the sniper in `new_state`, if it changed
+    ///
+    /// The status of an auction we just joined is always announced.
+    pub fn handle_status_change(
+        item_id: ItemIdRef,
+        old_state: Option<AuctionBiddingState>,
+        new_state: AuctionBiddingState,
+    ) -> Option<BiddingEngineEvent> {
+        let status = new_state.status();
+        (old_state.map(AuctionBiddingState::status) != Some(status)).then(|| {
+            BiddingEngineEvent::StatusChanged(ItemStatus {
+                item: item_id.to_owned(),
+                status,
+            })
+        })
+    }
+
+    /// Withhold a bid in `events` that would take the total committed over `budget`
+    ///
+    /// The bid is not counted as sent, so the strategy tries it again later.
+    /// Withholding the same bid again is not announced again.
+    pub fn handle_budget(
+        budget: Option<Amount>,
+        committed_elsewhere: Amount,
+        old_state: Option<AuctionBiddingState>,
+        mut new_state: AuctionBiddingState,
+        events: Vec<BiddingEngineEvent>,
+    ) -> (AuctionBiddingState, Vec<BiddingEngineEvent>) {
+        new_state.withheld_bid = None;
+        let events = events
+            .into_iter()
+            .filter_map(|event| match event {
+                BiddingEngineEvent::Bid(bid) => {
+                    let Some(budget) = budget
+                        .filter(|&budget| budget < committed_elsewhere.saturating_add(bid.price))
+                    else {
+                        return Some(BiddingEngineEvent::Bid(bid));
+                    };
+                    new_state.last_bid_sent = old_state.and_then(|old| old.last_bid_sent);
+                    new_state.withheld_bid = Some(bid.price);
+                    let announced = old_state.and_then(|old| old.withheld_bid) == Some(bid.price);
+                    (!announced).then_some(BiddingEngineEvent::BidWithheld(BidWithheld {
+                        item: bid.item,
+                        price: bid.price,
+                        reason: WithholdReason::OverBudget {
+                            budget,
+                            committed: committed_elsewhere,
+                        },
+                    }))
+                }
+                event => Some(event),
+            })
+            .collect();
+        (new_state, events)
+    }
+
+    pub fn handle_strategy_set_event(
+        item_id: ItemIdRef,
+        old_state: Option<AuctionBiddingState>,
+        strategy: Strategy,
+        now: SystemTime,
+    ) -> Result<(Option<AuctionBiddingState>, Vec<BiddingEngineEvent>)> {
+        let old_state = old_state.unwrap_or_default();
+
+        Self::handle_next_bid_decision_for_new_state(
+            item_id,
+            AuctionBiddingState {
+                strategy,
+                ..old_state
+            },
+            now,
         )
     }
 
     pub fn handle_next_bid_decision_for_new_state(
         item_id: ItemIdRef,
         mut new_state: AuctionBiddingState,
+        now: SystemTime,
     ) -> Result<(Option<AuctionBiddingState>, Vec<BiddingEngineEvent>)> {
-        if let Some(our_new_bid) = new_state
-            .auction_state
-            .get_next_valid_bid(new_state.max_bid_limit)
+        if let Some(our_new_bid) =
+            new_state
+                .strategy
+                .next_bid(new_state.auction_state, new_state.max_bid_limit, now)
         {
             if new_state.is_bid_better_than_last_bid_sent(our_new_bid) {
                 new_state.last_bid_sent = Some(our_new_bid);
@@ -315,24 +625,109 @@ impl BiddingEngine {
 }
 
 impl service::LogFollowerService for BiddingEngine {
-    fn handle_event(&mut self, transaction: &mut dyn Transaction<'_>, event: Event) -> Result<()> {
+    type Event = BiddingEngineInput;
+
+    fn restart_policy(&self) -> service::RestartPolicy {
+        // mostly transactions aborted by the database, eg. on serialization
+        // failures, which succeed when tried again
+        service::RestartPolicy::ExponentialBackoff {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            max_retries: 10,
+        }
+    }
+
+    fn batch_config(&self) -> service::BatchConfig {
+        // handling events is cheap, committing is not
+        service::BatchConfig {
+            max_size: 100,
+            max_latency: Duration::from_millis(10),
+        }
+    }
+
+    fn partition_key<'e>(&self, event: &'e BiddingEngineInput) -> Option<&'e str> {
+        match event {
+            BiddingEngineInput::AuctionHouse(event) => Some(&event.item),
+            BiddingEngineInput::Ui(UiEvent::MaxBidSet(item_bid)) => Some(&item_bid.item),
+            BiddingEngineInput::Ui(UiEvent::StrategySet(item_strategy)) => {
+                Some(&item_strategy.item)
+            }
+            // each partition checks the auctions it handles
+            BiddingEngineInput::Timer(_) => None,
+        }
+    }
+
+    fn handle_batch(
+        &mut self,
+        transaction: &mut dyn Transaction<'_>,
+        events: Vec<WithMetadata<BiddingEngineInput>>,
+    ) -> Result<()> {
+        // a failed batch is handled again, so its ticks must not count
+        let last_tick = self.last_tick;
+        for WithMetadata { metadata, data } in events {
+            let res = self.handle_event(&mut CausedTransaction::new(transaction, metadata), data);
+            if res.is_err() {
+                self.last_tick = last_tick;
+                return res;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_event(
+        &mut self,
+        transaction: &mut dyn Transaction<'_>,
+        event: BiddingEngineInput,
+    ) -> Result<()> {
         let span = span!(Level::DEBUG, "bidding engine - handle event");
         let _guard = span.enter();
         debug!(?event, "event");
+        let now = self.last_tick;
         match event {
-            Event::AuctionHouse(event) => self.handle_auction_item_event_with(
+            BiddingEngineInput::AuctionHouse(event) => self.handle_auction_item_event_with(
                 transaction,
                 &event.item,
                 event.event,
+                now,
                 Self::handle_auction_house_event,
             )?,
-            Event::Ui(UiEvent::MaxBidSet(item_bid)) => self.handle_auction_item_event_with(
-                transaction,
-                &item_bid.item,
-                item_bid.price,
-                Self::handle_max_bid_limit_event,
-            )?,
-            _ => (),
+            BiddingEngineInput::Ui(UiEvent::MaxBidSet(item_bid)) => self
+                .handle_auction_item_event_with(
+                    transaction,
+                    &item_bid.item,
+                    item_bid.price,
+                    now,
+                    Self::handle_max_bid_limit_event,
+                )?,
+            BiddingEngineInput::Ui(UiEvent::StrategySet(item_strategy)) => self
+                .handle_auction_item_event_with(
+                    transaction,
+                    &item_strategy.item,
+                    item_strategy.strategy,
+                    now,
+                    Self::handle_strategy_set_event,
+                )?,
+            BiddingEngineInput::Timer(TimerEvent::Tick(now)) => {
+                self.last_tick = self.last_tick.max(now);
+                let now = self.last_tick;
+                for (item_id, _) in self.bidding_state_store.load_pending_tr(transaction)? {
+                    let ours = self
+                        .partition
+                        .map_or(true, |partition| partition.contains(Some(&item_id)));
+                    if !ours {
+                        continue;
+                    }
+                    self.handle_auction_item_event_with(
+                        transaction,
+                        &item_id,
+                        (),
+                        now,
+                        |item_id, old_state, (), now| {
+                            Self::handle_tick_event(item_id, old_state, now)
+                        },
+                    )?;
+                }
+            }
         };
         Ok(())
     }
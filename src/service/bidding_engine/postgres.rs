@@ -1,57 +1,211 @@
+use super::{sql::*, *};
 use crate::persistence::{
-    postgres::PostgresTransaction, Connection, PostgresConnection, Transaction,
+    migration::{ComponentMigrations, Migration},
+    PostgresConnection, PostgresTransaction,
 };
-use anyhow::Result;
-use std::convert::TryFrom;
+use ::postgres::GenericClient;
 
-pub struct PostgresBiddingStateStore {
-    client: postgres::Client,
+/// Advisory lock serializing [`BiddingStateStore::load_committed_tr`]
+const COMMITTED_LOCK_ID: i64 = 0x5ec0_b1d5;
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        sql: "CREATE TABLE IF NOT EXISTS bidding_state (
+            item_id TEXT PRIMARY KEY,
+            max_bid_limit BIGINT NOT NULL,
+            last_bid_sent BIGINT,
+            highest_bid_bidder TEXT,
+            highest_bid_price BIGINT,
+            highest_bid_increment BIGINT,
+            closed BOOLEAN NOT NULL
+        )",
+    },
+    Migration {
+        version: 2,
+        // JSON of the `Strategy`; existing auctions keep bidding like before
+        sql: "ALTER TABLE bidding_state
+            ADD COLUMN strategy TEXT NOT NULL DEFAULT '{\"ImmediateOutbid\":null}'",
+    },
+    Migration {
+        version: 3,
+        sql: "ALTER TABLE bidding_state ADD COLUMN deadline TIMESTAMPTZ",
+    },
+    Migration {
+        version: 4,
+        sql: "ALTER TABLE bidding_state ADD COLUMN withheld_bid BIGINT",
+    },
+];
+
+/// [`BiddingStateStore`] keeping the state of each auction in a Postgres table
+#[derive(Debug, Clone)]
+pub struct PostgresBiddingStateStore;
+
+impl PostgresBiddingStateStore {
+    pub const MIGRATIONS: ComponentMigrations = ComponentMigrations {
+        component: "bidding_state",
+        migrations: MIGRATIONS,
+    };
+
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn new_shared() -> SharedBiddingStateStore {
+        Arc::new(Self::new())
+    }
 }
 
-impl super::BiddingStateStore for PostgresBiddingStateStore {
-    #[allow(unreachable_code)]
+fn state_from_row(row: &::postgres::Row) -> Result<AuctionBiddingState> {
+    let item_id: &str = row.get("item_id");
+    let higest_bid = highest_bid_from_sql(
+        item_id,
+        row.get("highest_bid_bidder"),
+        row.get("highest_bid_price"),
+        row.get("highest_bid_increment"),
+    )?;
+
+    Ok(AuctionBiddingState {
+        max_bid_limit: amount_from_sql(row.get("max_bid_limit"))?,
+        last_bid_sent: row
+            .get::<'_, _, Option<i64>>("last_bid_sent")
+            .map(amount_from_sql)
+            .transpose()?,
+        auction_state: AuctionState {
+            closed: row.get("closed"),
+            higest_bid,
+            deadline: row.get("deadline"),
+        },
+        strategy: strategy_from_sql(row.get("strategy"))?,
+        withheld_bid: row
+            .get::<'_, _, Option<i64>>("withheld_bid")
+            .map(amount_from_sql)
+            .transpose()?,
+    })
+}
+
+fn query_state(
+    client: &mut impl GenericClient,
+    item_id: ItemIdRef,
+) -> Result<Option<AuctionBiddingState>> {
+    client
+        .query_opt(
+            &format!("SELECT {COLUMNS} FROM bidding_state WHERE item_id = $1"),
+            &[&item_id],
+        )?
+        .map(|row| state_from_row(&row))
+        .transpose()
+}
+
+fn query_states_tr(
+    conn: &mut dyn Transaction,
+    condition: &str,
+    params: &[&(dyn ::postgres::types::ToSql + Sync)],
+) -> Result<Vec<(ItemId, AuctionBiddingState)>> {
+    conn.cast()
+        .as_mut::<PostgresTransaction>()?
+        .0
+        .query(
+            &format!(
+                "SELECT {COLUMNS} FROM bidding_state
+                WHERE {condition}
+                ORDER BY item_id"
+            ),
+            params,
+        )?
+        .iter()
+        .map(|row| Ok((row.get("item_id"), state_from_row(row)?)))
+        .collect()
+}
+
+impl BiddingStateStore for PostgresBiddingStateStore {
     fn load_tr(
         &self,
         conn: &mut dyn Transaction,
-        item_id: crate::auction::ItemIdRef,
-    ) -> anyhow::Result<Option<super::AuctionBiddingState>> {
-        conn.cast().as_mut::<PostgresTransaction>()?.0.query_opt("SELECT max_bid_limit, last_bid_sent, higest_bid_bidder, higest_bid_price, highest_bid_increment, closed FROM bidding_state WHERE item_id = $0", &[&item_id])?
-            .map::<Result<_>, _>(|row| {
-            Ok(super::AuctionBiddingState {
-                max_bid_limit: u64::try_from(row.get::<'_, _, i64>("max_bid_limit"))?,
-                last_bid_sent: row.get::<'_,_, Option<i64>>("last_bid_sent").map(u64::try_from).transpose()?,
-                auction_state: super::AuctionState {
-                    closed: row.get("closed"),
-                    higest_bid: todo!(),
-                }
-            })
-        }).transpose()
-    }
-
-    #[allow(unreachable_code)]
+        item_id: ItemIdRef,
+    ) -> Result<Option<AuctionBiddingState>> {
+        query_state(&mut conn.cast().as_mut::<PostgresTransaction>()?.0, item_id)
+    }
+
     fn load(
         &self,
         conn: &mut dyn Connection,
-        item_id: crate::auction::ItemIdRef,
-    ) -> anyhow::Result<Option<super::AuctionBiddingState>> {
-        conn.cast().as_mut::<PostgresConnection>()?.0.query_opt("SELECT max_bid_limit, last_bid_sent, higest_bid_bidder, higest_bid_price, highest_bid_increment, closed FROM bidding_state WHERE item_id = $0", &[&item_id])?
-            .map::<Result<_>, _>(|row| {
-            Ok(super::AuctionBiddingState {
-                max_bid_limit: u64::try_from(row.get::<'_, _, i64>("max_bid_limit"))?,
-                last_bid_sent: row.get::<'_,_, Option<i64>>("last_bid_sent").map(u64::try_from).transpose()?,
-                auction_state: super::AuctionState {
-                    closed: row.get("closed"),
-                    higest_bid: todo!(),
-                }
-            })
-        }).transpose()
+        item_id: ItemIdRef,
+    ) -> Result<Option<AuctionBiddingState>> {
+        query_state(&mut *conn.cast().as_mut::<PostgresConnection>()?.0, item_id)
+    }
+
+    fn load_pending_tr(
+        &self,
+        conn: &mut dyn Transaction,
+    ) -> Result<Vec<(ItemId, AuctionBiddingState)>> {
+        query_states_tr(
+            conn,
+            "NOT closed AND (deadline IS NOT NULL OR withheld_bid IS NOT NULL)",
+            &[],
+        )
+    }
+
+    fn load_committed_tr(
+        &self,
+        conn: &mut dyn Transaction,
+    ) -> Result<Vec<(ItemId, AuctionBiddingState)>> {
+        self.lock_committed_tr(conn)?;
+        query_states_tr(
+            conn,
+            "NOT closed OR highest_bid_bidder = $1",
+            &[&Bidder::Sniper.as_str()],
+        )
+    }
+
+    fn lock_committed_tr(&self, conn: &mut dyn Transaction<'_>) -> Result<()> {
+        // unlike the others, read committed transactions don't wait for each
+        // other; taking it again in the same transaction doesn't wait
+        conn.cast()
+            .as_mut::<PostgresTransaction>()?
+            .0
+            .execute("SELECT pg_advisory_xact_lock($1)", &[&COMMITTED_LOCK_ID])?;
+        Ok(())
     }
+
     fn store_tr(
         &self,
-        _conn: &mut dyn Transaction,
-        _item_id: crate::auction::ItemIdRef,
-        _state: super::AuctionBiddingState,
-    ) -> anyhow::Result<()> {
-        todo!()
+        conn: &mut dyn Transaction,
+        item_id: ItemIdRef,
+        state: AuctionBiddingState,
+    ) -> Result<()> {
+        let higest_bid = state.auction_state.higest_bid;
+
+        conn.cast().as_mut::<PostgresTransaction>()?.0.execute(
+            &format!(
+                "INSERT INTO bidding_state ({COLUMNS})
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                ON CONFLICT (item_id) DO UPDATE SET
+                    max_bid_limit = EXCLUDED.max_bid_limit,
+                    last_bid_sent = EXCLUDED.last_bid_sent,
+                    highest_bid_bidder = EXCLUDED.highest_bid_bidder,
+                    highest_bid_price = EXCLUDED.highest_bid_price,
+                    highest_bid_increment = EXCLUDED.highest_bid_increment,
+                    closed = EXCLUDED.closed,
+                    strategy = EXCLUDED.strategy,
+                    deadline = EXCLUDED.deadline,
+                    withheld_bid = EXCLUDED.withheld_bid"
+            ),
+            &[
+                &item_id,
+                &amount_to_sql(state.max_bid_limit)?,
+                &state.last_bid_sent.map(amount_to_sql).transpose()?,
+                &higest_bid.map(|bid| bid.bidder.as_str()),
+                &higest_bid.map(|bid| amount_to_sql(bid.price)).transpose()?,
+                &higest_bid
+                    .map(|bid| amount_to_sql(bid.increment))
+                    .transpose()?,
+                &state.auction_state.closed,
+                &strategy_to_sql(state.strategy)?,
+                &state.auction_state.deadline,
+                &state.withheld_bid.map(amount_to_sql).transpose()?,
+            ],
+        )?;
+        Ok(())
     }
 }
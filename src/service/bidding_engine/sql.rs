@@ -0,0 +1,39 @@
+//! Conversions shared by the SQL [`BiddingStateStore`]s
+use super::*;
+use anyhow::bail;
+
+pub(super) const COLUMNS: &str = "item_id, max_bid_limit, last_bid_sent, highest_bid_bidder, highest_bid_price, highest_bid_increment, closed, strategy, deadline, withheld_bid";
+
+pub(super) fn amount_to_sql(amount: Amount) -> Result<i64> {
+    Ok(i64::try_from(amount)?)
+}
+
+pub(super) fn amount_from_sql(amount: i64) -> Result<Amount> {
+    Ok(u64::try_from(amount)?)
+}
+
+pub(super) fn strategy_to_sql(strategy: Strategy) -> Result<String> {
+    Ok(serde_json::to_string(&strategy)?)
+}
+
+pub(super) fn strategy_from_sql(strategy: &str) -> Result<Strategy> {
+    Ok(serde_json::from_str(strategy)?)
+}
+
+/// Highest bid from its columns, which are all set or all `NULL`
+pub(super) fn highest_bid_from_sql(
+    item_id: ItemIdRef,
+    bidder: Option<&str>,
+    price: Option<i64>,
+    increment: Option<i64>,
+) -> Result<Option<BidDetails>> {
+    Ok(match (bidder, price, increment) {
+        (Some(bidder), Some(price), Some(increment)) => Some(BidDetails {
+            bidder: bidder.parse()?,
+            price: amount_from_sql(price)?,
+            increment: amount_from_sql(increment)?,
+        }),
+        (None, None, None) => None,
+        _ => bail!("incomplete highest bid of item {item_id}"),
+    })
+}
@@ -0,0 +1,231 @@
+use super::{sql::*, *};
+use crate::persistence::{
+    migration::{ComponentMigrations, Migration},
+    timestamp_from_sql, timestamp_to_sql, SqliteConnection, SqliteTransaction,
+};
+use rusqlite::OptionalExtension;
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        sql: "CREATE TABLE IF NOT EXISTS bidding_state (
+            item_id TEXT PRIMARY KEY,
+            max_bid_limit INTEGER NOT NULL,
+            last_bid_sent INTEGER,
+            highest_bid_bidder TEXT,
+            highest_bid_price INTEGER,
+            highest_bid_increment INTEGER,
+            closed BOOLEAN NOT NULL
+        )",
+    },
+    Migration {
+        version: 2,
+        // JSON of the `Strategy`; existing auctions keep bidding like before
+        sql: "ALTER TABLE bidding_state
+            ADD COLUMN strategy TEXT NOT NULL DEFAULT '{\"ImmediateOutbid\":null}'",
+    },
+    Migration {
+        version: 3,
+        // in microseconds since the unix epoch
+        sql: "ALTER TABLE bidding_state ADD COLUMN deadline INTEGER",
+    },
+    Migration {
+        version: 4,
+        sql: "ALTER TABLE bidding_state ADD COLUMN withheld_bid INTEGER",
+    },
+];
+
+/// [`BiddingStateStore`] keeping the state of each auction in a SQLite table
+#[derive(Debug, Clone)]
+pub struct SqliteBiddingStateStore;
+
+impl SqliteBiddingStateStore {
+    pub const MIGRATIONS: ComponentMigrations = ComponentMigrations {
+        component: "bidding_state",
+        migrations: MIGRATIONS,
+    };
+
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn new_shared() -> SharedBiddingStateStore {
+        Arc::new(Self::new())
+    }
+}
+
+type Row = (
+    String,
+    i64,
+    Option<i64>,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+    bool,
+    String,
+    Option<i64>,
+    Option<i64>,
+);
+
+fn get_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Row> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        row.get(6)?,
+        row.get(7)?,
+        row.get(8)?,
+        row.get(9)?,
+    ))
+}
+
+fn state_from_row(
+    (
+        item_id,
+        max_bid_limit,
+        last_bid_sent,
+        bidder,
+        price,
+        increment,
+        closed,
+        strategy,
+        deadline,
+        withheld_bid,
+    ): Row,
+) -> Result<(ItemId, AuctionBiddingState)> {
+    let higest_bid = highest_bid_from_sql(&item_id, bidder.as_deref(), price, increment)?;
+
+    let state = AuctionBiddingState {
+        max_bid_limit: amount_from_sql(max_bid_limit)?,
+        last_bid_sent: last_bid_sent.map(amount_from_sql).transpose()?,
+        auction_state: AuctionState {
+            closed,
+            higest_bid,
+            deadline: deadline.map(timestamp_from_sql).transpose()?,
+        },
+        strategy: strategy_from_sql(&strategy)?,
+        withheld_bid: withheld_bid.map(amount_from_sql).transpose()?,
+    };
+    Ok((item_id, state))
+}
+
+fn query_state(
+    conn: &rusqlite::Connection,
+    item_id: ItemIdRef,
+) -> Result<Option<AuctionBiddingState>> {
+    conn.query_row(
+        &format!("SELECT {COLUMNS} FROM bidding_state WHERE item_id = ?1"),
+        [item_id],
+        get_row,
+    )
+    .optional()?
+    .map(|row| Ok(state_from_row(row)?.1))
+    .transpose()
+}
+
+fn query_states_tr(
+    conn: &mut dyn Transaction,
+    condition: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<(ItemId, AuctionBiddingState)>> {
+    let mut caster = conn.cast();
+    let transaction = caster.as_mut::<SqliteTransaction>()?;
+    let rows = transaction
+        .0
+        .prepare(&format!(
+            "SELECT {COLUMNS} FROM bidding_state
+            WHERE {condition}
+            ORDER BY item_id"
+        ))?
+        .query_map(params, get_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    rows.into_iter().map(state_from_row).collect()
+}
+
+impl BiddingStateStore for SqliteBiddingStateStore {
+    fn load_tr(
+        &self,
+        conn: &mut dyn Transaction,
+        item_id: ItemIdRef,
+    ) -> Result<Option<AuctionBiddingState>> {
+        query_state(&conn.cast().as_mut::<SqliteTransaction>()?.0, item_id)
+    }
+
+    fn load(
+        &self,
+        conn: &mut dyn Connection,
+        item_id: ItemIdRef,
+    ) -> Result<Option<AuctionBiddingState>> {
+        query_state(&conn.cast().as_mut::<SqliteConnection>()?.0, item_id)
+    }
+
+    fn load_pending_tr(
+        &self,
+        conn: &mut dyn Transaction,
+    ) -> Result<Vec<(ItemId, AuctionBiddingState)>> {
+        query_states_tr(
+            conn,
+            "NOT closed AND (deadline IS NOT NULL OR withheld_bid IS NOT NULL)",
+            [],
+        )
+    }
+
+    fn load_committed_tr(
+        &self,
+        conn: &mut dyn Transaction,
+    ) -> Result<Vec<(ItemId, AuctionBiddingState)>> {
+        query_states_tr(
+            conn,
+            "NOT closed OR highest_bid_bidder = ?1",
+            [Bidder::Sniper.as_str()],
+        )
+    }
+
+    fn store_tr(
+        &self,
+        conn: &mut dyn Transaction,
+        item_id: ItemIdRef,
+        state: AuctionBiddingState,
+    ) -> Result<()> {
+        let higest_bid = state.auction_state.higest_bid;
+
+        conn.cast().as_mut::<SqliteTransaction>()?.0.execute(
+            &format!(
+                "INSERT INTO bidding_state ({COLUMNS})
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                ON CONFLICT (item_id) DO UPDATE SET
+                    max_bid_limit = excluded.max_bid_limit,
+                    last_bid_sent = excluded.last_bid_sent,
+                    highest_bid_bidder = excluded.highest_bid_bidder,
+                    highest_bid_price = excluded.highest_bid_price,
+                    highest_bid_increment = excluded.highest_bid_increment,
+                    closed = excluded.closed,
+                    strategy = excluded.strategy,
+                    deadline = excluded.deadline,
+                    withheld_bid = excluded.withheld_bid"
+            ),
+            rusqlite::params![
+                item_id,
+                amount_to_sql(state.max_bid_limit)?,
+                state.last_bid_sent.map(amount_to_sql).transpose()?,
+                higest_bid.map(|bid| bid.bidder.as_str()),
+                higest_bid.map(|bid| amount_to_sql(bid.price)).transpose()?,
+                higest_bid
+                    .map(|bid| amount_to_sql(bid.increment))
+                    .transpose()?,
+                state.auction_state.closed,
+                strategy_to_sql(state.strategy)?,
+                state
+                    .auction_state
+                    .deadline
+                    .map(timestamp_to_sql)
+                    .transpose()?,
+                state.withheld_bid.map(amount_to_sql).transpose()?,
+            ],
+        )?;
+        Ok(())
+    }
+}
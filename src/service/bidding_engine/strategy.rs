@@ -0,0 +1,78 @@
+//! Bidding strategies
+//!
+//! A strategy decides what (if anything) to bid next in an auction,
+//! within the limit set by the user. Each item can use a different one,
+//! selected with [`UiEvent::StrategySet`], so the strategies themselves
+//! are plain data in [`crate::event::topics`], and their logic is here.
+use super::*;
+use crate::event::topics::{FixedStep, ImmediateOutbid, LastMoment};
+
+pub trait BiddingStrategy {
+    /// The amount to bid next in the `auction`, if any, at time `now`
+    ///
+    /// Must never exceed `max_bid_limit`.
+    fn next_bid(
+        &self,
+        auction: AuctionState,
+        max_bid_limit: Amount,
+        now: SystemTime,
+    ) -> Option<Amount>;
+}
+
+impl BiddingStrategy for ImmediateOutbid {
+    fn next_bid(
+        &self,
+        auction: AuctionState,
+        max_bid_limit: Amount,
+        _now: SystemTime,
+    ) -> Option<Amount> {
+        auction.get_next_valid_bid(max_bid_limit)
+    }
+}
+
+impl BiddingStrategy for FixedStep {
+    fn next_bid(
+        &self,
+        auction: AuctionState,
+        max_bid_limit: Amount,
+        _now: SystemTime,
+    ) -> Option<Amount> {
+        auction
+            .get_next_valid_bid(max_bid_limit)
+            .map(|minimum| minimum.saturating_add(self.step).min(max_bid_limit))
+    }
+}
+
+impl BiddingStrategy for LastMoment {
+    fn next_bid(
+        &self,
+        auction: AuctionState,
+        max_bid_limit: Amount,
+        now: SystemTime,
+    ) -> Option<Amount> {
+        // without a known deadline, there's no telling when the last moment is
+        let deadline = auction.deadline?;
+        if deadline
+            .checked_sub(self.window)
+            .map_or(false, |window_start| now < window_start)
+        {
+            return None;
+        }
+        ImmediateOutbid.next_bid(auction, max_bid_limit, now)
+    }
+}
+
+impl BiddingStrategy for Strategy {
+    fn next_bid(
+        &self,
+        auction: AuctionState,
+        max_bid_limit: Amount,
+        now: SystemTime,
+    ) -> Option<Amount> {
+        match self {
+            Strategy::ImmediateOutbid(strategy) => strategy.next_bid(auction, max_bid_limit, now),
+            Strategy::FixedStep(strategy) => strategy.next_bid(auction, max_bid_limit, now),
+            Strategy::LastMoment(strategy) => strategy.next_bid(auction, max_bid_limit, now),
+        }
+    }
+}
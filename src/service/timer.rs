@@ -0,0 +1,82 @@
+//! Timer
+//!
+//! Writes the passing of time into the log, so log followers can act
+//! on it (eg. bid just before an auction ends) while still handling
+//! events one at a time, in order.
+use crate::{
+    clock::SharedClock,
+    event::{topics::TimerEvent, Event},
+    event_log,
+    persistence::SharedPersistence,
+    service::{bidding_engine::SharedBiddingStateStore, LoopService},
+};
+use anyhow::Result;
+use std::time::{Duration, SystemTime};
+
+/// Writes a [`TimerEvent::Tick`] every `interval` of the `clock`
+pub struct Timer {
+    persistence: SharedPersistence,
+    event_writer: event_log::SharedWriter,
+    clock: SharedClock,
+    interval: Duration,
+    /// When to write the next tick, `interval` after starting
+    next_tick: Option<SystemTime>,
+    /// Tick only while it has any pending auctions
+    bidding_state_store: Option<SharedBiddingStateStore>,
+}
+
+impl Timer {
+    pub fn new(
+        persistence: SharedPersistence,
+        event_writer: event_log::SharedWriter,
+        clock: SharedClock,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            persistence,
+            event_writer,
+            clock,
+            interval,
+            next_tick: None,
+            bidding_state_store: None,
+        }
+    }
+
+    /// Tick only while `bidding_state_store` has auctions that time passing
+    /// might make us bid in (see [`BiddingStateStore::load_pending_tr`]),
+    /// instead of filling the log with ticks no one acts on
+    ///
+    /// [`BiddingStateStore::load_pending_tr`]: crate::service::bidding_engine::BiddingStateStore::load_pending_tr
+    pub fn with_pending_auctions(self, bidding_state_store: SharedBiddingStateStore) -> Self {
+        Self {
+            bidding_state_store: Some(bidding_state_store),
+            ..self
+        }
+    }
+}
+
+impl LoopService for Timer {
+    fn run_iteration(&mut self) -> Result<()> {
+        let now = self.clock.now();
+        let next_tick = *self.next_tick.get_or_insert(now + self.interval);
+        if now < next_tick {
+            // the clock might wake us up early, check again in the next iteration
+            self.clock
+                .sleep(next_tick.duration_since(now).unwrap_or_default());
+            return Ok(());
+        }
+
+        let mut connection = self.persistence.get_connection()?;
+        self.next_tick = Some(now + self.interval);
+        if let Some(bidding_state_store) = &self.bidding_state_store {
+            let pending =
+                bidding_state_store.load_pending_tr(&mut *connection.start_transaction()?)?;
+            if pending.is_empty() {
+                return Ok(());
+            }
+        }
+        self.event_writer
+            .write(&mut *connection, &[Event::new(&TimerEvent::Tick(now))?])?;
+        Ok(())
+    }
+}
@@ -1,62 +1,104 @@
 use crate::{
     auction::{Amount, ItemBid},
-    event, event_log,
-    persistence::SharedPersistence,
-    service::LoopService,
+    dead_letter::{DeadLetter, SharedDeadLetterStore},
+    event::{
+        topics::{ItemStrategy, Strategy, UiEvent},
+        Event,
+    },
+    event_log::{self, Offset},
+    persistence::SharedAsyncPersistence,
+    service::AsyncLoopService,
 };
-use anyhow::{format_err, Context, Result};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
 use axum::{
+    extract::Path,
     http::StatusCode,
-    routing::{get, post},
+    routing::{delete, get, post},
     Json, Router,
 };
 use serde::Deserialize;
-use tokio::{runtime::Runtime, sync::oneshot};
+use std::net::SocketAddr;
 
+/// HTTP interface for the user (and the admin)
 pub struct Ui {
-    // cancels all tasks on drop
-    _runtime: Runtime,
-    server_rx: oneshot::Receiver<Result<()>>,
+    persistence: SharedAsyncPersistence,
+    even_writer: event_log::SharedAsyncWriter,
+    dead_letter_store: SharedDeadLetterStore,
+    address: SocketAddr,
 }
 
 #[derive(Deserialize)]
 struct BidRequest {
     item: String,
     price: Amount,
+    /// Keep the current strategy of the item if not set
+    #[serde(default)]
+    strategy: Option<Strategy>,
 }
 
 async fn handle_bid_request(
-    persistence: SharedPersistence,
-    even_writer: event_log::SharedWriter,
+    persistence: SharedAsyncPersistence,
+    even_writer: event_log::SharedAsyncWriter,
     bid_request: BidRequest,
 ) -> Result<()> {
-    // OK, so here's the deal; mixing sync & async
-    // code is a PITA and I don't want to convert
-    // the whole project into async, at least ATM.
-    // For mixing, one could define new set of traits
-    // with async methods, and that works OKish,
-    // though I've hit a problem of not being able
-    // to share a [tokio::sync::Mutex] between
-    // sync & async code in [crate::persistence::InMemoryPersistence].
-    //
-    // Using `spawn_blocking` is lazy and should work, so I
-    // leave it at that.
-    tokio::task::spawn_blocking(move || {
-        even_writer.write(
-            &mut *persistence.get_connection()?,
-            &[event::Event::Ui(event::UiEvent::MaxBidSet(ItemBid {
-                item: bid_request.item,
-                price: bid_request.price,
-            }))],
-        )
-    })
-    .await??;
+    let mut events = vec![];
+    // set the strategy first, so it's used already for the first bid
+    if let Some(strategy) = bid_request.strategy {
+        events.push(Event::new(&UiEvent::StrategySet(ItemStrategy {
+            item: bid_request.item.clone(),
+            strategy,
+        }))?);
+    }
+    events.push(Event::new(&UiEvent::MaxBidSet(ItemBid {
+        item: bid_request.item,
+        price: bid_request.price,
+    }))?);
+
+    even_writer
+        .write(&mut *persistence.get_connection().await?, &events)
+        .await?;
     Ok(())
 }
 
+async fn handle_list_dead_letters(
+    persistence: SharedAsyncPersistence,
+    dead_letter_store: SharedDeadLetterStore,
+) -> Result<Vec<DeadLetter>> {
+    persistence
+        .get_connection()
+        .await?
+        .blocking(move |conn| dead_letter_store.list(conn, None))
+        .await
+}
+
+/// Request a re-drive of the dead letter, or discard it
+///
+/// Returns `false` if there was no such dead letter.
+async fn handle_dead_letter_request(
+    persistence: SharedAsyncPersistence,
+    dead_letter_store: SharedDeadLetterStore,
+    (service_id, offset): (String, Offset),
+    redrive: bool,
+) -> Result<bool> {
+    persistence
+        .get_connection()
+        .await?
+        .blocking(move |conn| {
+            if redrive {
+                dead_letter_store.request_redrive(conn, &service_id, offset)
+            } else {
+                dead_letter_store.discard(conn, &service_id, offset)
+            }
+        })
+        .await
+}
+
 async fn run_http_server(
-    persistence: SharedPersistence,
-    even_writer: event_log::SharedWriter,
+    persistence: SharedAsyncPersistence,
+    even_writer: event_log::SharedAsyncWriter,
+    dead_letter_store: SharedDeadLetterStore,
+    address: SocketAddr,
 ) -> Result<()> {
     // build our application with a single route
     let app = Router::new()
@@ -74,16 +116,66 @@ async fn run_http_server(
                     }
                 }
             }),
+        )
+        .route(
+            "/dead-letters/",
+            get({
+                let dead_letter_store = dead_letter_store.clone();
+                let persistence = persistence.clone();
+
+                || async move {
+                    match handle_list_dead_letters(persistence, dead_letter_store).await {
+                        Ok(dead_letters) => Ok(Json(dead_letters)),
+                        Err(e) => Err(handle_anyhow_error(e).await),
+                    }
+                }
+            }),
+        )
+        .route(
+            "/dead-letters/:service_id/:offset/redrive",
+            post({
+                let dead_letter_store = dead_letter_store.clone();
+                let persistence = persistence.clone();
+
+                |Path(key): Path<(String, Offset)>| async move {
+                    dead_letter_response(
+                        handle_dead_letter_request(persistence, dead_letter_store, key, true).await,
+                    )
+                    .await
+                }
+            }),
+        )
+        .route(
+            "/dead-letters/:service_id/:offset",
+            delete({
+                let dead_letter_store = dead_letter_store.clone();
+                let persistence = persistence.clone();
+
+                |Path(key): Path<(String, Offset)>| async move {
+                    dead_letter_response(
+                        handle_dead_letter_request(persistence, dead_letter_store, key, false)
+                            .await,
+                    )
+                    .await
+                }
+            }),
         );
 
-    // run it with hyper on localhost:3000
-    axum::Server::try_bind(&"0.0.0.0:3000".parse()?)?
+    axum::Server::try_bind(&address)?
         .serve(app.into_make_service())
         .await?;
 
     Ok(())
 }
 
+async fn dead_letter_response(res: Result<bool>) -> (StatusCode, String) {
+    match res {
+        Ok(true) => (StatusCode::OK, "".into()),
+        Ok(false) => (StatusCode::NOT_FOUND, "No such dead letter".into()),
+        Err(e) => handle_anyhow_error(e).await,
+    }
+}
+
 async fn handle_anyhow_error(err: anyhow::Error) -> (StatusCode, String) {
     (
         StatusCode::INTERNAL_SERVER_ERROR,
@@ -93,40 +185,35 @@ async fn handle_anyhow_error(err: anyhow::Error) -> (StatusCode, String) {
 
 impl Ui {
     pub fn new(
-        persistence: SharedPersistence,
-        even_writer: event_log::SharedWriter,
-    ) -> Result<Self> {
-        let runtime = Runtime::new()?;
-
-        let (tx, rx) = oneshot::channel();
+        persistence: SharedAsyncPersistence,
+        even_writer: event_log::SharedAsyncWriter,
+        dead_letter_store: SharedDeadLetterStore,
+    ) -> Self {
+        Self {
+            persistence,
+            even_writer,
+            dead_letter_store,
+            address: SocketAddr::from(([0, 0, 0, 0], 3000)),
+        }
+    }
 
-        runtime.spawn(async move {
-            tx.send(
-                run_http_server(persistence, even_writer)
-                    .await
-                    .with_context(|| "Failed to run http server".to_string()),
-            )
-            .expect("send to work");
-        });
-
-        Ok(Self {
-            _runtime: runtime,
-            server_rx: rx,
-        })
+    /// Listen on `address` instead of port 3000 of all the interfaces
+    pub fn with_address(self, address: SocketAddr) -> Self {
+        Self { address, ..self }
     }
 }
 
-impl LoopService for Ui {
-    fn run_iteration<'a>(&mut self) -> Result<()> {
-        // don't hog the cpu
-        std::thread::sleep(std::time::Duration::from_millis(100));
-
-        match self.server_rx.try_recv() {
-            Ok(res) => res,
-            Err(oneshot::error::TryRecvError::Empty) => Ok(()),
-            Err(oneshot::error::TryRecvError::Closed) => {
-                Err(format_err!("ui server died without leaving a response?!"))
-            }
-        }
+#[async_trait]
+impl AsyncLoopService for Ui {
+    /// Serve requests until the service is stopped
+    async fn run_iteration(&mut self) -> Result<()> {
+        run_http_server(
+            self.persistence.clone(),
+            self.even_writer.clone(),
+            self.dead_letter_store.clone(),
+            self.address,
+        )
+        .await
+        .with_context(|| "Failed to run http server".to_string())
     }
 }
@@ -1,2 +1,34 @@
+mod asynchronous;
+mod auction_house_router;
 mod bidding_engine;
+mod clock;
+mod dead_letter;
+mod end_to_end;
+mod event;
 mod event_log;
+mod fake_auction_server;
+mod http_auction_house;
+mod migration;
+mod postgres;
+mod progress;
+mod service;
+mod sol;
+mod sqlite;
+mod timer;
+mod wire;
+mod xmpp;
+
+use crate::event::{Event, EventType};
+use serde::{Deserialize, Serialize};
+
+/// Event for tests that don't care about its details
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct TestEvent;
+
+impl EventType for TestEvent {
+    const TOPIC: &'static str = "test";
+}
+
+fn test_event() -> Event {
+    Event::new(&TestEvent).expect("no fail")
+}
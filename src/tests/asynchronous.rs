@@ -0,0 +1,472 @@
+use super::{test_event, TestEvent};
+use crate::{
+    auction::ItemBid,
+    dead_letter::{self, SharedDeadLetterStore},
+    event::{
+        topics::{ItemStrategy, UiEvent},
+        Event,
+    },
+    event_log::{self, Offset},
+    persistence::{self, AsyncTransaction, Persistence, SharedAsyncPersistence},
+    progress::{self, SharedProgressTracker},
+    service::{
+        AsyncJoinHandle, AsyncLogFollowerService, AsyncLoopService, AsyncServiceControl,
+        BatchConfig, ErrorClass, LoopService, RestartPolicy, ServiceControl,
+    },
+};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::runtime::Runtime;
+
+async fn check_async_event_log_sanity(
+    persistence: SharedAsyncPersistence,
+    event_writer: event_log::SharedAsyncWriter,
+    event_reader: event_log::SharedAsyncReader,
+) -> Result<()> {
+    let start_offset = event_reader.get_start_offset()?;
+    let mut conn = persistence.get_connection().await?;
+
+    let res = event_reader
+        .read(&mut *conn, start_offset, 1, Some(Duration::ZERO))
+        .await?;
+    assert_eq!((res.offset, res.data), (start_offset, vec![]));
+
+    // dropped without commit
+    let mut transaction = conn.start_transaction().await?;
+    event_writer
+        .write_tr(&mut *transaction, &[test_event()])
+        .await?;
+    drop(transaction);
+
+    let offset = event_writer
+        .write(&mut *conn, &[test_event(), test_event()])
+        .await?;
+
+    let res = event_reader
+        .read(&mut *conn, start_offset, 10, Some(Duration::ZERO))
+        .await?;
+    assert_eq!(res.offset, offset);
+    assert_eq!(
+        res.data
+            .into_iter()
+            .map(|e| (e.offset, e.details))
+            .collect::<Vec<_>>(),
+        vec![(offset - 2, test_event()), (offset - 1, test_event())]
+    );
+
+    // waits for an event written by someone else
+    let writer = tokio::spawn({
+        let persistence = persistence.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            event_writer
+                .write(&mut *persistence.get_connection().await?, &[test_event()])
+                .await
+        }
+    });
+    let res = event_reader
+        .read(&mut *conn, offset, 10, Some(Duration::from_secs(10)))
+        .await?;
+    assert_eq!(res.offset, writer.await??);
+    assert_eq!(res.data.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn in_memory_async_event_log_sanity_check() -> Result<()> {
+    let log = Arc::new(event_log::InMemoryLog::new());
+    Runtime::new()?.block_on(check_async_event_log_sanity(
+        Arc::new(persistence::InMemoryPersistence::new()),
+        log.clone(),
+        log,
+    ))
+}
+
+#[test]
+fn sqlite_blocking_event_log_sanity_check() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::SqliteLog::new_shared();
+    persistence.migrate(&[event_log::SqliteLog::MIGRATIONS])?;
+
+    Runtime::new()?.block_on(check_async_event_log_sanity(
+        persistence::BlockingPersistence::new_shared(Arc::new(persistence)),
+        event_log::BlockingWriter::new_shared(event_writer),
+        event_log::BlockingReader::new_shared(event_reader),
+    ))
+}
+
+#[test]
+fn blocking_event_log_works_on_current_thread_runtime() -> Result<()> {
+    let runtime = || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+    };
+
+    let log = Arc::new(event_log::InMemoryLog::new());
+    runtime()?.block_on(check_async_event_log_sanity(
+        Arc::new(persistence::InMemoryPersistence::new()),
+        event_log::BlockingWriter::new_shared(log.clone()),
+        event_log::BlockingReader::new_shared(log),
+    ))?;
+
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::SqliteLog::new_shared();
+    persistence.migrate(&[event_log::SqliteLog::MIGRATIONS])?;
+    runtime()?.block_on(check_async_event_log_sanity(
+        persistence::BlockingPersistence::new_shared(Arc::new(persistence)),
+        event_log::BlockingWriter::new_shared(event_writer),
+        event_log::BlockingReader::new_shared(event_reader),
+    ))
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_blocking_event_log_sanity_check() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::PostgresLog::new_shared();
+    persistence.migrate(&[event_log::PostgresLog::MIGRATIONS])?;
+
+    // dropping postgres connections within a runtime panics, so keep it outside
+    let persistence: persistence::SharedPersistence = Arc::new(persistence);
+    Runtime::new()?.block_on(check_async_event_log_sanity(
+        persistence::BlockingPersistence::new_shared(persistence.clone()),
+        event_log::BlockingWriter::new_shared(event_writer),
+        event_log::BlockingReader::new_shared(event_reader),
+    ))
+}
+
+#[test]
+fn in_memory_log_is_shared_by_sync_and_async_code() -> Result<()> {
+    let persistence = Arc::new(persistence::InMemoryPersistence::new());
+    let log = Arc::new(event_log::InMemoryLog::new());
+
+    let offset = event_log::Writer::write(
+        &*log,
+        &mut *Persistence::get_connection(&*persistence)?,
+        &[test_event()],
+    )?;
+
+    Runtime::new()?.block_on(async {
+        let mut conn = persistence::AsyncPersistence::get_connection(&*persistence).await?;
+        let res = event_log::AsyncReader::read(&*log, &mut *conn, 0, 10, None).await?;
+        assert_eq!(res.offset, offset);
+
+        event_log::AsyncWriter::write(&*log, &mut *conn, &[test_event()]).await
+    })?;
+
+    let res = event_log::Reader::read(
+        &*log,
+        &mut *Persistence::get_connection(&*persistence)?,
+        offset,
+        10,
+        None,
+    )?;
+    assert_eq!(res.offset, offset + 1);
+    Ok(())
+}
+
+fn max_bid_set(item: &str) -> Result<Event> {
+    Event::new(&UiEvent::MaxBidSet(ItemBid {
+        item: item.to_owned(),
+        price: 1,
+    }))
+}
+
+/// Writes a [`TestEvent`] for every max bid, failing on `poison` items
+struct Recorder {
+    poison: Vec<String>,
+    event_writer: event_log::SharedAsyncWriter,
+}
+
+#[async_trait]
+impl AsyncLogFollowerService for Recorder {
+    type Event = UiEvent;
+
+    fn get_log_progress_id(&self) -> String {
+        "recorder".to_owned()
+    }
+
+    fn batch_config(&self) -> BatchConfig {
+        BatchConfig {
+            max_size: 10,
+            max_latency: Duration::ZERO,
+        }
+    }
+
+    fn classify_error(&self, _error: &anyhow::Error) -> ErrorClass {
+        ErrorClass::Poison
+    }
+
+    async fn handle_event(
+        &mut self,
+        transaction: &mut dyn AsyncTransaction<'_>,
+        event: UiEvent,
+    ) -> Result<()> {
+        let (UiEvent::MaxBidSet(ItemBid { item, .. })
+        | UiEvent::StrategySet(ItemStrategy { item, .. })) = event;
+        if self.poison.contains(&item) {
+            bail!("poison: {item}");
+        }
+        self.event_writer
+            .write_tr(transaction, &[Event::new(&TestEvent)?])
+            .await?;
+        Ok(())
+    }
+}
+
+struct Fixture {
+    runtime: Runtime,
+    persistence: SharedAsyncPersistence,
+    progress_store: SharedProgressTracker,
+    dead_letter_store: SharedDeadLetterStore,
+    event_writer: event_log::SharedAsyncWriter,
+    event_reader: event_log::SharedAsyncReader,
+    svc_ctr: ServiceControl,
+    async_svc_ctr: AsyncServiceControl,
+}
+
+impl Fixture {
+    fn new_in_memory() -> Result<Self> {
+        let persistence = Arc::new(persistence::InMemoryPersistence::new());
+        let log = Arc::new(event_log::InMemoryLog::new());
+        Self::new(
+            persistence.clone(),
+            persistence,
+            progress::InMemoryProgressTracker::new_shared(),
+            dead_letter::InMemoryDeadLetterStore::new_shared(),
+            log.clone(),
+            log,
+        )
+    }
+
+    fn new(
+        sync_persistence: persistence::SharedPersistence,
+        persistence: SharedAsyncPersistence,
+        progress_store: SharedProgressTracker,
+        dead_letter_store: SharedDeadLetterStore,
+        event_writer: event_log::SharedAsyncWriter,
+        event_reader: event_log::SharedAsyncReader,
+    ) -> Result<Self> {
+        let runtime = Runtime::new()?;
+        let svc_ctr = ServiceControl::new(
+            sync_persistence,
+            progress_store.clone(),
+            dead_letter_store.clone(),
+        );
+        let async_svc_ctr =
+            AsyncServiceControl::new(&svc_ctr, runtime.handle().clone(), persistence.clone());
+        Ok(Self {
+            runtime,
+            persistence,
+            progress_store,
+            dead_letter_store,
+            event_writer,
+            event_reader,
+            svc_ctr,
+            async_svc_ctr,
+        })
+    }
+
+    fn write(&self, events: &[Event]) -> Result<Offset> {
+        self.runtime.block_on(async {
+            self.event_writer
+                .write(&mut *self.persistence.get_connection().await?, events)
+                .await
+        })
+    }
+
+    fn wait_for_progress(&self, offset: Offset) -> Result<()> {
+        self.runtime.block_on(async {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            let mut conn = self.persistence.get_connection().await?;
+            loop {
+                let progress_store = self.progress_store.clone();
+                let progress = conn
+                    .blocking(move |conn| progress_store.load(conn, "recorder"))
+                    .await?;
+                if progress == Some(offset) {
+                    return Ok(());
+                }
+                if deadline < Instant::now() {
+                    bail!("timeout waiting for progress {offset}");
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+    }
+
+    fn spawn_recorder(&self, poison: &[&str]) -> AsyncJoinHandle {
+        self.async_svc_ctr.spawn_log_follower(
+            Recorder {
+                poison: poison.iter().map(|item| item.to_string()).collect(),
+                event_writer: self.event_writer.clone(),
+            },
+            self.event_reader.clone(),
+        )
+    }
+}
+
+fn check_async_log_follower(fixture: Fixture) -> Result<()> {
+    let start = fixture.write(&[max_bid_set("a")?, max_bid_set("poison")?])?;
+    let offset = fixture.write(&[max_bid_set("b")?])?;
+
+    let handle = fixture.spawn_recorder(&["poison"]);
+    // its own events get written after the ones above
+    fixture.wait_for_progress(offset + 2)?;
+    fixture.async_svc_ctr.send_stop_to_all();
+    fixture.runtime.block_on(handle.join())?;
+
+    fixture.runtime.block_on(async {
+        let mut conn = fixture.persistence.get_connection().await?;
+
+        let res = fixture
+            .event_reader
+            .read(&mut *conn, offset, 10, Some(Duration::ZERO))
+            .await?;
+        assert_eq!(
+            res.data
+                .iter()
+                .map(|event| event.details.clone())
+                .collect::<Vec<_>>(),
+            vec![test_event(), test_event()]
+        );
+        // written while handling the max bids, so caused by them
+        let causes = fixture
+            .event_reader
+            .read(&mut *conn, start - 2, 3, Some(Duration::ZERO))
+            .await?;
+        assert_eq!(
+            res.data[0].metadata.causation_id,
+            Some(causes.data[0].metadata.id)
+        );
+        assert_eq!(
+            res.data[1].metadata.causation_id,
+            Some(causes.data[2].metadata.id)
+        );
+
+        let dead_letter_store = fixture.dead_letter_store.clone();
+        let dead_letters = conn
+            .blocking(move |conn| dead_letter_store.list(conn, None))
+            .await?;
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].service_id, "recorder");
+        assert_eq!(dead_letters[0].event.details, max_bid_set("poison")?);
+        Ok(())
+    })
+}
+
+#[test]
+fn in_memory_async_log_follower_handles_events() -> Result<()> {
+    check_async_log_follower(Fixture::new_in_memory()?)
+}
+
+#[test]
+fn sqlite_async_log_follower_handles_events() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::SqliteLog::new_shared();
+    let progress_store = progress::SqliteProgressTracker::new_shared();
+    let dead_letter_store = dead_letter::SqliteDeadLetterStore::new_shared();
+    persistence.migrate(&[
+        event_log::SqliteLog::MIGRATIONS,
+        progress::SqliteProgressTracker::MIGRATIONS,
+        dead_letter::SqliteDeadLetterStore::MIGRATIONS,
+    ])?;
+    let persistence: persistence::SharedPersistence = Arc::new(persistence);
+
+    check_async_log_follower(Fixture::new(
+        persistence.clone(),
+        persistence::BlockingPersistence::new_shared(persistence),
+        progress_store,
+        dead_letter_store,
+        event_log::BlockingWriter::new_shared(event_writer),
+        event_log::BlockingReader::new_shared(event_reader),
+    )?)
+}
+
+/// Fails the first `failures` iterations, then waits forever
+struct FlakyService {
+    policy: RestartPolicy,
+    failures: usize,
+    iterations: Arc<Mutex<usize>>,
+}
+
+#[async_trait]
+impl AsyncLoopService for FlakyService {
+    async fn run_iteration(&mut self) -> Result<()> {
+        let iteration = {
+            let mut iterations = self.iterations.lock().expect("lock");
+            *iterations += 1;
+            *iterations
+        };
+        if iteration <= self.failures {
+            bail!("failure #{iteration}");
+        }
+        futures::future::pending().await
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        self.policy
+    }
+}
+
+/// Sync service that does nothing until stopped
+struct IdleService;
+
+impl LoopService for IdleService {
+    fn run_iteration(&mut self) -> Result<()> {
+        std::thread::sleep(Duration::from_millis(1));
+        Ok(())
+    }
+}
+
+#[test]
+fn async_service_is_restarted_and_stopped() -> Result<()> {
+    let fixture = Fixture::new_in_memory()?;
+    let iterations = Arc::new(Mutex::new(0));
+    let handle = fixture.async_svc_ctr.spawn_loop(FlakyService {
+        policy: RestartPolicy::Always {
+            delay: Duration::ZERO,
+        },
+        failures: 3,
+        iterations: iterations.clone(),
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while *iterations.lock().expect("lock") < 4 {
+        assert!(Instant::now() < deadline, "timeout waiting for restarts");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // stopping the sync services stops the async ones too
+    fixture.svc_ctr.send_stop_to_all();
+    fixture.runtime.block_on(handle.join())?;
+    assert_eq!(*iterations.lock().expect("lock"), 4);
+    Ok(())
+}
+
+#[test]
+fn failed_async_service_stops_sync_services() -> Result<()> {
+    let fixture = Fixture::new_in_memory()?;
+    let sync_handle = fixture.svc_ctr.spawn_loop(IdleService);
+    let handle = fixture.async_svc_ctr.spawn_loop(FlakyService {
+        policy: RestartPolicy::Never,
+        failures: 1,
+        iterations: Default::default(),
+    });
+
+    assert_eq!(
+        fixture
+            .runtime
+            .block_on(handle.join())
+            .expect_err("failed")
+            .to_string(),
+        "failure #1"
+    );
+    sync_handle.join()
+}
@@ -0,0 +1,309 @@
+use crate::{
+    auction::{Amount, ItemId, ItemIdRef},
+    event::topics::{AuctionHouseEvent, AuctionHouseItemEvent},
+    service::auction_house::{
+        AuctionHouseClient, AuctionHouseCursor, AuctionHouseRouter, LoadCursor, PolledEvent,
+    },
+};
+use anyhow::{bail, Result};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Auction house client serving the events (or errors) it was given, recording the joins and bids
+///
+/// Polls wait for events like long polls do, until one is pushed or the timeout passes.
+#[derive(Default)]
+struct FakeClient {
+    events: Mutex<VecDeque<Option<PolledEvent>>>,
+    pushed: Condvar,
+    /// Cursor it was resumed from, if resumed
+    resumed_from: Mutex<Option<Option<String>>>,
+    joined: Mutex<Vec<ItemId>>,
+    bids: Mutex<Vec<(ItemId, Amount)>>,
+}
+
+impl FakeClient {
+    fn new_shared() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Push the next poll result, `None` for an error
+    fn push(&self, event: Option<PolledEvent>) {
+        self.events.lock().expect("lock").push_back(event);
+        self.pushed.notify_all();
+    }
+
+    fn push_closed(&self, item: &str) {
+        self.push(closed(item));
+    }
+
+    /// Push an event with a cursor after it
+    fn push_closed_at(&self, item: &str, position: &str) {
+        self.push(closed(item).map(|event| PolledEvent {
+            cursor: Some(AuctionHouseCursor {
+                auction_house: None,
+                position: position.to_owned(),
+            }),
+            ..event
+        }));
+    }
+
+    /// Make the next poll fail
+    fn push_error(&self) {
+        self.push(None);
+    }
+
+    fn joined(&self) -> Vec<ItemId> {
+        self.joined.lock().expect("lock").clone()
+    }
+
+    fn bids(&self) -> Vec<(ItemId, Amount)> {
+        self.bids.lock().expect("lock").clone()
+    }
+
+    fn resumed_from(&self) -> Option<Option<String>> {
+        self.resumed_from.lock().expect("lock").clone()
+    }
+}
+
+impl AuctionHouseClient for FakeClient {
+    fn join(&self, item_id: ItemIdRef) -> Result<()> {
+        self.joined.lock().expect("lock").push(item_id.to_owned());
+        Ok(())
+    }
+
+    fn place_bid(&self, item_id: ItemIdRef, price: Amount) -> Result<()> {
+        self.bids
+            .lock()
+            .expect("lock")
+            .push((item_id.to_owned(), price));
+        Ok(())
+    }
+
+    fn poll(&self, timeout: Option<Duration>) -> Result<Option<PolledEvent>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut events = self.events.lock().expect("lock");
+        while events.is_empty() {
+            events = match deadline {
+                Some(deadline) if deadline <= Instant::now() => return Ok(None),
+                Some(deadline) => {
+                    self.pushed
+                        .wait_timeout(events, deadline - Instant::now())
+                        .expect("lock")
+                        .0
+                }
+                None => self.pushed.wait(events).expect("lock"),
+            };
+        }
+        match events.pop_front() {
+            Some(Some(event)) => Ok(Some(event)),
+            Some(None) => bail!("poll failed"),
+            None => Ok(None),
+        }
+    }
+
+    fn resume(&self, load_cursor: &mut LoadCursor<'_>) -> Result<()> {
+        *self.resumed_from.lock().expect("lock") = Some(load_cursor(None)?);
+        Ok(())
+    }
+}
+
+fn closed(item: &str) -> Option<PolledEvent> {
+    Some(PolledEvent::new(AuctionHouseEvent {
+        item: item.to_owned(),
+        event: AuctionHouseItemEvent::Closed,
+    }))
+}
+
+#[test]
+fn routes_bids_by_auction_house_of_item() -> Result<()> {
+    let (default, ebay, other) = (
+        FakeClient::new_shared(),
+        FakeClient::new_shared(),
+        FakeClient::new_shared(),
+    );
+    let router = AuctionHouseRouter::new()
+        .with_default(default.clone())
+        .with_auction_house("ebay", ebay.clone())
+        .with_auction_house("other", other.clone());
+
+    router.place_bid("54321", 10)?;
+    router.place_bid("ebay:54321", 11)?;
+    router.place_bid("other:a:b", 12)?;
+    router.place_bid("unknown:54321", 13)?;
+
+    assert_eq!(
+        default.bids(),
+        vec![("54321".to_owned(), 10), ("unknown:54321".to_owned(), 13)]
+    );
+    assert_eq!(ebay.bids(), vec![("54321".to_owned(), 11)]);
+    assert_eq!(other.bids(), vec![("a:b".to_owned(), 12)]);
+    Ok(())
+}
+
+#[test]
+fn routes_joins_by_auction_house_of_item() -> Result<()> {
+    let (default, ebay) = (FakeClient::new_shared(), FakeClient::new_shared());
+    let router = AuctionHouseRouter::new()
+        .with_default(default.clone())
+        .with_auction_house("ebay", ebay.clone());
+
+    router.join("54321")?;
+    router.join("ebay:54321")?;
+
+    assert_eq!(default.joined(), vec!["54321".to_owned()]);
+    assert_eq!(ebay.joined(), vec!["54321".to_owned()]);
+    Ok(())
+}
+
+#[test]
+fn merges_events_of_all_auction_houses() -> Result<()> {
+    let (default, ebay) = (FakeClient::new_shared(), FakeClient::new_shared());
+    let router = AuctionHouseRouter::new()
+        .with_default(default.clone())
+        .with_auction_house("ebay", ebay.clone());
+    default.push_closed("1");
+    default.push_closed("2");
+    ebay.push_closed("3");
+    ebay.push_closed("4");
+
+    let timeout = Some(Duration::from_millis(100));
+    let mut events = vec![];
+    while let Some(event) = router.poll(timeout)? {
+        events.push(event.event.item);
+        router.acknowledge();
+    }
+    // in order within each auction house, in any order across them
+    let of = |ebay: bool| {
+        events
+            .iter()
+            .filter(|item| item.starts_with("ebay:") == ebay)
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(of(false), vec!["1", "2"]);
+    assert_eq!(of(true), vec!["ebay:3", "ebay:4"]);
+    Ok(())
+}
+
+#[test]
+fn quiet_auction_house_doesnt_hold_up_the_others() -> Result<()> {
+    let (default, ebay) = (FakeClient::new_shared(), FakeClient::new_shared());
+    let router = AuctionHouseRouter::new()
+        .with_default(default.clone())
+        .with_auction_house("ebay", ebay.clone());
+    assert_eq!(router.poll(Some(Duration::from_millis(100)))?, None);
+
+    // while the default one is still waiting for events
+    let start = Instant::now();
+    ebay.push_closed("1");
+    assert_eq!(
+        router.poll(Some(Duration::from_secs(10)))?,
+        closed("ebay:1")
+    );
+    assert!(start.elapsed() < Duration::from_secs(1));
+    Ok(())
+}
+
+#[test]
+fn polls_auction_house_again_only_once_its_event_is_acknowledged() -> Result<()> {
+    let default = FakeClient::new_shared();
+    let router = AuctionHouseRouter::new().with_default(default.clone());
+    default.push_closed("1");
+    default.push_closed("2");
+
+    assert_eq!(router.poll(Some(Duration::from_secs(10)))?, closed("1"));
+    // eg. writing it failed, so it's not going to be acknowledged
+    assert_eq!(router.poll(Some(Duration::from_millis(100)))?, None);
+    assert_eq!(default.events.lock().expect("lock").len(), 1);
+
+    router.acknowledge();
+    assert_eq!(router.poll(Some(Duration::from_secs(10)))?, closed("2"));
+    Ok(())
+}
+
+#[test]
+fn without_default_only_routes_to_the_auction_houses_added() -> Result<()> {
+    let ebay = FakeClient::new_shared();
+    let router = AuctionHouseRouter::new().with_auction_house("ebay", ebay.clone());
+
+    router.place_bid("ebay:54321", 11)?;
+    assert!(router.place_bid("54321", 10).is_err());
+    assert!(router.join("unknown:54321").is_err());
+
+    assert_eq!(ebay.bids(), vec![("54321".to_owned(), 11)]);
+    ebay.push_closed("1");
+    assert_eq!(
+        router.poll(Some(Duration::from_secs(10)))?,
+        closed("ebay:1")
+    );
+    Ok(())
+}
+
+#[test]
+fn fails_polling_only_if_all_auction_houses_fail() -> Result<()> {
+    let (default, ebay) = (FakeClient::new_shared(), FakeClient::new_shared());
+    let router = AuctionHouseRouter::new()
+        .with_default(default.clone())
+        .with_auction_house("ebay", ebay.clone());
+
+    default.push_error();
+    ebay.push_closed("1");
+    assert_eq!(
+        router.poll(Some(Duration::from_secs(10)))?,
+        closed("ebay:1")
+    );
+    router.acknowledge();
+
+    // the default one hasn't polled successfully since
+    ebay.push_error();
+    assert!(router.poll(Some(Duration::from_secs(10))).is_err());
+    assert_eq!(router.poll(Some(Duration::ZERO))?, None);
+    Ok(())
+}
+
+#[test]
+fn tags_cursors_with_auction_house() -> Result<()> {
+    let (default, ebay) = (FakeClient::new_shared(), FakeClient::new_shared());
+    let router = AuctionHouseRouter::new()
+        .with_default(default.clone())
+        .with_auction_house("ebay", ebay.clone());
+    default.push_closed_at("1", "a");
+    ebay.push_closed_at("2", "b");
+
+    let timeout = Some(Duration::from_secs(10));
+    let mut cursors = [router.poll(timeout)?, router.poll(timeout)?]
+        .map(|polled| polled.and_then(|polled| polled.cursor));
+    cursors.sort_by_key(|cursor| cursor.as_ref().map(|cursor| cursor.auction_house.clone()));
+    assert_eq!(
+        cursors,
+        [
+            Some(AuctionHouseCursor {
+                auction_house: None,
+                position: "a".to_owned(),
+            }),
+            Some(AuctionHouseCursor {
+                auction_house: Some("ebay".to_owned()),
+                position: "b".to_owned(),
+            }),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn resumes_each_auction_house_from_its_cursor() -> Result<()> {
+    let (default, ebay) = (FakeClient::new_shared(), FakeClient::new_shared());
+    let router = AuctionHouseRouter::new()
+        .with_default(default.clone())
+        .with_auction_house("ebay", ebay.clone());
+
+    router.resume(&mut |auction_house| Ok(auction_house.map(|id| format!("{id}-cursor"))))?;
+
+    assert_eq!(default.resumed_from(), Some(None));
+    assert_eq!(ebay.resumed_from(), Some(Some("ebay-cursor".to_owned())));
+    Ok(())
+}
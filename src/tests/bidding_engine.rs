@@ -1,13 +1,24 @@
 use crate::{
     auction,
     auction::{Amount, BidDetails, Bidder, ItemBid, ItemIdRef},
-    event::{BiddingEngineEvent, Event, UiEvent},
+    dead_letter,
+    event::{topics::*, Event},
     event_log,
     persistence::{self, Connection, Persistence},
-    service,
-    service::{bidding_engine::*, LogFollowerService},
+    progress, service,
+    service::{bidding_engine::*, LogFollowerService, Partition, ServiceControl},
 };
 use anyhow::Result;
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// A time that survives the round trip through databases storing microseconds
+fn now() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+}
 
 trait BiddingEngineTestExt {
     fn handle_max_bid_event(
@@ -25,24 +36,26 @@ impl BiddingEngineTestExt for BiddingEngine {
         id: ItemIdRef,
         price: Amount,
     ) -> Result<()> {
+        let mut transaction = conn.start_transaction()?;
         self.handle_event(
-            &mut *conn.start_transaction()?,
-            Event::Ui(UiEvent::MaxBidSet(auction::ItemBid {
+            &mut *transaction,
+            BiddingEngineInput::Ui(UiEvent::MaxBidSet(auction::ItemBid {
                 item: id.to_owned(),
                 price,
             })),
-        )
+        )?;
+        transaction.commit()
     }
 }
 
-#[test]
-fn sanity_check_sends_a_bid_when_asked_to_via_event_log() -> Result<()> {
-    let persistence = persistence::InMemoryPersistence::new();
+fn check_sends_a_bid_when_asked_to_via_event_log(
+    persistence: &dyn Persistence,
+    event_writer: event_log::SharedWriter,
+    event_reader: event_log::SharedReader,
+    bidding_state_store: SharedBiddingStateStore,
+) -> Result<()> {
     let mut conn = persistence.get_connection()?;
 
-    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
-    let bidding_state_store = service::bidding_engine::InMemoryBiddingStateStore::new_shared();
-
     let mut bidding_engine =
         service::bidding_engine::BiddingEngine::new(bidding_state_store, event_writer);
 
@@ -51,11 +64,28 @@ fn sanity_check_sends_a_bid_when_asked_to_via_event_log() -> Result<()> {
     let res = event_reader.read_one(&mut *conn, event_reader.get_start_offset()?)?;
 
     assert_eq!(
-        res.data.clone().map(|e| e.details),
-        Some(Event::BiddingEngine(BiddingEngineEvent::Bid(ItemBid {
+        res.data
+            .as_ref()
+            .map(|e| e.details.decode::<BiddingEngineEvent>())
+            .transpose()?
+            .flatten(),
+        Some(BiddingEngineEvent::Bid(ItemBid {
             item: "foo".to_owned(),
             price: 0
-        })))
+        }))
+    );
+
+    let res = event_reader.read_one(&mut *conn, res.offset)?;
+    assert_eq!(
+        res.data
+            .as_ref()
+            .map(|e| e.details.decode::<BiddingEngineEvent>())
+            .transpose()?
+            .flatten(),
+        Some(BiddingEngineEvent::StatusChanged(ItemStatus {
+            item: "foo".to_owned(),
+            status: SniperStatus::Joining
+        }))
     );
 
     let res = event_reader.read_one(&mut *conn, res.offset)?;
@@ -69,18 +99,377 @@ fn sanity_check_sends_a_bid_when_asked_to_via_event_log() -> Result<()> {
     Ok(())
 }
 
+fn check_bidding_state_store_round_trip(
+    persistence: &dyn Persistence,
+    bidding_state_store: SharedBiddingStateStore,
+) -> Result<()> {
+    let mut conn = persistence.get_connection()?;
+
+    assert_eq!(bidding_state_store.load(&mut *conn, "foo")?, None);
+
+    for state in [
+        AuctionBiddingState::default(),
+        AuctionBiddingState {
+            max_bid_limit: 100,
+            last_bid_sent: Some(12),
+            auction_state: AuctionState {
+                higest_bid: Some(BidDetails {
+                    bidder: Bidder::Other,
+                    increment: 1,
+                    price: 11,
+                }),
+                closed: false,
+                deadline: None,
+            },
+            strategy: Strategy::FixedStep(FixedStep { step: 5 }),
+            withheld_bid: Some(17),
+        },
+        AuctionBiddingState {
+            max_bid_limit: 100,
+            last_bid_sent: Some(12),
+            auction_state: AuctionState {
+                higest_bid: Some(BidDetails {
+                    bidder: Bidder::Sniper,
+                    increment: 2,
+                    price: 12,
+                }),
+                closed: true,
+                deadline: Some(now()),
+            },
+            strategy: Strategy::LastMoment(LastMoment {
+                window: Duration::from_secs(30),
+            }),
+            withheld_bid: None,
+        },
+    ] {
+        bidding_state_store.store(&mut *conn, "foo", state)?;
+        assert_eq!(bidding_state_store.load(&mut *conn, "foo")?, Some(state));
+    }
+
+    assert_eq!(bidding_state_store.load(&mut *conn, "bar")?, None);
+
+    Ok(())
+}
+
+fn check_bidding_state_store_loads_pending_and_committed_auctions(
+    persistence: &dyn Persistence,
+    bidding_state_store: SharedBiddingStateStore,
+) -> Result<()> {
+    let mut conn = persistence.get_connection()?;
+
+    let with_deadline = |deadline, closed| AuctionBiddingState {
+        max_bid_limit: 100,
+        auction_state: AuctionState {
+            deadline,
+            closed,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let withheld = AuctionBiddingState {
+        withheld_bid: Some(10),
+        ..with_deadline(None, false)
+    };
+    let won = AuctionBiddingState {
+        auction_state: AuctionState {
+            higest_bid: Some(BidDetails {
+                bidder: Bidder::Sniper,
+                price: 10,
+                increment: 1,
+            }),
+            ..with_deadline(None, true).auction_state
+        },
+        ..with_deadline(None, true)
+    };
+    bidding_state_store.store(&mut *conn, "open", with_deadline(Some(now()), false))?;
+    bidding_state_store.store(&mut *conn, "closed", with_deadline(Some(now()), true))?;
+    bidding_state_store.store(&mut *conn, "unknown", with_deadline(None, false))?;
+    bidding_state_store.store(
+        &mut *conn,
+        "another-open",
+        with_deadline(Some(now() + Duration::from_secs(60)), false),
+    )?;
+    bidding_state_store.store(&mut *conn, "withheld", withheld)?;
+    bidding_state_store.store(&mut *conn, "won", won)?;
+
+    let mut transaction = conn.start_transaction()?;
+    assert_eq!(
+        bidding_state_store.load_pending_tr(&mut *transaction)?,
+        vec![
+            (
+                "another-open".to_owned(),
+                with_deadline(Some(now() + Duration::from_secs(60)), false)
+            ),
+            ("open".to_owned(), with_deadline(Some(now()), false)),
+            ("withheld".to_owned(), withheld),
+        ]
+    );
+    assert_eq!(
+        bidding_state_store.load_committed_tr(&mut *transaction)?,
+        vec![
+            (
+                "another-open".to_owned(),
+                with_deadline(Some(now() + Duration::from_secs(60)), false)
+            ),
+            ("open".to_owned(), with_deadline(Some(now()), false)),
+            ("unknown".to_owned(), with_deadline(None, false)),
+            ("withheld".to_owned(), withheld),
+            ("won".to_owned(), won),
+        ]
+    );
+    transaction.commit()
+}
+
+fn check_bidding_state_store_discards_rolled_back_state(
+    persistence: &dyn Persistence,
+    bidding_state_store: SharedBiddingStateStore,
+) -> Result<()> {
+    let mut conn = persistence.get_connection()?;
+
+    let state = AuctionBiddingState {
+        max_bid_limit: 100,
+        ..Default::default()
+    };
+    bidding_state_store.store(&mut *conn, "foo", state)?;
+
+    let mut transaction = conn.start_transaction()?;
+    bidding_state_store.store_tr(&mut *transaction, "foo", AuctionBiddingState::default())?;
+    bidding_state_store.store_tr(&mut *transaction, "bar", state)?;
+    assert_eq!(
+        bidding_state_store.load_tr(&mut *transaction, "bar")?,
+        Some(state)
+    );
+    transaction.rollback()?;
+
+    assert_eq!(bidding_state_store.load(&mut *conn, "foo")?, Some(state));
+    assert_eq!(bidding_state_store.load(&mut *conn, "bar")?, None);
+
+    Ok(())
+}
+
+fn check_bidding_state_store_serializes_loading_committed_auctions(
+    persistence: &dyn Persistence,
+    bidding_state_store: SharedBiddingStateStore,
+) -> Result<()> {
+    let state = AuctionBiddingState {
+        max_bid_limit: 100,
+        last_bid_sent: Some(10),
+        ..Default::default()
+    };
+
+    let mut conn = persistence.get_connection()?;
+    let mut transaction = conn.start_transaction()?;
+    assert_eq!(
+        bidding_state_store.load_committed_tr(&mut *transaction)?,
+        vec![]
+    );
+
+    thread::scope(|scope| {
+        let other = scope.spawn(|| -> Result<_> {
+            let mut conn = persistence.get_connection()?;
+            let mut transaction = conn.start_transaction()?;
+            let committed = bidding_state_store.load_committed_tr(&mut *transaction)?;
+            transaction.commit()?;
+            Ok(committed)
+        });
+        // give the other one a chance to load before this one commits
+        thread::sleep(Duration::from_millis(100));
+        bidding_state_store.store_tr(&mut *transaction, "foo", state)?;
+        transaction.commit()?;
+
+        assert_eq!(
+            other.join().expect("no panic")?,
+            vec![("foo".to_owned(), state)]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn sanity_check_sends_a_bid_when_asked_to_via_event_log() -> Result<()> {
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+
+    check_sends_a_bid_when_asked_to_via_event_log(
+        &persistence::InMemoryPersistence::new(),
+        event_writer,
+        event_reader,
+        service::bidding_engine::InMemoryBiddingStateStore::new_shared(),
+    )
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_sanity_check_sends_a_bid_when_asked_to_via_event_log() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::PostgresLog::new_shared();
+    let bidding_state_store = PostgresBiddingStateStore::new_shared();
+    persistence.migrate(&[
+        event_log::PostgresLog::MIGRATIONS,
+        PostgresBiddingStateStore::MIGRATIONS,
+    ])?;
+
+    check_sends_a_bid_when_asked_to_via_event_log(
+        &persistence,
+        event_writer,
+        event_reader,
+        bidding_state_store,
+    )
+}
+
+#[test]
+fn in_memory_bidding_state_store_round_trip() -> Result<()> {
+    check_bidding_state_store_round_trip(
+        &persistence::InMemoryPersistence::new(),
+        InMemoryBiddingStateStore::new_shared(),
+    )
+}
+
+#[test]
+fn in_memory_bidding_state_store_loads_pending_and_committed_auctions() -> Result<()> {
+    check_bidding_state_store_loads_pending_and_committed_auctions(
+        &persistence::InMemoryPersistence::new(),
+        InMemoryBiddingStateStore::new_shared(),
+    )
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_bidding_state_store_loads_pending_and_committed_auctions() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let bidding_state_store = PostgresBiddingStateStore::new_shared();
+    persistence.migrate(&[PostgresBiddingStateStore::MIGRATIONS])?;
+
+    check_bidding_state_store_loads_pending_and_committed_auctions(
+        &persistence,
+        bidding_state_store,
+    )
+}
+
+#[test]
+fn sqlite_bidding_state_store_loads_pending_and_committed_auctions() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let bidding_state_store = SqliteBiddingStateStore::new_shared();
+    persistence.migrate(&[SqliteBiddingStateStore::MIGRATIONS])?;
+
+    check_bidding_state_store_loads_pending_and_committed_auctions(
+        &persistence,
+        bidding_state_store,
+    )
+}
+
+#[test]
+fn in_memory_bidding_state_store_serializes_loading_committed_auctions() -> Result<()> {
+    check_bidding_state_store_serializes_loading_committed_auctions(
+        &persistence::InMemoryPersistence::new(),
+        InMemoryBiddingStateStore::new_shared(),
+    )
+}
+
+#[test]
+fn sqlite_bidding_state_store_serializes_loading_committed_auctions() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let bidding_state_store = SqliteBiddingStateStore::new_shared();
+    persistence.migrate(&[SqliteBiddingStateStore::MIGRATIONS])?;
+
+    check_bidding_state_store_serializes_loading_committed_auctions(
+        &persistence,
+        bidding_state_store,
+    )
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_bidding_state_store_serializes_loading_committed_auctions() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let bidding_state_store = PostgresBiddingStateStore::new_shared();
+    persistence.migrate(&[PostgresBiddingStateStore::MIGRATIONS])?;
+
+    check_bidding_state_store_serializes_loading_committed_auctions(
+        &persistence,
+        bidding_state_store,
+    )
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_partitions_bidding_concurrently_dont_deadlock() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let (event_writer, _event_reader) = event_log::PostgresLog::new_shared();
+    let bidding_state_store = PostgresBiddingStateStore::new_shared();
+    persistence.migrate(&[
+        event_log::PostgresLog::MIGRATIONS,
+        PostgresBiddingStateStore::MIGRATIONS,
+    ])?;
+    let bidding_engine = BiddingEngine::new(bidding_state_store, event_writer).with_budget(1000);
+    let partition = |index| Partition { index, count: 2 };
+    let item_in = |partition: Partition| {
+        (0..)
+            .map(|i| format!("item-{i}"))
+            .find(|item| partition.contains(Some(item)))
+            .expect("some item")
+    };
+    let max_bid_set = |item: &str| {
+        BiddingEngineInput::Ui(UiEvent::MaxBidSet(ItemBid {
+            item: item.to_owned(),
+            price: 100,
+        }))
+    };
+
+    thread::scope(|scope| {
+        // a batch writing another event before its bid
+        let first = scope.spawn(|| -> Result<()> {
+            let mut bidding_engine = bidding_engine.clone().with_partition(partition(0));
+            let item = item_in(partition(0));
+            let mut conn = persistence.get_connection()?;
+            let mut transaction = conn.start_transaction()?;
+            bidding_engine.handle_event(
+                &mut *transaction,
+                BiddingEngineInput::AuctionHouse(AuctionHouseEvent {
+                    item: "unknown".to_owned(),
+                    event: AuctionHouseItemEvent::Closed,
+                }),
+            )?;
+            thread::sleep(Duration::from_millis(200));
+            bidding_engine.handle_event(&mut *transaction, max_bid_set(&item))?;
+            transaction.commit()
+        });
+        // while the other partition bids first thing in its batch
+        thread::sleep(Duration::from_millis(100));
+        let mut bidding_engine = bidding_engine.clone().with_partition(partition(1));
+        let mut conn = persistence.get_connection()?;
+        let mut transaction = conn.start_transaction()?;
+        bidding_engine.handle_event(&mut *transaction, max_bid_set(&item_in(partition(1))))?;
+        transaction.commit()?;
+
+        first.join().expect("no panic")
+    })
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_bidding_state_store_round_trip() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let bidding_state_store = PostgresBiddingStateStore::new_shared();
+    persistence.migrate(&[PostgresBiddingStateStore::MIGRATIONS])?;
+
+    check_bidding_state_store_round_trip(&persistence, bidding_state_store)
+}
+
 #[test]
 fn sends_an_initial_bid_when_max_bid_limit_set() -> Result<()> {
     assert_eq!(
-        BiddingEngine::handle_max_bid_limit_event("foo", None, 100)?,
+        BiddingEngine::handle_max_bid_limit_event("foo", None, 100, now())?,
         (
             Some(AuctionBiddingState {
                 max_bid_limit: 100,
                 last_bid_sent: Some(0),
                 auction_state: AuctionState {
                     higest_bid: None,
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
             vec![BiddingEngineEvent::Bid(ItemBid {
                 item: "foo".to_string(),
@@ -106,10 +495,14 @@ fn sends_a_new_bid_when_max_bid_limit_raised() -> Result<()> {
                         increment: 1,
                         price: 100
                     }),
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
-            101
+            101,
+            now(),
         )?,
         (
             Some(AuctionBiddingState {
@@ -121,8 +514,11 @@ fn sends_a_new_bid_when_max_bid_limit_raised() -> Result<()> {
                         increment: 1,
                         price: 100
                     }),
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
             vec![BiddingEngineEvent::Bid(ItemBid {
                 item: "foo".to_string(),
@@ -148,10 +544,14 @@ fn doesnt_send_a_new_bid_when_max_bid_limit_raised_but_not_enough_to_outbid() ->
                         increment: 1,
                         price: 101
                     }),
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
-            101
+            101,
+            now(),
         )?,
         (
             Some(AuctionBiddingState {
@@ -163,8 +563,11 @@ fn doesnt_send_a_new_bid_when_max_bid_limit_raised_but_not_enough_to_outbid() ->
                         increment: 1,
                         price: 101
                     }),
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
             vec![]
         )
@@ -187,10 +590,14 @@ fn doesnt_send_a_new_bid_when_max_bid_limit_raised_but_we_are_already_winning()
                         increment: 1,
                         price: 0
                     }),
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
-            101
+            101,
+            now(),
         )?,
         (
             Some(AuctionBiddingState {
@@ -202,8 +609,11 @@ fn doesnt_send_a_new_bid_when_max_bid_limit_raised_but_we_are_already_winning()
                         increment: 1,
                         price: 0
                     }),
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
             vec![]
         )
@@ -227,10 +637,14 @@ fn doesnt_send_a_new_bid_when_max_bid_limit_raised_but_we_are_already_have_a_goo
                         increment: 1,
                         price: 1
                     }),
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
-            101
+            101,
+            now(),
         )?,
         (
             Some(AuctionBiddingState {
@@ -242,8 +656,11 @@ fn doesnt_send_a_new_bid_when_max_bid_limit_raised_but_we_are_already_have_a_goo
                         increment: 1,
                         price: 1
                     }),
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
             vec![]
         )
@@ -266,14 +683,18 @@ fn sends_a_new_bid_when_someone_outbids() -> Result<()> {
                         increment: 1,
                         price: 10
                     }),
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
-            crate::event::AuctionHouseItemEvent::Bid(BidDetails {
+            AuctionHouseItemEvent::Bid(BidDetails {
                 bidder: Bidder::Other,
                 price: 11,
                 increment: 1
             }),
+            now(),
         )?,
         (
             Some(AuctionBiddingState {
@@ -285,8 +706,11 @@ fn sends_a_new_bid_when_someone_outbids() -> Result<()> {
                         increment: 1,
                         price: 11
                     }),
-                    closed: false
+                    closed: false,
+                    deadline: None,
                 },
+                strategy: Strategy::default(),
+                withheld_bid: None,
             }),
             vec![BiddingEngineEvent::Bid(ItemBid {
                 item: "foo".to_string(),
@@ -297,3 +721,574 @@ fn sends_a_new_bid_when_someone_outbids() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn strategies_never_bid_over_the_limit() {
+    let outbid = AuctionState {
+        higest_bid: Some(BidDetails {
+            bidder: Bidder::Other,
+            increment: 2,
+            price: 10,
+        }),
+        closed: false,
+        deadline: None,
+    };
+    let fixed_step = FixedStep { step: 5 };
+
+    assert_eq!(ImmediateOutbid.next_bid(outbid, 100, now()), Some(12));
+    assert_eq!(fixed_step.next_bid(outbid, 100, now()), Some(17));
+    assert_eq!(fixed_step.next_bid(outbid, 14, now()), Some(14));
+    assert_eq!(fixed_step.next_bid(outbid, 11, now()), None);
+    assert_eq!(
+        fixed_step.next_bid(AuctionState::default(), 100, now()),
+        Some(5),
+        "no bids yet"
+    );
+}
+
+#[test]
+fn last_moment_bids_only_within_the_window() {
+    let strategy = LastMoment {
+        window: Duration::from_secs(10),
+    };
+    let auction = AuctionState {
+        deadline: Some(now()),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        strategy.next_bid(AuctionState::default(), 100, now()),
+        None,
+        "unknown deadline"
+    );
+    assert_eq!(
+        strategy.next_bid(auction, 100, now() - Duration::from_secs(11)),
+        None
+    );
+    assert_eq!(
+        strategy.next_bid(auction, 100, now() - Duration::from_secs(10)),
+        Some(0)
+    );
+    assert_eq!(
+        strategy.next_bid(auction, 100, now() + Duration::from_secs(1)),
+        Some(0),
+        "late, but the auction is not closed yet"
+    );
+}
+
+#[test]
+fn auction_house_announces_the_deadline() {
+    let later = now() + Duration::from_secs(60);
+    let auction =
+        AuctionState::default().handle_auction_event(AuctionHouseItemEvent::ClosesAt(now()));
+    assert_eq!(auction.deadline, Some(now()));
+
+    // auctions can be extended
+    let auction = auction.handle_auction_event(AuctionHouseItemEvent::ClosesAt(later));
+    assert_eq!(auction.deadline, Some(later));
+
+    let auction = auction
+        .handle_auction_event(AuctionHouseItemEvent::Closed)
+        .handle_auction_event(AuctionHouseItemEvent::ClosesAt(now()));
+    assert_eq!(auction.deadline, Some(later));
+}
+
+#[test]
+fn committed_amount_counts_winning_and_outstanding_bids() {
+    let state = |last_bid_sent, bidder, price, closed| AuctionBiddingState {
+        max_bid_limit: 100,
+        last_bid_sent,
+        auction_state: AuctionState {
+            higest_bid: Some(BidDetails {
+                bidder,
+                price,
+                increment: 1,
+            }),
+            closed,
+            deadline: None,
+        },
+        ..Default::default()
+    };
+
+    assert_eq!(AuctionBiddingState::default().committed_amount(), 0);
+    // winning, and won
+    assert_eq!(
+        state(Some(20), Bidder::Sniper, 20, false).committed_amount(),
+        20
+    );
+    assert_eq!(
+        state(Some(20), Bidder::Sniper, 20, true).committed_amount(),
+        20
+    );
+    // our bid didn't make it yet
+    assert_eq!(
+        state(Some(30), Bidder::Other, 20, false).committed_amount(),
+        30
+    );
+    assert_eq!(
+        state(Some(30), Bidder::Sniper, 20, false).committed_amount(),
+        30
+    );
+    // outbid, and lost
+    assert_eq!(
+        state(Some(20), Bidder::Other, 25, false).committed_amount(),
+        0
+    );
+    assert_eq!(
+        state(Some(30), Bidder::Other, 20, true).committed_amount(),
+        0
+    );
+}
+
+#[test]
+fn bids_over_budget_are_withheld_and_announced_once() {
+    let bid = |price| {
+        vec![BiddingEngineEvent::Bid(ItemBid {
+            item: "foo".to_owned(),
+            price,
+        })]
+    };
+    let old_state = AuctionBiddingState {
+        max_bid_limit: 100,
+        last_bid_sent: Some(10),
+        ..Default::default()
+    };
+    let new_state = AuctionBiddingState {
+        last_bid_sent: Some(20),
+        ..old_state
+    };
+
+    // within the budget, or without any
+    assert_eq!(
+        BiddingEngine::handle_budget(Some(100), 80, Some(old_state), new_state, bid(20)),
+        (new_state, bid(20))
+    );
+    assert_eq!(
+        BiddingEngine::handle_budget(None, 1000, Some(old_state), new_state, bid(20)),
+        (new_state, bid(20))
+    );
+
+    let withheld = AuctionBiddingState {
+        withheld_bid: Some(20),
+        ..old_state
+    };
+    assert_eq!(
+        BiddingEngine::handle_budget(Some(100), 81, Some(old_state), new_state, bid(20)),
+        (
+            withheld,
+            vec![BiddingEngineEvent::BidWithheld(BidWithheld {
+                item: "foo".to_owned(),
+                price: 20,
+                reason: WithholdReason::OverBudget {
+                    budget: 100,
+                    committed: 81
+                },
+            })]
+        )
+    );
+    assert_eq!(
+        BiddingEngine::handle_budget(
+            Some(100),
+            81,
+            Some(withheld),
+            AuctionBiddingState {
+                last_bid_sent: Some(20),
+                ..withheld
+            },
+            bid(20)
+        ),
+        (withheld, vec![])
+    );
+    // once the budget allows it, the bid goes out
+    assert_eq!(
+        BiddingEngine::handle_budget(
+            Some(100),
+            50,
+            Some(withheld),
+            AuctionBiddingState {
+                last_bid_sent: Some(20),
+                ..withheld
+            },
+            bid(20)
+        ),
+        (new_state, bid(20))
+    );
+}
+
+#[test]
+fn withheld_bid_is_placed_once_budget_frees_up() -> Result<()> {
+    let persistence = persistence::InMemoryPersistence::new();
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+    let mut conn = persistence.get_connection()?;
+    let mut bidding_engine =
+        BiddingEngine::new(InMemoryBiddingStateStore::new_shared(), event_writer).with_budget(80);
+
+    let mut handle = |event| -> Result<()> {
+        let mut transaction = conn.start_transaction()?;
+        bidding_engine.handle_event(&mut *transaction, event)?;
+        transaction.commit()
+    };
+    let max_bid = |item: &str| {
+        BiddingEngineInput::Ui(UiEvent::MaxBidSet(ItemBid {
+            item: item.to_owned(),
+            price: 70,
+        }))
+    };
+    let outbid = |item: &str, price| {
+        BiddingEngineInput::AuctionHouse(AuctionHouseEvent {
+            item: item.to_owned(),
+            event: AuctionHouseItemEvent::Bid(BidDetails {
+                bidder: Bidder::Other,
+                price,
+                increment: 10,
+            }),
+        })
+    };
+
+    handle(max_bid("foo"))?;
+    handle(outbid("foo", 50))?;
+    handle(max_bid("bar"))?;
+    // 60 committed to "foo" already
+    handle(outbid("bar", 30))?;
+    // over the limit of "foo", so nothing committed to it anymore
+    handle(outbid("foo", 100))?;
+    handle(BiddingEngineInput::Timer(TimerEvent::Tick(now())))?;
+
+    let bids = event_reader
+        .read(&mut *conn, 0, 100, None)?
+        .data
+        .into_iter()
+        .filter_map(|event| match event.details.decode() {
+            Ok(Some(event @ (BiddingEngineEvent::Bid(_) | BiddingEngineEvent::BidWithheld(_)))) => {
+                Some(event)
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let bid = |item: &str, price| {
+        BiddingEngineEvent::Bid(ItemBid {
+            item: item.to_owned(),
+            price,
+        })
+    };
+    assert_eq!(
+        bids,
+        vec![
+            bid("foo", 0),
+            bid("foo", 60),
+            bid("bar", 0),
+            BiddingEngineEvent::BidWithheld(BidWithheld {
+                item: "bar".to_owned(),
+                price: 40,
+                reason: WithholdReason::OverBudget {
+                    budget: 80,
+                    committed: 60
+                },
+            }),
+            bid("bar", 40),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn tick_places_last_moment_bids() -> Result<()> {
+    let persistence = persistence::InMemoryPersistence::new();
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+    let bidding_state_store = InMemoryBiddingStateStore::new_shared();
+    let mut conn = persistence.get_connection()?;
+
+    let mut handle = |bidding_engine: &mut BiddingEngine, event| -> Result<()> {
+        let mut transaction = conn.start_transaction()?;
+        bidding_engine.handle_event(&mut *transaction, event)?;
+        transaction.commit()
+    };
+    let tick = |time| BiddingEngineInput::Timer(TimerEvent::Tick(time));
+
+    let mut bidding_engine = BiddingEngine::new(bidding_state_store.clone(), event_writer);
+    // only ever sees the ticks, as it's in the other partition than the item
+    let mut other_partition = bidding_engine.clone().with_partition(
+        (0..2)
+            .map(|index| Partition { index, count: 2 })
+            .find(|partition| !partition.contains(Some("foo")))
+            .expect("some partition"),
+    );
+
+    handle(
+        &mut bidding_engine,
+        BiddingEngineInput::Ui(UiEvent::StrategySet(ItemStrategy {
+            item: "foo".to_owned(),
+            strategy: Strategy::LastMoment(LastMoment {
+                window: Duration::from_secs(10),
+            }),
+        })),
+    )?;
+    handle(
+        &mut bidding_engine,
+        BiddingEngineInput::Ui(UiEvent::MaxBidSet(ItemBid {
+            item: "foo".to_owned(),
+            price: 100,
+        })),
+    )?;
+    handle(
+        &mut bidding_engine,
+        BiddingEngineInput::AuctionHouse(AuctionHouseEvent {
+            item: "foo".to_owned(),
+            event: AuctionHouseItemEvent::ClosesAt(now()),
+        }),
+    )?;
+    handle(&mut bidding_engine, tick(now() - Duration::from_secs(20)))?;
+    handle(&mut other_partition, tick(now() - Duration::from_secs(5)))?;
+    handle(&mut bidding_engine, tick(now() - Duration::from_secs(5)))?;
+    handle(&mut bidding_engine, tick(now() - Duration::from_secs(4)))?;
+
+    let bids = event_reader
+        .read(&mut *conn, 0, 100, None)?
+        .data
+        .into_iter()
+        .filter_map(|event| match event.details.decode() {
+            Ok(Some(BiddingEngineEvent::Bid(bid))) => Some(bid),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        bids,
+        vec![ItemBid {
+            item: "foo".to_owned(),
+            price: 0
+        }]
+    );
+    Ok(())
+}
+
+#[test]
+fn default_strategy_is_immediate_outbid() -> Result<()> {
+    // what auctions stored before strategies were introduced get in the databases
+    assert_eq!(
+        serde_json::from_str::<Strategy>(r#"{"ImmediateOutbid":null}"#)?,
+        Strategy::default()
+    );
+    assert_eq!(
+        AuctionBiddingState::default().strategy,
+        Strategy::ImmediateOutbid(ImmediateOutbid)
+    );
+    Ok(())
+}
+
+#[test]
+fn setting_a_strategy_changes_the_next_bid() -> Result<()> {
+    let (state, events) = BiddingEngine::handle_max_bid_limit_event("foo", None, 100, now())?;
+    assert_eq!(
+        events,
+        vec![BiddingEngineEvent::Bid(ItemBid {
+            item: "foo".to_string(),
+            price: 0
+        })]
+    );
+
+    let outbid = AuctionBiddingState {
+        auction_state: AuctionState {
+            higest_bid: Some(BidDetails {
+                bidder: Bidder::Other,
+                increment: 1,
+                price: 10,
+            }),
+            closed: false,
+            deadline: None,
+        },
+        ..state.expect("state")
+    };
+    assert_eq!(
+        BiddingEngine::handle_strategy_set_event(
+            "foo",
+            Some(outbid),
+            Strategy::FixedStep(FixedStep { step: 10 }),
+            now(),
+        )?,
+        (
+            Some(AuctionBiddingState {
+                last_bid_sent: Some(21),
+                strategy: Strategy::FixedStep(FixedStep { step: 10 }),
+                ..outbid
+            }),
+            vec![BiddingEngineEvent::Bid(ItemBid {
+                item: "foo".to_string(),
+                price: 21
+            })]
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn sniper_status_follows_the_auction() {
+    let bid = |bidder| {
+        Some(BidDetails {
+            bidder,
+            increment: 1,
+            price: 10,
+        })
+    };
+    for (higest_bid, closed, status) in [
+        (None, false, SniperStatus::Joining),
+        (bid(Bidder::Other), false, SniperStatus::Bidding),
+        (bid(Bidder::Sniper), false, SniperStatus::Winning),
+        (None, true, SniperStatus::Lost),
+        (bid(Bidder::Other), true, SniperStatus::Lost),
+        (bid(Bidder::Sniper), true, SniperStatus::Won),
+    ] {
+        assert_eq!(
+            AuctionState {
+                higest_bid,
+                closed,
+                deadline: None
+            }
+            .sniper_status(),
+            status,
+            "{higest_bid:?} {closed}"
+        );
+    }
+}
+
+#[test]
+fn announces_only_status_changes() {
+    let joining = AuctionBiddingState::default();
+    let won = AuctionBiddingState {
+        auction_state: AuctionState {
+            higest_bid: Some(BidDetails {
+                bidder: Bidder::Sniper,
+                increment: 1,
+                price: 0,
+            }),
+            closed: true,
+            deadline: None,
+        },
+        ..joining
+    };
+
+    assert_eq!(
+        BiddingEngine::handle_status_change("foo", None, joining),
+        Some(BiddingEngineEvent::StatusChanged(ItemStatus {
+            item: "foo".to_owned(),
+            status: SniperStatus::Joining
+        }))
+    );
+    assert_eq!(
+        BiddingEngine::handle_status_change(
+            "foo",
+            Some(joining),
+            AuctionBiddingState {
+                max_bid_limit: 100,
+                ..joining
+            }
+        ),
+        None
+    );
+    assert_eq!(
+        BiddingEngine::handle_status_change("foo", Some(joining), won),
+        Some(BiddingEngineEvent::StatusChanged(ItemStatus {
+            item: "foo".to_owned(),
+            status: SniperStatus::Won
+        }))
+    );
+}
+
+#[test]
+fn in_memory_bidding_state_store_discards_rolled_back_state() -> Result<()> {
+    check_bidding_state_store_discards_rolled_back_state(
+        &persistence::InMemoryPersistence::new(),
+        InMemoryBiddingStateStore::new_shared(),
+    )
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_bidding_state_store_discards_rolled_back_state() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let bidding_state_store = PostgresBiddingStateStore::new_shared();
+    persistence.migrate(&[PostgresBiddingStateStore::MIGRATIONS])?;
+
+    check_bidding_state_store_discards_rolled_back_state(&persistence, bidding_state_store)
+}
+
+#[test]
+fn sqlite_sanity_check_sends_a_bid_when_asked_to_via_event_log() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::SqliteLog::new_shared();
+    let bidding_state_store = SqliteBiddingStateStore::new_shared();
+    persistence.migrate(&[
+        event_log::SqliteLog::MIGRATIONS,
+        SqliteBiddingStateStore::MIGRATIONS,
+    ])?;
+
+    check_sends_a_bid_when_asked_to_via_event_log(
+        &persistence,
+        event_writer,
+        event_reader,
+        bidding_state_store,
+    )
+}
+
+#[test]
+fn sqlite_bidding_state_store_round_trip() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let bidding_state_store = SqliteBiddingStateStore::new_shared();
+    persistence.migrate(&[SqliteBiddingStateStore::MIGRATIONS])?;
+
+    check_bidding_state_store_round_trip(&persistence, bidding_state_store)
+}
+
+#[test]
+fn sqlite_bidding_state_store_discards_rolled_back_state() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let bidding_state_store = SqliteBiddingStateStore::new_shared();
+    persistence.migrate(&[SqliteBiddingStateStore::MIGRATIONS])?;
+
+    check_bidding_state_store_discards_rolled_back_state(&persistence, bidding_state_store)
+}
+
+#[test]
+fn bids_are_caused_by_the_max_bid_event() -> Result<()> {
+    let persistence = Arc::new(persistence::InMemoryPersistence::new());
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+    let svc_ctr = ServiceControl::new(
+        persistence.clone(),
+        progress::InMemoryProgressTracker::new_shared(),
+        dead_letter::InMemoryDeadLetterStore::new_shared(),
+    );
+
+    let _bidding_engine = svc_ctr.spawn_log_follower(
+        BiddingEngine::new(
+            InMemoryBiddingStateStore::new_shared(),
+            event_writer.clone(),
+        ),
+        event_reader.clone(),
+    );
+
+    let mut conn = persistence.get_connection()?;
+    event_writer.write(
+        &mut *conn,
+        &[Event::new(&UiEvent::MaxBidSet(ItemBid {
+            item: "foo".to_owned(),
+            price: 100,
+        }))?],
+    )?;
+
+    let max_bid = event_reader.read_one(&mut *conn, 0)?;
+    let bid = event_reader.read(&mut *conn, max_bid.offset, 1, Some(Duration::from_secs(10)))?;
+    svc_ctr.send_stop_to_all();
+
+    let max_bid = max_bid.data.expect("written");
+    let bid = bid.data.into_iter().next().expect("bid placed");
+    assert_eq!(
+        bid.details.decode()?,
+        Some(BiddingEngineEvent::Bid(ItemBid {
+            item: "foo".to_owned(),
+            price: 0
+        }))
+    );
+    assert_eq!(bid.metadata.causation_id, Some(max_bid.metadata.id));
+    assert_eq!(bid.metadata.correlation_id, max_bid.metadata.id);
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use crate::{
+    auction::ItemBid,
+    clock::{Clock, SimulatedClock},
+    dead_letter,
+    event::{
+        topics::{
+            AuctionHouseEvent, AuctionHouseItemEvent, BiddingEngineEvent, ItemStrategy, LastMoment,
+            Strategy, UiEvent,
+        },
+        Event,
+    },
+    event_log::{self, Offset},
+    persistence::{self, Persistence},
+    progress,
+    service::{
+        bidding_engine::{BiddingEngine, InMemoryBiddingStateStore},
+        LogFollowerService, ServiceControl, Timer,
+    },
+};
+use anyhow::{bail, Result};
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+fn start() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+}
+
+fn wait_for(mut done: impl FnMut() -> Result<bool>) -> Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !done()? {
+        if deadline < Instant::now() {
+            bail!("timeout");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+#[test]
+fn simulated_clock_stands_still_until_advanced() {
+    let clock = SimulatedClock::new(start());
+    assert_eq!(clock.now(), start());
+    // nothing to wait for
+    clock.sleep(Duration::ZERO);
+    assert_eq!(clock.now(), start());
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(clock.now(), start() + Duration::from_secs(60));
+}
+
+#[test]
+fn fast_forwarded_time_triggers_last_moment_bid() -> Result<()> {
+    let clock = SimulatedClock::new_shared(start());
+    let persistence = Arc::new(persistence::InMemoryPersistence::new());
+    let progress_store = progress::InMemoryProgressTracker::new_shared();
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+    let svc_ctr = ServiceControl::new(
+        persistence.clone(),
+        progress_store.clone(),
+        dead_letter::InMemoryDeadLetterStore::new_shared(),
+    )
+    .with_clock(clock.clone());
+
+    let bidding_engine = BiddingEngine::new(
+        InMemoryBiddingStateStore::new_shared(),
+        event_writer.clone(),
+    );
+    let bidding_engine_id = bidding_engine.get_log_progress_id();
+    let handles = [
+        svc_ctr.spawn_log_follower(bidding_engine, event_reader.clone()),
+        svc_ctr.spawn_loop(Timer::new(
+            persistence.clone(),
+            event_writer.clone(),
+            svc_ctr.clock(),
+            Duration::from_secs(1),
+        )),
+    ];
+
+    let offset = event_writer.write(
+        &mut *persistence.get_connection()?,
+        &[
+            Event::new(&UiEvent::StrategySet(ItemStrategy {
+                item: "foo".to_owned(),
+                strategy: Strategy::LastMoment(LastMoment {
+                    window: Duration::from_secs(10),
+                }),
+            }))?,
+            Event::new(&UiEvent::MaxBidSet(ItemBid {
+                item: "foo".to_owned(),
+                price: 100,
+            }))?,
+            Event::new(&AuctionHouseEvent {
+                item: "foo".to_owned(),
+                event: AuctionHouseItemEvent::ClosesAt(start() + Duration::from_secs(60)),
+            })?,
+        ],
+    )?;
+
+    let bids = || -> Result<Vec<ItemBid>> {
+        Ok(event_reader
+            .read(&mut *persistence.get_connection()?, 0, 100, None)?
+            .data
+            .into_iter()
+            .filter_map(|event| match event.details.decode() {
+                Ok(Some(BiddingEngineEvent::Bid(bid))) => Some(bid),
+                _ => None,
+            })
+            .collect())
+    };
+
+    wait_for(|| {
+        Ok(progress_store
+            .load(&mut *persistence.get_connection()?, &bidding_engine_id)?
+            .map_or(false, |progress: Offset| offset <= progress))
+    })?;
+    // still 60s before the auction closes
+    assert_eq!(bids()?, vec![]);
+
+    clock.advance(Duration::from_secs(55));
+    wait_for(|| Ok(!bids()?.is_empty()))?;
+    assert_eq!(
+        bids()?,
+        vec![ItemBid {
+            item: "foo".to_owned(),
+            price: 0
+        }]
+    );
+
+    svc_ctr.send_stop_to_all();
+    for handle in handles {
+        handle.join()?;
+    }
+    Ok(())
+}
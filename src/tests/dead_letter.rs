@@ -0,0 +1,246 @@
+use super::test_event;
+use crate::{
+    dead_letter::{self, DeadLetter, SharedDeadLetterStore},
+    event_log::{EventId, EventMetadata, LogEvent, Offset},
+    persistence::{self, Persistence},
+};
+use anyhow::Result;
+use std::time::{Duration, SystemTime};
+
+/// A time that survives the round trip through databases storing microseconds
+fn whole_seconds(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn dead_letter(service_id: &str, offset: Offset) -> DeadLetter {
+    DeadLetter {
+        service_id: service_id.to_owned(),
+        event: LogEvent {
+            offset,
+            metadata: EventMetadata {
+                id: EventId::from_u128(offset.into()),
+                timestamp: whole_seconds(1_600_000_000),
+                causation_id: None,
+                correlation_id: EventId::from_u128(offset.into()),
+            },
+            details: test_event(),
+        },
+        error: "poison".to_owned(),
+        attempts: 3,
+        failed_at: whole_seconds(1_700_000_000),
+        redrive_requested: false,
+    }
+}
+
+fn store(
+    persistence: &dyn Persistence,
+    dead_letter_store: &SharedDeadLetterStore,
+    dead_letters: impl IntoIterator<Item = DeadLetter>,
+) -> Result<()> {
+    let mut conn = persistence.get_connection()?;
+    let mut transaction = conn.start_transaction()?;
+    for dead_letter in dead_letters {
+        dead_letter_store.store_tr(&mut *transaction, dead_letter)?;
+    }
+    transaction.commit()
+}
+
+fn check_dead_letter_store_round_trip(
+    persistence: &dyn Persistence,
+    dead_letter_store: SharedDeadLetterStore,
+) -> Result<()> {
+    let foo_3 = dead_letter("foo", 3);
+    let foo_1 = dead_letter("foo", 1);
+    let bar_2 = dead_letter("bar", 2);
+    store(
+        persistence,
+        &dead_letter_store,
+        [foo_3.clone(), foo_1.clone()],
+    )?;
+    store(persistence, &dead_letter_store, [bar_2.clone()])?;
+
+    let mut conn = persistence.get_connection()?;
+    assert_eq!(
+        dead_letter_store.list(&mut *conn, None)?,
+        vec![bar_2.clone(), foo_1.clone(), foo_3.clone()]
+    );
+    assert_eq!(
+        dead_letter_store.list(&mut *conn, Some("foo"))?,
+        vec![foo_1.clone(), foo_3.clone()]
+    );
+    assert_eq!(dead_letter_store.list(&mut *conn, Some("baz"))?, vec![]);
+
+    // storing again replaces the dead letter
+    let foo_1_again = DeadLetter {
+        error: "still poison".to_owned(),
+        attempts: 4,
+        ..foo_1
+    };
+    store(persistence, &dead_letter_store, [foo_1_again.clone()])?;
+
+    let mut transaction = conn.start_transaction()?;
+    assert_eq!(
+        dead_letter_store.load_tr(&mut *transaction, "foo", 1)?,
+        Some(foo_1_again)
+    );
+    assert_eq!(
+        dead_letter_store.load_tr(&mut *transaction, "bar", 1)?,
+        None
+    );
+    transaction.commit()?;
+
+    assert!(dead_letter_store.discard(&mut *conn, "foo", 1)?);
+    assert!(!dead_letter_store.discard(&mut *conn, "foo", 1)?);
+    assert_eq!(
+        dead_letter_store.list(&mut *conn, None)?,
+        vec![bar_2, foo_3]
+    );
+
+    Ok(())
+}
+
+fn check_dead_letter_store_redrive(
+    persistence: &dyn Persistence,
+    dead_letter_store: SharedDeadLetterStore,
+) -> Result<()> {
+    store(
+        persistence,
+        &dead_letter_store,
+        [
+            dead_letter("foo", 1),
+            dead_letter("foo", 2),
+            dead_letter("foo", 3),
+            dead_letter("bar", 1),
+        ],
+    )?;
+
+    let mut conn = persistence.get_connection()?;
+    assert_eq!(dead_letter_store.next_redrive(&mut *conn, "foo")?, None);
+
+    assert!(dead_letter_store.request_redrive(&mut *conn, "foo", 3)?);
+    assert!(dead_letter_store.request_redrive(&mut *conn, "foo", 2)?);
+    assert!(!dead_letter_store.request_redrive(&mut *conn, "foo", 4)?);
+
+    assert_eq!(
+        dead_letter_store.next_redrive(&mut *conn, "foo")?,
+        Some(DeadLetter {
+            redrive_requested: true,
+            ..dead_letter("foo", 2)
+        })
+    );
+    assert_eq!(dead_letter_store.next_redrive(&mut *conn, "bar")?, None);
+
+    Ok(())
+}
+
+fn check_dead_letter_store_discards_rolled_back_changes(
+    persistence: &dyn Persistence,
+    dead_letter_store: SharedDeadLetterStore,
+) -> Result<()> {
+    store(persistence, &dead_letter_store, [dead_letter("foo", 1)])?;
+
+    let mut conn = persistence.get_connection()?;
+    let mut transaction = conn.start_transaction()?;
+    dead_letter_store.store_tr(&mut *transaction, dead_letter("foo", 2))?;
+    dead_letter_store.store_tr(
+        &mut *transaction,
+        DeadLetter {
+            attempts: 10,
+            ..dead_letter("foo", 1)
+        },
+    )?;
+    transaction.rollback()?;
+
+    // dropping without commit works like a rollback
+    let mut transaction = conn.start_transaction()?;
+    assert!(dead_letter_store.remove_tr(&mut *transaction, "foo", 1)?);
+    drop(transaction);
+
+    assert_eq!(
+        dead_letter_store.list(&mut *conn, None)?,
+        vec![dead_letter("foo", 1)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn in_memory_dead_letter_store_round_trip() -> Result<()> {
+    check_dead_letter_store_round_trip(
+        &persistence::InMemoryPersistence::new(),
+        dead_letter::InMemoryDeadLetterStore::new_shared(),
+    )
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_dead_letter_store_round_trip() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let dead_letter_store = dead_letter::PostgresDeadLetterStore::new_shared();
+    persistence.migrate(&[dead_letter::PostgresDeadLetterStore::MIGRATIONS])?;
+
+    check_dead_letter_store_round_trip(&persistence, dead_letter_store)
+}
+
+#[test]
+fn sqlite_dead_letter_store_round_trip() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let dead_letter_store = dead_letter::SqliteDeadLetterStore::new_shared();
+    persistence.migrate(&[dead_letter::SqliteDeadLetterStore::MIGRATIONS])?;
+
+    check_dead_letter_store_round_trip(&persistence, dead_letter_store)
+}
+
+#[test]
+fn in_memory_dead_letter_store_redrive() -> Result<()> {
+    check_dead_letter_store_redrive(
+        &persistence::InMemoryPersistence::new(),
+        dead_letter::InMemoryDeadLetterStore::new_shared(),
+    )
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_dead_letter_store_redrive() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let dead_letter_store = dead_letter::PostgresDeadLetterStore::new_shared();
+    persistence.migrate(&[dead_letter::PostgresDeadLetterStore::MIGRATIONS])?;
+
+    check_dead_letter_store_redrive(&persistence, dead_letter_store)
+}
+
+#[test]
+fn sqlite_dead_letter_store_redrive() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let dead_letter_store = dead_letter::SqliteDeadLetterStore::new_shared();
+    persistence.migrate(&[dead_letter::SqliteDeadLetterStore::MIGRATIONS])?;
+
+    check_dead_letter_store_redrive(&persistence, dead_letter_store)
+}
+
+#[test]
+fn in_memory_dead_letter_store_discards_rolled_back_changes() -> Result<()> {
+    check_dead_letter_store_discards_rolled_back_changes(
+        &persistence::InMemoryPersistence::new(),
+        dead_letter::InMemoryDeadLetterStore::new_shared(),
+    )
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_dead_letter_store_discards_rolled_back_changes() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let dead_letter_store = dead_letter::PostgresDeadLetterStore::new_shared();
+    persistence.migrate(&[dead_letter::PostgresDeadLetterStore::MIGRATIONS])?;
+
+    check_dead_letter_store_discards_rolled_back_changes(&persistence, dead_letter_store)
+}
+
+#[test]
+fn sqlite_dead_letter_store_discards_rolled_back_changes() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let dead_letter_store = dead_letter::SqliteDeadLetterStore::new_shared();
+    persistence.migrate(&[dead_letter::SqliteDeadLetterStore::MIGRATIONS])?;
+
+    check_dead_letter_store_discards_rolled_back_changes(&persistence, dead_letter_store)
+}
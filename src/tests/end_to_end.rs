@@ -0,0 +1,222 @@
+use super::fake_auction_server::{FakeAuctionServer, SNIPER_JID};
+use crate::{
+    auction::Amount,
+    dead_letter,
+    event::topics::{BiddingEngineEvent, ItemStatus, SniperStatus},
+    event_log::{self, Reader},
+    persistence::{self, Persistence},
+    progress,
+    service::{
+        auction_house::XmppAuctionHouseClient, AsyncJoinHandle, AsyncServiceControl,
+        AuctionHouseReceiver, AuctionHouseSender, BiddingEngine, InMemoryBiddingStateStore,
+        JoinHandle, ServiceControl, Ui,
+    },
+};
+use anyhow::{bail, Result};
+use serde_json::json;
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::runtime::Runtime;
+
+/// The whole sniper, in-process and in-memory, driven through its HTTP Ui
+struct ApplicationRunner {
+    svc_ctr: ServiceControl,
+    handles: Vec<JoinHandle>,
+    runtime: Runtime,
+    ui: AsyncJoinHandle,
+    ui_address: SocketAddr,
+    persistence: Arc<persistence::InMemoryPersistence>,
+    event_log: Arc<event_log::InMemoryLog>,
+}
+
+impl ApplicationRunner {
+    fn start(auction_server: &FakeAuctionServer) -> Result<Self> {
+        let persistence = Arc::new(persistence::InMemoryPersistence::new());
+        let event_log = Arc::new(event_log::InMemoryLog::new());
+        let dead_letter_store = dead_letter::InMemoryDeadLetterStore::new_shared();
+        let auction_house_client =
+            XmppAuctionHouseClient::new_shared(auction_server.sniper_config());
+
+        let progress_store = progress::InMemoryProgressTracker::new_shared();
+        let svc_ctr = ServiceControl::new(
+            persistence.clone(),
+            progress_store.clone(),
+            dead_letter_store.clone(),
+        );
+        let runtime = Runtime::new()?;
+        let async_svc_ctr =
+            AsyncServiceControl::new(&svc_ctr, runtime.handle().clone(), persistence.clone());
+
+        // a port nobody else is using (most likely, once it's released)
+        let ui_address = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let ui = async_svc_ctr.spawn_loop(
+            Ui::new(persistence.clone(), event_log.clone(), dead_letter_store)
+                .with_address(ui_address),
+        );
+
+        let handles = vec![
+            svc_ctr.spawn_log_follower(
+                BiddingEngine::new(InMemoryBiddingStateStore::new_shared(), event_log.clone()),
+                event_log.clone(),
+            ),
+            svc_ctr.spawn_loop(AuctionHouseReceiver::new(
+                persistence.clone(),
+                event_log.clone(),
+                auction_house_client.clone(),
+                progress_store,
+            )),
+            svc_ctr.spawn_log_follower(
+                AuctionHouseSender::new(auction_house_client),
+                event_log.clone(),
+            ),
+        ];
+
+        Ok(Self {
+            svc_ctr,
+            handles,
+            runtime,
+            ui,
+            ui_address,
+            persistence,
+            event_log,
+        })
+    }
+
+    /// Ask the sniper to bid for `item`, up to `max_price`, like the user would
+    fn bid(&self, item: &str, max_price: Amount) -> Result<()> {
+        let body = serde_json::to_vec(&json!({ "item": item, "price": max_price }))?;
+        let client = hyper::Client::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let request = hyper::Request::post(format!("http://{}/bid/", self.ui_address))
+                .header("content-type", "application/json")
+                .body(hyper::Body::from(body.clone()))?;
+            match self.runtime.block_on(client.request(request)) {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => bail!("bid request failed: {}", response.status()),
+                // the Ui might not be listening yet
+                Err(e) if Instant::now() < deadline => {
+                    tracing::debug!(error = %e, "bid request failed, retrying");
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Wait for the sniper to announce `status` as the last one of `item`
+    fn shows_sniper_status(&self, item: &str, status: SniperStatus) -> Result<()> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let mut conn = self.persistence.get_connection()?;
+            let last_status = Reader::read(&*self.event_log, &mut *conn, 0, usize::MAX, None)?
+                .data
+                .into_iter()
+                .rev()
+                .find_map(|event| match event.details.decode() {
+                    Ok(Some(BiddingEngineEvent::StatusChanged(ItemStatus {
+                        item: status_item,
+                        status,
+                    }))) if status_item == item => Some(status),
+                    _ => None,
+                });
+            if last_status == Some(status) {
+                return Ok(());
+            }
+            if deadline < Instant::now() {
+                bail!("timeout waiting for status {status:?} of {item}, last: {last_status:?}");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn stop(self) -> Result<()> {
+        self.svc_ctr.send_stop_to_all();
+        for handle in self.handles {
+            handle.join()?;
+        }
+        self.runtime.block_on(self.ui.join())
+    }
+}
+
+#[test]
+fn sniper_joins_auction_until_auction_closes() -> Result<()> {
+    let auction = FakeAuctionServer::start("sniper")?;
+    let application = ApplicationRunner::start(&auction)?;
+
+    application.bid("item-54321", 1000)?;
+    auction.has_received_join_request_from_sniper("item-54321")?;
+    application.shows_sniper_status("item-54321", SniperStatus::Joining)?;
+
+    auction.announce_closed("item-54321")?;
+    application.shows_sniper_status("item-54321", SniperStatus::Lost)?;
+
+    application.stop()
+}
+
+#[test]
+fn sniper_makes_a_higher_bid_but_loses() -> Result<()> {
+    let auction = FakeAuctionServer::start("sniper")?;
+    let application = ApplicationRunner::start(&auction)?;
+
+    application.bid("item-54321", 2000)?;
+    auction.has_received_join_request_from_sniper("item-54321")?;
+
+    auction.report_price("item-54321", 1000, 98, "other bidder")?;
+    application.shows_sniper_status("item-54321", SniperStatus::Bidding)?;
+    auction.has_received_bid("item-54321", 1098)?;
+
+    auction.announce_closed("item-54321")?;
+    application.shows_sniper_status("item-54321", SniperStatus::Lost)?;
+
+    application.stop()
+}
+
+#[test]
+fn sniper_wins_an_auction_by_bidding_higher() -> Result<()> {
+    let auction = FakeAuctionServer::start("sniper")?;
+    let application = ApplicationRunner::start(&auction)?;
+
+    application.bid("item-54321", 2000)?;
+    auction.has_received_join_request_from_sniper("item-54321")?;
+
+    auction.report_price("item-54321", 1000, 98, "other bidder")?;
+    auction.has_received_bid("item-54321", 1098)?;
+
+    auction.report_price("item-54321", 1098, 97, SNIPER_JID)?;
+    application.shows_sniper_status("item-54321", SniperStatus::Winning)?;
+
+    auction.announce_closed("item-54321")?;
+    application.shows_sniper_status("item-54321", SniperStatus::Won)?;
+
+    application.stop()
+}
+
+#[test]
+fn sniper_bids_for_multiple_items() -> Result<()> {
+    let auction = FakeAuctionServer::start("sniper")?;
+    let application = ApplicationRunner::start(&auction)?;
+
+    application.bid("item-54321", 2000)?;
+    application.bid("item-65432", 2000)?;
+    auction.has_received_join_request_from_sniper("item-54321")?;
+    auction.has_received_join_request_from_sniper("item-65432")?;
+
+    auction.report_price("item-54321", 1000, 98, "other bidder")?;
+    auction.has_received_bid("item-54321", 1098)?;
+    auction.report_price("item-65432", 500, 21, "other bidder")?;
+    auction.has_received_bid("item-65432", 521)?;
+
+    auction.report_price("item-54321", 1098, 97, SNIPER_JID)?;
+    auction.report_price("item-65432", 521, 22, SNIPER_JID)?;
+    auction.announce_closed("item-54321")?;
+    auction.announce_closed("item-65432")?;
+    application.shows_sniper_status("item-54321", SniperStatus::Won)?;
+    application.shows_sniper_status("item-65432", SniperStatus::Won)?;
+
+    application.stop()
+}
@@ -0,0 +1,70 @@
+use super::{test_event, TestEvent};
+use crate::{
+    auction::ItemBid,
+    event::{topics::*, Event, Subscription},
+    service::*,
+};
+use anyhow::Result;
+
+#[test]
+fn events_are_decoded_only_from_their_topic() -> Result<()> {
+    let max_bid = UiEvent::MaxBidSet(ItemBid {
+        item: "foo".to_owned(),
+        price: 1,
+    });
+    let event = Event::new(&max_bid)?;
+
+    assert_eq!(event.topic, "ui");
+    assert_eq!(event.decode()?, Some(max_bid));
+    assert_eq!(event.decode::<BiddingEngineEvent>()?, None);
+    assert_eq!(test_event().decode::<UiEvent>()?, None);
+    assert_eq!(test_event().decode()?, Some(TestEvent));
+    Ok(())
+}
+
+#[test]
+fn subscriptions_decode_all_their_topics() -> Result<()> {
+    let max_bid = UiEvent::MaxBidSet(ItemBid {
+        item: "foo".to_owned(),
+        price: 1,
+    });
+    let closed = AuctionHouseEvent {
+        item: "foo".to_owned(),
+        event: AuctionHouseItemEvent::Closed,
+    };
+
+    assert_eq!(
+        BiddingEngineInput::decode(&Event::new(&max_bid)?)?,
+        Some(BiddingEngineInput::Ui(max_bid))
+    );
+    assert_eq!(
+        BiddingEngineInput::decode(&Event::new(&closed)?)?,
+        Some(BiddingEngineInput::AuctionHouse(closed))
+    );
+    assert_eq!(BiddingEngineInput::decode(&test_event())?, None);
+    assert_eq!(
+        BiddingEngineInput::decode(&Event::new(&BiddingEngineEvent::Bid(ItemBid {
+            item: "foo".to_owned(),
+            price: 1,
+        }))?)?,
+        None
+    );
+    Ok(())
+}
+
+#[test]
+fn events_of_wrong_shape_are_an_error() {
+    let event = Event {
+        topic: "ui".to_owned(),
+        payload: serde_json::json!({"NoSuchVariant": 1}),
+    };
+    assert!(event.decode::<UiEvent>().is_err());
+}
+
+#[test]
+fn known_topics_are_unique() {
+    let mut topics = KNOWN_TOPICS.to_vec();
+    topics.sort_unstable();
+    topics.dedup();
+    assert_eq!(topics.len(), KNOWN_TOPICS.len());
+}
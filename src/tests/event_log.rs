@@ -1,17 +1,23 @@
-use std::time::Duration;
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
+use super::test_event;
 use crate::{
-    event::*,
+    clock::{Clock, SimulatedClock},
+    event::{topics::UiEvent, *},
     event_log::{self, LogEvent, WithOffset},
     persistence::{self, Persistence},
 };
 use anyhow::Result;
 
-#[test]
-fn event_logs_sanity_check() -> Result<()> {
-    let persistence = persistence::InMemoryPersistence::new();
-    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
-
+fn check_event_log_sanity(
+    persistence: &dyn Persistence,
+    event_writer: event_log::SharedWriter,
+    event_reader: event_log::SharedReader,
+) -> Result<()> {
     let start_offset = event_reader.get_start_offset()?;
 
     let mut conn = persistence.get_connection()?;
@@ -32,7 +38,7 @@ fn event_logs_sanity_check() -> Result<()> {
         }
     );
 
-    let inserted_offset = event_writer.write(&mut *conn, &[Event::Test])?;
+    let inserted_offset = event_writer.write(&mut *conn, &[test_event()])?;
 
     assert_eq!(
         event_reader.read(&mut *conn, inserted_offset, 1, Some(Duration::from_secs(0)))?,
@@ -42,21 +48,568 @@ fn event_logs_sanity_check() -> Result<()> {
         }
     );
 
+    let res = event_reader.read(
+        &mut *conn,
+        event_reader.get_start_offset()?,
+        1,
+        Some(Duration::from_secs(0)),
+    )?;
+    assert_eq!(res.offset, inserted_offset);
+    assert_eq!(
+        res.data
+            .into_iter()
+            .map(|e| (e.offset, e.details))
+            .collect::<Vec<_>>(),
+        vec![(event_reader.get_start_offset()?, test_event())]
+    );
+
+    Ok(())
+}
+
+fn check_event_log_read_waits_for_new_events(
+    persistence: impl Persistence + Clone + 'static,
+    event_writer: event_log::SharedWriter,
+    event_reader: event_log::SharedReader,
+) -> Result<()> {
+    let mut conn = persistence.get_connection()?;
+    let start_offset = event_reader.get_start_offset()?;
+
+    let writer_thread = thread::spawn({
+        let persistence = persistence.clone();
+        move || -> Result<()> {
+            thread::sleep(Duration::from_millis(200));
+            event_writer.write(&mut *persistence.get_connection()?, &[test_event()])?;
+            Ok(())
+        }
+    });
+
+    let start = Instant::now();
+    let res = event_reader.read(&mut *conn, start_offset, 1, Some(Duration::from_secs(10)))?;
+    assert_eq!(res.data.len(), 1);
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    writer_thread.join().expect("no panic")?;
+
+    // nothing more to read, so it should time out
+    let start = Instant::now();
+    let res = event_reader.read(&mut *conn, res.offset, 1, Some(Duration::from_millis(100)))?;
+    assert_eq!(res.data, vec![]);
+    assert!(Duration::from_millis(100) <= start.elapsed());
+
+    Ok(())
+}
+
+fn check_event_log_discards_rolled_back_events(
+    persistence: &dyn Persistence,
+    event_writer: event_log::SharedWriter,
+    event_reader: event_log::SharedReader,
+) -> Result<()> {
+    let start_offset = event_reader.get_start_offset()?;
+    let mut conn = persistence.get_connection()?;
+
+    let mut transaction = conn.start_transaction()?;
+    let offset = event_writer.write_tr(&mut *transaction, &[test_event()])?;
+    assert_eq!(
+        event_writer.write_tr(&mut *transaction, &[test_event(), test_event()])?,
+        offset + 2
+    );
+    transaction.rollback()?;
+
+    let mut transaction = conn.start_transaction()?;
+    event_writer.write_tr(&mut *transaction, &[test_event()])?;
+    drop(transaction);
+
     assert_eq!(
-        event_reader.read(
-            &mut *conn,
-            event_reader.get_start_offset()?,
-            1,
-            Some(Duration::from_secs(0))
-        )?,
+        event_reader.read(&mut *conn, start_offset, 10, Some(Duration::from_secs(0)))?,
         WithOffset {
-            offset: inserted_offset,
-            data: vec![LogEvent {
-                offset: event_reader.get_start_offset()?,
-                details: Event::Test
-            }]
+            offset: start_offset,
+            data: vec![]
+        }
+    );
+
+    // offsets of rolled back events are reused
+    assert_eq!(event_writer.write(&mut *conn, &[test_event()])?, offset);
+
+    Ok(())
+}
+
+fn check_event_log_records_metadata(
+    persistence: &dyn Persistence,
+    event_writer: event_log::SharedWriter,
+    event_reader: event_log::SharedReader,
+) -> Result<()> {
+    let start_offset = event_reader.get_start_offset()?;
+    let mut conn = persistence.get_connection()?;
+
+    let before = SystemTime::now();
+    event_writer.write(&mut *conn, &[test_event()])?;
+    let root = event_reader.read_one(&mut *conn, start_offset)?;
+    let root = root.data.expect("written").metadata;
+
+    assert_eq!(root.causation_id, None);
+    assert_eq!(root.correlation_id, root.id);
+    // some backends store it with a lower precision
+    assert!(before - Duration::from_millis(1) <= root.timestamp);
+    assert!(root.timestamp <= SystemTime::now());
+
+    let mut transaction = conn.start_transaction()?;
+    event_writer.write_tr(
+        &mut event_log::CausedTransaction::new(&mut *transaction, root.clone()),
+        &[test_event(), test_event()],
+    )?;
+    transaction.commit()?;
+
+    let caused = event_reader.read(
+        &mut *conn,
+        start_offset + 1,
+        10,
+        Some(Duration::from_secs(0)),
+    )?;
+    assert_eq!(caused.data.len(), 2);
+    let child = caused.data[0].metadata.clone();
+    assert_eq!(child.causation_id, Some(root.id));
+    assert_eq!(child.correlation_id, root.id);
+    assert_ne!(child.id, caused.data[1].metadata.id);
+
+    let mut transaction = conn.start_transaction()?;
+    event_writer.write_tr(
+        &mut event_log::CausedTransaction::new(&mut *transaction, child.clone()),
+        &[test_event()],
+    )?;
+    transaction.commit()?;
+
+    let grandchild = event_reader.read_one(&mut *conn, caused.offset)?;
+    let grandchild = grandchild.data.expect("written").metadata;
+    assert_eq!(grandchild.causation_id, Some(child.id));
+    assert_eq!(grandchild.correlation_id, root.id);
+
+    Ok(())
+}
+
+/// `event_writer` and `event_reader` being a log using `clock`
+fn check_event_log_timestamps_events_with_its_clock(
+    persistence: &dyn Persistence,
+    clock: &SimulatedClock,
+    event_writer: event_log::SharedWriter,
+    event_reader: event_log::SharedReader,
+) -> Result<()> {
+    let start_offset = event_reader.get_start_offset()?;
+    let mut conn = persistence.get_connection()?;
+    let start = clock.now();
+
+    event_writer.write(&mut *conn, &[test_event()])?;
+    clock.advance(Duration::from_secs(3600));
+    event_writer.write(&mut *conn, &[test_event()])?;
+
+    let events = event_reader.read(&mut *conn, start_offset, 10, Some(Duration::from_secs(0)))?;
+    let timestamps: Vec<_> = events
+        .data
+        .iter()
+        .map(|event| event.metadata.timestamp)
+        .collect();
+    assert_eq!(timestamps, [start, start + Duration::from_secs(3600)]);
+
+    Ok(())
+}
+
+/// Far enough from now, to tell it apart from the real time
+fn simulated_clock() -> Arc<SimulatedClock> {
+    SimulatedClock::new_shared(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000))
+}
+
+#[test]
+fn event_logs_sanity_check() -> Result<()> {
+    let persistence = persistence::InMemoryPersistence::new();
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+
+    check_event_log_sanity(&persistence, event_writer, event_reader)
+}
+
+#[test]
+fn in_memory_log_read_waits_for_new_events() -> Result<()> {
+    let persistence = persistence::InMemoryPersistence::new();
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+
+    check_event_log_read_waits_for_new_events(persistence, event_writer, event_reader)
+}
+
+#[test]
+fn in_memory_log_discards_rolled_back_events() -> Result<()> {
+    let persistence = persistence::InMemoryPersistence::new();
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+
+    check_event_log_discards_rolled_back_events(&persistence, event_writer, event_reader)
+}
+
+#[test]
+fn in_memory_log_tracks_uncommitted_events_per_transaction() -> Result<()> {
+    // two persistences sharing a log can have transactions open at once
+    let (persistence_a, persistence_b) = (
+        persistence::InMemoryPersistence::new(),
+        persistence::InMemoryPersistence::new(),
+    );
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+    let (mut conn_a, mut conn_b) = (
+        persistence_a.get_connection()?,
+        persistence_b.get_connection()?,
+    );
+
+    let mut transaction_a = conn_a.start_transaction()?;
+    let mut transaction_b = conn_b.start_transaction()?;
+    assert_eq!(
+        event_writer.write_tr(&mut *transaction_a, &[test_event()])?,
+        1
+    );
+    assert_eq!(
+        event_writer.write_tr(&mut *transaction_b, &[test_event(), test_event()])?,
+        2
+    );
+    transaction_a.rollback()?;
+    transaction_b.commit()?;
+
+    let read = event_reader.read(&mut *conn_a, 0, 10, Some(Duration::from_secs(0)))?;
+    assert_eq!(read.offset, 2);
+    assert_eq!(event_writer.write(&mut *conn_a, &[test_event()])?, 3);
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_log_sanity_check() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::PostgresLog::new_shared();
+    persistence.migrate(&[event_log::PostgresLog::MIGRATIONS])?;
+
+    check_event_log_sanity(&persistence, event_writer, event_reader)
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_log_read_waits_for_new_events() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::PostgresLog::new_shared();
+    persistence.migrate(&[event_log::PostgresLog::MIGRATIONS])?;
+
+    check_event_log_read_waits_for_new_events(persistence, event_writer, event_reader)
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_log_writing_nothing_doesnt_wait_for_other_writers() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let (event_writer, _event_reader) = event_log::PostgresLog::new_shared();
+    persistence.migrate(&[event_log::PostgresLog::MIGRATIONS])?;
+
+    let mut conn = persistence.get_connection()?;
+    let mut transaction = conn.start_transaction()?;
+    event_writer.write_tr(&mut *transaction, &[test_event()])?;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let writer = thread::spawn({
+        let (persistence, event_writer) = (persistence.clone(), event_writer.clone());
+        move || -> Result<()> {
+            let offset = event_writer.write(&mut *persistence.get_connection()?, &[])?;
+            sender.send(offset)?;
+            Ok(())
+        }
+    });
+    let written = receiver.recv_timeout(Duration::from_secs(5));
+    transaction.commit()?;
+    writer.join().expect("no panic")?;
+
+    assert_eq!(written, Ok(0));
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_log_discards_rolled_back_events() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::PostgresLog::new_shared();
+    persistence.migrate(&[event_log::PostgresLog::MIGRATIONS])?;
+
+    check_event_log_discards_rolled_back_events(&persistence, event_writer, event_reader)
+}
+
+#[test]
+fn sqlite_log_sanity_check() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::SqliteLog::new_shared();
+    persistence.migrate(&[event_log::SqliteLog::MIGRATIONS])?;
+
+    check_event_log_sanity(&persistence, event_writer, event_reader)
+}
+
+#[test]
+fn sqlite_log_read_waits_for_new_events() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::SqliteLog::new_shared();
+    persistence.migrate(&[event_log::SqliteLog::MIGRATIONS])?;
+
+    check_event_log_read_waits_for_new_events(persistence, event_writer, event_reader)
+}
+
+#[test]
+fn sqlite_log_discards_rolled_back_events() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::SqliteLog::new_shared();
+    persistence.migrate(&[event_log::SqliteLog::MIGRATIONS])?;
+
+    check_event_log_discards_rolled_back_events(&persistence, event_writer, event_reader)
+}
+
+#[test]
+fn file_log_sanity_check() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let persistence = persistence::InMemoryPersistence::new();
+    let (event_writer, event_reader) = event_log::file::new_file_shared(dir.path())?;
+
+    check_event_log_sanity(&persistence, event_writer, event_reader)
+}
+
+#[test]
+fn file_log_read_waits_for_new_events() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let persistence = persistence::InMemoryPersistence::new();
+    let (event_writer, event_reader) = event_log::file::new_file_shared(dir.path())?;
+
+    check_event_log_read_waits_for_new_events(persistence, event_writer, event_reader)
+}
+
+#[test]
+fn file_log_discards_rolled_back_events() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let persistence = persistence::InMemoryPersistence::new();
+    let (event_writer, event_reader) = event_log::file::new_file_shared(dir.path())?;
+
+    check_event_log_discards_rolled_back_events(&persistence, event_writer, event_reader)
+}
+
+fn read_all(
+    log: &event_log::file::FileLog,
+    conn: &mut dyn persistence::Connection,
+) -> Result<Vec<LogEvent>> {
+    use event_log::Reader;
+    Ok(log
+        .read(conn, 0, usize::MAX, Some(Duration::from_secs(0)))?
+        .data)
+}
+
+#[test]
+fn file_log_recovers_events_from_multiple_segments() -> Result<()> {
+    use event_log::Writer;
+
+    let dir = tempfile::tempdir()?;
+    let persistence = persistence::InMemoryPersistence::new();
+    let mut conn = persistence.get_connection()?;
+
+    let events: Vec<_> = (0..10)
+        .map(|i| {
+            Event::new(&UiEvent::MaxBidSet(crate::auction::ItemBid {
+                item: format!("item-{i}"),
+                price: i,
+            }))
+            .expect("no fail")
+        })
+        .collect();
+
+    {
+        let log = event_log::file::FileLog::open_with_max_segment_size(dir.path(), 100)?;
+        for chunk in events.chunks(3) {
+            log.write(&mut *conn, chunk)?;
         }
+    }
+
+    assert!(3 <= std::fs::read_dir(dir.path())?.count());
+
+    let log = event_log::file::FileLog::open_with_max_segment_size(dir.path(), 100)?;
+    let read = read_all(&log, &mut *conn)?;
+    assert_eq!(
+        read.iter().map(|e| e.details.clone()).collect::<Vec<_>>(),
+        events
+    );
+    assert_eq!(
+        read.iter().map(|e| e.offset).collect::<Vec<_>>(),
+        (0..10).collect::<Vec<_>>()
+    );
+
+    // reading from the middle of a segment
+    use event_log::Reader;
+    let res = log.read(&mut *conn, 4, 3, Some(Duration::from_secs(0)))?;
+    assert_eq!(res.offset, 7);
+    assert_eq!(
+        res.data.into_iter().map(|e| e.details).collect::<Vec<_>>(),
+        events[4..7]
     );
 
     Ok(())
 }
+
+#[test]
+fn file_log_truncates_torn_tail_on_open() -> Result<()> {
+    use event_log::Writer;
+    use std::io::Write;
+
+    let dir = tempfile::tempdir()?;
+    let persistence = persistence::InMemoryPersistence::new();
+    let mut conn = persistence.get_connection()?;
+    let segment = dir.path().join(format!("{:020}.log", 0));
+
+    {
+        let log = event_log::file::FileLog::open(dir.path())?;
+        log.write(&mut *conn, &[test_event(), test_event()])?;
+    }
+    let valid_len = std::fs::metadata(&segment)?.len();
+
+    // a record with a header, but only part of the payload
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&segment)?
+        .write_all(&[100, 0, 0, 0, 1, 2, 3, 4, b'{'])?;
+
+    {
+        let log = event_log::file::FileLog::open(dir.path())?;
+        assert_eq!(std::fs::metadata(&segment)?.len(), valid_len);
+        assert_eq!(read_all(&log, &mut *conn)?.len(), 2);
+
+        assert_eq!(log.write(&mut *conn, &[test_event()])?, 3);
+    }
+
+    // a complete record with a payload that doesn't match the checksum
+    let mut file = std::fs::OpenOptions::new().append(true).open(&segment)?;
+    file.write_all(&[1, 0, 0, 0, 0, 0, 0, 0, b'x'])?;
+
+    let log = event_log::file::FileLog::open(dir.path())?;
+    assert_eq!(read_all(&log, &mut *conn)?.len(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn file_log_fails_to_open_with_corruption_before_tail() -> Result<()> {
+    use event_log::Writer;
+
+    let dir = tempfile::tempdir()?;
+    let persistence = persistence::InMemoryPersistence::new();
+    let mut conn = persistence.get_connection()?;
+    let segment = dir.path().join(format!("{:020}.log", 0));
+
+    {
+        let log = event_log::file::FileLog::open(dir.path())?;
+        log.write(&mut *conn, &[test_event(), test_event()])?;
+    }
+
+    // flip a byte in the payload of the first record
+    let mut bytes = std::fs::read(&segment)?;
+    bytes[10] ^= 0xff;
+    std::fs::write(&segment, &bytes)?;
+
+    assert!(event_log::file::FileLog::open(dir.path()).is_err());
+    // and nothing was truncated
+    assert_eq!(std::fs::read(&segment)?, bytes);
+
+    Ok(())
+}
+
+#[test]
+fn sqlite_log_reads_unversioned_events() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let (_event_writer, event_reader) = event_log::SqliteLog::new_shared();
+    persistence.migrate(&[event_log::SqliteLog::MIGRATIONS])?;
+
+    // as written before the versioned wire format
+    let mut conn = persistence.get_connection()?;
+    conn.cast()
+        .as_mut::<persistence::SqliteConnection>()?
+        .0
+        .execute(
+            "INSERT INTO event_log (log_offset, event_id, recorded_at, correlation_id, details)
+            VALUES (0, '2a0e5e5d-3f9e-4f32-9a43-e0c4bb8f1a4e', 0, '2a0e5e5d-3f9e-4f32-9a43-e0c4bb8f1a4e', '\"Test\"')",
+            [],
+        )?;
+
+    let res = event_reader.read(&mut *conn, 0, 10, Some(Duration::from_secs(0)))?;
+    assert_eq!(res.offset, 1);
+    assert_eq!(
+        res.data.into_iter().map(|e| e.details).collect::<Vec<_>>(),
+        vec![test_event()]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn in_memory_log_records_metadata() -> Result<()> {
+    let persistence = persistence::InMemoryPersistence::new();
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+
+    check_event_log_records_metadata(&persistence, event_writer, event_reader)
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_log_records_metadata() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::PostgresLog::new_shared();
+    persistence.migrate(&[event_log::PostgresLog::MIGRATIONS])?;
+
+    check_event_log_records_metadata(&persistence, event_writer, event_reader)
+}
+
+#[test]
+fn sqlite_log_records_metadata() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let (event_writer, event_reader) = event_log::SqliteLog::new_shared();
+    persistence.migrate(&[event_log::SqliteLog::MIGRATIONS])?;
+
+    check_event_log_records_metadata(&persistence, event_writer, event_reader)
+}
+
+#[test]
+fn file_log_records_metadata() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let persistence = persistence::InMemoryPersistence::new();
+    let (event_writer, event_reader) = event_log::file::new_file_shared(dir.path())?;
+
+    check_event_log_records_metadata(&persistence, event_writer, event_reader)
+}
+
+#[test]
+fn in_memory_log_timestamps_events_with_its_clock() -> Result<()> {
+    let persistence = persistence::InMemoryPersistence::new();
+    let clock = simulated_clock();
+    let log = Arc::new(event_log::InMemoryLog::new().with_clock(clock.clone()));
+
+    check_event_log_timestamps_events_with_its_clock(&persistence, &clock, log.clone(), log)
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_log_timestamps_events_with_its_clock() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let clock = simulated_clock();
+    let log = Arc::new(event_log::PostgresLog::new().with_clock(clock.clone()));
+    persistence.migrate(&[event_log::PostgresLog::MIGRATIONS])?;
+
+    check_event_log_timestamps_events_with_its_clock(&persistence, &clock, log.clone(), log)
+}
+
+#[test]
+fn sqlite_log_timestamps_events_with_its_clock() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let clock = simulated_clock();
+    let log = Arc::new(event_log::SqliteLog::new().with_clock(clock.clone()));
+    persistence.migrate(&[event_log::SqliteLog::MIGRATIONS])?;
+
+    check_event_log_timestamps_events_with_its_clock(&persistence, &clock, log.clone(), log)
+}
+
+#[test]
+fn file_log_timestamps_events_with_its_clock() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let persistence = persistence::InMemoryPersistence::new();
+    let clock = simulated_clock();
+    let log = Arc::new(event_log::file::FileLog::open(dir.path())?.with_clock(clock.clone()));
+
+    check_event_log_timestamps_events_with_its_clock(&persistence, &clock, log.clone(), log)
+}
@@ -0,0 +1,217 @@
+use crate::{
+    auction::Amount,
+    service::auction_house::{
+        sol::{SolMessage, TextValue},
+        xmpp::stream::{escape, StreamReader, BIND_NS, SASL_NS, STREAM_NS},
+        XmppConfig,
+    },
+};
+use anyhow::{bail, Result};
+use base64::Engine;
+use std::{
+    io::{BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub const SNIPER_JID: &str = "sniper@localhost/Auction";
+
+/// Auction house for tests, on an in-process XMPP server
+///
+/// Just enough of an XMPP server to chat with one client at the time,
+/// as the auctions of all the items.
+pub struct FakeAuctionServer {
+    address: String,
+    /// Writing half of the current client connection
+    client: Arc<Mutex<Option<TcpStream>>>,
+    /// `(to, body)` of the messages received
+    received: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl FakeAuctionServer {
+    pub fn start(password: &str) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let server = Self {
+            address: listener.local_addr()?.to_string(),
+            client: Default::default(),
+            received: Default::default(),
+        };
+        thread::spawn({
+            let password = password.to_owned();
+            let client = server.client.clone();
+            let received = server.received.clone();
+            move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else {
+                        return;
+                    };
+                    // the client just reconnects if this fails
+                    let _ = Self::handle_client(stream, &password, &client, &received);
+                }
+            }
+        });
+        Ok(server)
+    }
+
+    fn handle_client(
+        stream: TcpStream,
+        password: &str,
+        client: &Mutex<Option<TcpStream>>,
+        received: &Mutex<Vec<(String, String)>>,
+    ) -> Result<()> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = StreamReader::new(BufReader::new(stream));
+        let open_stream = |reader: &mut StreamReader<_>,
+                           writer: &mut TcpStream,
+                           features: &str|
+         -> Result<()> {
+            reader.read_stream_start()?;
+            writer.write_all(
+                format!(
+                    "<?xml version='1.0'?><stream:stream from='localhost' id='stand-in' version='1.0' xmlns='jabber:client' xmlns:stream='{STREAM_NS}'><stream:features>{features}</stream:features>"
+                )
+                .as_bytes(),
+            )?;
+            Ok(())
+        };
+
+        open_stream(
+            &mut reader,
+            &mut writer,
+            &format!("<mechanisms xmlns='{SASL_NS}'><mechanism>PLAIN</mechanism></mechanisms>"),
+        )?;
+        let auth = reader.read_stanza()?;
+        let credentials = base64::engine::general_purpose::STANDARD.decode(auth.text)?;
+        if !credentials.ends_with(format!("\0{password}").as_bytes()) {
+            writer.write_all(
+                format!("<failure xmlns='{SASL_NS}'><not-authorized/></failure>").as_bytes(),
+            )?;
+            bail!("not authorized");
+        }
+        writer.write_all(format!("<success xmlns='{SASL_NS}'/>").as_bytes())?;
+
+        open_stream(
+            &mut reader,
+            &mut writer,
+            &format!("<bind xmlns='{BIND_NS}'/>"),
+        )?;
+        let bind = reader.read_stanza()?;
+        writer.write_all(
+            format!(
+                "<iq type='result' id='{}'><bind xmlns='{BIND_NS}'><jid>{SNIPER_JID}</jid></bind></iq>",
+                bind.attr("id").unwrap_or_default()
+            )
+            .as_bytes(),
+        )?;
+        *client.lock().expect("lock") = Some(writer);
+
+        loop {
+            let stanza = reader.read_stanza()?;
+            if let (Some(to), Some(body)) = (stanza.attr("to"), stanza.child("body")) {
+                received
+                    .lock()
+                    .expect("lock")
+                    .push((to.to_owned(), body.text.clone()));
+            }
+        }
+    }
+
+    /// Config of the sniper to connect with
+    pub fn sniper_config(&self) -> XmppConfig {
+        XmppConfig {
+            address: self.address.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Send a message to the sniper
+    pub fn send(&self, from: &str, body: &str) -> Result<()> {
+        let mut client = self.client.lock().expect("lock");
+        let Some(client) = client.as_mut() else {
+            bail!("no client");
+        };
+        client.write_all(
+            format!(
+                "<message from='{}' to='{SNIPER_JID}' type='chat'><body>{}</body></message>",
+                escape(from),
+                escape(body)
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    pub fn disconnect(&self) {
+        if let Some(client) = self.client.lock().expect("lock").take() {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Wait for at least `count` messages, returning all of them as `(to, body)`
+    pub fn wait_for_received(&self, count: usize) -> Result<Vec<(String, String)>> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let received = self.received.lock().expect("lock").clone();
+            if count <= received.len() {
+                return Ok(received);
+            }
+            if deadline < Instant::now() {
+                bail!("timeout waiting for {count} messages, got: {received:?}");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    pub fn report_price(
+        &self,
+        item: &str,
+        price: Amount,
+        increment: Amount,
+        bidder: &str,
+    ) -> Result<()> {
+        self.send(
+            &format!("{}/Auction", auction_jid(item)),
+            &SolMessage::Price {
+                current_price: price,
+                increment,
+                bidder: TextValue::new(bidder)?,
+            }
+            .to_string(),
+        )
+    }
+
+    pub fn announce_closed(&self, item: &str) -> Result<()> {
+        self.send(
+            &format!("{}/Auction", auction_jid(item)),
+            &SolMessage::Close.to_string(),
+        )
+    }
+
+    pub fn has_received_join_request_from_sniper(&self, item: &str) -> Result<()> {
+        self.wait_for_message(item, &SolMessage::Join)
+    }
+
+    pub fn has_received_bid(&self, item: &str, price: Amount) -> Result<()> {
+        self.wait_for_message(item, &SolMessage::Bid { price })
+    }
+
+    /// Wait for `message` to the auction of `item`
+    fn wait_for_message(&self, item: &str, message: &SolMessage) -> Result<()> {
+        let message = (auction_jid(item), message.to_string());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !self.received.lock().expect("lock").contains(&message) {
+            if deadline < Instant::now() {
+                bail!("timeout waiting for {message:?}");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+}
+
+/// The JID of the auction of `item`, at the default [`XmppConfig::auction_domain`]
+pub fn auction_jid(item: &str) -> String {
+    format!("auction-{item}@localhost")
+}
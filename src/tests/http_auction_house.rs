@@ -0,0 +1,358 @@
+use crate::{
+    auction::{BidDetails, Bidder},
+    event::topics::{AuctionHouseEvent, AuctionHouseItemEvent},
+    event::Event,
+    event_log::{self, Offset, Reader, Writer},
+    persistence::{InMemoryPersistence, Persistence, Transaction},
+    progress,
+    service::{
+        auction_house::{
+            AuctionHouseClient, AuctionHouseCursor, HttpAuctionHouseClient, HttpConfig,
+        },
+        AuctionHouseReceiver, LoopService,
+    },
+};
+use anyhow::{bail, Result};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::runtime::Runtime;
+
+/// Auction house REST API for tests, serving events added with [`StandIn::push_event`]
+struct StandIn {
+    address: SocketAddr,
+    state: Arc<StandInState>,
+    _runtime: Runtime,
+}
+
+#[derive(Default)]
+struct StandInState {
+    events: Mutex<Vec<Value>>,
+    bids: Mutex<Vec<Value>>,
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    cursor: Option<usize>,
+    timeout_ms: u64,
+}
+
+async fn handle_events(
+    State(state): State<Arc<StandInState>>,
+    Query(query): Query<EventsQuery>,
+) -> Json<Value> {
+    let cursor = query.cursor.unwrap_or(0);
+    let deadline = Instant::now() + Duration::from_millis(query.timeout_ms);
+    loop {
+        let events = state.events.lock().expect("lock").clone();
+        if cursor < events.len() || deadline <= Instant::now() {
+            let new_events = events.get(cursor..).unwrap_or_default().to_vec();
+            return Json(json!({
+                "events": new_events,
+                "cursor": events.len().max(cursor).to_string(),
+            }));
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+async fn handle_bid(State(state): State<Arc<StandInState>>, Json(bid): Json<Value>) -> StatusCode {
+    if bid["price"].as_u64() == Some(0) {
+        return StatusCode::BAD_REQUEST;
+    }
+    state.bids.lock().expect("lock").push(bid);
+    StatusCode::OK
+}
+
+impl StandIn {
+    fn start() -> Result<Self> {
+        let runtime = Runtime::new()?;
+        let state = Arc::new(StandInState::default());
+        let app = Router::new()
+            .route("/api/events", get(handle_events))
+            .route("/api/bids", post(handle_bid))
+            .with_state(state.clone());
+        let server = runtime.block_on(async {
+            axum::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+                .map(|server| server.serve(app.into_make_service()))
+        })?;
+        let address = server.local_addr();
+        runtime.spawn(server);
+        Ok(Self {
+            address,
+            state,
+            _runtime: runtime,
+        })
+    }
+
+    fn config(&self) -> HttpConfig {
+        HttpConfig::new(&format!("http://{}/api/", self.address))
+    }
+
+    fn push_event(&self, event: Value) {
+        self.state.events.lock().expect("lock").push(event);
+    }
+
+    fn bids(&self) -> Vec<Value> {
+        self.state.bids.lock().expect("lock").clone()
+    }
+}
+
+fn price_event(item: &str, price: u64, bidder: &str) -> Value {
+    json!({ "item": item, "type": "price", "price": price, "increment": 2, "bidder": bidder })
+}
+
+fn bid(item: &str, price: u64, bidder: Bidder) -> AuctionHouseEvent {
+    AuctionHouseEvent {
+        item: item.to_owned(),
+        event: AuctionHouseItemEvent::Bid(BidDetails {
+            bidder,
+            price,
+            increment: 2,
+        }),
+    }
+}
+
+/// Next event from `client`, without its cursor
+fn poll(
+    client: &HttpAuctionHouseClient,
+    timeout: Option<Duration>,
+) -> Result<Option<AuctionHouseEvent>> {
+    Ok(client.poll(timeout)?.map(|polled| polled.event))
+}
+
+#[test]
+fn posts_bids() -> Result<()> {
+    let stand_in = StandIn::start()?;
+    let client = HttpAuctionHouseClient::new(stand_in.config());
+
+    client.place_bid("foo", 10)?;
+    client.place_bid("bar", 12)?;
+    assert!(client.place_bid("foo", 0).is_err());
+
+    assert_eq!(
+        stand_in.bids(),
+        vec![
+            json!({ "item": "foo", "price": 10, "bidder": "sniper" }),
+            json!({ "item": "bar", "price": 12, "bidder": "sniper" }),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn polls_events_in_order() -> Result<()> {
+    let stand_in = StandIn::start()?;
+    let client = HttpAuctionHouseClient::new(stand_in.config());
+    stand_in.push_event(price_event("foo", 10, "someone"));
+    stand_in.push_event(price_event("foo", 12, "sniper"));
+    stand_in.push_event(json!({ "item": "foo", "type": "closes_at", "at": 60 }));
+    stand_in.push_event(json!({ "item": "foo", "type": "closed" }));
+
+    let timeout = Some(Duration::from_secs(10));
+    assert_eq!(poll(&client, timeout)?, Some(bid("foo", 10, Bidder::Other)));
+    assert_eq!(
+        poll(&client, timeout)?,
+        Some(bid("foo", 12, Bidder::Sniper))
+    );
+    assert_eq!(
+        poll(&client, timeout)?,
+        Some(AuctionHouseEvent {
+            item: "foo".to_owned(),
+            event: AuctionHouseItemEvent::ClosesAt(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(60)
+            ),
+        })
+    );
+    assert_eq!(
+        poll(&client, timeout)?,
+        Some(AuctionHouseEvent {
+            item: "foo".to_owned(),
+            event: AuctionHouseItemEvent::Closed,
+        })
+    );
+    assert_eq!(poll(&client, Some(Duration::from_millis(100)))?, None);
+    Ok(())
+}
+
+#[test]
+fn long_polls_until_events_come() -> Result<()> {
+    let stand_in = StandIn::start()?;
+    let client = HttpAuctionHouseClient::new(stand_in.config());
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(200));
+            stand_in.push_event(price_event("foo", 10, "someone"));
+        });
+        assert_eq!(
+            poll(&client, Some(Duration::from_secs(10)))?,
+            Some(bid("foo", 10, Bidder::Other))
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn returns_cursor_after_the_last_event_of_a_batch() -> Result<()> {
+    let stand_in = StandIn::start()?;
+    let client = HttpAuctionHouseClient::new(stand_in.config());
+    stand_in.push_event(price_event("foo", 10, "someone"));
+    stand_in.push_event(price_event("foo", 12, "someone"));
+
+    // the second event was fetched too, so the cursor is after both
+    let first = client.poll(Some(Duration::from_secs(10)))?;
+    assert_eq!(first.map(|polled| polled.cursor), Some(None));
+    let second = client.poll(Some(Duration::from_secs(10)))?;
+    assert_eq!(
+        second.map(|polled| polled.cursor),
+        Some(Some(AuctionHouseCursor {
+            auction_house: None,
+            position: "2".to_owned(),
+        }))
+    );
+    Ok(())
+}
+
+#[test]
+fn resumes_from_cursor() -> Result<()> {
+    let stand_in = StandIn::start()?;
+    stand_in.push_event(price_event("foo", 10, "someone"));
+    stand_in.push_event(price_event("bar", 20, "someone"));
+
+    let client = HttpAuctionHouseClient::new(stand_in.config());
+    client.resume(&mut |auction_house| {
+        assert_eq!(auction_house, None);
+        Ok(Some("1".to_owned()))
+    })?;
+    assert_eq!(
+        poll(&client, Some(Duration::from_secs(10)))?,
+        Some(bid("bar", 20, Bidder::Other))
+    );
+
+    // without a stored cursor, from the start
+    client.resume(&mut |_| Ok(None))?;
+    assert_eq!(
+        poll(&client, Some(Duration::from_secs(10)))?,
+        Some(bid("foo", 10, Bidder::Other))
+    );
+    Ok(())
+}
+
+#[test]
+fn receiver_stores_cursor_with_events_and_resumes_from_it() -> Result<()> {
+    let stand_in = StandIn::start()?;
+    let persistence = Arc::new(InMemoryPersistence::new());
+    let event_log = Arc::new(event_log::InMemoryLog::new());
+    let progress_store = progress::InMemoryProgressTracker::new_shared();
+    let new_receiver = || {
+        AuctionHouseReceiver::new(
+            persistence.clone(),
+            event_log.clone(),
+            HttpAuctionHouseClient::new_shared(stand_in.config()),
+            progress_store.clone(),
+        )
+    };
+    let received = || -> Result<Vec<AuctionHouseEvent>> {
+        let mut conn = persistence.get_connection()?;
+        event_log
+            .read(
+                &mut *conn,
+                event_log.get_start_offset()?,
+                10,
+                Some(Duration::ZERO),
+            )?
+            .data
+            .into_iter()
+            .filter_map(|event| event.details.decode().transpose())
+            .collect()
+    };
+
+    stand_in.push_event(price_event("foo", 10, "someone"));
+    let mut receiver = new_receiver();
+    receiver.run_iteration()?;
+    assert_eq!(received()?, vec![bid("foo", 10, Bidder::Other)]);
+
+    // a restarted receiver gets only the events it didn't write yet
+    stand_in.push_event(price_event("bar", 20, "someone"));
+    let mut receiver = new_receiver();
+    receiver.run_iteration()?;
+    assert_eq!(
+        received()?,
+        vec![bid("foo", 10, Bidder::Other), bid("bar", 20, Bidder::Other)]
+    );
+    Ok(())
+}
+
+/// Writer failing the given number of writes, before writing to the `inner` one
+struct FlakyWriter {
+    inner: Arc<event_log::InMemoryLog>,
+    failures: Mutex<usize>,
+}
+
+impl Writer for FlakyWriter {
+    fn write_tr(&self, conn: &mut dyn Transaction<'_>, events: &[Event]) -> Result<Offset> {
+        let mut failures = self.failures.lock().expect("lock");
+        if 0 < *failures {
+            *failures -= 1;
+            bail!("write failed");
+        }
+        self.inner.write_tr(conn, events)
+    }
+}
+
+#[test]
+fn receiver_writes_event_it_failed_to_write_before_polling_again() -> Result<()> {
+    let stand_in = StandIn::start()?;
+    let persistence = Arc::new(InMemoryPersistence::new());
+    let event_log = Arc::new(event_log::InMemoryLog::new());
+    let mut receiver = AuctionHouseReceiver::new(
+        persistence.clone(),
+        Arc::new(FlakyWriter {
+            inner: event_log.clone(),
+            failures: Mutex::new(1),
+        }),
+        HttpAuctionHouseClient::new_shared(stand_in.config()),
+        progress::InMemoryProgressTracker::new_shared(),
+    );
+
+    stand_in.push_event(price_event("foo", 10, "someone"));
+    assert!(receiver.run_iteration().is_err());
+    stand_in.push_event(price_event("bar", 20, "someone"));
+    receiver.run_iteration()?;
+    receiver.run_iteration()?;
+
+    let mut conn = persistence.get_connection()?;
+    let received = event_log
+        .read(&mut *conn, 0, 10, Some(Duration::ZERO))?
+        .data
+        .into_iter()
+        .filter_map(|event| event.details.decode().transpose())
+        .collect::<Result<Vec<AuctionHouseEvent>>>()?;
+    assert_eq!(
+        received,
+        vec![bid("foo", 10, Bidder::Other), bid("bar", 20, Bidder::Other)]
+    );
+    Ok(())
+}
+
+#[test]
+fn fails_on_invalid_events() -> Result<()> {
+    let stand_in = StandIn::start()?;
+    let client = HttpAuctionHouseClient::new(stand_in.config());
+    stand_in.push_event(json!({ "item": "foo", "type": "opened" }));
+
+    assert!(poll(&client, Some(Duration::from_secs(10))).is_err());
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use crate::persistence::{
+    migration::{
+        pending_migrations, ComponentMigrations, Migration, MigrationError, MigrationRegistry,
+    },
+    PostgresPersistence, SqlitePersistence,
+};
+use anyhow::Result;
+
+const V1_MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    sql: "CREATE TABLE foo (id INTEGER PRIMARY KEY)",
+}];
+
+const V2_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        sql: "CREATE TABLE foo (id INTEGER PRIMARY KEY)",
+    },
+    Migration {
+        version: 2,
+        sql: "ALTER TABLE foo ADD COLUMN name TEXT",
+    },
+];
+
+const V1: ComponentMigrations = ComponentMigrations {
+    component: "foo",
+    migrations: V1_MIGRATIONS,
+};
+
+const V2: ComponentMigrations = ComponentMigrations {
+    component: "foo",
+    migrations: V2_MIGRATIONS,
+};
+
+#[test]
+fn pending_migrations_are_selected_by_version() -> Result<()> {
+    assert_eq!(pending_migrations("foo", V2_MIGRATIONS, 0)?, V2_MIGRATIONS);
+    assert_eq!(
+        pending_migrations("foo", V2_MIGRATIONS, 1)?,
+        &V2_MIGRATIONS[1..]
+    );
+    assert_eq!(pending_migrations("foo", V2_MIGRATIONS, 2)?, &[]);
+
+    assert!(matches!(
+        pending_migrations("foo", V1_MIGRATIONS, 2),
+        Err(MigrationError::SchemaTooNew {
+            current: 2,
+            supported: 1,
+            ..
+        })
+    ));
+    assert!(matches!(
+        pending_migrations("foo", &V2_MIGRATIONS[1..], 0),
+        Err(MigrationError::InvalidVersions { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn conflicting_migrations_are_an_error() -> Result<()> {
+    assert!(MigrationRegistry::new(&[V1, V1]).is_ok());
+    assert!(matches!(
+        MigrationRegistry::new(&[V1, V2]),
+        Err(MigrationError::Conflicting { .. })
+    ));
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_applies_only_new_migrations() -> Result<()> {
+    let config = super::postgres::new_test_config()?;
+    let persistence = PostgresPersistence::new(&config)?;
+
+    persistence.migrate(&[V1])?;
+    // nothing new to apply
+    persistence.migrate(&[V1])?;
+
+    // simulate a restart of a newer version of the binary
+    let upgraded = PostgresPersistence::new(&config)?;
+    upgraded.migrate(&[V2])?;
+
+    let mut client = ::postgres::Client::connect(&config, ::postgres::NoTls)?;
+    client.execute("INSERT INTO foo (id, name) VALUES (1, 'one')", &[])?;
+    assert_eq!(
+        client
+            .query(
+                "SELECT version FROM schema_migrations WHERE component = 'foo' ORDER BY version",
+                &[]
+            )?
+            .iter()
+            .map(|row| row.get::<'_, _, i32>(0))
+            .collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_refuses_schema_newer_than_binary() -> Result<()> {
+    let config = super::postgres::new_test_config()?;
+    let persistence = PostgresPersistence::new(&config)?;
+
+    persistence.migrate(&[V2])?;
+
+    let downgraded = PostgresPersistence::new(&config)?;
+    assert!(downgraded.migrate(&[V1]).is_err());
+    // a binary that doesn't know about `foo` at all
+    assert!(downgraded.migrate(&[]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn sqlite_applies_only_new_migrations() -> Result<()> {
+    let (dir, persistence) = super::sqlite::new_test_persistence()?;
+    let path = dir.path().join("sniper.sqlite");
+
+    persistence.migrate(&[V1])?;
+    persistence.migrate(&[V1])?;
+
+    let upgraded = SqlitePersistence::new(&path)?;
+    upgraded.migrate(&[V2])?;
+
+    let conn = rusqlite::Connection::open(&path)?;
+    conn.execute("INSERT INTO foo (id, name) VALUES (1, 'one')", [])?;
+
+    let downgraded = SqlitePersistence::new(&path)?;
+    assert!(downgraded.migrate(&[V1]).is_err());
+    assert!(downgraded.migrate(&[]).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use crate::persistence::PostgresPersistence;
+use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static SCHEMA_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Create a config for connecting to a new, empty database schema
+///
+/// Postgres tests are `#[ignore]`d, and need `SNIPER_TEST_POSTGRES_URL` set to
+/// a libpq-style config, eg. `host=localhost user=postgres dbname=sniper_test`,
+/// to run with `cargo test -- --include-ignored`.
+pub fn new_test_config() -> Result<String> {
+    let config = std::env::var("SNIPER_TEST_POSTGRES_URL")
+        .context("SNIPER_TEST_POSTGRES_URL is needed to run Postgres tests")?;
+
+    let schema = format!(
+        "test_{}_{}",
+        std::process::id(),
+        SCHEMA_COUNTER.fetch_add(1, Ordering::SeqCst)
+    );
+
+    ::postgres::Client::connect(&config, ::postgres::NoTls)?
+        .batch_execute(&format!("CREATE SCHEMA {schema}"))?;
+
+    Ok(format!("{config} options='-c search_path={schema}'"))
+}
+
+/// Create a [`PostgresPersistence`] using a new, empty database schema
+///
+/// See [`new_test_config`].
+pub fn new_test_persistence() -> Result<PostgresPersistence> {
+    PostgresPersistence::new(&new_test_config()?)
+}
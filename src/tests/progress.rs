@@ -0,0 +1,176 @@
+use crate::{
+    persistence::{self, Persistence},
+    progress::{self, SharedProgressTracker},
+};
+use anyhow::Result;
+
+fn check_progress_tracker_round_trip(
+    persistence: &dyn Persistence,
+    progress_store: SharedProgressTracker,
+) -> Result<()> {
+    let mut conn = persistence.get_connection()?;
+
+    assert_eq!(progress_store.load(&mut *conn, "foo")?, None);
+
+    let mut transaction = conn.start_transaction()?;
+    progress_store.store_tr(&mut *transaction, "foo", 3)?;
+    assert_eq!(progress_store.load_tr(&mut *transaction, "foo")?, Some(3));
+    progress_store.store_tr(&mut *transaction, "foo", 5)?;
+    transaction.commit()?;
+
+    assert_eq!(progress_store.load(&mut *conn, "foo")?, Some(5));
+    assert_eq!(progress_store.load(&mut *conn, "bar")?, None);
+
+    Ok(())
+}
+
+fn check_progress_tracker_discards_rolled_back_progress(
+    persistence: &dyn Persistence,
+    progress_store: SharedProgressTracker,
+) -> Result<()> {
+    let mut conn = persistence.get_connection()?;
+
+    let mut transaction = conn.start_transaction()?;
+    progress_store.store_tr(&mut *transaction, "foo", 3)?;
+    transaction.commit()?;
+
+    let mut transaction = conn.start_transaction()?;
+    progress_store.store_tr(&mut *transaction, "foo", 5)?;
+    progress_store.store_tr(&mut *transaction, "bar", 5)?;
+    transaction.rollback()?;
+
+    // dropping without commit works like a rollback
+    let mut transaction = conn.start_transaction()?;
+    progress_store.store_tr(&mut *transaction, "foo", 7)?;
+    drop(transaction);
+
+    assert_eq!(progress_store.load(&mut *conn, "foo")?, Some(3));
+    assert_eq!(progress_store.load(&mut *conn, "bar")?, None);
+
+    Ok(())
+}
+
+fn check_progress_tracker_stores_cursors(
+    persistence: &dyn Persistence,
+    progress_store: SharedProgressTracker,
+) -> Result<()> {
+    let mut conn = persistence.get_connection()?;
+
+    assert_eq!(progress_store.load_cursor(&mut *conn, "foo")?, None);
+
+    let mut transaction = conn.start_transaction()?;
+    progress_store.store_cursor_tr(&mut *transaction, "foo", "a")?;
+    progress_store.store_cursor_tr(&mut *transaction, "foo", "b")?;
+    transaction.commit()?;
+
+    let mut transaction = conn.start_transaction()?;
+    progress_store.store_cursor_tr(&mut *transaction, "foo", "c")?;
+    transaction.rollback()?;
+
+    assert_eq!(
+        progress_store.load_cursor(&mut *conn, "foo")?,
+        Some("b".to_owned())
+    );
+    // apart from the offsets
+    assert_eq!(progress_store.load(&mut *conn, "foo")?, None);
+
+    Ok(())
+}
+
+#[test]
+fn in_memory_progress_tracker_round_trip() -> Result<()> {
+    check_progress_tracker_round_trip(
+        &persistence::InMemoryPersistence::new(),
+        progress::InMemoryProgressTracker::new_shared(),
+    )
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_progress_tracker_round_trip() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let progress_store = progress::PostgresProgressTracker::new_shared();
+    persistence.migrate(&[progress::PostgresProgressTracker::MIGRATIONS])?;
+
+    check_progress_tracker_round_trip(&persistence, progress_store)
+}
+
+#[test]
+fn in_memory_progress_tracker_discards_rolled_back_progress() -> Result<()> {
+    check_progress_tracker_discards_rolled_back_progress(
+        &persistence::InMemoryPersistence::new(),
+        progress::InMemoryProgressTracker::new_shared(),
+    )
+}
+
+#[test]
+fn in_memory_progress_tracker_hides_uncommitted_progress() -> Result<()> {
+    let persistence = persistence::InMemoryPersistence::new();
+    let progress_store = progress::InMemoryProgressTracker::new_shared();
+    let (mut conn, mut reader_conn) =
+        (persistence.get_connection()?, persistence.get_connection()?);
+
+    let mut transaction = conn.start_transaction()?;
+    progress_store.store_tr(&mut *transaction, "foo", 3)?;
+    assert_eq!(progress_store.load_tr(&mut *transaction, "foo")?, Some(3));
+    assert_eq!(progress_store.load(&mut *reader_conn, "foo")?, None);
+    transaction.commit()?;
+
+    assert_eq!(progress_store.load(&mut *reader_conn, "foo")?, Some(3));
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_progress_tracker_discards_rolled_back_progress() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let progress_store = progress::PostgresProgressTracker::new_shared();
+    persistence.migrate(&[progress::PostgresProgressTracker::MIGRATIONS])?;
+
+    check_progress_tracker_discards_rolled_back_progress(&persistence, progress_store)
+}
+
+#[test]
+fn sqlite_progress_tracker_round_trip() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let progress_store = progress::SqliteProgressTracker::new_shared();
+    persistence.migrate(&[progress::SqliteProgressTracker::MIGRATIONS])?;
+
+    check_progress_tracker_round_trip(&persistence, progress_store)
+}
+
+#[test]
+fn sqlite_progress_tracker_discards_rolled_back_progress() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let progress_store = progress::SqliteProgressTracker::new_shared();
+    persistence.migrate(&[progress::SqliteProgressTracker::MIGRATIONS])?;
+
+    check_progress_tracker_discards_rolled_back_progress(&persistence, progress_store)
+}
+
+#[test]
+fn in_memory_progress_tracker_stores_cursors() -> Result<()> {
+    check_progress_tracker_stores_cursors(
+        &persistence::InMemoryPersistence::new(),
+        progress::InMemoryProgressTracker::new_shared(),
+    )
+}
+
+#[test]
+fn sqlite_progress_tracker_stores_cursors() -> Result<()> {
+    let (_dir, persistence) = super::sqlite::new_test_persistence()?;
+    let progress_store = progress::SqliteProgressTracker::new_shared();
+    persistence.migrate(&[progress::SqliteProgressTracker::MIGRATIONS])?;
+
+    check_progress_tracker_stores_cursors(&persistence, progress_store)
+}
+
+#[test]
+#[ignore = "needs Postgres, see `SNIPER_TEST_POSTGRES_URL`"]
+fn postgres_progress_tracker_stores_cursors() -> Result<()> {
+    let persistence = super::postgres::new_test_persistence()?;
+    let progress_store = progress::PostgresProgressTracker::new_shared();
+    persistence.migrate(&[progress::PostgresProgressTracker::MIGRATIONS])?;
+
+    check_progress_tracker_stores_cursors(&persistence, progress_store)
+}
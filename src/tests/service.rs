@@ -0,0 +1,1089 @@
+use super::{test_event, TestEvent};
+use crate::{
+    auction::ItemBid,
+    clock::SimulatedClock,
+    dead_letter::{self, DeadLetter, SharedDeadLetterStore},
+    event::{
+        topics::{ItemStrategy, UiEvent},
+        Event,
+    },
+    event_log::{self, Offset, WithMetadata},
+    persistence::{self, Persistence, Transaction},
+    progress,
+    service::{
+        BatchConfig, ErrorClass, JoinHandle, LogFollowerService, LoopService, Partition,
+        RestartPolicy, ServiceControl,
+    },
+};
+use anyhow::{bail, Result};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    thread::{self, ThreadId},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Records sizes of batches it handled
+struct BatchRecorder {
+    config: BatchConfig,
+    batches: Arc<Mutex<Vec<usize>>>,
+}
+
+impl LogFollowerService for BatchRecorder {
+    type Event = TestEvent;
+
+    fn get_log_progress_id(&self) -> String {
+        "batch-recorder".to_owned()
+    }
+
+    fn batch_config(&self) -> BatchConfig {
+        self.config
+    }
+
+    fn handle_event(
+        &mut self,
+        _transaction: &mut dyn Transaction<'_>,
+        _event: TestEvent,
+    ) -> Result<()> {
+        unreachable!("handles batches")
+    }
+
+    fn handle_batch(
+        &mut self,
+        _transaction: &mut dyn Transaction<'_>,
+        events: Vec<WithMetadata<TestEvent>>,
+    ) -> Result<()> {
+        self.batches.lock().expect("lock").push(events.len());
+        Ok(())
+    }
+}
+
+struct Fixture {
+    persistence: Arc<persistence::InMemoryPersistence>,
+    progress_store: progress::SharedProgressTracker,
+    dead_letter_store: SharedDeadLetterStore,
+    event_writer: event_log::SharedWriter,
+    event_reader: event_log::SharedReader,
+    svc_ctr: ServiceControl,
+}
+
+impl Fixture {
+    fn new() -> Result<Self> {
+        let persistence = Arc::new(persistence::InMemoryPersistence::new());
+        let progress_store = progress::InMemoryProgressTracker::new_shared();
+        let dead_letter_store = dead_letter::InMemoryDeadLetterStore::new_shared();
+        let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+        let svc_ctr = ServiceControl::new(
+            persistence.clone(),
+            progress_store.clone(),
+            dead_letter_store.clone(),
+        );
+        Ok(Self {
+            persistence,
+            progress_store,
+            dead_letter_store,
+            event_writer,
+            event_reader,
+            svc_ctr,
+        })
+    }
+
+    fn spawn_recorder(&self, config: BatchConfig) -> (JoinHandle, Arc<Mutex<Vec<usize>>>) {
+        let batches = Arc::new(Mutex::new(vec![]));
+        let handle = self.svc_ctr.spawn_log_follower(
+            BatchRecorder {
+                config,
+                batches: batches.clone(),
+            },
+            self.event_reader.clone(),
+        );
+        (handle, batches)
+    }
+
+    fn write(&self, events: &[Event]) -> Result<Offset> {
+        self.event_writer
+            .write(&mut *self.persistence.get_connection()?, events)
+    }
+
+    fn wait_for_progress(&self, offset: Offset) -> Result<()> {
+        self.wait_for_progress_of("batch-recorder", offset)
+    }
+
+    fn wait_for_progress_of(&self, id: &str, offset: Offset) -> Result<()> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut conn = self.persistence.get_connection()?;
+        while self.progress_store.load(&mut *conn, id)? != Some(offset) {
+            if deadline < Instant::now() {
+                bail!("timeout waiting for progress {offset} of {id}");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn events_are_handled_in_batches_of_max_size() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let offset = fixture.write(&vec![test_event(); 10])?;
+
+    let (handle, batches) = fixture.spawn_recorder(BatchConfig {
+        max_size: 4,
+        max_latency: Duration::ZERO,
+    });
+    fixture.wait_for_progress(offset)?;
+    fixture.svc_ctr.send_stop_to_all();
+    handle.join()?;
+
+    assert_eq!(*batches.lock().expect("lock"), vec![4, 4, 2]);
+    Ok(())
+}
+
+#[test]
+fn batches_wait_for_more_events_up_to_max_latency() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let (handle, batches) = fixture.spawn_recorder(BatchConfig {
+        max_size: 10,
+        max_latency: Duration::from_secs(5),
+    });
+
+    fixture.write(&[test_event()])?;
+    thread::sleep(Duration::from_millis(100));
+    fixture.write(&[test_event()])?;
+    thread::sleep(Duration::from_millis(100));
+    // fills the batch up, so there's no need to wait until the deadline
+    let offset = fixture.write(&vec![test_event(); 8])?;
+
+    let start = Instant::now();
+    fixture.wait_for_progress(offset)?;
+    assert!(start.elapsed() < Duration::from_secs(5));
+    fixture.svc_ctr.send_stop_to_all();
+    handle.join()?;
+
+    assert_eq!(*batches.lock().expect("lock"), vec![10]);
+    Ok(())
+}
+
+#[test]
+fn events_of_other_topics_are_skipped() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let max_bid = Event::new(&UiEvent::MaxBidSet(ItemBid {
+        item: "foo".to_owned(),
+        price: 1,
+    }))?;
+    fixture.write(&[max_bid.clone(), test_event(), max_bid.clone()])?;
+    fixture.write(&[max_bid.clone(), max_bid.clone()])?;
+    let offset = fixture.write(&[test_event()])?;
+
+    let (handle, batches) = fixture.spawn_recorder(BatchConfig {
+        max_size: 2,
+        max_latency: Duration::ZERO,
+    });
+    fixture.wait_for_progress(offset)?;
+    fixture.svc_ctr.send_stop_to_all();
+    handle.join()?;
+
+    // batches with no subscribed events are not passed to the service at all
+    assert_eq!(*batches.lock().expect("lock"), vec![1, 1]);
+    Ok(())
+}
+
+#[test]
+fn exponential_backoff_doubles_the_delay_up_to_max() {
+    let policy = RestartPolicy::ExponentialBackoff {
+        initial_delay: Duration::from_millis(100),
+        max_delay: Duration::from_secs(1),
+        max_retries: 6,
+    };
+
+    assert_eq!(
+        (0..7)
+            .map(|failures| policy.restart_delay(failures))
+            .collect::<Vec<_>>(),
+        vec![
+            Some(Duration::from_millis(100)),
+            Some(Duration::from_millis(200)),
+            Some(Duration::from_millis(400)),
+            Some(Duration::from_millis(800)),
+            Some(Duration::from_secs(1)),
+            Some(Duration::from_secs(1)),
+            None,
+        ]
+    );
+    assert_eq!(RestartPolicy::Never.restart_delay(0), None);
+    assert_eq!(
+        RestartPolicy::Always {
+            delay: Duration::ZERO
+        }
+        .restart_delay(1000),
+        Some(Duration::ZERO)
+    );
+}
+
+/// Fails the first `failures` iterations, then succeeds
+struct FlakyService {
+    policy: RestartPolicy,
+    failures: usize,
+    iterations: Arc<Mutex<usize>>,
+}
+
+impl FlakyService {
+    fn new(policy: RestartPolicy, failures: usize) -> (Self, Arc<Mutex<usize>>) {
+        let iterations = Arc::new(Mutex::new(0));
+        (
+            Self {
+                policy,
+                failures,
+                iterations: iterations.clone(),
+            },
+            iterations,
+        )
+    }
+}
+
+impl LoopService for FlakyService {
+    fn run_iteration(&mut self) -> Result<()> {
+        let mut iterations = self.iterations.lock().expect("lock");
+        *iterations += 1;
+        if *iterations <= self.failures {
+            bail!("failure #{}", *iterations);
+        }
+        drop(iterations);
+        thread::sleep(Duration::from_millis(1));
+        Ok(())
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        self.policy
+    }
+}
+
+fn wait_for_iterations(iterations: &Mutex<usize>, count: usize) -> Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while *iterations.lock().expect("lock") < count {
+        if deadline < Instant::now() {
+            bail!("timeout waiting for {count} iterations");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+#[test]
+fn failed_service_is_restarted_with_backoff() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let (service, iterations) = FlakyService::new(
+        RestartPolicy::ExponentialBackoff {
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            max_retries: 3,
+        },
+        3,
+    );
+
+    let handle = fixture.svc_ctr.spawn_loop(service);
+    wait_for_iterations(&iterations, 10)?;
+    fixture.svc_ctr.send_stop_to_all();
+
+    handle.join()
+}
+
+#[test]
+fn restart_backoff_waits_on_the_services_clock() -> Result<()> {
+    let clock = SimulatedClock::new_shared(SystemTime::now());
+    let fixture = Fixture::new()?;
+    let svc_ctr = fixture.svc_ctr.clone().with_clock(clock.clone());
+    let (service, iterations) = FlakyService::new(
+        RestartPolicy::ExponentialBackoff {
+            initial_delay: Duration::from_secs(3600),
+            max_delay: Duration::from_secs(3600),
+            max_retries: 1,
+        },
+        1,
+    );
+
+    let handle = svc_ctr.spawn_loop(service);
+    wait_for_iterations(&iterations, 1)?;
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(*iterations.lock().expect("lock"), 1);
+
+    clock.advance(Duration::from_secs(3600));
+    wait_for_iterations(&iterations, 2)?;
+    svc_ctr.send_stop_to_all();
+
+    handle.join()
+}
+
+#[test]
+fn exhausted_restart_policy_stops_all_services() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let (failing, failing_iterations) = FlakyService::new(
+        RestartPolicy::ExponentialBackoff {
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            max_retries: 2,
+        },
+        usize::MAX,
+    );
+    let (healthy, _) = FlakyService::new(RestartPolicy::Never, 0);
+
+    let healthy = fixture.svc_ctr.spawn_loop(healthy);
+    let failing = fixture.svc_ctr.spawn_loop(failing);
+
+    assert_eq!(
+        failing.join().expect_err("failed").to_string(),
+        "failure #3"
+    );
+    assert_eq!(*failing_iterations.lock().expect("lock"), 3);
+    // stopped by the escalated failure
+    healthy.join()
+}
+
+#[test]
+fn service_without_restart_policy_fails_immediately() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let (service, iterations) = FlakyService::new(RestartPolicy::Never, 1);
+
+    assert!(fixture.svc_ctr.spawn_loop(service).join().is_err());
+    assert_eq!(*iterations.lock().expect("lock"), 1);
+    Ok(())
+}
+
+/// Fails handling the first `failures` batches
+struct FlakyFollower {
+    failures: usize,
+    handled: Arc<Mutex<Vec<usize>>>,
+}
+
+impl LogFollowerService for FlakyFollower {
+    type Event = TestEvent;
+
+    fn get_log_progress_id(&self) -> String {
+        "batch-recorder".to_owned()
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Always {
+            delay: Duration::ZERO,
+        }
+    }
+
+    fn batch_config(&self) -> BatchConfig {
+        BatchConfig {
+            max_size: 10,
+            max_latency: Duration::ZERO,
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        _transaction: &mut dyn Transaction<'_>,
+        _event: TestEvent,
+    ) -> Result<()> {
+        unreachable!("handles batches")
+    }
+
+    fn handle_batch(
+        &mut self,
+        _transaction: &mut dyn Transaction<'_>,
+        events: Vec<WithMetadata<TestEvent>>,
+    ) -> Result<()> {
+        if 0 < self.failures {
+            self.failures -= 1;
+            bail!("failed");
+        }
+        self.handled.lock().expect("lock").push(events.len());
+        Ok(())
+    }
+}
+
+#[test]
+fn failed_log_follower_retries_the_same_events() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let offset = fixture.write(&vec![test_event(); 3])?;
+
+    let handled = Arc::new(Mutex::new(vec![]));
+    let handle = fixture.svc_ctr.spawn_log_follower(
+        FlakyFollower {
+            failures: 2,
+            handled: handled.clone(),
+        },
+        fixture.event_reader.clone(),
+    );
+    fixture.wait_for_progress(offset)?;
+    fixture.svc_ctr.send_stop_to_all();
+    handle.join()?;
+
+    assert_eq!(*handled.lock().expect("lock"), vec![3]);
+    Ok(())
+}
+
+fn max_bid_set(item: &str) -> Result<Event> {
+    Event::new(&UiEvent::MaxBidSet(ItemBid {
+        item: item.to_owned(),
+        price: 1,
+    }))
+}
+
+type Items = Arc<Mutex<Vec<String>>>;
+
+/// Fails handling any batch containing a max bid for a `poison` item
+struct PoisonFollower {
+    poison: Items,
+    class: ErrorClass,
+    handled: Items,
+}
+
+impl LogFollowerService for PoisonFollower {
+    type Event = UiEvent;
+
+    fn get_log_progress_id(&self) -> String {
+        "batch-recorder".to_owned()
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Always {
+            delay: Duration::ZERO,
+        }
+    }
+
+    fn batch_config(&self) -> BatchConfig {
+        BatchConfig {
+            max_size: 10,
+            max_latency: Duration::ZERO,
+        }
+    }
+
+    fn classify_error(&self, _error: &anyhow::Error) -> ErrorClass {
+        self.class
+    }
+
+    fn handle_event(
+        &mut self,
+        _transaction: &mut dyn Transaction<'_>,
+        _event: UiEvent,
+    ) -> Result<()> {
+        unreachable!("handles batches")
+    }
+
+    fn handle_batch(
+        &mut self,
+        _transaction: &mut dyn Transaction<'_>,
+        events: Vec<WithMetadata<UiEvent>>,
+    ) -> Result<()> {
+        let items = events
+            .into_iter()
+            .map(|event| match event.data {
+                UiEvent::MaxBidSet(ItemBid { item, .. })
+                | UiEvent::StrategySet(ItemStrategy { item, .. }) => item,
+            })
+            .collect::<Vec<_>>();
+        let poison = self.poison.lock().expect("lock");
+        if let Some(item) = items.iter().find(|item| poison.contains(item)) {
+            bail!("poison: {item}");
+        }
+        self.handled.lock().expect("lock").extend(items);
+        Ok(())
+    }
+}
+
+impl Fixture {
+    fn spawn_poison_follower(
+        &self,
+        poison: &[&str],
+        class: ErrorClass,
+    ) -> (JoinHandle, Items, Items) {
+        let poison = Arc::new(Mutex::new(
+            poison.iter().map(|item| item.to_string()).collect(),
+        ));
+        let handled = Arc::new(Mutex::new(vec![]));
+        let handle = self.svc_ctr.spawn_log_follower(
+            PoisonFollower {
+                poison: poison.clone(),
+                class,
+                handled: handled.clone(),
+            },
+            self.event_reader.clone(),
+        );
+        (handle, poison, handled)
+    }
+
+    fn wait_for_dead_letters(&self, count: usize) -> Result<Vec<DeadLetter>> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut conn = self.persistence.get_connection()?;
+        loop {
+            let dead_letters = self.dead_letter_store.list(&mut *conn, None)?;
+            if dead_letters.len() == count {
+                return Ok(dead_letters);
+            }
+            if deadline < Instant::now() {
+                bail!("timeout waiting for {count} dead letters");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+#[test]
+fn poison_event_is_moved_to_dead_letters() -> Result<()> {
+    let fixture = Fixture::new()?;
+    fixture.write(&[max_bid_set("a")?, max_bid_set("poison")?, max_bid_set("b")?])?;
+    let offset = fixture.write(&[max_bid_set("c")?])?;
+
+    let start = Instant::now();
+    let (handle, _poison, handled) = fixture.spawn_poison_follower(&["poison"], ErrorClass::Poison);
+    fixture.wait_for_progress(offset)?;
+    fixture.svc_ctr.send_stop_to_all();
+    handle.join()?;
+    // retried with a backoff, of 100ms and then 200ms
+    assert!(Duration::from_millis(300) <= start.elapsed());
+
+    // the rest of the batch got handled, one by one
+    assert_eq!(*handled.lock().expect("lock"), vec!["a", "b", "c"]);
+
+    let dead_letters = fixture.wait_for_dead_letters(1)?;
+    assert_eq!(dead_letters[0].service_id, "batch-recorder");
+    assert_eq!(dead_letters[0].event.details, max_bid_set("poison")?);
+    assert_eq!(dead_letters[0].error, "poison: poison");
+    assert_eq!(dead_letters[0].attempts, 3);
+    assert!(!dead_letters[0].redrive_requested);
+    Ok(())
+}
+
+#[test]
+fn retryable_errors_are_not_dead_lettered() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let offset = fixture.write(&[max_bid_set("a")?, max_bid_set("poison")?])?;
+
+    let (handle, poison, handled) =
+        fixture.spawn_poison_follower(&["poison"], ErrorClass::Retryable);
+    // give it a chance to fail more times than a poison event would be attempted
+    thread::sleep(Duration::from_millis(200));
+    assert!(handled.lock().expect("lock").is_empty());
+    poison.lock().expect("lock").clear();
+
+    fixture.wait_for_progress(offset)?;
+    fixture.svc_ctr.send_stop_to_all();
+    handle.join()?;
+
+    assert_eq!(*handled.lock().expect("lock"), vec!["a", "poison"]);
+    fixture.wait_for_dead_letters(0)?;
+    Ok(())
+}
+
+#[test]
+fn redriven_dead_letter_is_handled_again() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let offset = fixture.write(&[max_bid_set("poison")?, max_bid_set("a")?])?;
+
+    let (handle, poison, handled) = fixture.spawn_poison_follower(&["poison"], ErrorClass::Poison);
+    fixture.wait_for_progress(offset)?;
+    let dead_letters = fixture.wait_for_dead_letters(1)?;
+
+    // still poison: it stays a dead letter, with one more attempt
+    let mut conn = fixture.persistence.get_connection()?;
+    let dead_letter_offset = dead_letters[0].event.offset;
+    assert!(fixture.dead_letter_store.request_redrive(
+        &mut *conn,
+        "batch-recorder",
+        dead_letter_offset
+    )?);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while fixture.dead_letter_store.list(&mut *conn, None)?[0].attempts != 4 {
+        if deadline < Instant::now() {
+            bail!("timeout waiting for a re-drive");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!fixture.dead_letter_store.list(&mut *conn, None)?[0].redrive_requested);
+
+    poison.lock().expect("lock").clear();
+    assert!(fixture.dead_letter_store.request_redrive(
+        &mut *conn,
+        "batch-recorder",
+        dead_letter_offset
+    )?);
+    fixture.wait_for_dead_letters(0)?;
+    fixture.svc_ctr.send_stop_to_all();
+    handle.join()?;
+
+    assert_eq!(*handled.lock().expect("lock"), vec!["a", "poison"]);
+    Ok(())
+}
+
+/// Records which thread handled each max bid, unless the item is `stuck`
+#[derive(Clone)]
+struct ItemRecorder {
+    stuck: Arc<Mutex<Option<String>>>,
+    handled: Arc<Mutex<Vec<(ThreadId, ItemBid)>>>,
+}
+
+impl LogFollowerService for ItemRecorder {
+    type Event = UiEvent;
+
+    fn get_log_progress_id(&self) -> String {
+        "item-recorder".to_owned()
+    }
+
+    fn partition_key<'e>(&self, event: &'e UiEvent) -> Option<&'e str> {
+        match event {
+            UiEvent::MaxBidSet(ItemBid { item, .. })
+            | UiEvent::StrategySet(ItemStrategy { item, .. }) => Some(item),
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        _transaction: &mut dyn Transaction<'_>,
+        event: UiEvent,
+    ) -> Result<()> {
+        let UiEvent::MaxBidSet(item_bid) = event else {
+            return Ok(());
+        };
+        while self.stuck.lock().expect("lock").as_ref() == Some(&item_bid.item) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.handled
+            .lock()
+            .expect("lock")
+            .push((thread::current().id(), item_bid));
+        Ok(())
+    }
+}
+
+fn max_bid(item: &str, price: u64) -> Result<Event> {
+    Event::new(&UiEvent::MaxBidSet(ItemBid {
+        item: item.to_owned(),
+        price,
+    }))
+}
+
+/// Progress ids of all `partitions` of the [`ItemRecorder`]
+fn item_recorder_partitions(partitions: usize) -> impl Iterator<Item = String> {
+    (0..partitions).map(move |index| {
+        Partition {
+            index,
+            count: partitions,
+        }
+        .progress_id("item-recorder")
+    })
+}
+
+#[test]
+fn every_key_belongs_to_exactly_one_partition() {
+    for key in ["", "a", "foo", "some-longer-item-id"] {
+        for count in 1..10 {
+            let containing = (0..count)
+                .filter(|&index| Partition { index, count }.contains(Some(key)))
+                .count();
+            assert_eq!(containing, 1, "{key} in {count}");
+        }
+    }
+    // events without a key go to every partition
+    assert!(Partition { index: 0, count: 3 }.contains(None));
+    assert!(Partition { index: 1, count: 3 }.contains(None));
+}
+
+#[test]
+fn partitioned_follower_keeps_events_of_an_item_in_order() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let items = ["a", "b", "c", "d", "e", "f", "g", "h"];
+    let mut offset = 0;
+    for price in 0..5 {
+        for item in items {
+            offset = fixture.write(&[max_bid(item, price)?])?;
+        }
+    }
+
+    let recorder = ItemRecorder {
+        stuck: Default::default(),
+        handled: Default::default(),
+    };
+    let handles = fixture.svc_ctr.spawn_partitioned_log_follower(
+        |_| recorder.clone(),
+        3,
+        fixture.event_reader.clone(),
+    )?;
+    for id in item_recorder_partitions(3) {
+        fixture.wait_for_progress_of(&id, offset)?;
+    }
+    fixture.svc_ctr.send_stop_to_all();
+    for handle in handles {
+        handle.join()?;
+    }
+
+    let mut by_item = BTreeMap::<_, Vec<_>>::new();
+    for (thread, item_bid) in recorder.handled.lock().expect("lock").iter() {
+        by_item
+            .entry(item_bid.item.clone())
+            .or_default()
+            .push((*thread, item_bid.price));
+    }
+    assert_eq!(by_item.len(), items.len());
+    for (item, handled) in by_item {
+        assert_eq!(
+            handled.iter().map(|(_, price)| *price).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4],
+            "{item}"
+        );
+        assert!(handled.iter().all(|(thread, _)| *thread == handled[0].0));
+    }
+    Ok(())
+}
+
+#[test]
+fn stuck_item_holds_back_only_its_partition() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let stuck = (0..2)
+        .map(|index| Partition { index, count: 2 })
+        .find(|partition| partition.contains(Some("stuck")))
+        .expect("some partition");
+    let other = (0..)
+        .map(|i| format!("item-{i}"))
+        .find(|item| !stuck.contains(Some(item)))
+        .expect("some item in the other partition");
+    let other_id = Partition {
+        index: 1 - stuck.index,
+        count: 2,
+    }
+    .progress_id("item-recorder");
+    fixture.write(&[max_bid("stuck", 1)?])?;
+    let offset = fixture.write(&[max_bid(&other, 1)?, max_bid(&other, 2)?])?;
+
+    let recorder = ItemRecorder {
+        stuck: Arc::new(Mutex::new(Some("stuck".to_owned()))),
+        handled: Default::default(),
+    };
+    let handles = fixture.svc_ctr.spawn_partitioned_log_follower(
+        |_| recorder.clone(),
+        2,
+        fixture.event_reader.clone(),
+    )?;
+    fixture.wait_for_progress_of(&other_id, offset)?;
+    assert_eq!(recorder.handled.lock().expect("lock").len(), 2);
+
+    *recorder.stuck.lock().expect("lock") = None;
+    fixture.wait_for_progress_of(&stuck.progress_id("item-recorder"), offset)?;
+    fixture.svc_ctr.send_stop_to_all();
+    for handle in handles {
+        handle.join()?;
+    }
+    assert_eq!(recorder.handled.lock().expect("lock").len(), 3);
+    Ok(())
+}
+
+#[test]
+fn new_partitions_start_from_the_unpartitioned_progress() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let unpartitioned = fixture.write(&[max_bid("a", 1)?, max_bid("b", 1)?])?;
+    let offset = fixture.write(&[max_bid("a", 2)?, max_bid("b", 2)?])?;
+    let mut conn = fixture.persistence.get_connection()?;
+    let mut transaction = conn.start_transaction()?;
+    fixture
+        .progress_store
+        .store_tr(&mut *transaction, "item-recorder", unpartitioned)?;
+    transaction.commit()?;
+    drop(conn);
+
+    let recorder = ItemRecorder {
+        stuck: Default::default(),
+        handled: Default::default(),
+    };
+    let handles = fixture.svc_ctr.spawn_partitioned_log_follower(
+        |_| recorder.clone(),
+        2,
+        fixture.event_reader.clone(),
+    )?;
+    for id in item_recorder_partitions(2) {
+        fixture.wait_for_progress_of(&id, offset)?;
+    }
+    fixture.svc_ctr.send_stop_to_all();
+    for handle in handles {
+        handle.join()?;
+    }
+
+    let mut prices = recorder
+        .handled
+        .lock()
+        .expect("lock")
+        .iter()
+        .map(|(_, item_bid)| item_bid.price)
+        .collect::<Vec<_>>();
+    prices.sort();
+    assert_eq!(prices, vec![2, 2]);
+    Ok(())
+}
+
+#[test]
+fn changing_partition_count_skips_events_the_old_partitions_handled() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let items = ["a", "b", "c", "d"];
+    let first = fixture.write(&[max_bid("a", 1)?])?;
+    let mut offset = first;
+    for item in &items[1..] {
+        offset = fixture.write(&[max_bid(item, 1)?])?;
+    }
+
+    let recorder = ItemRecorder {
+        stuck: Default::default(),
+        handled: Default::default(),
+    };
+    let handles = fixture.svc_ctr.spawn_partitioned_log_follower(
+        |_| recorder.clone(),
+        3,
+        fixture.event_reader.clone(),
+    )?;
+    for id in item_recorder_partitions(3) {
+        fixture.wait_for_progress_of(&id, offset)?;
+    }
+    fixture.svc_ctr.send_stop_to_all();
+    for handle in handles {
+        handle.join()?;
+    }
+
+    // one of the partitions lags behind
+    let mut conn = fixture.persistence.get_connection()?;
+    let mut transaction = conn.start_transaction()?;
+    fixture
+        .progress_store
+        .store_tr(&mut *transaction, "item-recorder/1", first)?;
+    transaction.commit()?;
+    drop(conn);
+    for item in items {
+        offset = fixture.write(&[max_bid(item, 2)?])?;
+    }
+
+    let recorder = ItemRecorder {
+        stuck: Default::default(),
+        handled: Default::default(),
+    };
+    let svc_ctr = ServiceControl::new(
+        fixture.persistence.clone(),
+        fixture.progress_store.clone(),
+        fixture.dead_letter_store.clone(),
+    );
+    let handles = svc_ctr.spawn_partitioned_log_follower(
+        |_| recorder.clone(),
+        2,
+        fixture.event_reader.clone(),
+    )?;
+    for id in item_recorder_partitions(2) {
+        fixture.wait_for_progress_of(&id, offset)?;
+    }
+    svc_ctr.send_stop_to_all();
+    for handle in handles {
+        handle.join()?;
+    }
+
+    let mut handled = recorder
+        .handled
+        .lock()
+        .expect("lock")
+        .iter()
+        .map(|(_, item_bid)| (item_bid.item.clone(), item_bid.price))
+        .collect::<Vec<_>>();
+    handled.sort();
+    // only the lagging partition's items get handled past its progress
+    let lagging = Partition { index: 1, count: 3 };
+    let mut expected = items
+        .iter()
+        .flat_map(|item| [(item.to_string(), 1), (item.to_string(), 2)])
+        .filter(|(item, price)| *price == 2 || (item != "a" && lagging.contains(Some(item))))
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(handled, expected);
+    Ok(())
+}
+
+impl Fixture {
+    /// Store the event at `offset` as a dead letter of `service_id`, with a re-drive requested
+    fn store_dead_letter(&self, service_id: &str, offset: Offset) -> Result<()> {
+        let mut conn = self.persistence.get_connection()?;
+        let event = self
+            .event_reader
+            .read(&mut *conn, offset, 1, Some(Duration::ZERO))?
+            .data
+            .pop()
+            .expect("written");
+        let mut transaction = conn.start_transaction()?;
+        self.dead_letter_store.store_tr(
+            &mut *transaction,
+            DeadLetter {
+                service_id: service_id.to_owned(),
+                event,
+                error: "poison".to_owned(),
+                attempts: 3,
+                failed_at: SystemTime::now(),
+                redrive_requested: true,
+            },
+        )?;
+        transaction.commit()
+    }
+}
+
+#[test]
+fn changing_partition_count_moves_dead_letters_to_the_partition_of_their_key() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let items = ["a", "b", "c", "d", "e", "f"];
+    let mut offset = 0;
+    for item in items {
+        offset = fixture.write(&[max_bid(item, 1)?])?;
+    }
+    let mut conn = fixture.persistence.get_connection()?;
+    let mut transaction = conn.start_transaction()?;
+    for id in item_recorder_partitions(3) {
+        fixture
+            .progress_store
+            .store_tr(&mut *transaction, &id, offset)?;
+    }
+    fixture
+        .progress_store
+        .store_tr(&mut *transaction, "item-recorder/partitions", 3)?;
+    transaction.commit()?;
+    for (event_offset, item) in items.iter().enumerate() {
+        let index = (0..3)
+            .find(|&index| Partition { index, count: 3 }.contains(Some(item)))
+            .expect("some partition");
+        fixture.store_dead_letter(
+            &item_recorder_partitions(3).nth(index).expect("exists"),
+            Offset::try_from(event_offset)?,
+        )?;
+    }
+
+    let recorder = ItemRecorder {
+        stuck: Default::default(),
+        handled: Default::default(),
+    };
+    let handles = fixture.svc_ctr.spawn_partitioned_log_follower(
+        |_| recorder.clone(),
+        2,
+        fixture.event_reader.clone(),
+    )?;
+    fixture.wait_for_dead_letters(0)?;
+    for id in item_recorder_partitions(2) {
+        fixture.wait_for_progress_of(&id, offset)?;
+    }
+    fixture.svc_ctr.send_stop_to_all();
+    for handle in handles {
+        handle.join()?;
+    }
+
+    // each handled once, as a re-driven dead letter, by the partition of its key
+    let handled = recorder.handled.lock().expect("lock").clone();
+    let mut handled_items = handled
+        .iter()
+        .map(|(_, item_bid)| item_bid.item.as_str())
+        .collect::<Vec<_>>();
+    handled_items.sort();
+    assert_eq!(handled_items, items);
+    for partition in (0..2).map(|index| Partition { index, count: 2 }) {
+        let threads = handled
+            .iter()
+            .filter(|(_, item_bid)| partition.contains(Some(&item_bid.item)))
+            .map(|(thread, _)| *thread)
+            .collect::<Vec<_>>();
+        assert!(threads.iter().all(|thread| *thread == threads[0]));
+    }
+    Ok(())
+}
+
+#[test]
+fn partition_count_cant_change_again_until_past_the_last_change() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let first = fixture.write(&[max_bid("a", 1)?])?;
+    let offset = fixture.write(&[max_bid("b", 1)?])?;
+    let mut conn = fixture.persistence.get_connection()?;
+    let mut transaction = conn.start_transaction()?;
+    // changed from 1 partition at `offset`, and one of the 2 new ones is still behind it
+    for (id, progress) in [
+        ("item-recorder/handover", 1),
+        ("item-recorder/handover/0", offset),
+        ("item-recorder/partitions", 2),
+        ("item-recorder/0", offset),
+        ("item-recorder/1", first),
+    ] {
+        fixture
+            .progress_store
+            .store_tr(&mut *transaction, id, progress)?;
+    }
+    transaction.commit()?;
+
+    let new_recorder = |_| ItemRecorder {
+        stuck: Default::default(),
+        handled: Default::default(),
+    };
+    assert!(fixture
+        .svc_ctr
+        .spawn_partitioned_log_follower(new_recorder, 3, fixture.event_reader.clone())
+        .is_err());
+    let handles = fixture.svc_ctr.spawn_partitioned_log_follower(
+        new_recorder,
+        2,
+        fixture.event_reader.clone(),
+    )?;
+    fixture.svc_ctr.send_stop_to_all();
+    for handle in handles {
+        handle.join()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn redriven_dead_letter_of_another_partition_is_kept() -> Result<()> {
+    let fixture = Fixture::new()?;
+    let other = (0..)
+        .map(|i| format!("item-{i}"))
+        .find(|item| !Partition { index: 0, count: 2 }.contains(Some(item)))
+        .expect("some item in the other partition");
+    let offset = fixture.write(&[max_bid(&other, 1)?])?;
+    fixture.store_dead_letter("item-recorder/0", 0)?;
+
+    let recorder = ItemRecorder {
+        stuck: Default::default(),
+        handled: Default::default(),
+    };
+    let handles = fixture.svc_ctr.spawn_partitioned_log_follower(
+        |_| recorder.clone(),
+        2,
+        fixture.event_reader.clone(),
+    )?;
+    for id in item_recorder_partitions(2) {
+        fixture.wait_for_progress_of(&id, offset)?;
+    }
+    let mut conn = fixture.persistence.get_connection()?;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while fixture.dead_letter_store.list(&mut *conn, None)?[0].redrive_requested {
+        if deadline < Instant::now() {
+            bail!("timeout waiting for a re-drive");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    fixture.svc_ctr.send_stop_to_all();
+    for handle in handles {
+        handle.join()?;
+    }
+
+    let dead_letters = fixture.dead_letter_store.list(&mut *conn, None)?;
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].attempts, 4);
+    // handled once, by its own partition
+    assert_eq!(recorder.handled.lock().expect("lock").len(), 1);
+    Ok(())
+}
+
+#[test]
+fn partitioned_follower_needs_a_partition() -> Result<()> {
+    let fixture = Fixture::new()?;
+    assert!(fixture
+        .svc_ctr
+        .spawn_partitioned_log_follower(
+            |_| ItemRecorder {
+                stuck: Default::default(),
+                handled: Default::default(),
+            },
+            0,
+            fixture.event_reader.clone(),
+        )
+        .is_err());
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use crate::{
+    auction::{BidDetails, Bidder},
+    event::topics::AuctionHouseItemEvent,
+    service::auction_house::sol::{SolError, SolMessage, TextValue},
+};
+use anyhow::Result;
+
+#[test]
+fn messages_round_trip() -> Result<()> {
+    for (message, text) in [
+        (
+            SolMessage::Price {
+                current_price: 192,
+                increment: 7,
+                bidder: TextValue::new("other bidder")?,
+            },
+            "SOLVersion: 1.1; Event: PRICE; CurrentPrice: 192; Increment: 7; Bidder: other bidder;",
+        ),
+        (
+            SolMessage::Price {
+                current_price: 192,
+                increment: 7,
+                bidder: TextValue::new("sniper@localhost/Auction:1")?,
+            },
+            "SOLVersion: 1.1; Event: PRICE; CurrentPrice: 192; Increment: 7; Bidder: sniper@localhost/Auction:1;",
+        ),
+        (SolMessage::Close, "SOLVersion: 1.1; Event: CLOSE;"),
+        (SolMessage::Join, "SOLVersion: 1.1; Command: JOIN;"),
+        (
+            SolMessage::Bid { price: 199 },
+            "SOLVersion: 1.1; Command: BID; Price: 199;",
+        ),
+    ] {
+        assert_eq!(message.to_string(), text);
+        assert_eq!(text.parse::<SolMessage>()?, message);
+    }
+    Ok(())
+}
+
+#[test]
+fn parsing_ignores_whitespace_and_field_order() -> Result<()> {
+    assert_eq!(
+        "  Bidder:someone ;Increment: 7;CurrentPrice :192;Event: PRICE; SOLVersion: 1.1"
+            .parse::<SolMessage>()?,
+        SolMessage::Price {
+            current_price: 192,
+            increment: 7,
+            bidder: TextValue::new("someone")?,
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn text_values_that_would_break_the_message_are_rejected() {
+    for value in ["a;b", ";", " a", "a\n"] {
+        assert_eq!(
+            TextValue::new(value),
+            Err(SolError::InvalidValue(value.to_owned())),
+            "{value:?}"
+        );
+    }
+    assert!(TextValue::new("").is_ok());
+}
+
+#[test]
+fn malformed_messages_are_errors() {
+    for (text, error) in [
+        ("", SolError::MissingField("SOLVersion")),
+        (
+            "not a valid message",
+            SolError::InvalidField("not a valid message".to_owned()),
+        ),
+        (
+            "SOLVersion: 1.1; : CLOSE;",
+            SolError::InvalidField(": CLOSE".to_owned()),
+        ),
+        (
+            "SOLVersion: 1.1; Event: CLOSE; Event: CLOSE;",
+            SolError::DuplicateField("Event".to_owned()),
+        ),
+        (
+            "SOLVersion: 2.0; Event: CLOSE;",
+            SolError::UnsupportedVersion("2.0".to_owned()),
+        ),
+        (
+            "SOLVersion: 1.1; Event: PRICE; CurrentPrice: 192; Increment: 7;",
+            SolError::MissingField("Bidder"),
+        ),
+        (
+            "SOLVersion: 1.1; Event: PRICE; CurrentPrice: -1; Increment: 7; Bidder: someone;",
+            SolError::InvalidAmount {
+                field: "CurrentPrice",
+                value: "-1".to_owned(),
+            },
+        ),
+        (
+            "SOLVersion: 1.1; Command: BID; Price: lots;",
+            SolError::InvalidAmount {
+                field: "Price",
+                value: "lots".to_owned(),
+            },
+        ),
+        (
+            "SOLVersion: 1.1; Event: OPEN;",
+            SolError::UnknownEvent("OPEN".to_owned()),
+        ),
+        (
+            "SOLVersion: 1.1; Command: LEAVE;",
+            SolError::UnknownCommand("LEAVE".to_owned()),
+        ),
+        ("SOLVersion: 1.1;", SolError::NeitherEventNorCommand),
+        (
+            "SOLVersion: 1.1; Event: CLOSE; Command: JOIN;",
+            SolError::BothEventAndCommand,
+        ),
+    ] {
+        assert_eq!(text.parse::<SolMessage>(), Err(error), "{text}");
+    }
+}
+
+#[test]
+fn events_become_item_events() -> Result<()> {
+    let price = SolMessage::Price {
+        current_price: 192,
+        increment: 7,
+        bidder: TextValue::new("sniper")?,
+    };
+    assert_eq!(
+        price.to_item_event(|bidder| bidder == "sniper"),
+        Some(AuctionHouseItemEvent::Bid(BidDetails {
+            bidder: Bidder::Sniper,
+            price: 192,
+            increment: 7,
+        }))
+    );
+    assert_eq!(
+        price.to_item_event(|_| false),
+        Some(AuctionHouseItemEvent::Bid(BidDetails {
+            bidder: Bidder::Other,
+            price: 192,
+            increment: 7,
+        }))
+    );
+    assert_eq!(
+        SolMessage::Close.to_item_event(|_| false),
+        Some(AuctionHouseItemEvent::Closed)
+    );
+    assert_eq!(SolMessage::Join.to_item_event(|_| false), None);
+    assert_eq!(SolMessage::Bid { price: 1 }.to_item_event(|_| false), None);
+    Ok(())
+}
@@ -0,0 +1,12 @@
+use crate::persistence::SqlitePersistence;
+use anyhow::Result;
+use tempfile::TempDir;
+
+/// Create a [`SqlitePersistence`] using a new database in a temporary directory
+///
+/// The database is removed when the returned [`TempDir`] is dropped.
+pub fn new_test_persistence() -> Result<(TempDir, SqlitePersistence)> {
+    let dir = tempfile::tempdir()?;
+    let persistence = SqlitePersistence::new(dir.path().join("sniper.sqlite"))?;
+    Ok((dir, persistence))
+}
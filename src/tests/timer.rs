@@ -0,0 +1,163 @@
+use crate::{
+    clock::{SimulatedClock, SystemClock},
+    dead_letter,
+    event::topics::TimerEvent,
+    event_log::{self, Offset},
+    persistence::{self, Persistence},
+    progress,
+    service::{
+        bidding_engine::{AuctionBiddingState, AuctionState, InMemoryBiddingStateStore},
+        ServiceControl, Timer,
+    },
+};
+use anyhow::{bail, Result};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+#[test]
+fn timer_writes_ticks() -> Result<()> {
+    let persistence = Arc::new(persistence::InMemoryPersistence::new());
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+    let svc_ctr = ServiceControl::new(
+        persistence.clone(),
+        progress::InMemoryProgressTracker::new_shared(),
+        dead_letter::InMemoryDeadLetterStore::new_shared(),
+    );
+
+    let handle = svc_ctr.spawn_loop(Timer::new(
+        persistence.clone(),
+        event_writer,
+        SystemClock::new_shared(),
+        Duration::from_millis(10),
+    ));
+
+    let mut conn = persistence.get_connection()?;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut ticks = vec![];
+    let mut offset: Offset = 0;
+    while ticks.len() < 3 {
+        if deadline < Instant::now() {
+            bail!("timeout waiting for ticks");
+        }
+        let events = event_reader.read(&mut *conn, offset, 10, Some(Duration::from_millis(100)))?;
+        offset = events.offset;
+        for event in events.data {
+            let Some(TimerEvent::Tick(time)) = event.details.decode()? else {
+                bail!("not a tick: {:?}", event.details);
+            };
+            ticks.push(time);
+        }
+    }
+    svc_ctr.send_stop_to_all();
+    handle.join()?;
+
+    assert!(ticks.windows(2).all(|pair| pair[0] < pair[1]));
+    Ok(())
+}
+
+#[test]
+fn timer_ticks_on_simulated_time() -> Result<()> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let clock = SimulatedClock::new_shared(start);
+    let persistence = Arc::new(persistence::InMemoryPersistence::new());
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+    let svc_ctr = ServiceControl::new(
+        persistence.clone(),
+        progress::InMemoryProgressTracker::new_shared(),
+        dead_letter::InMemoryDeadLetterStore::new_shared(),
+    )
+    .with_clock(clock.clone());
+
+    let handle = svc_ctr.spawn_loop(Timer::new(
+        persistence.clone(),
+        event_writer,
+        svc_ctr.clock(),
+        Duration::from_secs(1),
+    ));
+
+    let mut conn = persistence.get_connection()?;
+    let mut read_ticks = |offset| -> Result<(Offset, Vec<SystemTime>)> {
+        let events = event_reader.read(&mut *conn, offset, 10, Some(Duration::from_millis(300)))?;
+        let mut ticks = vec![];
+        for event in events.data {
+            let Some(TimerEvent::Tick(time)) = event.details.decode()? else {
+                bail!("not a tick: {:?}", event.details);
+            };
+            ticks.push(time);
+        }
+        Ok((events.offset, ticks))
+    };
+
+    // the time stands still
+    let (offset, ticks) = read_ticks(0)?;
+    assert_eq!(ticks, vec![]);
+
+    clock.advance(Duration::from_secs(1));
+    let (offset, ticks) = read_ticks(offset)?;
+    assert_eq!(ticks, vec![start + Duration::from_secs(1)]);
+
+    // missed ticks are not made up for
+    clock.advance(Duration::from_millis(2500));
+    let (_, ticks) = read_ticks(offset)?;
+    assert_eq!(ticks, vec![start + Duration::from_millis(3500)]);
+
+    svc_ctr.send_stop_to_all();
+    handle.join()?;
+    Ok(())
+}
+
+#[test]
+fn timer_ticks_only_while_auctions_are_pending() -> Result<()> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let clock = SimulatedClock::new_shared(start);
+    let persistence = Arc::new(persistence::InMemoryPersistence::new());
+    let (event_writer, event_reader) = event_log::new_in_memory_shared()?;
+    let bidding_state_store = InMemoryBiddingStateStore::new_shared();
+    let svc_ctr = ServiceControl::new(
+        persistence.clone(),
+        progress::InMemoryProgressTracker::new_shared(),
+        dead_letter::InMemoryDeadLetterStore::new_shared(),
+    )
+    .with_clock(clock.clone());
+
+    let handle = svc_ctr.spawn_loop(
+        Timer::new(
+            persistence.clone(),
+            event_writer,
+            svc_ctr.clock(),
+            Duration::from_secs(1),
+        )
+        .with_pending_auctions(bidding_state_store.clone()),
+    );
+
+    let mut conn = persistence.get_connection()?;
+    let mut read_ticks = |offset| -> Result<(Offset, usize)> {
+        let events = event_reader.read(&mut *conn, offset, 10, Some(Duration::from_millis(300)))?;
+        Ok((events.offset, events.data.len()))
+    };
+
+    clock.advance(Duration::from_secs(1));
+    let (offset, ticks) = read_ticks(0)?;
+    assert_eq!(ticks, 0);
+
+    bidding_state_store.store(
+        &mut *persistence.get_connection()?,
+        "foo",
+        AuctionBiddingState {
+            auction_state: AuctionState {
+                deadline: Some(start + Duration::from_secs(60)),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )?;
+    clock.advance(Duration::from_secs(1));
+    let (_, ticks) = read_ticks(offset)?;
+    assert_eq!(ticks, 1);
+
+    svc_ctr.send_stop_to_all();
+    handle.join()?;
+    Ok(())
+}
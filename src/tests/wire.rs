@@ -0,0 +1,151 @@
+use super::{test_event, TestEvent};
+use crate::{
+    auction::*,
+    event::{topics::*, wire, *},
+};
+use anyhow::Result;
+use serde_json::json;
+use std::time::SystemTime;
+
+fn all_kinds_of_events() -> Result<Vec<Event>> {
+    Ok(vec![
+        Event::new(&AuctionHouseEvent {
+            item: "foo".to_owned(),
+            event: AuctionHouseItemEvent::Bid(BidDetails {
+                bidder: Bidder::Other,
+                price: 10,
+                increment: 2,
+            }),
+        })?,
+        Event::new(&AuctionHouseEvent {
+            item: "foo".to_owned(),
+            event: AuctionHouseItemEvent::ClosesAt(SystemTime::UNIX_EPOCH),
+        })?,
+        Event::new(&AuctionHouseEvent {
+            item: "foo".to_owned(),
+            event: AuctionHouseItemEvent::Closed,
+        })?,
+        Event::new(&BiddingEngineEvent::Bid(ItemBid {
+            item: "foo".to_owned(),
+            price: 12,
+        }))?,
+        Event::new(&BiddingEngineEvent::AuctionError(
+            BiddingEngineAuctionError::UnknownAuction("bar".to_owned()),
+        ))?,
+        Event::new(&BiddingEngineEvent::UserError(
+            BiddingEngineUserError::TooLow,
+        ))?,
+        Event::new(&BiddingEngineEvent::StatusChanged(ItemStatus {
+            item: "foo".to_owned(),
+            status: SniperStatus::Winning,
+        }))?,
+        Event::new(&BiddingEngineEvent::BidWithheld(BidWithheld {
+            item: "foo".to_owned(),
+            price: 40,
+            reason: WithholdReason::OverBudget {
+                budget: 100,
+                committed: 70,
+            },
+        }))?,
+        Event::new(&UiEvent::MaxBidSet(ItemBid {
+            item: "foo".to_owned(),
+            price: 100,
+        }))?,
+        Event::new(&UiEvent::StrategySet(ItemStrategy {
+            item: "foo".to_owned(),
+            strategy: Strategy::FixedStep(FixedStep { step: 5 }),
+        }))?,
+        Event::new(&TimerEvent::Tick(SystemTime::UNIX_EPOCH))?,
+        test_event(),
+    ])
+}
+
+#[test]
+fn events_round_trip() -> Result<()> {
+    for event in all_kinds_of_events()? {
+        assert_eq!(wire::decode(wire::encode(&event)?)?, event);
+    }
+    Ok(())
+}
+
+#[test]
+fn encoding_is_stable() -> Result<()> {
+    let event = Event::new(&UiEvent::MaxBidSet(ItemBid {
+        item: "foo".to_owned(),
+        price: 10,
+    }))?;
+
+    assert_eq!(
+        wire::encode(&event)?,
+        json!({"version": 2, "event": {
+            "topic": "ui",
+            "payload": {"MaxBidSet": {"item": "foo", "price": 10}},
+        }})
+    );
+    Ok(())
+}
+
+#[test]
+fn unversioned_events_are_upcast() -> Result<()> {
+    assert_eq!(
+        wire::decode(json!({"AuctionHouse": {
+            "item": "foo",
+            "event": {"Bid": {"bidder": "Sniper", "price": 3, "increment": 1}},
+        }}))?
+        .decode()?,
+        Some(AuctionHouseEvent {
+            item: "foo".to_owned(),
+            event: AuctionHouseItemEvent::Bid(BidDetails {
+                bidder: Bidder::Sniper,
+                price: 3,
+                increment: 1,
+            }),
+        })
+    );
+    assert_eq!(
+        wire::decode(json!("Test"))?.decode::<TestEvent>()?,
+        Some(TestEvent)
+    );
+    Ok(())
+}
+
+#[test]
+fn single_enum_events_are_split_into_topics() -> Result<()> {
+    assert_eq!(
+        wire::decode(
+            json!({"version": 1, "event": {"BiddingEngine": {"Bid": {"item": "foo", "price": 5}}}})
+        )?
+        .decode()?,
+        Some(BiddingEngineEvent::Bid(ItemBid {
+            item: "foo".to_owned(),
+            price: 5
+        }))
+    );
+    assert_eq!(
+        wire::decode(
+            json!({"version": 1, "event": {"Ui": {"MaxBidSet": {"item": "foo", "price": 7}}}})
+        )?
+        .decode()?,
+        Some(UiEvent::MaxBidSet(ItemBid {
+            item: "foo".to_owned(),
+            price: 7
+        }))
+    );
+    assert!(matches!(
+        wire::decode(json!({"version": 1, "event": {"Unknown": {}}})),
+        Err(wire::WireError::UnknownV1Event(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn newer_versions_are_rejected() {
+    assert!(matches!(
+        wire::decode(json!({"version": wire::CURRENT_VERSION + 1, "event": null})),
+        Err(wire::WireError::VersionTooNew { .. })
+    ));
+    assert!(matches!(
+        wire::decode(json!({"version": "1", "event": "Test"})),
+        Err(wire::WireError::InvalidEnvelope(_))
+    ));
+}
@@ -0,0 +1,201 @@
+use super::fake_auction_server::FakeAuctionServer;
+use crate::{
+    auction::{BidDetails, Bidder},
+    event::topics::{AuctionHouseEvent, AuctionHouseItemEvent},
+    persistence::{InMemoryPersistence, Persistence},
+    service::{
+        auction_house::{
+            join_open_auctions, AuctionHouseClient, XmppAuctionHouseClient, XmppConfig,
+        },
+        bidding_engine::{
+            AuctionBiddingState, AuctionState, BiddingStateStore, InMemoryBiddingStateStore,
+        },
+    },
+};
+use anyhow::{bail, Result};
+use std::{
+    net::TcpListener,
+    time::{Duration, Instant},
+};
+
+fn poll(client: &XmppAuctionHouseClient) -> Result<AuctionHouseEvent> {
+    match client.poll(Some(Duration::from_secs(10)))? {
+        Some(polled) => Ok(polled.event),
+        None => bail!("timeout waiting for an event"),
+    }
+}
+
+#[test]
+fn joins_the_auction_before_bidding() -> Result<()> {
+    let server = FakeAuctionServer::start("sniper")?;
+    let client = XmppAuctionHouseClient::new(server.sniper_config());
+
+    client.place_bid("54321", 100)?;
+    client.place_bid("54321", 110)?;
+
+    let to = "auction-54321@localhost".to_owned();
+    assert_eq!(
+        server.wait_for_received(3)?,
+        vec![
+            (to.clone(), "SOLVersion: 1.1; Command: JOIN;".to_owned()),
+            (
+                to.clone(),
+                "SOLVersion: 1.1; Command: BID; Price: 100;".to_owned()
+            ),
+            (to, "SOLVersion: 1.1; Command: BID; Price: 110;".to_owned()),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn joins_auctions_without_bidding() -> Result<()> {
+    let server = FakeAuctionServer::start("sniper")?;
+    let client = XmppAuctionHouseClient::new(server.sniper_config());
+
+    // joined once connected, by polling
+    client.join("54321")?;
+    client.join("54321")?;
+    assert_eq!(client.poll(Some(Duration::from_millis(100)))?, None);
+    client.join("12345")?;
+    client.join("54321")?;
+
+    assert_eq!(
+        server.wait_for_received(2)?,
+        vec![
+            (
+                "auction-54321@localhost".to_owned(),
+                "SOLVersion: 1.1; Command: JOIN;".to_owned()
+            ),
+            (
+                "auction-12345@localhost".to_owned(),
+                "SOLVersion: 1.1; Command: JOIN;".to_owned()
+            ),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn joins_open_auctions_on_startup() -> Result<()> {
+    let server = FakeAuctionServer::start("sniper")?;
+    let client = XmppAuctionHouseClient::new(server.sniper_config());
+    let persistence = InMemoryPersistence::new();
+    let bidding_state_store = InMemoryBiddingStateStore::new();
+    let state = |closed| AuctionBiddingState {
+        max_bid_limit: 100,
+        auction_state: AuctionState {
+            closed,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut conn = persistence.get_connection()?;
+    bidding_state_store.store(&mut *conn, "open", state(false))?;
+    bidding_state_store.store(&mut *conn, "closed", state(true))?;
+
+    join_open_auctions(&persistence, &bidding_state_store, &client)?;
+    assert_eq!(client.poll(Some(Duration::from_millis(100)))?, None);
+
+    assert_eq!(
+        server.wait_for_received(1)?,
+        vec![(
+            "auction-open@localhost".to_owned(),
+            "SOLVersion: 1.1; Command: JOIN;".to_owned()
+        )]
+    );
+    Ok(())
+}
+
+#[test]
+fn receives_prices_and_close_of_auctions() -> Result<()> {
+    let server = FakeAuctionServer::start("sniper")?;
+    let client = XmppAuctionHouseClient::new(server.sniper_config());
+    client.place_bid("54321", 100)?;
+    server.wait_for_received(2)?;
+
+    let from = "auction-54321@localhost/Auction";
+    server.send(
+        from,
+        "SOLVersion: 1.1; Event: PRICE; CurrentPrice: 192; Increment: 7; Bidder: other bidder;",
+    )?;
+    server.send("someone@localhost", "SOLVersion: 1.1; Event: CLOSE;")?;
+    server.send(from, "not a valid message")?;
+    server.send(
+        from,
+        "SOLVersion: 1.1; Event: PRICE; CurrentPrice: 199; Increment: 8; Bidder: sniper@localhost/Auction;",
+    )?;
+    server.send(from, "SOLVersion: 1.1; Event: CLOSE;")?;
+
+    let event = |event| AuctionHouseEvent {
+        item: "54321".to_owned(),
+        event,
+    };
+    assert_eq!(
+        poll(&client)?,
+        event(AuctionHouseItemEvent::Bid(BidDetails {
+            bidder: Bidder::Other,
+            price: 192,
+            increment: 7,
+        }))
+    );
+    assert_eq!(
+        poll(&client)?,
+        event(AuctionHouseItemEvent::Bid(BidDetails {
+            bidder: Bidder::Sniper,
+            price: 199,
+            increment: 8,
+        }))
+    );
+    assert_eq!(poll(&client)?, event(AuctionHouseItemEvent::Closed));
+    assert_eq!(client.poll(Some(Duration::from_millis(100)))?, None);
+    Ok(())
+}
+
+#[test]
+fn rejoins_auctions_after_reconnecting() -> Result<()> {
+    let server = FakeAuctionServer::start("sniper")?;
+    let client = XmppAuctionHouseClient::new(server.sniper_config());
+    client.place_bid("54321", 100)?;
+    server.wait_for_received(2)?;
+
+    server.disconnect();
+    assert!(client.poll(Some(Duration::from_secs(10))).is_err());
+
+    client.place_bid("54321", 110)?;
+    let to = "auction-54321@localhost".to_owned();
+    assert_eq!(
+        server.wait_for_received(4)?[2..],
+        [
+            (to.clone(), "SOLVersion: 1.1; Command: JOIN;".to_owned()),
+            (to, "SOLVersion: 1.1; Command: BID; Price: 110;".to_owned()),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn fails_with_wrong_password() -> Result<()> {
+    let server = FakeAuctionServer::start("secret")?;
+    let client = XmppAuctionHouseClient::new(server.sniper_config());
+
+    assert!(client.place_bid("54321", 100).is_err());
+    assert!(client.poll(Some(Duration::from_millis(100))).is_err());
+    Ok(())
+}
+
+#[test]
+fn gives_up_on_a_silent_server() -> Result<()> {
+    // accepts connections, but never says anything
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let client = XmppAuctionHouseClient::new(XmppConfig {
+        address: listener.local_addr()?.to_string(),
+        timeout: Duration::from_millis(100),
+        ..Default::default()
+    });
+
+    let start = Instant::now();
+    assert!(client.place_bid("54321", 100).is_err());
+    assert!(start.elapsed() < Duration::from_secs(5));
+    Ok(())
+}